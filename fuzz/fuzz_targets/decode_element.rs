@@ -0,0 +1,49 @@
+//! Decodes arbitrary bytes into Telnet elements, once in one go and once byte by byte (as if each
+//! byte arrived in its own packet), and checks that both ways agree.
+//!
+//! Run using `cargo +nightly fuzz run decode_element` (requires cargo-fuzz).
+
+#![no_main]
+
+
+use std::net::SocketAddr;
+
+use libfuzzer_sys::fuzz_target;
+use telnet_animations::fuzzing::{Element, decode_element};
+
+
+const MAX_SUB_NEGOTIATION_LENGTH: usize = 64;
+
+
+/// Decodes the chunks as they arrive, returning the elements and whether decoding failed.
+fn decode_all<'a, I: Iterator<Item = &'a [u8]>>(chunks: I) -> (Vec<Element>, bool) {
+    let addr = SocketAddr::from(([127, 0, 0, 1], 23));
+    let mut buf = Vec::new();
+    let mut elements = Vec::new();
+    for chunk in chunks {
+        buf.extend_from_slice(chunk);
+        loop {
+            match decode_element(&buf, addr, MAX_SUB_NEGOTIATION_LENGTH) {
+                Ok(Some((element, length))) => {
+                    assert!(length > 0, "element {:?} has no length", element);
+                    assert!(length <= buf.len(), "element {:?} is longer than the buffer", element);
+                    if let Element::SubNegotiation(sub_buf) = &element {
+                        assert!(sub_buf.len() <= MAX_SUB_NEGOTIATION_LENGTH);
+                    }
+                    elements.push(element);
+                    buf.drain(..length);
+                },
+                Ok(None) => break,
+                Err(_) => return (elements, true),
+            }
+        }
+    }
+    (elements, false)
+}
+
+
+fuzz_target!(|data: &[u8]| {
+    let whole = decode_all(std::iter::once(data));
+    let bytewise = decode_all(data.chunks(1));
+    assert_eq!(whole, bytewise);
+});
@@ -0,0 +1,77 @@
+//! Feeds arbitrary bytes to a Telnet connection over an in-memory pipe and reads events until the
+//! input is exhausted, checking that the connection neither hangs nor answers out of proportion.
+//!
+//! Run using `cargo +nightly fuzz run read_event` (requires cargo-fuzz).
+
+#![no_main]
+
+
+use std::net::SocketAddr;
+use std::sync::OnceLock;
+
+use libfuzzer_sys::fuzz_target;
+use telnet_animations::fuzzing::{Event, TelnetConnection};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::runtime::Runtime;
+
+
+const MAX_SUB_NEGOTIATION_LENGTH: usize = 64;
+
+/// How many bytes `TelnetConnection::negotiate` sends.
+const NEGOTIATION_LENGTH: usize = 9;
+
+
+fn runtime() -> &'static Runtime {
+    static RUNTIME: OnceLock<Runtime> = OnceLock::new();
+    RUNTIME.get_or_init(|| {
+        tokio::runtime::Builder::new_current_thread()
+            .build()
+            .expect("failed to build runtime")
+    })
+}
+
+
+fuzz_target!(|data: &[u8]| {
+    runtime().block_on(async {
+        let addr = SocketAddr::from(([127, 0, 0, 1], 23));
+        let (client, server) = tokio::io::duplex(256);
+        let (mut client_reader, mut client_writer) = tokio::io::split(client);
+        let mut connection = TelnetConnection::new(server, addr, MAX_SUB_NEGOTIATION_LENGTH);
+
+        let serve = async {
+            connection.negotiate().await.expect("negotiation failed");
+            let mut event_count = 0;
+            loop {
+                match connection.read_event().await {
+                    Ok(Event::EndOfInput) => break,
+                    Ok(_) => {
+                        // each event consumes at least one byte
+                        event_count += 1;
+                        assert!(event_count <= data.len(), "{} events from {} bytes", event_count, data.len());
+                    },
+                    Err(_) => break,
+                }
+            }
+            // lets the client see the end of the output
+            drop(connection);
+        };
+        let send = async {
+            // the connection may give up before reading everything
+            let _ = client_writer.write_all(data).await;
+            let _ = client_writer.shutdown().await;
+        };
+        let receive = async {
+            let mut output = Vec::new();
+            let _ = client_reader.read_to_end(&mut output).await;
+            output
+        };
+        let ((), (), output) = tokio::join!(serve, send, receive);
+
+        // at worst, each negotiation (three bytes) is answered with a terminal type query (six
+        // bytes)
+        assert!(
+            output.len() <= NEGOTIATION_LENGTH + 2 * data.len(),
+            "{} bytes of answers to {} bytes", output.len(), data.len(),
+        );
+    });
+});
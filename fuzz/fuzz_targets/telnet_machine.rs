@@ -0,0 +1,80 @@
+//! Feeds arbitrary bytes to a Telnet state machine, once in one go and once byte by byte (as if
+//! each byte arrived in its own packet), and checks that both ways yield the same events and
+//! answers, and that the answers are not out of proportion.
+//!
+//! Run using `cargo +nightly fuzz run telnet_machine` (requires cargo-fuzz).
+
+#![no_main]
+
+
+use std::net::SocketAddr;
+
+use libfuzzer_sys::fuzz_target;
+use telnet_animations::fuzzing::{ESC, Event, TelnetMachine};
+
+
+const MAX_SUB_NEGOTIATION_LENGTH: usize = 64;
+
+/// How many bytes `TelnetMachine::negotiate` queues.
+const NEGOTIATION_LENGTH: usize = 9;
+
+
+/// Takes the events and the output from the machine; returns whether decoding failed.
+fn drain(machine: &mut TelnetMachine, events: &mut Vec<Event>, output: &mut Vec<u8>) -> bool {
+    let failed = loop {
+        match machine.next_event() {
+            Ok(Some(event)) => events.push(event),
+            Ok(None) => break false,
+            Err(_) => break true,
+        }
+    };
+    output.extend_from_slice(machine.output());
+    machine.consume_output(machine.output().len());
+    failed
+}
+
+
+/// Feeds the chunks to a new state machine, returning the events, whether decoding failed and
+/// everything the machine wanted to send.
+fn run<'a, I: Iterator<Item = &'a [u8]>>(chunks: I) -> (Vec<Event>, bool, Vec<u8>) {
+    let addr = SocketAddr::from(([127, 0, 0, 1], 23));
+    let mut machine = TelnetMachine::new(addr, MAX_SUB_NEGOTIATION_LENGTH);
+    machine.negotiate();
+
+    let mut events = Vec::new();
+    let mut output = Vec::new();
+    for chunk in chunks {
+        machine.receive(chunk);
+        if drain(&mut machine, &mut events, &mut output) {
+            return (events, true, output);
+        }
+    }
+    machine.receive_end();
+    let failed = drain(&mut machine, &mut events, &mut output);
+    (events, failed, output)
+}
+
+
+fuzz_target!(|data: &[u8]| {
+    let (whole_events, whole_failed, whole_output) = run(std::iter::once(data));
+
+    // each event consumes at least one byte, except for the end of input which comes last
+    assert_eq!(whole_failed, whole_events.last() != Some(&Event::EndOfInput));
+    assert!(whole_events.len() <= data.len() + 1, "{} events from {} bytes", whole_events.len(), data.len());
+    assert_eq!(whole_events.iter().filter(|e| **e == Event::EndOfInput).count(), usize::from(!whole_failed));
+
+    // at worst, each negotiation (three bytes) is answered with a terminal type query (six bytes)
+    assert!(
+        whole_output.len() <= NEGOTIATION_LENGTH + 2 * data.len(),
+        "{} bytes of answers to {} bytes", whole_output.len(), data.len(),
+    );
+
+    if data.contains(&ESC) {
+        // an ESC at the end of a chunk counts as the Escape key, so chunking matters
+        return;
+    }
+    let (bytewise_events, bytewise_failed, bytewise_output) = run(data.chunks(1));
+    assert_eq!(whole_events, bytewise_events);
+    assert_eq!(whole_failed, bytewise_failed);
+    assert_eq!(whole_output, bytewise_output);
+});
@@ -0,0 +1,264 @@
+//! The admin interface, a simple line-based protocol on a local socket.
+//!
+//! Each command is answered with zero or more lines of output followed by a line reading `OK`
+//! or a line starting with `ERR`.
+
+
+use std::fmt::Write;
+use std::io;
+use std::net::SocketAddr;
+#[cfg(unix)]
+use std::os::unix::fs::FileTypeExt;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::Duration;
+
+use log::{info, warn};
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use tokio::io::{AsyncBufReadExt, AsyncRead, AsyncWrite, AsyncWriteExt, BufReader};
+use tokio::net::TcpListener;
+use tokio::sync::watch;
+
+use crate::animations::{self, AnimationConfig};
+use crate::config::SocketConfig;
+use crate::registry::{Registry, SessionCommand};
+use crate::task;
+
+
+const HELP: &str = concat!(
+    "list                          list live sessions (id, client, socket, animation, seconds, bytes sent)\n",
+    "frames ID                     show frame timing statistics of a session\n",
+    "kick ID                       end a session\n",
+    "broadcast MESSAGE             show a message to all clients\n",
+    "set-animation SOCKET NAME     serve an animation to new connections on a socket\n",
+    "help                          show this help\n",
+    "quit                          close the admin connection\n",
+);
+
+
+/// Configuration of the admin interface.
+///
+/// The admin interface has no authentication, so it should only be reachable by trusted users.
+#[derive(Clone, Debug, Default, Deserialize, JsonSchema, PartialEq, Serialize)]
+pub(crate) struct AdminConfig {
+    /// A (loopback) TCP address to listen on.
+    #[serde(default)]
+    pub listen_socket_addr: Option<SocketAddr>,
+
+    /// The path of a Unix socket to listen on (Unix only).
+    #[serde(default)]
+    pub unix_socket_path: Option<PathBuf>,
+}
+
+
+/// What the admin interface operates on.
+pub(crate) struct AdminState {
+    pub registry: Registry,
+
+    /// The configurations of the listening sockets, which are used for new connections.
+    pub sockets: Vec<watch::Sender<SocketConfig>>,
+}
+
+
+type Reader = Box<dyn AsyncRead + Send + Unpin>;
+type Writer = Box<dyn AsyncWrite + Send + Unpin>;
+
+
+/// Listens for connections to the admin interface.
+pub(crate) enum AdminListener {
+    Tcp(TcpListener),
+
+    #[cfg(unix)]
+    Unix { listener: tokio::net::UnixListener, path: PathBuf },
+}
+impl AdminListener {
+    /// Binds all the sockets configured for the admin interface.
+    pub fn bind(config: &AdminConfig) -> io::Result<Vec<Self>> {
+        let mut ret = Vec::new();
+        if let Some(addr) = config.listen_socket_addr {
+            let listener = std::net::TcpListener::bind(addr)?;
+            listener.set_nonblocking(true)?;
+            ret.push(Self::Tcp(TcpListener::from_std(listener)?));
+        }
+        #[cfg(unix)]
+        if let Some(path) = &config.unix_socket_path {
+            // remove a socket left behind by an earlier run, but nothing else
+            match std::fs::symlink_metadata(path) {
+                Ok(metadata) if metadata.file_type().is_socket() => std::fs::remove_file(path)?,
+                Ok(_) => return Err(io::Error::new(
+                    io::ErrorKind::AlreadyExists,
+                    format!("{} exists and is not a socket", path.display()),
+                )),
+                Err(e) if e.kind() == io::ErrorKind::NotFound => {},
+                Err(e) => return Err(e),
+            }
+            let listener = tokio::net::UnixListener::bind(path)?;
+            ret.push(Self::Unix { listener, path: path.clone() });
+        }
+        Ok(ret)
+    }
+
+    fn describe(&self) -> String {
+        match self {
+            Self::Tcp(listener) => listener.local_addr()
+                .map(|a| a.to_string())
+                .unwrap_or_else(|_| "TCP".to_owned()),
+            #[cfg(unix)]
+            Self::Unix { path, .. } => path.display().to_string(),
+        }
+    }
+
+    async fn accept(&self) -> io::Result<(Reader, Writer)> {
+        match self {
+            Self::Tcp(listener) => {
+                let (stream, _addr) = listener.accept().await?;
+                let (reader, writer) = stream.into_split();
+                Ok((Box::new(reader), Box::new(writer)))
+            },
+            #[cfg(unix)]
+            Self::Unix { listener, .. } => {
+                let (stream, _addr) = listener.accept().await?;
+                let (reader, writer) = stream.into_split();
+                Ok((Box::new(reader), Box::new(writer)))
+            },
+        }
+    }
+}
+impl Drop for AdminListener {
+    fn drop(&mut self) {
+        #[cfg(unix)]
+        if let Self::Unix { path, .. } = self {
+            let _ = std::fs::remove_file(path);
+        }
+    }
+}
+
+
+/// Accepts admin connections until `shutdown` changes.
+pub(crate) async fn serve(listener: AdminListener, state: Arc<AdminState>, mut shutdown: watch::Receiver<bool>) {
+    info!("admin interface listening on {}", listener.describe());
+    loop {
+        let accepted = tokio::select! {
+            accepted = listener.accept() => accepted,
+            _ = shutdown.changed() => break,
+        };
+        match accepted {
+            Ok((reader, writer)) => {
+                let client_state = Arc::clone(&state);
+                task::spawn_logged("admin connection".to_owned(), async move {
+                    if let Err(e) = handle_client(reader, writer, &client_state).await {
+                        warn!("admin connection failed: {}", e);
+                    }
+                });
+            },
+            Err(e) => warn!("failed to accept admin connection: {}", e),
+        }
+    }
+}
+
+
+async fn handle_client(reader: Reader, mut writer: Writer, state: &AdminState) -> io::Result<()> {
+    let mut lines = BufReader::new(reader).lines();
+    while let Some(line) = lines.next_line().await? {
+        let line = line.trim();
+        if line.is_empty() {
+            continue;
+        }
+        if line == "quit" {
+            writer.write_all(b"OK\n").await?;
+            break;
+        }
+
+        let response = match execute(line, state) {
+            Ok(output) => format!("{}OK\n", output),
+            Err(message) => format!("ERR {}\n", message),
+        };
+        writer.write_all(response.as_bytes()).await?;
+        writer.flush().await?;
+    }
+    Ok(())
+}
+
+
+/// Executes an admin command, returning its output or an error message.
+fn execute(line: &str, state: &AdminState) -> Result<String, String> {
+    let (command, args) = line.split_once(' ')
+        .map(|(c, a)| (c, a.trim()))
+        .unwrap_or((line, ""));
+    match command {
+        "help" => Ok(HELP.to_owned()),
+        "list" => {
+            let mut output = String::new();
+            for info in state.registry.list() {
+                writeln!(
+                    output,
+                    "{} {} {} {} {} {}",
+                    info.id,
+                    info.peer_addr,
+                    info.listen_addr,
+                    info.animation.as_deref().unwrap_or("-"),
+                    info.connected_at.elapsed().as_secs(),
+                    info.bytes_sent,
+                ).unwrap();
+            }
+            Ok(output)
+        },
+        "frames" => {
+            let id = args.parse()
+                .map_err(|_| format!("invalid session ID {:?}", args))?;
+            let frame_stats = state.registry.frame_stats(id)
+                .ok_or_else(|| format!("no session {}", id))?;
+            let optional_millis = |duration: Option<Duration>| duration
+                .map(|d| format!("{:.3}", d.as_secs_f64() * 1000.0))
+                .unwrap_or_else(|| "-".to_owned());
+            let mut output = String::new();
+            writeln!(output, "frames_sent {}", frame_stats.frames_sent).unwrap();
+            writeln!(output, "frames_rendered {}", frame_stats.frames_rendered).unwrap();
+            writeln!(output, "frames_skipped {}", frame_stats.frames_skipped).unwrap();
+            writeln!(output, "bytes_per_frame {}", frame_stats.bytes_per_frame().map(|b| b.to_string()).unwrap_or_else(|| "-".to_owned())).unwrap();
+            writeln!(output, "render_avg_ms {}", optional_millis(frame_stats.average_render_time())).unwrap();
+            for percent in [50, 95, 99] {
+                writeln!(output, "render_p{}_ms {}", percent, optional_millis(frame_stats.render_time_percentile(percent))).unwrap();
+            }
+            writeln!(output, "write_avg_ms {}", optional_millis(frame_stats.average_write_time())).unwrap();
+            Ok(output)
+        },
+        "kick" => {
+            let id = args.parse()
+                .map_err(|_| format!("invalid session ID {:?}", args))?;
+            if state.registry.send_command(id, SessionCommand::Kick) {
+                info!("admin kicked session {}", id);
+                Ok(String::new())
+            } else {
+                Err(format!("no session {}", id))
+            }
+        },
+        "broadcast" => {
+            if args.is_empty() {
+                return Err("no message given".to_owned());
+            }
+            let count = state.registry.send_command_to_all(SessionCommand::Message(args.to_owned()));
+            Ok(format!("{} sessions\n", count))
+        },
+        "set-animation" => {
+            let (socket, name) = args.split_once(' ')
+                .ok_or_else(|| "usage: set-animation SOCKET NAME".to_owned())?;
+            let socket_addr: SocketAddr = socket.parse()
+                .map_err(|_| format!("invalid socket address {:?}", socket))?;
+            let name = name.trim();
+            animations::by_name(name)
+                .map_err(|e| e.to_string())?;
+            let sender = state.sockets.iter()
+                .find(|s| s.borrow().listen_socket_addr == socket_addr)
+                .ok_or_else(|| format!("no socket {}", socket_addr))?;
+            sender.send_modify(|socket_config| {
+                socket_config.animation = Some(AnimationConfig { name: name.to_owned(), params: toml::Table::new() });
+                socket_config.animations.clear();
+            });
+            info!("admin set animation of {} to {:?}", socket_addr, name);
+            Ok(String::new())
+        },
+        other => Err(format!("unknown command {:?} (try \"help\")", other)),
+    }
+}
@@ -0,0 +1,227 @@
+//! Playback of ANSI art (.ans files) as drawn over a modem line, like on a BBS.
+
+
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use log::warn;
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+
+use crate::animations::{self, Animation, AnimationInfo, CreateError, Frame};
+use crate::telnet::SessionInfo;
+
+
+/// The characters of code page 437 from 0x80 to 0xFF.
+const CP437_HIGH: &str = concat!(
+    "ÇüéâäàåçêëèïîìÄÅÉæÆôöòûùÿÖÜ¢£¥₧ƒ",
+    "áíóúñÑªº¿⌐¬½¼¡«»░▒▓│┤╡╢╖╕╣║╗╝╜╛┐",
+    "└┴┬├─┼╞╟╚╔╩╦╠═╬╧╨╤╥╙╘╒╓╫╪┘┌█▄▌▐▀",
+    "αßΓπΣσµτΦΘΩδ∞φε∩≡±≥≤⌠⌡÷≈°∙·√ⁿ²■\u{A0}",
+);
+
+/// The characters of code page 437 from 0x01 to 0x1F, which DOS showed as glyphs unless they were
+/// one of the few control characters it understood.
+const CP437_LOW: &str = "☺☻♥♦♣♠•◘○◙♂♀♪♫☼►◄↕‼¶§▬↨↑↓→←∟↔▲▼";
+
+/// Marks the end of the text; the SAUCE metadata record (if any) follows it.
+const EOF: u8 = 0x1A;
+
+/// How many bits it takes to send a byte over a serial line (8N1: start bit, 8 data bits, stop
+/// bit).
+const BITS_PER_BYTE: u64 = 10;
+
+/// Resets the attributes and clears the screen before each piece.
+const CLEAR: &str = "\x1B[0m\x1B[2J\x1B[H";
+
+
+pub(crate) const INFO: AnimationInfo = AnimationInfo {
+    name: "ansi_art",
+    description: "ANSI art (.ans files) drawn at modem speed, like on a BBS.",
+    default_frame_ms: 50,
+    size: (80, 25),
+    create: |config, _context| {
+        let params: Params = config.parse_params()?;
+        let files = list_files(&params.path)
+            .map_err(|error| CreateError::LoadFailed { name: config.name.clone(), path: params.path.clone(), error })?;
+        if files.is_empty() {
+            return Err(CreateError::NoFrames { name: config.name.clone(), path: params.path.clone() });
+        }
+        Ok(Box::new(AnsiArt::new(params, files)))
+    },
+    params_schema: animations::params_schema::<Params>,
+    default_params: animations::default_params::<Params>,
+    needs_params: true,
+};
+
+
+/// Parameters of the ANSI art playback.
+#[derive(Clone, Debug, Deserialize, Eq, Hash, JsonSchema, Ord, PartialEq, PartialOrd, Serialize)]
+#[serde(default, deny_unknown_fields)]
+pub(crate) struct Params {
+    /// How often the next part of the piece is sent, in milliseconds.
+    pub frame_ms: u64,
+
+    /// Either a single .ans file or a directory (e.g. an unpacked artpack) whose .ans files are
+    /// shown one after the other in the order of their names. Relative paths are resolved against
+    /// the working directory.
+    pub path: PathBuf,
+
+    /// The speed of the emulated modem line in bits per second; 0 shows each piece at once.
+    pub baud: u64,
+
+    /// How long each piece stays on the screen once it has been drawn, in milliseconds.
+    pub pause_ms: u64,
+}
+impl Default for Params {
+    fn default() -> Self {
+        Self {
+            frame_ms: INFO.default_frame_ms,
+            path: PathBuf::from("art"),
+            baud: 14400,
+            pause_ms: 5000,
+        }
+    }
+}
+
+
+/// Lists the .ans files to show: the given file itself or those within the given directory.
+fn list_files(path: &Path) -> io::Result<Vec<PathBuf>> {
+    if !path.is_dir() {
+        // fail now rather than once the file is to be shown
+        fs::metadata(path)?;
+        return Ok(vec![path.to_owned()]);
+    }
+
+    let mut files = Vec::new();
+    for entry in fs::read_dir(path)? {
+        let file_path = entry?.path();
+        let is_ans = file_path.extension()
+            .is_some_and(|e| e.eq_ignore_ascii_case("ans"));
+        if is_ans && file_path.is_file() {
+            files.push(file_path);
+        }
+    }
+    files.sort_unstable();
+    Ok(files)
+}
+
+
+/// Decodes the contents of a .ans file (CP437 text interspersed with ANSI escape sequences),
+/// dropping the SAUCE metadata at its end.
+pub(crate) fn decode_ans(bytes: &[u8]) -> String {
+    let end = bytes.iter()
+        .position(|b| *b == EOF)
+        .unwrap_or(bytes.len());
+    let high: Vec<char> = CP437_HIGH.chars().collect();
+    let low: Vec<char> = CP437_LOW.chars().collect();
+
+    let mut ret = String::with_capacity(end);
+    let mut after_cr = false;
+    for &b in &bytes[..end] {
+        match b {
+            b'\n' if !after_cr => {
+                // many files end lines with LF alone, which would leave the cursor in its column
+                ret.push_str("\r\n");
+            },
+            b'\x08' | b'\t' | b'\n' | b'\r' | b'\x1B' => ret.push(char::from(b)),
+            // DOS rang the bell, which nobody wants to hear
+            b'\x07' => {},
+            0x00 => ret.push(' '),
+            0x01..=0x1F => ret.push(low[usize::from(b - 0x01)]),
+            0x7F => ret.push('⌂'),
+            0x80..=0xFF => ret.push(high[usize::from(b - 0x80)]),
+            _ => ret.push(char::from(b)),
+        }
+        after_cr = b == b'\r';
+    }
+    ret
+}
+
+
+#[derive(Clone, Debug, Eq, Hash, Ord, PartialEq, PartialOrd)]
+pub(crate) struct AnsiArt {
+    params: Params,
+    files: Vec<PathBuf>,
+
+    /// The index of the file being shown.
+    file_index: usize,
+
+    /// The piece being shown, or `None` if no file could be loaded.
+    piece: Option<Vec<char>>,
+
+    /// How many characters of the piece have been sent.
+    position: usize,
+}
+impl AnsiArt {
+    pub fn new(params: Params, files: Vec<PathBuf>) -> Self {
+        let mut ret = Self {
+            params,
+            files,
+            file_index: 0,
+            piece: None,
+            position: 0,
+        };
+        ret.load(0);
+        ret
+    }
+
+    /// Loads the first readable piece, starting at the given index.
+    fn load(&mut self, start_index: usize) {
+        self.piece = None;
+        self.position = 0;
+        for offset in 0..self.files.len() {
+            let index = (start_index + offset) % self.files.len();
+            match fs::read(&self.files[index]) {
+                Ok(bytes) => {
+                    self.file_index = index;
+                    self.piece = Some(decode_ans(&bytes).chars().collect());
+                    return;
+                },
+                Err(e) => warn!("failed to read ANSI art {}: {}", self.files[index].display(), e),
+            }
+        }
+    }
+
+    /// How many characters are sent with each frame, or `None` if the whole piece is.
+    fn chars_per_frame(&self) -> Option<usize> {
+        if self.params.baud == 0 {
+            return None;
+        }
+        let chars = self.params.baud * self.params.frame_ms / (1000 * BITS_PER_BYTE);
+        Some(usize::try_from(chars).unwrap_or(usize::MAX).max(1))
+    }
+}
+impl Animation for AnsiArt {
+    fn next_frame(&mut self, _session: &SessionInfo) -> Option<Frame> {
+        let piece = self.piece.as_ref()?;
+        let starts_piece = self.position == 0;
+        let starts_cycle = starts_piece && self.file_index == 0;
+
+        let mut commands = String::new();
+        if starts_piece {
+            commands.push_str(CLEAR);
+        }
+        let end = match self.chars_per_frame() {
+            Some(count) => piece.len().min(self.position.saturating_add(count)),
+            None => piece.len(),
+        };
+        commands.extend(&piece[self.position..end]);
+        self.position = end;
+
+        let delay = if self.position == piece.len() {
+            // don't let the colors bleed into whatever comes next
+            commands.push_str("\x1B[0m");
+            self.load(self.file_index + 1);
+            Duration::from_millis(self.params.pause_ms)
+        } else {
+            Duration::from_millis(self.params.frame_ms)
+        };
+
+        let mut frame = Frame::new(commands, delay);
+        frame.starts_cycle = starts_cycle;
+        Some(frame)
+    }
+}
@@ -0,0 +1,171 @@
+//! Playback of asciimations in the classic format made famous by the Star Wars asciimation: frames
+//! of a fixed number of lines, the first of which tells how many ticks the frame is held for.
+
+
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+
+use crate::animations::{self, Animation, AnimationInfo, CreateError, Frame};
+use crate::animations::deck::{clean_line, full_frame};
+use crate::keys::Key;
+use crate::telnet::{Event, SessionInfo};
+
+
+pub(crate) const INFO: AnimationInfo = AnimationInfo {
+    name: "asciimation",
+    description: "A long-form asciimation in the classic format; cursor keys seek, Home rewinds.",
+    default_frame_ms: 67,
+    size: (67, 13),
+    create: |config, _context| {
+        let params: Params = config.parse_params()?;
+        let frames = load(&params.path, params.frame_height)
+            .map_err(|error| CreateError::LoadFailed { name: config.name.clone(), path: params.path.clone(), error })?;
+        if frames.is_empty() {
+            return Err(CreateError::NoFrames { name: config.name.clone(), path: params.path.clone() });
+        }
+        Ok(Box::new(Asciimation::new(params, frames)))
+    },
+    params_schema: animations::params_schema::<Params>,
+    default_params: animations::default_params::<Params>,
+    needs_params: true,
+};
+
+
+/// Parameters of the asciimation playback.
+#[derive(Clone, Debug, Deserialize, Eq, Hash, JsonSchema, Ord, PartialEq, PartialOrd, Serialize)]
+#[serde(default, deny_unknown_fields)]
+pub(crate) struct Params {
+    /// How long a tick lasts, in milliseconds; each frame is held for the number of ticks given in
+    /// its first line. The classic format assumes 15 ticks per second.
+    pub frame_ms: u64,
+
+    /// The file containing the asciimation. Relative paths are resolved against the working
+    /// directory.
+    pub path: PathBuf,
+
+    /// How many lines each frame takes up in the file, including the line with the tick count.
+    pub frame_height: usize,
+
+    /// How far the left and right cursor keys seek, in seconds.
+    pub seek_secs: u64,
+}
+impl Default for Params {
+    fn default() -> Self {
+        Self {
+            frame_ms: INFO.default_frame_ms,
+            path: PathBuf::from("asciimation.txt"),
+            frame_height: 14,
+            seek_secs: 10,
+        }
+    }
+}
+
+
+#[derive(Clone, Debug, Eq, Hash, Ord, PartialEq, PartialOrd)]
+struct AsciimationFrame {
+    /// How many ticks the frame is held for.
+    ticks: u64,
+
+    lines: Vec<String>,
+}
+
+
+/// Loads the frames of an asciimation.
+fn load(path: &Path, frame_height: usize) -> io::Result<Vec<AsciimationFrame>> {
+    if frame_height < 2 {
+        return Err(io::Error::new(io::ErrorKind::InvalidInput, "frames must be at least two lines high"));
+    }
+
+    let text = fs::read_to_string(path)?;
+    let lines: Vec<&str> = text.lines().collect();
+    let mut frames = Vec::with_capacity(lines.len() / frame_height);
+    for (frame_index, frame_lines) in lines.chunks(frame_height).enumerate() {
+        let ticks_line = frame_lines[0].trim();
+        if ticks_line.is_empty() && frame_lines.len() == 1 {
+            // a stray line at the end of the file
+            break;
+        }
+        let ticks = ticks_line.parse()
+            .map_err(|_| io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("line {}: expected the tick count of a frame, found {:?}", frame_index * frame_height + 1, ticks_line),
+            ))?;
+        frames.push(AsciimationFrame {
+            ticks,
+            lines: frame_lines[1..].iter().map(|line| clean_line(line)).collect(),
+        });
+    }
+    Ok(frames)
+}
+
+
+#[derive(Clone, Debug, Eq, Hash, Ord, PartialEq, PartialOrd)]
+pub(crate) struct Asciimation {
+    params: Params,
+    frames: Vec<AsciimationFrame>,
+
+    /// The tick at which each frame starts.
+    start_ticks: Vec<u64>,
+
+    /// The index of the frame to show next.
+    frame_index: usize,
+}
+impl Asciimation {
+    fn new(params: Params, frames: Vec<AsciimationFrame>) -> Self {
+        let start_ticks = frames.iter()
+            .scan(0, |tick, frame| {
+                let start = *tick;
+                *tick += frame.ticks;
+                Some(start)
+            })
+            .collect();
+        Self {
+            params,
+            frames,
+            start_ticks,
+            frame_index: 0,
+        }
+    }
+
+    /// Continues playback at the frame shown at the given tick (or the last frame, if the
+    /// asciimation is over by then).
+    fn seek_to_tick(&mut self, tick: u64) {
+        self.frame_index = self.start_ticks.partition_point(|start| *start <= tick)
+            .saturating_sub(1);
+    }
+
+    /// How many ticks seeking skips.
+    fn seek_ticks(&self) -> u64 {
+        (self.params.seek_secs * 1000) / self.params.frame_ms.max(1)
+    }
+}
+impl Animation for Asciimation {
+    fn next_frame(&mut self, _session: &SessionInfo) -> Option<Frame> {
+        let frame = &self.frames[self.frame_index];
+        let delay = Duration::from_millis(self.params.frame_ms.saturating_mul(frame.ticks));
+        let mut ret = Frame::new(full_frame(&frame.lines), delay);
+        ret.starts_cycle = self.frame_index == 0;
+        self.frame_index = (self.frame_index + 1) % self.frames.len();
+        Some(ret)
+    }
+
+    fn handle_event(&mut self, event: &Event) -> bool {
+        // the next frame is the one after the frame on the screen
+        let shown_index = self.frame_index.checked_sub(1)
+            .unwrap_or(self.frames.len() - 1);
+        let current_tick = self.start_ticks[shown_index];
+        match event {
+            Event::Key(Key::Left) => self.seek_to_tick(current_tick.saturating_sub(self.seek_ticks())),
+            Event::Key(Key::Right) => self.seek_to_tick(current_tick.saturating_add(self.seek_ticks())),
+            Event::Key(Key::Home) => self.frame_index = 0,
+            Event::Key(Key::End) => self.frame_index = self.frames.len() - 1,
+            _ => return false,
+        }
+        true
+    }
+}
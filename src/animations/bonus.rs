@@ -0,0 +1,76 @@
+use std::fmt::Write;
+use std::time::Duration;
+
+use crate::animations::{Animation, Color, Frame};
+use crate::telnet::SessionInfo;
+
+
+const BONUS_PICTURE: [&str; 6] = [
+    "#       #######  #      ",
+    "#       #     #  #      ",
+    "#       #     #  #      ",
+    "#       #     #  #      ",
+    "#       #     #  #      ",
+    "####### #######  #######",
+];
+
+const BONUS_MESSAGE: &str = "You found the secret! +30 lives";
+
+/// The colors the rainbow cycles through.
+const RAINBOW: [Color; 6] = [
+    Color::BrightRed,
+    Color::BrightYellow,
+    Color::BrightGreen,
+    Color::BrightCyan,
+    Color::BrightBlue,
+    Color::BrightMagenta,
+];
+
+const FRAME_DURATION: Duration = Duration::from_millis(100);
+
+
+/// A rainbow wave running through giant letters; the hidden bonus unlocked using the Konami code.
+#[derive(Clone, Debug, Default, Eq, Hash, Ord, PartialEq, PartialOrd)]
+pub(crate) struct Bonus {
+    frame_index: usize,
+}
+impl Bonus {
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+impl Animation for Bonus {
+    fn next_frame(&mut self, session: &SessionInfo) -> Option<Frame> {
+        let (cols, rows) = session.window_size.unwrap_or((80, 24));
+        let width = BONUS_PICTURE[0].len().max(BONUS_MESSAGE.len());
+        let left = (usize::from(cols).saturating_sub(width) / 2) + 1;
+        let top = (usize::from(rows).saturating_sub(BONUS_PICTURE.len() + 2) / 2) + 1;
+
+        let mut commands = String::new();
+        if self.frame_index == 0 {
+            // clear screen
+            commands.push_str("\x1B[2J");
+        }
+
+        // each column gets the next color of the rainbow, moving along with each frame
+        for (i, line) in BONUS_PICTURE.iter().enumerate() {
+            write!(commands, "\x1B[{};{}H", top + i, left + (width - line.len()) / 2).unwrap();
+            for (column, c) in line.chars().enumerate() {
+                let color = RAINBOW[(column + RAINBOW.len() - self.frame_index % RAINBOW.len()) % RAINBOW.len()];
+                write!(commands, "{}{}", color.foreground(), c).unwrap();
+            }
+        }
+        let message_color = RAINBOW[self.frame_index % RAINBOW.len()];
+        write!(
+            commands,
+            "\x1B[{};{}H{}{}\x1B[0m",
+            top + BONUS_PICTURE.len() + 1, left + (width - BONUS_MESSAGE.len()) / 2,
+            message_color.foreground(), BONUS_MESSAGE,
+        ).unwrap();
+
+        let mut frame = Frame::new(commands, FRAME_DURATION);
+        frame.starts_cycle = self.frame_index.is_multiple_of(RAINBOW.len());
+        self.frame_index += 1;
+        Some(frame)
+    }
+}
@@ -0,0 +1,275 @@
+//! Balls bouncing around the screen, pulled down by gravity and losing a bit of speed with each
+//! bounce.
+
+
+use std::collections::VecDeque;
+use std::time::Duration;
+
+use rand::{Rng, SeedableRng};
+use rand::rngs::SmallRng;
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+
+use crate::animations::{self, Animation, AnimationInfo, Color, Frame};
+use crate::animations::canvas::Canvas;
+use crate::telnet::SessionInfo;
+
+
+/// How many units of length a row is tall; a column is one unit wide, which makes the units
+/// roughly square.
+const ROW_HEIGHT: f64 = 2.0;
+
+/// How far apart the centers of two balls are when they touch, in units of length.
+const BALL_DIAMETER: f64 = 2.0;
+
+/// The characters of the trail, from the oldest position to the newest.
+const TRAIL_CHARS: [char; 2] = ['.', 'o'];
+
+
+pub(crate) const INFO: AnimationInfo = AnimationInfo {
+    name: "bouncing_ball",
+    description: "Balls bouncing around the screen.",
+    default_frame_ms: 50,
+    size: (80, 24),
+    create: |config, _context| Ok(Box::new(BouncingBall::new(config.parse_params()?))),
+    params_schema: animations::params_schema::<Params>,
+    default_params: animations::default_params::<Params>,
+    needs_params: false,
+};
+
+
+/// Parameters of the bouncing balls.
+#[derive(Clone, Debug, Deserialize, Eq, Hash, JsonSchema, Ord, PartialEq, PartialOrd, Serialize)]
+#[serde(default, deny_unknown_fields)]
+pub(crate) struct Params {
+    /// How long each frame is shown, in milliseconds.
+    pub frame_ms: u64,
+
+    /// How many balls there are; they bounce off each other too.
+    pub balls: u8,
+
+    /// How strongly the balls are pulled down, in rows per second squared.
+    pub gravity: u16,
+
+    /// How much of its speed a ball keeps when it bounces, in percent. Once the balls have come to
+    /// rest, they are thrown up again.
+    pub restitution_percent: u8,
+
+    /// How many of its previous positions each ball leaves behind.
+    pub trail: u8,
+
+    /// The colors of the balls, used in turn.
+    pub colors: Vec<Color>,
+
+    /// Seeds how the balls are thrown, making the animation reproducible; by default, it is
+    /// different each time.
+    pub seed: Option<u64>,
+}
+impl Default for Params {
+    fn default() -> Self {
+        Self {
+            frame_ms: INFO.default_frame_ms,
+            balls: 1,
+            gravity: 30,
+            restitution_percent: 85,
+            trail: 6,
+            colors: vec![
+                Color::BrightRed, Color::BrightYellow, Color::BrightGreen,
+                Color::BrightCyan, Color::BrightBlue, Color::BrightMagenta,
+            ],
+            seed: None,
+        }
+    }
+}
+
+
+#[derive(Clone, Debug)]
+struct Ball {
+    /// The position, in units of length from the top left corner.
+    position: (f64, f64),
+
+    /// The velocity, in units of length per second.
+    velocity: (f64, f64),
+
+    /// Whether the ball has stopped bouncing and lies on the floor.
+    resting: bool,
+
+    /// The cells the ball has been in, the newest last.
+    trail: VecDeque<(i64, i64)>,
+}
+impl Ball {
+    fn cell(&self) -> (i64, i64) {
+        (self.position.0.round() as i64, (self.position.1 / ROW_HEIGHT).round() as i64)
+    }
+}
+
+
+#[derive(Clone, Debug)]
+pub(crate) struct BouncingBall {
+    params: Params,
+    rng: SmallRng,
+    balls: Vec<Ball>,
+
+    /// What is on the client's screen.
+    shown: Option<Canvas>,
+}
+impl BouncingBall {
+    pub fn new(params: Params) -> Self {
+        let rng = match params.seed {
+            Some(seed) => SmallRng::seed_from_u64(seed),
+            None => SmallRng::from_os_rng(),
+        };
+        Self {
+            params,
+            rng,
+            balls: Vec::new(),
+            shown: None,
+        }
+    }
+
+    fn gravity(&self) -> f64 {
+        f64::from(self.params.gravity) * ROW_HEIGHT
+    }
+
+    /// Throws the balls up from where they are, or drops them in from random places if there are
+    /// none yet.
+    fn launch(&mut self, (width, height): (f64, f64)) {
+        if self.balls.is_empty() {
+            for _ in 0..self.params.balls.max(1) {
+                let position = (self.rng.random_range(0.0..=width), self.rng.random_range(0.0..=height / 2.0));
+                self.balls.push(Ball {
+                    position,
+                    velocity: (0.0, 0.0),
+                    resting: false,
+                    trail: VecDeque::new(),
+                });
+            }
+        }
+        let gravity = self.gravity();
+        for ball in &mut self.balls {
+            // high enough to reach somewhere in the upper half of the screen
+            let rise = self.rng.random_range(0.5..=0.9) * height;
+            let speed = self.rng.random_range(10.0..=30.0) * if self.rng.random_bool(0.5) { -1.0 } else { 1.0 };
+            ball.velocity = (speed, -(2.0 * gravity * rise).sqrt());
+            ball.resting = false;
+        }
+    }
+
+    /// Moves the balls on by the given number of seconds, bouncing them off the edges of the
+    /// screen and each other.
+    fn advance(&mut self, seconds: f64, (width, height): (f64, f64)) {
+        let gravity = self.gravity();
+        let restitution = f64::from(self.params.restitution_percent.min(100)) / 100.0;
+
+        // any slower than this after a bounce and the ball would not even rise by a row
+        let resting_speed = (2.0 * gravity * ROW_HEIGHT).sqrt();
+
+        for ball in &mut self.balls {
+            if ball.resting {
+                ball.position.1 = height;
+                continue;
+            }
+            ball.velocity.1 += gravity * seconds;
+            ball.position.0 += ball.velocity.0 * seconds;
+            ball.position.1 += ball.velocity.1 * seconds;
+
+            if ball.position.0 < 0.0 {
+                ball.position.0 = -ball.position.0;
+                ball.velocity.0 = ball.velocity.0.abs() * restitution;
+            } else if ball.position.0 > width {
+                ball.position.0 = (2.0 * width - ball.position.0).max(0.0);
+                ball.velocity.0 = -ball.velocity.0.abs() * restitution;
+            }
+            if ball.position.1 < 0.0 {
+                ball.position.1 = -ball.position.1;
+                ball.velocity.1 = ball.velocity.1.abs() * restitution;
+            } else if ball.position.1 > height {
+                ball.position.1 = (2.0 * height - ball.position.1).max(0.0);
+                ball.velocity.1 = -ball.velocity.1.abs() * restitution;
+
+                // the floor slows down the rolling as well
+                ball.velocity.0 *= restitution;
+                if -ball.velocity.1 < resting_speed {
+                    ball.position.1 = height;
+                    ball.velocity = (0.0, 0.0);
+                    ball.resting = true;
+                }
+            }
+        }
+
+        // elastic collisions between balls of equal mass exchange the parts of their velocities
+        // along the line between their centers
+        for first in 0..self.balls.len() {
+            for second in first + 1..self.balls.len() {
+                let (left, right) = self.balls.split_at_mut(second);
+                let (a, b) = (&mut left[first], &mut right[0]);
+                let offset = (b.position.0 - a.position.0, b.position.1 - a.position.1);
+                let distance = offset.0.hypot(offset.1);
+                if distance >= BALL_DIAMETER || distance == 0.0 {
+                    continue;
+                }
+                let normal = (offset.0 / distance, offset.1 / distance);
+                let approach = (a.velocity.0 - b.velocity.0) * normal.0 + (a.velocity.1 - b.velocity.1) * normal.1;
+                if approach > 0.0 {
+                    a.velocity.0 -= approach * normal.0;
+                    a.velocity.1 -= approach * normal.1;
+                    b.velocity.0 += approach * normal.0;
+                    b.velocity.1 += approach * normal.1;
+                    a.resting = false;
+                    b.resting = false;
+                }
+
+                // move them apart so they do not stick together
+                let push = (BALL_DIAMETER - distance) / 2.0;
+                a.position.0 = (a.position.0 - push * normal.0).clamp(0.0, width);
+                a.position.1 = (a.position.1 - push * normal.1).clamp(0.0, height);
+                b.position.0 = (b.position.0 + push * normal.0).clamp(0.0, width);
+                b.position.1 = (b.position.1 + push * normal.1).clamp(0.0, height);
+            }
+        }
+    }
+}
+impl Animation for BouncingBall {
+    fn next_frame(&mut self, session: &SessionInfo) -> Option<Frame> {
+        let size = session.window_size.unwrap_or(INFO.size);
+        let bounds = (
+            f64::from(size.0.saturating_sub(1)),
+            f64::from(size.1.saturating_sub(1)) * ROW_HEIGHT,
+        );
+
+        // throw the balls again once all of them lie still
+        let starts_cycle = self.balls.is_empty() || self.balls.iter().all(|ball| ball.resting);
+        if starts_cycle {
+            self.launch(bounds);
+        } else {
+            self.advance(self.params.frame_ms as f64 / 1000.0, bounds);
+        }
+
+        let colors = &self.params.colors;
+        let color = |index: usize| (!colors.is_empty()).then(|| colors[index % colors.len()]);
+        let mut canvas = Canvas::new(size);
+        for (index, ball) in self.balls.iter().enumerate() {
+            for (age, (col, row)) in ball.trail.iter().enumerate() {
+                let c = TRAIL_CHARS[age * TRAIL_CHARS.len() / ball.trail.len()];
+                canvas.set(*col, *row, (c, color(index)));
+            }
+        }
+        let trail_length = usize::from(self.params.trail);
+        for (index, ball) in self.balls.iter_mut().enumerate() {
+            let cell = ball.cell();
+            canvas.set(cell.0, cell.1, ('O', color(index)));
+            if ball.trail.back() != Some(&cell) {
+                ball.trail.push_back(cell);
+            }
+            while ball.trail.len() > trail_length {
+                ball.trail.pop_front();
+            }
+        }
+        let commands = canvas.draw_over(self.shown.as_ref());
+        self.shown = Some(canvas);
+
+        let mut frame = Frame::new(commands, Duration::from_millis(self.params.frame_ms));
+        frame.starts_cycle = starts_cycle;
+        Some(frame)
+    }
+}
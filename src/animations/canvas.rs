@@ -0,0 +1,109 @@
+//! A grid of colored characters for animations that compose their picture cell by cell, redrawn
+//! on the client's terminal by sending only what has changed.
+
+
+use std::fmt::Write;
+
+use unicode_width::UnicodeWidthChar;
+
+use crate::animations::Color;
+
+
+/// Changed cells this close together are redrawn along with the unchanged ones between them, which
+/// takes fewer bytes than moving the cursor.
+const MAX_GAP: usize = 4;
+
+
+/// A cell of the canvas: a character and its color (`None` for the terminal's default).
+pub(crate) type Cell = (char, Option<Color>);
+
+
+#[derive(Clone, Debug, Eq, Hash, PartialEq)]
+pub(crate) struct Canvas {
+    cells: Vec<Vec<Cell>>,
+}
+impl Canvas {
+    /// Creates an empty canvas with the given number of columns and rows.
+    pub fn new((cols, rows): (u16, u16)) -> Self {
+        Self {
+            cells: vec![vec![(' ', None); usize::from(cols)]; usize::from(rows)],
+        }
+    }
+
+    /// The size of the canvas as (columns, rows).
+    pub fn size(&self) -> (u16, u16) {
+        let cols = self.cells.first().map_or(0, Vec::len);
+        (cols as u16, self.cells.len() as u16)
+    }
+
+    /// Empties the canvas.
+    pub fn clear(&mut self) {
+        for row in &mut self.cells {
+            row.fill((' ', None));
+        }
+    }
+
+    /// Sets the cell at the given position, if it is on the canvas.
+    pub fn set(&mut self, col: i64, row: i64, cell: Cell) {
+        let target = usize::try_from(row).ok()
+            .and_then(|r| self.cells.get_mut(r))
+            .and_then(|cells| usize::try_from(col).ok().and_then(|c| cells.get_mut(c)));
+        if let Some(target) = target {
+            *target = cell;
+        }
+    }
+
+    /// Writes text starting at the given position, cutting off whatever doesn't fit. Control and
+    /// wide characters are skipped, as they would throw off the positioning.
+    pub fn put(&mut self, col: i64, row: i64, text: &str, color: Option<Color>) {
+        let narrow_chars = text.chars()
+            .filter(|c| c.width() == Some(1));
+        for (offset, c) in narrow_chars.enumerate() {
+            self.set(col + offset as i64, row, (c, color));
+        }
+    }
+
+    /// Returns the commands turning the terminal from showing `shown` (or, if `None`, something
+    /// unknown) into showing this canvas.
+    pub fn draw_over(&self, shown: Option<&Canvas>) -> String {
+        let mut commands = String::new();
+        let blank;
+        let shown = match shown {
+            Some(shown) if shown.size() == self.size() => shown,
+            _ => {
+                commands.push_str("\x1B[0m\x1B[2J");
+                blank = Canvas::new(self.size());
+                &blank
+            },
+        };
+
+        let mut color = None;
+        for (row, (old, new)) in shown.cells.iter().zip(self.cells.iter()).enumerate() {
+            let mut col = 0;
+            while col < new.len() {
+                if old[col] == new[col] {
+                    col += 1;
+                    continue;
+                }
+                let start = col;
+                let mut end = col + 1;
+                while let Some(next_change) = (end..new.len().min(end + MAX_GAP)).find(|c| old[*c] != new[*c]) {
+                    end = next_change + 1;
+                }
+                write!(commands, "\x1B[{};{}H", row + 1, start + 1).unwrap();
+                for (c, cell_color) in &new[start..end] {
+                    if color != *cell_color {
+                        commands.push_str(cell_color.map_or("\x1B[39m", |c| c.foreground()));
+                        color = *cell_color;
+                    }
+                    commands.push(*c);
+                }
+                col = end;
+            }
+        }
+        if color.is_some() {
+            commands.push_str("\x1B[39m");
+        }
+        commands
+    }
+}
@@ -0,0 +1,291 @@
+use std::collections::{HashMap, VecDeque};
+use std::fmt::Write;
+use std::net::{IpAddr, SocketAddr};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use log::info;
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use unicode_width::UnicodeWidthChar;
+
+use crate::animations::{self, Animation, AnimationInfo, Frame};
+use crate::keys::{BS, DEL};
+use crate::telnet::{self, Event, SessionInfo};
+
+
+/// How many lines a wall remembers.
+const WALL_LINES: usize = 200;
+
+/// The period over which posts are counted for rate limiting.
+const RATE_PERIOD: Duration = Duration::from_secs(60);
+
+
+pub(crate) const INFO: AnimationInfo = AnimationInfo {
+    name: "chatwall",
+    description: "A wall everyone connected to the same port can write on.",
+    default_frame_ms: 200,
+    size: (40, 5),
+    create: |config, context| {
+        let wall = context.chat_wall.clone().unwrap_or_default();
+        Ok(Box::new(ChatWall::new(config.parse_params()?, wall, context.client_addr)))
+    },
+    params_schema: animations::params_schema::<Params>,
+    default_params: animations::default_params::<Params>,
+    needs_params: false,
+};
+
+
+/// Parameters of the chat wall.
+#[derive(Clone, Debug, Deserialize, Eq, Hash, JsonSchema, Ord, PartialEq, PartialOrd, Serialize)]
+#[serde(default, deny_unknown_fields)]
+pub(crate) struct Params {
+    /// How often to check the wall for new lines, in milliseconds.
+    pub frame_ms: u64,
+
+    /// The maximum length of a line typed by a client, in bytes.
+    pub max_line_length: usize,
+
+    /// How many lines a client (identified by its IP address) may write per minute.
+    pub max_lines_per_minute: usize,
+}
+impl Default for Params {
+    fn default() -> Self {
+        Self {
+            frame_ms: INFO.default_frame_ms,
+            max_line_length: 200,
+            max_lines_per_minute: 10,
+        }
+    }
+}
+
+
+#[derive(Debug, Default)]
+struct WallInner {
+    lines: VecDeque<String>,
+
+    /// Increased whenever a line is added.
+    version: u64,
+
+    /// When each client has recently written a line, for rate limiting.
+    recent_posts: HashMap<IpAddr, VecDeque<Instant>>,
+}
+
+
+/// A wall of lines written by clients.
+#[derive(Clone, Debug, Default)]
+pub(crate) struct Wall {
+    inner: Arc<Mutex<WallInner>>,
+}
+impl Wall {
+    /// Adds a line, tagged with its source, unless the source has written too many lines recently.
+    ///
+    /// Returns whether the line has been added.
+    pub fn post(&self, source: Option<IpAddr>, text: &str, max_lines_per_minute: usize) -> bool {
+        let mut inner = self.inner.lock().unwrap();
+        let now = Instant::now();
+        inner.recent_posts.retain(|_source, times| {
+            while times.front().is_some_and(|&t| now.duration_since(t) >= RATE_PERIOD) {
+                times.pop_front();
+            }
+            !times.is_empty()
+        });
+
+        let tag = match source {
+            Some(ip) => {
+                let times = inner.recent_posts.entry(ip).or_default();
+                if times.len() >= max_lines_per_minute {
+                    return false;
+                }
+                times.push_back(now);
+                ip.to_string()
+            },
+            None => "local".to_owned(),
+        };
+
+        inner.lines.push_back(format!("[{}] {}", tag, text));
+        while inner.lines.len() > WALL_LINES {
+            inner.lines.pop_front();
+        }
+        inner.version += 1;
+        true
+    }
+
+    /// Returns the version of the wall, which changes whenever a line is added.
+    pub fn version(&self) -> u64 {
+        self.inner.lock().unwrap().version
+    }
+
+    /// Returns (up to) the given number of most recent lines, oldest first.
+    pub fn last_lines(&self, count: usize) -> Vec<String> {
+        let inner = self.inner.lock().unwrap();
+        let skip = inner.lines.len().saturating_sub(count);
+        inner.lines.iter()
+            .skip(skip)
+            .cloned()
+            .collect()
+    }
+}
+
+
+/// The walls of all sockets.
+#[derive(Clone, Debug, Default)]
+pub(crate) struct ChatWalls {
+    walls: Arc<Mutex<HashMap<SocketAddr, Wall>>>,
+}
+impl ChatWalls {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Returns the wall of the socket listening on the given address.
+    pub fn for_socket(&self, listen_addr: SocketAddr) -> Wall {
+        self.walls.lock().unwrap()
+            .entry(listen_addr)
+            .or_default()
+            .clone()
+    }
+}
+
+
+/// Returns the longest beginning of the text that fits into the given number of columns.
+pub(crate) fn truncate_to_width(text: &str, width: usize) -> &str {
+    let mut used = 0;
+    for (i, c) in text.char_indices() {
+        used += c.width().unwrap_or(0);
+        if used > width {
+            return &text[..i];
+        }
+    }
+    text
+}
+
+
+/// Returns the longest end of the text that fits into the given number of columns.
+pub(crate) fn tail_to_width(text: &str, width: usize) -> &str {
+    let mut used = 0;
+    for (i, c) in text.char_indices().rev() {
+        used += c.width().unwrap_or(0);
+        if used > width {
+            return &text[i + c.len_utf8()..];
+        }
+    }
+    text
+}
+
+
+/// The state of the wall, the input and the terminal at a redraw.
+#[derive(Clone, Debug, Eq, PartialEq)]
+struct Drawn {
+    version: u64,
+    input: Vec<u8>,
+    notice: Option<&'static str>,
+    size: (u16, u16),
+}
+
+
+/// Shows the wall of the socket and lets the client write on it.
+#[derive(Debug)]
+pub(crate) struct ChatWall {
+    params: Params,
+    wall: Wall,
+    client_addr: Option<SocketAddr>,
+
+    /// The line the client is typing.
+    input: Vec<u8>,
+
+    /// Shown after the input line, e.g. when the client is writing too fast.
+    notice: Option<&'static str>,
+
+    /// What was shown at the last redraw.
+    drawn: Option<Drawn>,
+}
+impl ChatWall {
+    pub fn new(params: Params, wall: Wall, client_addr: Option<SocketAddr>) -> Self {
+        Self {
+            params,
+            wall,
+            client_addr,
+            input: Vec::new(),
+            notice: None,
+            drawn: None,
+        }
+    }
+
+    fn post_input(&mut self) {
+        let text = telnet::decode_string(&self.input);
+        self.input.clear();
+        let text = text.trim();
+        if text.is_empty() {
+            return;
+        }
+        let source = self.client_addr.map(|a| a.ip());
+        if self.wall.post(source, text, self.params.max_lines_per_minute) {
+            match self.client_addr {
+                Some(addr) => info!("{} wrote on the chat wall: {:?}", addr, text),
+                None => info!("wrote on the chat wall: {:?}", text),
+            }
+            self.notice = None;
+        } else {
+            self.notice = Some("(slow down!)");
+        }
+    }
+}
+impl Animation for ChatWall {
+    fn next_frame(&mut self, session: &SessionInfo) -> Option<Frame> {
+        let delay = Duration::from_millis(self.params.frame_ms);
+        let size = session.window_size.unwrap_or((80, 24));
+        let state = Drawn {
+            version: self.wall.version(),
+            input: self.input.clone(),
+            notice: self.notice,
+            size,
+        };
+        let first = self.drawn.is_none();
+        if self.drawn.as_ref() == Some(&state) {
+            // nothing new
+            return Some(Frame::new("", delay));
+        }
+        self.drawn = Some(state);
+
+        let (cols, rows) = (usize::from(size.0), usize::from(size.1));
+        let mut commands = String::new();
+
+        // clear screen, go to top left
+        commands.push_str("\x1B[2J\x1B[H");
+
+        // the most recent lines above a separator and the input line
+        let line_count = rows.saturating_sub(2);
+        for (i, line) in self.wall.last_lines(line_count).iter().enumerate() {
+            write!(commands, "\x1B[{};1H{}", i + 1, truncate_to_width(line, cols)).unwrap();
+        }
+        write!(commands, "\x1B[{};1H{}", rows.saturating_sub(1), "-".repeat(cols)).unwrap();
+
+        let notice = self.notice.map(|n| format!(" {}", n)).unwrap_or_default();
+        let input = telnet::decode_string(&self.input);
+        let input_width = cols.saturating_sub(2 + notice.len() + 1);
+        write!(commands, "\x1B[{};1H> {}{}", rows, tail_to_width(&input, input_width), notice).unwrap();
+
+        let mut frame = Frame::new(commands, delay);
+        frame.starts_cycle = first;
+        Some(frame)
+    }
+
+    fn handle_event(&mut self, event: &Event) -> bool {
+        match event {
+            Event::Data(BS|DEL) => {
+                let mut input = String::from_utf8_lossy(&self.input).into_owned();
+                input.pop();
+                self.input = input.into_bytes();
+            },
+            Event::Data(b) if *b >= 0x20 => {
+                if self.input.len() < self.params.max_line_length {
+                    self.input.push(*b);
+                }
+            },
+            Event::Newline => self.post_input(),
+            _ => return false,
+        }
+        true
+    }
+}
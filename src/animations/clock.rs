@@ -0,0 +1,219 @@
+//! A clock showing the current time in big digits.
+
+
+use std::time::Duration;
+
+use jiff::Zoned;
+use jiff::fmt::strtime;
+use jiff::tz::TimeZone;
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use serde::de::Error as _;
+
+use crate::animations::{self, Animation, AnimationInfo, Color, CreateError, Frame};
+use crate::animations::canvas::Canvas;
+use crate::telnet::SessionInfo;
+
+
+/// The digits and the colon, as rows of pixels (`#`).
+const GLYPHS: [(char, [&str; 5]); 11] = [
+    ('0', ["###", "# #", "# #", "# #", "###"]),
+    ('1', [" # ", "## ", " # ", " # ", "###"]),
+    ('2', ["###", "  #", "###", "#  ", "###"]),
+    ('3', ["###", "  #", "###", "  #", "###"]),
+    ('4', ["# #", "# #", "###", "  #", "  #"]),
+    ('5', ["###", "#  ", "###", "  #", "###"]),
+    ('6', ["###", "#  ", "###", "# #", "###"]),
+    ('7', ["###", "  #", "  #", "  #", "  #"]),
+    ('8', ["###", "# #", "###", "# #", "###"]),
+    ('9', ["###", "# #", "###", "  #", "###"]),
+    (':', [" ", "#", " ", "#", " "]),
+];
+
+/// How many character cells wide each pixel of a digit is, to make up for cells being taller than
+/// they are wide.
+const PIXEL_WIDTH: usize = 2;
+
+/// How many character cells separate the digits.
+const GLYPH_GAP: usize = 2;
+
+
+pub(crate) const INFO: AnimationInfo = AnimationInfo {
+    name: "clock",
+    description: "The current time in big digits.",
+    default_frame_ms: 1000,
+    size: (80, 24),
+    create: |config, _context| {
+        let params: Params = config.parse_params()?;
+        let invalid = |message: String| CreateError::InvalidParameters {
+            name: config.name.clone(),
+            error: toml::de::Error::custom(message),
+        };
+        let time_zone = match &params.time_zone {
+            Some(name) => TimeZone::get(name)
+                .map_err(|e| invalid(format!("unknown time zone {:?}: {}", name, e)))?,
+            None => TimeZone::system(),
+        };
+        if let Err(e) = strtime::format(&params.date_format, &Zoned::now()) {
+            return Err(invalid(format!("invalid date format {:?}: {}", params.date_format, e)));
+        }
+        Ok(Box::new(Clock::new(params, time_zone)))
+    },
+    params_schema: animations::params_schema::<Params>,
+    default_params: animations::default_params::<Params>,
+    needs_params: false,
+};
+
+
+/// Parameters of the clock.
+#[derive(Clone, Debug, Deserialize, Eq, Hash, JsonSchema, Ord, PartialEq, PartialOrd, Serialize)]
+#[serde(default, deny_unknown_fields)]
+pub(crate) struct Params {
+    /// The time zone whose time is shown (e.g. `Europe/Vienna`); by default, that of the server.
+    pub time_zone: Option<String>,
+
+    /// Whether hours go up to 24 or to 12 (followed by AM or PM).
+    pub hours: HourFormat,
+
+    /// Whether to show the seconds.
+    pub seconds: bool,
+
+    /// Whether to show the date below the time.
+    pub date: bool,
+
+    /// How the date is written, in strftime notation.
+    pub date_format: String,
+
+    /// The character the digits are drawn with; by default a full block, or `#` if the client's
+    /// terminal type suggests that it cannot show one.
+    pub digit_char: Option<char>,
+
+    /// The color of the clock.
+    pub color: Option<Color>,
+}
+impl Default for Params {
+    fn default() -> Self {
+        Self {
+            time_zone: None,
+            hours: HourFormat::default(),
+            seconds: true,
+            date: true,
+            date_format: "%A, %-d %B %Y".to_owned(),
+            digit_char: None,
+            color: None,
+        }
+    }
+}
+
+
+/// How far the hours of the clock go.
+#[derive(Clone, Copy, Debug, Default, Deserialize, Eq, Hash, JsonSchema, Ord, PartialEq, PartialOrd, Serialize)]
+pub(crate) enum HourFormat {
+    /// From 0 to 23.
+    #[default]
+    #[serde(rename = "24h")]
+    TwentyFour,
+
+    /// From 1 to 12, followed by AM or PM.
+    #[serde(rename = "12h")]
+    Twelve,
+}
+
+
+#[derive(Debug)]
+pub(crate) struct Clock {
+    params: Params,
+    time_zone: TimeZone,
+
+    /// The minute shown last, to tell when a cycle begins.
+    last_minute: Option<i8>,
+
+    /// What is on the client's screen.
+    shown: Option<Canvas>,
+}
+impl Clock {
+    fn new(params: Params, time_zone: TimeZone) -> Self {
+        Self {
+            params,
+            time_zone,
+            last_minute: None,
+            shown: None,
+        }
+    }
+
+    /// The digits and colons of the time, and AM or PM if the hours go up to 12.
+    fn time_text(&self, now: &Zoned) -> (String, Option<&'static str>) {
+        let (hour, suffix) = match self.params.hours {
+            HourFormat::TwentyFour => (now.hour(), None),
+            HourFormat::Twelve => {
+                let suffix = if now.hour() < 12 { "AM" } else { "PM" };
+                let hour = match now.hour() % 12 {
+                    0 => 12,
+                    other => other,
+                };
+                (hour, Some(suffix))
+            },
+        };
+        let mut text = format!("{:02}:{:02}", hour, now.minute());
+        if self.params.seconds {
+            text.push_str(&format!(":{:02}", now.second()));
+        }
+        (text, suffix)
+    }
+}
+impl Animation for Clock {
+    fn next_frame(&mut self, session: &SessionInfo) -> Option<Frame> {
+        let size = session.window_size.unwrap_or(INFO.size);
+        let now = Zoned::now().with_time_zone(self.time_zone.clone());
+        let (time, suffix) = self.time_text(&now);
+        let date = self.params.date
+            .then(|| strtime::format(&self.params.date_format, &now).unwrap_or_default());
+
+        let digit_char = self.params.digit_char
+            .unwrap_or(if session.is_basic_terminal() { '#' } else { '\u{2588}' });
+        let glyphs: Vec<&[&str; 5]> = time.chars()
+            .filter_map(|c| GLYPHS.iter().find(|(glyph_char, _rows)| *glyph_char == c))
+            .map(|(_glyph_char, rows)| rows)
+            .collect();
+        let glyph_width = |rows: &[&str; 5]| rows[0].len() * PIXEL_WIDTH;
+        let digits_width = glyphs.iter().map(|rows| glyph_width(rows) + GLYPH_GAP).sum::<usize>() - GLYPH_GAP;
+        let time_width = digits_width + suffix.map_or(0, |s| 1 + s.len());
+        let height = 5 + if date.is_some() { 2 } else { 0 };
+        let left = (i64::from(size.0) - time_width as i64).max(0) / 2;
+        let top = (i64::from(size.1) - height).max(0) / 2;
+
+        let mut canvas = Canvas::new(size);
+        let color = self.params.color;
+        let mut glyph_left = left;
+        for glyph in glyphs {
+            for (row, pixels) in glyph.iter().enumerate() {
+                for (pixel, _) in pixels.char_indices().filter(|(_i, p)| *p == '#') {
+                    for offset in 0..PIXEL_WIDTH {
+                        let col = glyph_left + (pixel * PIXEL_WIDTH + offset) as i64;
+                        canvas.set(col, top + row as i64, (digit_char, color));
+                    }
+                }
+            }
+            glyph_left += (glyph_width(glyph) + GLYPH_GAP) as i64;
+        }
+        if let Some(suffix) = suffix {
+            canvas.put(left + time_width as i64 - suffix.len() as i64, top + 4, suffix, color);
+        }
+        if let Some(date) = &date {
+            let date_left = (i64::from(size.0) - date.chars().count() as i64).max(0) / 2;
+            canvas.put(date_left, top + 6, date, color);
+        }
+        let commands = canvas.draw_over(self.shown.as_ref());
+        self.shown = Some(canvas);
+
+        // a cycle per minute
+        let starts_cycle = self.last_minute != Some(now.minute());
+        self.last_minute = Some(now.minute());
+
+        // wake up just after the next second begins
+        let until_next_second = 1_000_000_000 - u64::try_from(now.subsec_nanosecond()).unwrap_or(0);
+        let mut frame = Frame::new(commands, Duration::from_nanos(until_next_second));
+        frame.starts_cycle = starts_cycle;
+        Some(frame)
+    }
+}
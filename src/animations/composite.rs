@@ -0,0 +1,342 @@
+//! Animations composed of layers: other animations or fixed text, stacked on top of each other and
+//! moving about the screen, with spaces letting the layers beneath show through.
+
+
+use std::time::Duration;
+
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use serde::de::Error as _;
+
+use crate::animations::{self, Animation, AnimationConfig, AnimationInfo, Color, CreateError, Frame};
+use crate::animations::canvas::Canvas;
+use crate::animations::deck::clean_line;
+use crate::screen::Screen;
+use crate::telnet::SessionInfo;
+
+
+pub(crate) const INFO: AnimationInfo = AnimationInfo {
+    name: "composite",
+    description: "Layers of other animations and text, moving over each other.",
+    default_frame_ms: 50,
+    size: (80, 24),
+    create: |config, context| {
+        let params: Params = config.parse_params()?;
+        let mut layers = Vec::with_capacity(params.layers.len());
+        for layer_params in &params.layers {
+            let source = match &layer_params.animation {
+                Some(layer_config) if layer_config.name == INFO.name => {
+                    let error = toml::de::Error::custom("layers cannot be composites themselves");
+                    return Err(CreateError::InvalidParameters { name: config.name.clone(), error });
+                },
+                Some(layer_config) => {
+                    let animation = animations::create_in(layer_config, context)?;
+                    let size = layer_params.size
+                        .or_else(|| animations::info(&layer_config.name).map(|info| info.size))
+                        .unwrap_or(INFO.size);
+                    // a spare row, since many animations end their last line with a line break,
+                    // which would scroll everything up
+                    let screen = Screen::new(size.0, size.1.saturating_add(1));
+                    Source::Animation { animation, size, screen, next_frame_ms: 0, over: false }
+                },
+                None => {
+                    let lines = layer_params.text.lines()
+                        .map(|line| clean_line(line).chars().collect())
+                        .collect();
+                    Source::Text(lines)
+                },
+            };
+            layers.push(Layer::new(layer_params.clone(), source));
+        }
+        Ok(Box::new(Composite::new(params, layers)))
+    },
+    params_schema: animations::params_schema::<Params>,
+    default_params: animations::default_params::<Params>,
+    needs_params: false,
+};
+
+
+/// Parameters of the composite animation.
+#[derive(Clone, Debug, Deserialize, JsonSchema, PartialEq, Serialize)]
+#[serde(default, deny_unknown_fields)]
+pub(crate) struct Params {
+    /// How often the layers are moved and the screen is updated, in milliseconds.
+    pub frame_ms: u64,
+
+    /// The layers, drawn in the order of their `z` (and, if that is the same, in the order given).
+    /// A cycle of the composite animation begins whenever the first layer given begins a cycle.
+    pub layers: Vec<LayerParams>,
+}
+impl Default for Params {
+    fn default() -> Self {
+        // the roflcopter flying over the lollercoaster
+        Self {
+            frame_ms: INFO.default_frame_ms,
+            layers: vec![
+                LayerParams {
+                    animation: Some(AnimationConfig::named("lollercoaster")),
+                    ..LayerParams::default()
+                },
+                LayerParams {
+                    animation: Some(AnimationConfig::named("roflcopter")),
+                    x: -23,
+                    y: 1,
+                    dx: 1,
+                    z: 1,
+                    ..LayerParams::default()
+                },
+            ],
+        }
+    }
+}
+
+
+/// Parameters of a layer of the composite animation.
+#[derive(Clone, Debug, Deserialize, JsonSchema, PartialEq, Serialize)]
+#[serde(default, deny_unknown_fields)]
+pub(crate) struct LayerParams {
+    /// The animation shown in the layer, as its name or a table containing the name and any
+    /// parameters. Only the characters it draws are taken over, not their colors.
+    pub animation: Option<AnimationConfig>,
+
+    /// Fixed text shown in the layer if it has no animation.
+    pub text: String,
+
+    /// The size of the layer's animation as (columns, rows); by default, the size the animation is
+    /// designed for.
+    pub size: Option<(u16, u16)>,
+
+    /// The column of the layer's left edge, counting from 0; may be negative.
+    pub x: i64,
+
+    /// The row of the layer's top edge, counting from 0; may be negative.
+    pub y: i64,
+
+    /// How many columns the layer moves to the right (or, if negative, to the left) at a time.
+    pub dx: i64,
+
+    /// How many rows the layer moves down (or, if negative, up) at a time.
+    pub dy: i64,
+
+    /// How often the layer moves, in milliseconds.
+    pub move_ms: u64,
+
+    /// Once the layer has moved off the screen, bring it back in on the opposite side.
+    pub wrap: bool,
+
+    /// Layers with a higher `z` are drawn over those with a lower one.
+    pub z: i32,
+
+    /// The color of the layer.
+    pub color: Option<Color>,
+}
+impl Default for LayerParams {
+    fn default() -> Self {
+        Self {
+            animation: None,
+            text: String::new(),
+            size: None,
+            x: 0,
+            y: 0,
+            dx: 0,
+            dy: 0,
+            move_ms: 100,
+            wrap: true,
+            z: 0,
+            color: None,
+        }
+    }
+}
+
+
+/// What a layer shows.
+enum Source {
+    /// An animation, rendered onto a screen of its own.
+    Animation {
+        animation: Box<dyn Animation>,
+
+        /// The size (columns and rows) the animation is told the terminal has.
+        size: (u16, u16),
+
+        screen: Screen,
+
+        /// When the animation's next frame is due, in milliseconds since the start.
+        next_frame_ms: u64,
+
+        /// Whether the animation has ended, leaving its final frame in place.
+        over: bool,
+    },
+
+    /// Fixed lines of text.
+    Text(Vec<Vec<char>>),
+}
+
+
+struct Layer {
+    params: LayerParams,
+    source: Source,
+
+    /// The position of the top left corner as (column, row).
+    position: (i64, i64),
+
+    /// When the layer moves next, in milliseconds since the start.
+    next_move_ms: u64,
+}
+impl Layer {
+    fn new(params: LayerParams, source: Source) -> Self {
+        let position = (params.x, params.y);
+        let next_move_ms = params.move_ms;
+        Self {
+            params,
+            source,
+            position,
+            next_move_ms,
+        }
+    }
+
+    fn rows(&self) -> &[Vec<char>] {
+        match &self.source {
+            Source::Animation { screen, .. } => screen.rows(),
+            Source::Text(lines) => lines,
+        }
+    }
+
+    /// The size of what the layer shows as (columns, rows), not counting blank space at the right
+    /// and at the bottom.
+    fn size(&self) -> (i64, i64) {
+        let rows = self.rows();
+        let cols = rows.iter()
+            .map(|row| row.iter().rposition(|c| *c != ' ').map_or(0, |p| p + 1))
+            .max()
+            .unwrap_or(0);
+        let height = rows.iter()
+            .rposition(|row| row.iter().any(|c| *c != ' '))
+            .map_or(0, |p| p + 1);
+        (cols as i64, height as i64)
+    }
+
+    /// Advances the layer's animation and position to the given time; returns whether its
+    /// animation has begun a cycle.
+    fn advance(&mut self, now_ms: u64, session: &SessionInfo) -> bool {
+        let mut starts_cycle = false;
+        if let Source::Animation { animation, size, screen, next_frame_ms, over } = &mut self.source {
+            if !*over && *next_frame_ms <= now_ms {
+                let layer_session = SessionInfo {
+                    window_size: Some(*size),
+                    ..session.clone()
+                };
+                match animation.next_frame(&layer_session) {
+                    Some(frame) => {
+                        screen.feed(&frame.commands);
+                        starts_cycle = frame.starts_cycle;
+
+                        // at most one frame per update, even if the animation wants more
+                        let delay_ms = u64::try_from(frame.delay.as_millis()).unwrap_or(u64::MAX);
+                        *next_frame_ms = next_frame_ms.saturating_add(delay_ms).max(now_ms + 1);
+                    },
+                    None => *over = true,
+                }
+            }
+        }
+
+        if self.params.move_ms > 0 {
+            while self.next_move_ms <= now_ms {
+                self.position.0 += self.params.dx;
+                self.position.1 += self.params.dy;
+                self.next_move_ms += self.params.move_ms;
+            }
+        }
+        starts_cycle
+    }
+
+    /// Brings the layer back onto a screen of the given size if it has moved off it.
+    fn wrap(&mut self, (cols, rows): (i64, i64)) {
+        if !self.params.wrap {
+            return;
+        }
+        let (width, height) = self.size();
+        let (x, y) = &mut self.position;
+        if self.params.dx > 0 && *x >= cols {
+            *x = -width;
+        } else if self.params.dx < 0 && *x + width <= 0 {
+            *x = cols;
+        }
+        if self.params.dy > 0 && *y >= rows {
+            *y = -height;
+        } else if self.params.dy < 0 && *y + height <= 0 {
+            *y = rows;
+        }
+    }
+}
+
+
+pub(crate) struct Composite {
+    params: Params,
+
+    /// The layers in the order they are drawn.
+    layers: Vec<Layer>,
+
+    /// The index (within `layers`) of the layer whose cycles are those of the whole animation.
+    leading_layer: Option<usize>,
+
+    /// How much time has passed since the start, in milliseconds.
+    now_ms: u64,
+
+    /// What is on the client's screen.
+    shown: Option<Canvas>,
+}
+impl Composite {
+    fn new(params: Params, layers: Vec<Layer>) -> Self {
+        // keep the order of the layers with the same z
+        let mut indexed: Vec<(usize, Layer)> = layers.into_iter().enumerate().collect();
+        indexed.sort_by_key(|(_index, layer)| layer.params.z);
+        let leading_layer = indexed.iter().position(|(index, _layer)| *index == 0);
+        let layers = indexed.into_iter().map(|(_index, layer)| layer).collect();
+        Self {
+            params,
+            layers,
+            leading_layer,
+            now_ms: 0,
+            shown: None,
+        }
+    }
+
+    /// Draws the layers onto a canvas of the given size.
+    fn compose(&self, size: (u16, u16)) -> Canvas {
+        let mut ret = Canvas::new(size);
+        for layer in &self.layers {
+            let (x, y) = layer.position;
+            for (row_offset, layer_row) in layer.rows().iter().enumerate() {
+                for (col_offset, c) in layer_row.iter().enumerate() {
+                    if *c == ' ' {
+                        // transparent
+                        continue;
+                    }
+                    ret.set(x + col_offset as i64, y + row_offset as i64, (*c, layer.params.color));
+                }
+            }
+        }
+        ret
+    }
+}
+impl Animation for Composite {
+    fn next_frame(&mut self, session: &SessionInfo) -> Option<Frame> {
+        let size = session.window_size.unwrap_or(INFO.size);
+        let mut starts_cycle = self.now_ms == 0;
+        for (index, layer) in self.layers.iter_mut().enumerate() {
+            let layer_starts_cycle = layer.advance(self.now_ms, session);
+            if Some(index) == self.leading_layer && layer_starts_cycle {
+                starts_cycle = true;
+            }
+            layer.wrap((i64::from(size.0), i64::from(size.1)));
+        }
+        let composed = self.compose(size);
+        let commands = composed.draw_over(self.shown.as_ref());
+        self.shown = Some(composed);
+
+        self.now_ms += self.params.frame_ms.max(1);
+        let mut frame = Frame::new(commands, Duration::from_millis(self.params.frame_ms));
+        frame.starts_cycle = starts_cycle;
+        Some(frame)
+    }
+}
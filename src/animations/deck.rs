@@ -0,0 +1,225 @@
+//! Full-screen text frames loaded from files, for animations that need no code of their own.
+
+
+use std::fmt::Write;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use unicode_width::UnicodeWidthStr;
+
+use crate::animations::{self, Animation, AnimationInfo, Color, CreateError, Frame};
+use crate::telnet::SessionInfo;
+
+
+/// How many columns a tab advances to.
+const TAB_WIDTH: usize = 8;
+
+
+pub(crate) const INFO: AnimationInfo = AnimationInfo {
+    name: "deck",
+    description: "Full-screen text frames loaded from files.",
+    default_frame_ms: 200,
+    size: (80, 24),
+    create: |config, _context| {
+        let params: Params = config.parse_params()?;
+        let frames = load_frames(&params.path, &params.delimiter)
+            .map_err(|error| CreateError::LoadFailed { name: config.name.clone(), path: params.path.clone(), error })?;
+        if frames.is_empty() {
+            return Err(CreateError::NoFrames { name: config.name.clone(), path: params.path.clone() });
+        }
+        Ok(Box::new(Deck::new(params, frames)))
+    },
+    params_schema: animations::params_schema::<Params>,
+    default_params: animations::default_params::<Params>,
+    needs_params: true,
+};
+
+
+/// Parameters of the frame deck.
+#[derive(Clone, Debug, Deserialize, Eq, Hash, JsonSchema, Ord, PartialEq, PartialOrd, Serialize)]
+#[serde(default, deny_unknown_fields)]
+pub(crate) struct Params {
+    /// How long each frame is shown, in milliseconds.
+    pub frame_ms: u64,
+
+    /// Either a directory containing one text file per frame (shown in the order of their names;
+    /// names starting with a dot are skipped) or a single text file containing all frames,
+    /// separated by delimiter lines. Relative paths are resolved against the working directory.
+    pub path: PathBuf,
+
+    /// The line separating the frames within a single file.
+    pub delimiter: String,
+
+    /// Only redraw what has changed since the previous frame instead of the whole screen, which
+    /// takes less bandwidth for frames that differ little.
+    pub diff: bool,
+
+    /// The color of the text.
+    pub color: Option<Color>,
+}
+impl Default for Params {
+    fn default() -> Self {
+        Self {
+            frame_ms: INFO.default_frame_ms,
+            path: PathBuf::from("frames"),
+            delimiter: "%".to_owned(),
+            diff: false,
+            color: None,
+        }
+    }
+}
+
+
+/// Loads the lines of each frame from a directory of frame files or a single file of delimited
+/// frames.
+fn load_frames(path: &Path, delimiter: &str) -> io::Result<Vec<Vec<String>>> {
+    if path.is_dir() {
+        let mut file_paths = Vec::new();
+        for entry in fs::read_dir(path)? {
+            let entry = entry?;
+            let hidden = entry.file_name().to_string_lossy().starts_with('.');
+            if !hidden && entry.path().is_file() {
+                file_paths.push(entry.path());
+            }
+        }
+        file_paths.sort_unstable();
+        return file_paths.iter()
+            .map(|file_path| {
+                let text = fs::read_to_string(file_path)?;
+                Ok(text.lines().map(clean_line).collect())
+            })
+            .collect();
+    }
+
+    let text = fs::read_to_string(path)?;
+    let mut frames = Vec::new();
+    let mut lines = Vec::new();
+    for line in text.lines() {
+        if line == delimiter {
+            frames.push(std::mem::take(&mut lines));
+        } else {
+            lines.push(clean_line(line));
+        }
+    }
+    frames.push(lines);
+
+    // delimiters at the beginning and the end of the file don't delimit anything
+    frames.retain(|lines| !lines.is_empty());
+    Ok(frames)
+}
+
+
+/// Expands tabs and removes control characters (which would throw off the positioning) as well as
+/// trailing whitespace.
+pub(crate) fn clean_line(line: &str) -> String {
+    let mut ret = String::with_capacity(line.len());
+    for c in line.chars() {
+        if c == '\t' {
+            let column = ret.width();
+            ret.extend(std::iter::repeat_n(' ', TAB_WIDTH - column % TAB_WIDTH));
+        } else if !c.is_control() {
+            ret.push(c);
+        }
+    }
+    ret.truncate(ret.trim_end().len());
+    ret
+}
+
+
+/// Draws a frame over whatever is on the screen.
+pub(crate) fn full_frame(lines: &[String]) -> String {
+    let mut ret = String::from("\x1B[H");
+    for (i, line) in lines.iter().enumerate() {
+        if i > 0 {
+            ret.push_str("\r\n");
+        }
+        ret.push_str(line);
+
+        // clear the rest of the line
+        ret.push_str("\x1B[K");
+    }
+
+    // clear the rest of the screen
+    ret.push_str("\x1B[J");
+    ret
+}
+
+
+/// Turns the previous frame into the next one, redrawing only the changed part of each line.
+fn diff_frame(previous: &[String], next: &[String]) -> String {
+    let mut ret = String::new();
+    for row in 0..previous.len().max(next.len()) {
+        let old = previous.get(row).map(String::as_str).unwrap_or("");
+        let new = next.get(row).map(String::as_str).unwrap_or("");
+        if old == new {
+            continue;
+        }
+
+        let prefix_len: usize = old.chars().zip(new.chars())
+            .take_while(|(o, n)| o == n)
+            .map(|(o, _n)| o.len_utf8())
+            .sum();
+        let (old_rest, new_rest) = (&old[prefix_len..], &new[prefix_len..]);
+        let suffix_len: usize = old_rest.chars().rev().zip(new_rest.chars().rev())
+            .take_while(|(o, n)| o == n)
+            .map(|(o, _n)| o.len_utf8())
+            .sum();
+        let old_changed = &old_rest[..old_rest.len() - suffix_len];
+        let new_changed = &new_rest[..new_rest.len() - suffix_len];
+
+        write!(ret, "\x1B[{};{}H", row + 1, new[..prefix_len].width() + 1).unwrap();
+        if old_changed.width() == new_changed.width() {
+            // the rest of the line stays where it is
+            ret.push_str(new_changed);
+        } else {
+            ret.push_str(new_rest);
+            ret.push_str("\x1B[K");
+        }
+    }
+    ret
+}
+
+
+#[derive(Clone, Debug, Eq, Hash, Ord, PartialEq, PartialOrd)]
+pub(crate) struct Deck {
+    params: Params,
+    frames: Vec<Vec<String>>,
+    frame_index: usize,
+
+    /// The index of the frame currently on the screen, if any.
+    shown: Option<usize>,
+}
+impl Deck {
+    pub fn new(params: Params, frames: Vec<Vec<String>>) -> Self {
+        Self {
+            params,
+            frames,
+            frame_index: 0,
+            shown: None,
+        }
+    }
+}
+impl Animation for Deck {
+    fn next_frame(&mut self, _session: &SessionInfo) -> Option<Frame> {
+        let mut commands = String::new();
+        if let Some(color) = self.params.color {
+            commands.push_str(color.foreground());
+        }
+
+        let lines = &self.frames[self.frame_index];
+        match self.shown.filter(|_| self.params.diff) {
+            Some(shown) => commands.push_str(&diff_frame(&self.frames[shown], lines)),
+            None => commands.push_str(&full_frame(lines)),
+        }
+
+        let mut frame = Frame::new(commands, Duration::from_millis(self.params.frame_ms));
+        frame.starts_cycle = self.frame_index == 0;
+        self.shown = Some(self.frame_index);
+        self.frame_index = (self.frame_index + 1) % self.frames.len();
+        Some(frame)
+    }
+}
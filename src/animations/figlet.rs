@@ -0,0 +1,231 @@
+//! Text in big letters made of characters, using FIGlet fonts.
+
+
+use std::fs;
+use std::io;
+use std::path::PathBuf;
+use std::time::Duration;
+
+use figlet_rs::FIGlet;
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+
+use crate::animations::{self, Animation, AnimationInfo, Color, CreateError, Frame};
+use crate::animations::canvas::Canvas;
+use crate::telnet::SessionInfo;
+
+
+pub(crate) const INFO: AnimationInfo = AnimationInfo {
+    name: "figlet",
+    description: "Text in big letters, typed out, scrolling or changing colors.",
+    default_frame_ms: 100,
+    size: (80, 24),
+    create: |config, _context| {
+        let params: Params = config.parse_params()?;
+        let font = load_font(&params)
+            .map_err(|error| CreateError::LoadFailed {
+                name: config.name.clone(),
+                path: params.font_file.clone().unwrap_or_default(),
+                error,
+            })?;
+        Ok(Box::new(Figlet::new(params, font)))
+    },
+    params_schema: animations::params_schema::<Params>,
+    default_params: animations::default_params::<Params>,
+    needs_params: false,
+};
+
+
+/// Parameters of the FIGlet animation.
+#[derive(Clone, Debug, Deserialize, Eq, Hash, JsonSchema, Ord, PartialEq, PartialOrd, Serialize)]
+#[serde(default, deny_unknown_fields)]
+pub(crate) struct Params {
+    /// How long each step of the effect is shown, in milliseconds.
+    pub frame_ms: u64,
+
+    /// The text; each line is drawn below the previous one.
+    pub text: String,
+
+    /// One of the fonts that come with the server.
+    pub font: BundledFont,
+
+    /// A FIGlet font file (.flf) to use instead of `font`. Relative paths are resolved against the
+    /// working directory.
+    pub font_file: Option<PathBuf>,
+
+    /// How the text is animated.
+    pub effect: Effect,
+
+    /// The color of the text, unless the effect changes it.
+    pub color: Option<Color>,
+
+    /// The colors the `colors` effect goes through.
+    pub colors: Vec<Color>,
+
+    /// How long the complete text stays on the screen before it is typed again, in milliseconds.
+    pub hold_ms: u64,
+}
+impl Default for Params {
+    fn default() -> Self {
+        Self {
+            frame_ms: INFO.default_frame_ms,
+            text: "Welcome!".to_owned(),
+            font: BundledFont::default(),
+            font_file: None,
+            effect: Effect::default(),
+            color: None,
+            colors: vec![
+                Color::BrightRed, Color::BrightYellow, Color::BrightGreen,
+                Color::BrightCyan, Color::BrightBlue, Color::BrightMagenta,
+            ],
+            hold_ms: 3000,
+        }
+    }
+}
+
+
+/// The FIGlet fonts that come with the server.
+#[derive(Clone, Copy, Debug, Default, Deserialize, Eq, Hash, JsonSchema, Ord, PartialEq, PartialOrd, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub(crate) enum BundledFont {
+    #[default]
+    Standard,
+    Small,
+    Big,
+    Slant,
+}
+
+
+/// How the text of the FIGlet animation is animated.
+#[derive(Clone, Copy, Debug, Default, Deserialize, Eq, Hash, JsonSchema, Ord, PartialEq, PartialOrd, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub(crate) enum Effect {
+    /// The text is typed out a letter at a time, then stays on the screen for `hold_ms`.
+    #[default]
+    Typing,
+
+    /// The text scrolls across the screen from right to left.
+    Scroll,
+
+    /// Stripes of `colors` move across the text.
+    Colors,
+}
+
+
+fn load_font(params: &Params) -> io::Result<FIGlet> {
+    let font = match &params.font_file {
+        Some(path) => FIGlet::from_content(&fs::read_to_string(path)?),
+        None => match params.font {
+            BundledFont::Standard => FIGlet::standard(),
+            BundledFont::Small => FIGlet::small(),
+            BundledFont::Big => FIGlet::big(),
+            BundledFont::Slant => FIGlet::slant(),
+        },
+    };
+    font.map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+}
+
+
+pub(crate) struct Figlet {
+    params: Params,
+    font: FIGlet,
+
+    /// The rows of the whole text in big letters.
+    block: Vec<Vec<char>>,
+
+    /// How many steps of the effect have been shown in this cycle.
+    step: u64,
+
+    /// What is on the client's screen.
+    shown: Option<Canvas>,
+}
+impl Figlet {
+    fn new(params: Params, font: FIGlet) -> Self {
+        let mut ret = Self {
+            params,
+            font,
+            block: Vec::new(),
+            step: 0,
+            shown: None,
+        };
+        ret.block = ret.render(usize::MAX);
+        ret
+    }
+
+    /// Renders the first `char_count` characters of the text (not counting line breaks) in big
+    /// letters.
+    fn render(&self, char_count: usize) -> Vec<Vec<char>> {
+        let mut remaining = char_count;
+        let mut block = Vec::new();
+        for line in self.params.text.lines() {
+            let typed: String = line.chars().take(remaining).collect();
+            remaining -= typed.chars().count();
+            if let Some(figure) = self.font.convert(&typed) {
+                block.extend(figure.to_string().lines().map(|row| row.chars().collect::<Vec<char>>()));
+            }
+        }
+        block
+    }
+
+    /// The number of characters in the text, not counting line breaks.
+    fn char_count(&self) -> usize {
+        self.params.text.lines()
+            .map(|line| line.chars().count())
+            .sum()
+    }
+}
+impl Animation for Figlet {
+    fn next_frame(&mut self, session: &SessionInfo) -> Option<Frame> {
+        let (cols, rows) = session.window_size.unwrap_or(INFO.size);
+        let (cols, rows) = (i64::from(cols), i64::from(rows));
+        let block_width = self.block.iter().map(Vec::len).max().unwrap_or(0) as i64;
+        let block_height = self.block.len() as i64;
+        let centered_column = ((cols - block_width) / 2).max(0);
+        let top = ((rows - block_height) / 2).max(0);
+
+        let mut delay_ms = self.params.frame_ms;
+        let (steps, column, rendered) = match self.params.effect {
+            Effect::Typing => {
+                let char_count = self.char_count();
+                let typed = usize::try_from(self.step).unwrap_or(usize::MAX);
+                if typed >= char_count {
+                    delay_ms = self.params.hold_ms;
+                }
+                (char_count as u64 + 1, centered_column, Some(self.render(typed)))
+            },
+            Effect::Scroll => {
+                // from the first column entering the screen to the last one leaving it
+                let steps = (cols + block_width - 1).max(1) as u64;
+                (steps, cols - 1 - self.step as i64, None)
+            },
+            Effect::Colors => (self.params.colors.len().max(1) as u64, centered_column, None),
+        };
+        let block = rendered.as_ref().unwrap_or(&self.block);
+
+        let mut canvas = Canvas::new((cols as u16, rows as u16));
+        for (row_offset, row) in block.iter().enumerate() {
+            for (col_offset, c) in row.iter().enumerate() {
+                if *c == ' ' {
+                    continue;
+                }
+                let color = match self.params.effect {
+                    Effect::Colors if !self.params.colors.is_empty() => {
+                        // diagonal stripes, two columns wide, moving to the right
+                        let stripe = (col_offset + row_offset) / 2 + self.params.colors.len() - self.step as usize;
+                        Some(self.params.colors[stripe % self.params.colors.len()])
+                    },
+                    _ => self.params.color,
+                };
+                canvas.set(column + col_offset as i64, top + row_offset as i64, (*c, color));
+            }
+        }
+        let commands = canvas.draw_over(self.shown.as_ref());
+        self.shown = Some(canvas);
+
+        let starts_cycle = self.step == 0;
+        self.step = (self.step + 1) % steps;
+        let mut frame = Frame::new(commands, Duration::from_millis(delay_ms));
+        frame.starts_cycle = starts_cycle;
+        Some(frame)
+    }
+}
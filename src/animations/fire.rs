@@ -0,0 +1,158 @@
+//! The classic demoscene fire effect: heat rising from the bottom of the screen and cooling off
+//! on its way up.
+
+
+use std::time::Duration;
+
+use rand::{Rng, SeedableRng};
+use rand::rngs::SmallRng;
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+
+use crate::animations::{self, Animation, AnimationInfo, Color, Frame};
+use crate::animations::canvas::{Canvas, Cell};
+use crate::telnet::SessionInfo;
+
+
+/// The hottest a cell can get.
+const MAX_HEAT: u16 = 255;
+
+/// How the heat of a cell is drawn, from cold to hot; the characters alone (without the colors)
+/// are used on terminals that cannot show colors.
+const RAMP: [Cell; 10] = [
+    (' ', None),
+    ('.', Some(Color::Red)),
+    (':', Some(Color::Red)),
+    ('^', Some(Color::BrightRed)),
+    ('*', Some(Color::BrightRed)),
+    ('x', Some(Color::Yellow)),
+    ('s', Some(Color::Yellow)),
+    ('S', Some(Color::BrightYellow)),
+    ('#', Some(Color::BrightYellow)),
+    ('$', Some(Color::BrightWhite)),
+];
+
+
+pub(crate) const INFO: AnimationInfo = AnimationInfo {
+    name: "fire",
+    description: "Flames licking up from the bottom of the screen.",
+    default_frame_ms: 60,
+    size: (80, 24),
+    create: |config, _context| Ok(Box::new(Fire::new(config.parse_params()?))),
+    params_schema: animations::params_schema::<Params>,
+    default_params: animations::default_params::<Params>,
+    needs_params: false,
+};
+
+
+/// Parameters of the fire effect.
+#[derive(Clone, Debug, Deserialize, Eq, Hash, JsonSchema, Ord, PartialEq, PartialOrd, Serialize)]
+#[serde(default, deny_unknown_fields)]
+pub(crate) struct Params {
+    /// How long each frame is shown, in milliseconds.
+    pub frame_ms: u64,
+
+    /// How high the flames reach, as a percentage of the screen's height.
+    pub intensity: u8,
+
+    /// Draw the flames with characters alone, without colors; by default, this is only done if
+    /// the client's terminal type suggests that it cannot show colors.
+    pub mono: Option<bool>,
+
+    /// Seeds the flickering of the flames, making it reproducible; by default, it is different
+    /// each time.
+    pub seed: Option<u64>,
+}
+impl Default for Params {
+    fn default() -> Self {
+        Self {
+            frame_ms: INFO.default_frame_ms,
+            intensity: 60,
+            mono: None,
+            seed: None,
+        }
+    }
+}
+
+
+#[derive(Clone, Debug)]
+pub(crate) struct Fire {
+    params: Params,
+    rng: SmallRng,
+
+    /// The heat of each cell by row and column, with two extra rows below the screen that the
+    /// heat comes from.
+    heat: Vec<Vec<u16>>,
+
+    /// What is on the client's screen.
+    shown: Option<Canvas>,
+}
+impl Fire {
+    pub fn new(params: Params) -> Self {
+        let rng = match params.seed {
+            Some(seed) => SmallRng::seed_from_u64(seed),
+            None => SmallRng::from_os_rng(),
+        };
+        Self {
+            params,
+            rng,
+            heat: Vec::new(),
+            shown: None,
+        }
+    }
+
+    /// Lets the heat rise by a row.
+    fn spread(&mut self, (cols, rows): (usize, usize)) {
+        if self.heat.len() != rows + 2 || self.heat.first().is_some_and(|row| row.len() != cols) {
+            self.heat = vec![vec![0; cols]; rows + 2];
+        }
+        if cols == 0 {
+            return;
+        }
+
+        // stoke the fire
+        for row in &mut self.heat[rows..] {
+            for cell in row.iter_mut() {
+                *cell = if self.rng.random_ratio(2, 3) { MAX_HEAT } else { 0 };
+            }
+        }
+
+        // cool off just enough on the way up for the flames to reach the configured height
+        let flame_rows = (rows * usize::from(self.params.intensity.min(100)) / 100).max(1);
+        let cooling = (2 * MAX_HEAT as usize / flame_rows).max(1) as u16;
+        for row in 0..rows {
+            for col in 0..cols {
+                let below = &self.heat[row + 1];
+                let sum = below[col.saturating_sub(1)]
+                    + below[col]
+                    + below[(col + 1).min(cols - 1)]
+                    + self.heat[row + 2][col];
+                let decay = self.rng.random_range(0..=cooling);
+                self.heat[row][col] = (sum / 4).saturating_sub(decay);
+            }
+        }
+    }
+}
+impl Animation for Fire {
+    fn next_frame(&mut self, session: &SessionInfo) -> Option<Frame> {
+        let size = session.window_size.unwrap_or(INFO.size);
+        self.spread((usize::from(size.0), usize::from(size.1)));
+
+        let mono = self.params.mono.unwrap_or_else(|| session.is_basic_terminal());
+        let mut canvas = Canvas::new(size);
+        for (row, heats) in self.heat.iter().take(usize::from(size.1)).enumerate() {
+            for (col, heat) in heats.iter().enumerate() {
+                let (c, color) = RAMP[usize::from(*heat) * (RAMP.len() - 1) / usize::from(MAX_HEAT)];
+                let color = if mono { None } else { color };
+                canvas.set(col as i64, row as i64, (c, color));
+            }
+        }
+        let commands = canvas.draw_over(self.shown.as_ref());
+        let starts_cycle = self.shown.is_none();
+        self.shown = Some(canvas);
+
+        let mut frame = Frame::new(commands, Duration::from_millis(self.params.frame_ms));
+        frame.starts_cycle = starts_cycle;
+        Some(frame)
+    }
+}
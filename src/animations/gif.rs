@@ -0,0 +1,147 @@
+//! Animated GIFs, turned into text at the size of the client's terminal.
+
+
+use std::fs::File;
+use std::io::BufReader;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::Duration;
+
+use image::{AnimationDecoder, ImageDecoder, ImageError, Limits, RgbImage};
+use image::codecs::gif::GifDecoder;
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+
+use crate::animations::{self, Animation, AnimationInfo, CreateError, Frame};
+use crate::animations::raster::{self, Cache, Style};
+use crate::telnet::SessionInfo;
+
+
+/// How many frames of a GIF are shown at most.
+const MAX_FRAMES: usize = 1000;
+
+/// Browsers disregard delays shorter than this (showing such frames for 100 ms instead), and so
+/// many GIFs have them that the delay is better disregarded here too.
+const MIN_DELAY: Duration = Duration::from_millis(20);
+
+
+/// The GIFs that have been decoded, shared among the sessions showing them.
+static DECODED: Cache<DecodedGif> = Cache::new();
+
+
+pub(crate) const INFO: AnimationInfo = AnimationInfo {
+    name: "gif",
+    description: "An animated GIF, drawn with colored blocks or ASCII characters.",
+    default_frame_ms: 100,
+    size: (80, 24),
+    create: |config, _context| {
+        let params: Params = config.parse_params()?;
+        let gif = DECODED.get_or_load(&params.path, decode)
+            .map_err(|error| CreateError::LoadFailed { name: config.name.clone(), path: params.path.clone(), error })?;
+        if gif.frames.is_empty() {
+            return Err(CreateError::NoFrames { name: config.name.clone(), path: params.path.clone() });
+        }
+        Ok(Box::new(GifAnimation::new(params, gif)))
+    },
+    params_schema: animations::params_schema::<Params>,
+    default_params: animations::default_params::<Params>,
+    needs_params: true,
+};
+
+
+/// Parameters of the GIF animation.
+#[derive(Clone, Debug, Deserialize, Eq, Hash, JsonSchema, Ord, PartialEq, PartialOrd, Serialize)]
+#[serde(default, deny_unknown_fields)]
+pub(crate) struct Params {
+    /// How long frames that don't specify a delay of their own are shown, in milliseconds.
+    pub frame_ms: u64,
+
+    /// The GIF file. Relative paths are resolved against the working directory.
+    pub path: PathBuf,
+
+    /// How the frames are drawn.
+    pub style: Style,
+}
+impl Default for Params {
+    fn default() -> Self {
+        Self {
+            frame_ms: INFO.default_frame_ms,
+            path: PathBuf::from("animation.gif"),
+            style: Style::default(),
+        }
+    }
+}
+
+
+#[derive(Debug)]
+struct DecodedGif {
+    /// Each frame with its delay, if it specifies a usable one.
+    frames: Vec<(RgbImage, Option<Duration>)>,
+}
+
+
+fn decode(path: &Path) -> Result<DecodedGif, ImageError> {
+    let mut decoder = GifDecoder::new(BufReader::new(File::open(path)?))?;
+    let mut limits = Limits::default();
+    limits.max_alloc = Some(raster::MAX_DECODE_ALLOCATION);
+    decoder.set_limits(limits)?;
+
+    let mut frames = Vec::new();
+    for frame in decoder.into_frames().take(MAX_FRAMES) {
+        let frame = frame?;
+        let (numerator, denominator) = frame.delay().numer_denom_ms();
+        let delay = Duration::from_millis(u64::from(numerator / denominator.max(1)));
+        let image = raster::prepare(&frame.into_buffer());
+        frames.push((image, Some(delay).filter(|d| *d >= MIN_DELAY)));
+    }
+    Ok(DecodedGif { frames })
+}
+
+
+#[derive(Debug)]
+pub(crate) struct GifAnimation {
+    params: Params,
+    gif: Arc<DecodedGif>,
+    frame_index: usize,
+
+    /// The terminal size (columns and rows) and the style the frames are being rendered for.
+    rendered_for: Option<((u16, u16), Style)>,
+
+    /// The frames rendered so far.
+    rendered: Vec<Option<String>>,
+}
+impl GifAnimation {
+    fn new(params: Params, gif: Arc<DecodedGif>) -> Self {
+        Self {
+            params,
+            gif,
+            frame_index: 0,
+            rendered_for: None,
+            rendered: Vec::new(),
+        }
+    }
+}
+impl Animation for GifAnimation {
+    fn next_frame(&mut self, session: &SessionInfo) -> Option<Frame> {
+        let size = session.window_size.unwrap_or((80, 24));
+        let style = self.params.style.for_session(session);
+        let mut commands = String::new();
+        if self.rendered_for != Some((size, style)) {
+            // each frame is rendered when it is first shown at this size, then kept; rendering all
+            // of them at once would hold up the session (and others) whenever the size changes
+            self.rendered_for = Some((size, style));
+            self.rendered = vec![None; self.gif.frames.len()];
+            commands.push_str("\x1B[0m\x1B[2J");
+        }
+        let (image, delay) = &self.gif.frames[self.frame_index];
+        let rendered = self.rendered[self.frame_index]
+            .get_or_insert_with(|| raster::render(image, size.0, size.1, style));
+        commands.push_str(rendered);
+
+        let delay = delay.unwrap_or(Duration::from_millis(self.params.frame_ms));
+        let mut frame = Frame::new(commands, delay);
+        frame.starts_cycle = self.frame_index == 0;
+        self.frame_index = (self.frame_index + 1) % self.gif.frames.len();
+        Some(frame)
+    }
+}
@@ -0,0 +1,386 @@
+use std::collections::{HashMap, VecDeque};
+use std::fmt::Write as _;
+use std::fs::{self, OpenOptions};
+use std::io::{self, Write as _};
+use std::net::{IpAddr, SocketAddr};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant, SystemTime};
+
+use log::{info, warn};
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+
+use crate::animations::{self, Animation, AnimationInfo, Frame};
+use crate::animations::chatwall::{tail_to_width, truncate_to_width};
+use crate::keys::{BS, DEL};
+use crate::logging;
+use crate::telnet::{self, Event, SessionInfo};
+
+
+/// How many of the most recent entries are kept in memory to be shown.
+const KEPT_ENTRIES: usize = 100;
+
+/// The period over which entries are counted for rate limiting.
+const RATE_PERIOD: Duration = Duration::from_secs(60 * 60);
+
+/// How often the guestbook is checked for entries written by others.
+const POLL_INTERVAL: Duration = Duration::from_millis(200);
+
+
+pub(crate) const INFO: AnimationInfo = AnimationInfo {
+    name: "guestbook",
+    description: "A guestbook kept on disk; sign it and read what others have written.",
+    default_frame_ms: 1000,
+    size: (40, 8),
+    create: |config, context| {
+        let params: Params = config.parse_params()?;
+        let guestbook = context.guestbooks.clone().unwrap_or_default()
+            .for_path(&params.path);
+        Ok(Box::new(GuestbookAnimation::new(params, guestbook, context.client_addr)))
+    },
+    params_schema: animations::params_schema::<Params>,
+    default_params: animations::default_params::<Params>,
+    needs_params: false,
+};
+
+
+/// Parameters of the guestbook.
+#[derive(Clone, Debug, Deserialize, Eq, Hash, JsonSchema, Ord, PartialEq, PartialOrd, Serialize)]
+#[serde(default, deny_unknown_fields)]
+pub(crate) struct Params {
+    /// How long the entries take to scroll by one line, in milliseconds.
+    pub frame_ms: u64,
+
+    /// The file the entries are appended to, one per line. Relative paths are resolved against
+    /// the working directory.
+    pub path: PathBuf,
+
+    /// The maximum length of an entry, in characters.
+    pub max_entry_length: usize,
+
+    /// How many entries a client (identified by its IP address) may write per hour.
+    pub max_entries_per_hour: usize,
+}
+impl Default for Params {
+    fn default() -> Self {
+        Self {
+            frame_ms: INFO.default_frame_ms,
+            path: PathBuf::from("guestbook.txt"),
+            max_entry_length: 100,
+            max_entries_per_hour: 3,
+        }
+    }
+}
+
+
+/// Why an entry has not been added to the guestbook.
+#[derive(Debug)]
+pub(crate) enum SignError {
+    /// The source has written too many entries recently.
+    TooFast,
+
+    /// The entry could not be written to the file.
+    Io(io::Error),
+}
+
+
+#[derive(Debug, Default)]
+struct GuestbookInner {
+    /// The most recent entries as shown to clients, oldest first, or `None` if the file has not
+    /// been read yet.
+    entries: Option<VecDeque<String>>,
+
+    /// Increased whenever an entry is added.
+    version: u64,
+
+    /// When each client has recently written an entry, for rate limiting.
+    recent_entries: HashMap<IpAddr, VecDeque<Instant>>,
+}
+
+
+/// A guestbook stored in a file.
+///
+/// Each line of the file is an entry consisting of the time (in UTC), the IP address of its
+/// author and the text, separated by tabs; only the date and the text are shown to clients.
+#[derive(Clone, Debug)]
+pub(crate) struct Guestbook {
+    path: PathBuf,
+    inner: Arc<Mutex<GuestbookInner>>,
+}
+impl Guestbook {
+    fn new(path: PathBuf) -> Self {
+        Self {
+            path,
+            inner: Arc::new(Mutex::new(GuestbookInner::default())),
+        }
+    }
+
+    /// Makes sure the entries have been read from the file.
+    fn load(&self, inner: &mut GuestbookInner) {
+        if inner.entries.is_some() {
+            return;
+        }
+        let text = match fs::read_to_string(&self.path) {
+            Ok(t) => t,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => String::new(),
+            Err(e) => {
+                warn!("failed to read guestbook {}: {}", self.path.display(), e);
+                String::new()
+            },
+        };
+        let mut entries: VecDeque<String> = text.lines()
+            .filter_map(displayed_entry)
+            .collect();
+        while entries.len() > KEPT_ENTRIES {
+            entries.pop_front();
+        }
+        inner.entries = Some(entries);
+    }
+
+    /// Appends an entry written by the given source, unless the source has written too many
+    /// entries recently.
+    ///
+    /// The text must already have been sanitized.
+    pub fn sign(&self, source: Option<IpAddr>, text: &str, max_entries_per_hour: usize) -> Result<(), SignError> {
+        let mut inner = self.inner.lock().unwrap();
+        self.load(&mut inner);
+
+        let now = Instant::now();
+        inner.recent_entries.retain(|_source, times| {
+            while times.front().is_some_and(|&t| now.duration_since(t) >= RATE_PERIOD) {
+                times.pop_front();
+            }
+            !times.is_empty()
+        });
+        if let Some(ip) = source {
+            if inner.recent_entries.get(&ip).is_some_and(|times| times.len() >= max_entries_per_hour) {
+                return Err(SignError::TooFast);
+            }
+        }
+
+        let source_text = source.map(|ip| ip.to_string()).unwrap_or_else(|| "local".to_owned());
+        let line = format!("{}\t{}\t{}", logging::format_timestamp(SystemTime::now()), source_text, text);
+        let mut file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.path)
+            .map_err(SignError::Io)?;
+        writeln!(file, "{}", line)
+            .map_err(SignError::Io)?;
+
+        if let Some(ip) = source {
+            inner.recent_entries.entry(ip).or_default().push_back(now);
+        }
+        let entries = inner.entries.as_mut().unwrap();
+        entries.extend(displayed_entry(&line));
+        while entries.len() > KEPT_ENTRIES {
+            entries.pop_front();
+        }
+        inner.version += 1;
+        Ok(())
+    }
+
+    /// Returns the version of the guestbook, which changes whenever an entry is added, and the
+    /// entries to show, oldest first.
+    pub fn entries(&self) -> (u64, Vec<String>) {
+        let mut inner = self.inner.lock().unwrap();
+        self.load(&mut inner);
+        let entries = inner.entries.as_ref().unwrap().iter().cloned().collect();
+        (inner.version, entries)
+    }
+}
+
+
+/// The guestbooks of all animations, by path.
+#[derive(Clone, Debug, Default)]
+pub(crate) struct Guestbooks {
+    guestbooks: Arc<Mutex<HashMap<PathBuf, Guestbook>>>,
+}
+impl Guestbooks {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Returns the guestbook stored in the given file.
+    pub fn for_path(&self, path: &Path) -> Guestbook {
+        self.guestbooks.lock().unwrap()
+            .entry(path.to_owned())
+            .or_insert_with(|| Guestbook::new(path.to_owned()))
+            .clone()
+    }
+}
+
+
+/// The state of the guestbook, the input and the terminal at a redraw.
+#[derive(Clone, Debug, Eq, PartialEq)]
+struct Drawn {
+    version: u64,
+    scroll_offset: usize,
+    input: Vec<u8>,
+    notice: Option<&'static str>,
+    size: (u16, u16),
+}
+
+
+/// Turns a line of the guestbook file into the text shown to clients (the date and the text).
+fn displayed_entry(line: &str) -> Option<String> {
+    let mut pieces = line.splitn(3, '\t');
+    let timestamp = pieces.next()?;
+    let _source = pieces.next()?;
+    let text = pieces.next()?;
+    let date = timestamp.split('T').next().unwrap_or(timestamp);
+    Some(format!("{}  {}", date, telnet::decode_string(text.as_bytes())))
+}
+
+
+/// Shows the entries of a guestbook scrolling by and lets the client sign it.
+#[derive(Debug)]
+pub(crate) struct GuestbookAnimation {
+    params: Params,
+    guestbook: Guestbook,
+    client_addr: Option<SocketAddr>,
+
+    /// The entry the client is typing.
+    input: Vec<u8>,
+
+    /// Shown after the input line, e.g. once the client has signed.
+    notice: Option<&'static str>,
+
+    /// What was shown at the last redraw.
+    drawn: Option<Drawn>,
+
+    /// How many lines the entries have scrolled up.
+    scroll_offset: usize,
+
+    /// When the entries scroll up next.
+    next_scroll_at: Instant,
+}
+impl GuestbookAnimation {
+    pub fn new(params: Params, guestbook: Guestbook, client_addr: Option<SocketAddr>) -> Self {
+        Self {
+            params,
+            guestbook,
+            client_addr,
+            input: Vec::new(),
+            notice: None,
+            drawn: None,
+            scroll_offset: 0,
+            next_scroll_at: Instant::now(),
+        }
+    }
+
+    fn sign(&mut self) {
+        let text = telnet::decode_string(&self.input);
+        self.input.clear();
+        let text: String = text.trim()
+            .chars()
+            .take(self.params.max_entry_length)
+            .collect();
+        if text.is_empty() {
+            return;
+        }
+        let source = self.client_addr.map(|a| a.ip());
+        match self.guestbook.sign(source, &text, self.params.max_entries_per_hour) {
+            Ok(()) => {
+                match self.client_addr {
+                    Some(addr) => info!("{} signed the guestbook: {:?}", addr, text),
+                    None => info!("signed the guestbook: {:?}", text),
+                }
+                self.notice = Some("(thank you!)");
+            },
+            Err(SignError::TooFast) => {
+                self.notice = Some("(slow down!)");
+            },
+            Err(SignError::Io(e)) => {
+                warn!("failed to write to guestbook {}: {}", self.guestbook.path.display(), e);
+                self.notice = Some("(could not save, sorry)");
+            },
+        }
+    }
+}
+impl Animation for GuestbookAnimation {
+    fn next_frame(&mut self, session: &SessionInfo) -> Option<Frame> {
+        let size = session.window_size.unwrap_or((80, 24));
+        let (cols, rows) = (usize::from(size.0), usize::from(size.1));
+        let (version, entries) = self.guestbook.entries();
+        let first = self.drawn.is_none();
+
+        // the newest entry at the top, followed by the older ones
+        let visible_rows = rows.saturating_sub(4);
+        let scrolls = entries.len() > visible_rows;
+        let now = Instant::now();
+        let mut starts_cycle = first;
+        if self.drawn.as_ref().map(|d| d.version) != Some(version) {
+            // start over with the newest entry
+            self.scroll_offset = 0;
+            self.next_scroll_at = now + Duration::from_millis(self.params.frame_ms);
+        } else if scrolls && self.next_scroll_at <= now {
+            // leave an empty line between the oldest and the newest entry
+            self.scroll_offset = (self.scroll_offset + 1) % (entries.len() + 1);
+            starts_cycle = self.scroll_offset == 0;
+            self.next_scroll_at = now + Duration::from_millis(self.params.frame_ms);
+        }
+        let delay = self.next_scroll_at.saturating_duration_since(now).min(POLL_INTERVAL);
+
+        let state = Drawn {
+            version,
+            scroll_offset: self.scroll_offset,
+            input: self.input.clone(),
+            notice: self.notice,
+            size,
+        };
+        if self.drawn.as_ref() == Some(&state) {
+            // nothing new
+            return Some(Frame::new("", delay));
+        }
+        self.drawn = Some(state);
+
+        let mut commands = String::new();
+        if first {
+            // clear screen
+            commands.push_str("\x1B[0m\x1B[2J");
+        }
+        write!(commands, "\x1B[1;1H\x1B[2KGuestbook ({} recent entries)", entries.len()).unwrap();
+        for row in 0..visible_rows {
+            let index = if scrolls {
+                (self.scroll_offset + row) % (entries.len() + 1)
+            } else {
+                row
+            };
+            let entry = entries.iter().rev().nth(index).map(|e| e.as_str()).unwrap_or("");
+            write!(commands, "\x1B[{};1H\x1B[2K{}", row + 3, truncate_to_width(entry, cols)).unwrap();
+        }
+        write!(commands, "\x1B[{};1H\x1B[2K{}", rows.saturating_sub(1), "-".repeat(cols)).unwrap();
+
+        let notice = self.notice.map(|n| format!(" {}", n)).unwrap_or_default();
+        let input = telnet::decode_string(&self.input);
+        let prompt = "Sign: ";
+        let input_width = cols.saturating_sub(prompt.len() + notice.len() + 1);
+        write!(commands, "\x1B[{};1H\x1B[2K{}{}{}", rows, prompt, tail_to_width(&input, input_width), notice).unwrap();
+
+        let mut frame = Frame::new(commands, delay);
+        frame.starts_cycle = starts_cycle;
+        Some(frame)
+    }
+
+    fn handle_event(&mut self, event: &Event) -> bool {
+        match event {
+            Event::Data(BS|DEL) => {
+                let mut input = String::from_utf8_lossy(&self.input).into_owned();
+                input.pop();
+                self.input = input.into_bytes();
+            },
+            Event::Data(b) if *b >= 0x20 => {
+                // (the limit is in characters, but bytes are cheaper to count while typing)
+                if self.input.len() < self.params.max_entry_length * 4 {
+                    self.input.push(*b);
+                }
+                self.notice = None;
+            },
+            Event::Newline => self.sign(),
+            _ => return false,
+        }
+        true
+    }
+}
@@ -1,14 +1,12 @@
-use std::net::SocketAddr;
-use std::sync::Arc;
 use std::time::Duration;
 
-use tokio::io::BufWriter;
-use tokio::net::tcp::OwnedWriteHalf;
-use tokio::sync::Mutex;
-use tokio::time::sleep;
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use serde::de::Error as _;
 
-use crate::coaster::{decode_movements, Rollercoaster};
-use crate::telnet;
+use crate::animations::{self, Animation, AnimationInfo, Color, CreateError, Frame};
+use crate::coaster::{self, decode_movements, line_up_train, Rollercoaster};
+use crate::telnet::SessionInfo;
 
 
 const LOLLERCOASTER_BASE: &str = concat!(
@@ -43,47 +41,126 @@ const LOLLERCOASTER_MOVEMENTS: &str = concat!(
     "44412323236666666666666666",
     "666666",
 );
-const SLEEP_DURATION: Duration = Duration::from_millis(50);
 
 
-pub(crate) async fn run(writer: Arc<Mutex<BufWriter<OwnedWriteHalf>>>, addr: SocketAddr) -> Result<(), telnet::Error> {
-    let base_lines: Vec<String> = LOLLERCOASTER_BASE
-        .split('\n')
-        .map(|bl| bl.to_owned())
-        .collect();
-    let mut coaster = Rollercoaster::new(
-        base_lines,
-        "LOL".to_owned(),
-        vec![(1, -3), (1, -2), (1, -1)],
-        decode_movements(LOLLERCOASTER_MOVEMENTS).unwrap(),
-    );
+pub(crate) const INFO: AnimationInfo = AnimationInfo {
+    name: "lollercoaster",
+    description: "A LOL train riding the ultimate lollercoaster.",
+    default_frame_ms: 50,
+    size: (50, 22),
+    create: |config, _context| {
+        let params: Params = config.parse_params()?;
+        let invalid = |message: String| CreateError::InvalidParameters {
+            name: config.name.clone(),
+            error: toml::de::Error::custom(message),
+        };
+        if coaster::is_invisible_train(&params.train) {
+            return Err(invalid("train must not be empty".to_owned()));
+        }
+        let lollercoaster = Lollercoaster::new(params)
+            .map_err(|e| invalid(e.to_string()))?;
+        Ok(Box::new(lollercoaster))
+    },
+    params_schema: animations::params_schema::<Params>,
+    default_params: animations::default_params::<Params>,
+    needs_params: false,
+};
+
+
+/// Parameters of the lollercoaster animation.
+#[derive(Clone, Debug, Deserialize, Eq, Hash, JsonSchema, Ord, PartialEq, PartialOrd, Serialize)]
+#[serde(default, deny_unknown_fields)]
+pub(crate) struct Params {
+    /// How long each frame is shown, in milliseconds.
+    pub frame_ms: u64,
+
+    /// The text of the train, as read from left to right.
+    pub train: String,
 
-    loop {
-        coaster.reset();
+    /// Whether the ride starts again once the train has left.
+    #[serde(rename = "loop")]
+    pub looping: bool,
 
-        {
-            let mut writer_guard = writer.lock().await;
-            let base_frame = coaster.get_base_frame();
+    /// The color of the lollercoaster.
+    pub color: Option<Color>,
+}
+impl Default for Params {
+    fn default() -> Self {
+        Self {
+            frame_ms: INFO.default_frame_ms,
+            train: "LOL".to_owned(),
+            looping: true,
+            color: None,
+        }
+    }
+}
 
-            // clear screen
-            telnet::write_all(&mut *writer_guard, addr, b"\x1B[2J").await?;
 
-            // go to top left
-            telnet::write_all(&mut *writer_guard, addr, b"\x1B[H").await?;
+#[derive(Clone, Debug, Eq, Hash, Ord, PartialEq, PartialOrd)]
+pub(crate) struct Lollercoaster {
+    params: Params,
+    coaster: Rollercoaster,
+    needs_base_frame: bool,
+    finished: bool,
+}
+impl Lollercoaster {
+    pub fn new(params: Params) -> Result<Self, coaster::Error> {
+        let base_lines: Vec<String> = LOLLERCOASTER_BASE
+            .split('\n')
+            .map(|bl| bl.to_owned())
+            .collect();
+        let (train, train_start) = line_up_train(&params.train, 1, -3);
+        let coaster = Rollercoaster::new(
+            base_lines,
+            train,
+            train_start,
+            decode_movements(LOLLERCOASTER_MOVEMENTS).unwrap(),
+        )?;
+        Ok(Self {
+            params,
+            coaster,
+            needs_base_frame: true,
+            finished: false,
+        })
+    }
+}
+impl Animation for Lollercoaster {
+    fn next_frame(&mut self, _session: &SessionInfo) -> Option<Frame> {
+        if self.finished {
+            return None;
+        }
 
-            // output base frame
-            telnet::write_all(&mut *writer_guard, addr, base_frame.as_bytes()).await?;
-            telnet::flush(&mut *writer_guard, addr).await?;
+        let mut commands = String::new();
+        if let Some(color) = self.params.color {
+            commands.push_str(color.foreground());
         }
 
-        while let Some(new_commands) = coaster.advance() {
-            let mut writer_guard = writer.lock().await;
-            telnet::write_all(&mut *writer_guard, addr, new_commands.as_bytes()).await?;
-            telnet::flush(&mut *writer_guard, addr).await?;
+        if !self.needs_base_frame {
+            if let Some(new_commands) = self.coaster.advance() {
+                commands.push_str(&new_commands);
+                return Some(Frame::new(commands, Duration::from_millis(self.params.frame_ms)));
+            }
 
-            sleep(SLEEP_DURATION).await;
+            if !self.params.looping {
+                // the ride is over
+                self.finished = true;
+                return None;
+            }
         }
 
         // reset and start again
+        self.coaster.reset();
+        self.needs_base_frame = false;
+
+        // clear screen
+        commands.push_str("\x1B[2J");
+
+        // go to top left
+        commands.push_str("\x1B[H");
+
+        // output base frame
+        commands.push_str(&self.coaster.get_base_frame());
+
+        Some(Frame::new(commands, Duration::ZERO).starting_cycle())
     }
 }
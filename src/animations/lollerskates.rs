@@ -1,13 +1,10 @@
-use std::net::SocketAddr;
-use std::sync::Arc;
 use std::time::Duration;
 
-use tokio::io::BufWriter;
-use tokio::net::tcp::OwnedWriteHalf;
-use tokio::sync::Mutex;
-use tokio::time::sleep;
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
 
-use crate::telnet;
+use crate::animations::{self, Animation, AnimationInfo, Color, Frame};
+use crate::telnet::SessionInfo;
 
 
 const LOLLERSKATES_BASE: &str = concat!(
@@ -18,102 +15,170 @@ const LOLLERSKATES_BASE: &str = concat!(
     "      LOL LOL\r\n",
     ":-D LOLLERSKATES :-D\r\n",
 );
-const SLEEP_DURATION: Duration = Duration::from_millis(100);
 
 
-async fn base_frame(writer: &mut BufWriter<OwnedWriteHalf>, addr: SocketAddr) -> Result<(), telnet::Error> {
+fn base_frame() -> String {
+    let mut ret = String::new();
+
     // clear screen
-    telnet::write_all(writer, addr, b"\x1B[2J").await?;
+    ret.push_str("\x1B[2J");
 
     // go to top left
-    telnet::write_all(writer, addr, b"\x1B[H").await?;
+    ret.push_str("\x1B[H");
 
     // output lollerskater
-    telnet::write_all(writer, addr, LOLLERSKATES_BASE.as_bytes()).await?;
+    ret.push_str(LOLLERSKATES_BASE);
 
-    telnet::flush(writer, addr).await
+    ret
 }
 
-async fn frame0(writer: &mut BufWriter<OwnedWriteHalf>, addr: SocketAddr) -> Result<(), telnet::Error> {
-    telnet::write_all(writer, addr, b"\x1B[1;9H").await?;
-    telnet::write_all(writer, addr, b" _").await?;
+fn frame0() -> String {
+    let mut ret = String::new();
+
+    ret.push_str("\x1B[1;9H");
+    ret.push_str(" _");
 
-    telnet::write_all(writer, addr, b"\x1B[2;9H").await?;
-    telnet::write_all(writer, addr, b"//|_").await?;
+    ret.push_str("\x1B[2;9H");
+    ret.push_str("//|_");
 
-    telnet::write_all(writer, addr, b"\x1B[3;9H").await?;
-    telnet::write_all(writer, addr, b" |").await?;
+    ret.push_str("\x1B[3;9H");
+    ret.push_str(" |");
 
-    telnet::write_all(writer, addr, b"\x1B[4;8H").await?;
-    telnet::write_all(writer, addr, b" /| ").await?;
+    ret.push_str("\x1B[4;8H");
+    ret.push_str(" /| ");
 
-    telnet::write_all(writer, addr, b"\x1B[5;7H").await?;
-    telnet::write_all(writer, addr, b" LLOL   ").await?;
+    ret.push_str("\x1B[5;7H");
+    ret.push_str(" LLOL   ");
 
-    telnet::flush(writer, addr).await
+    ret
 }
 
-async fn frame1(writer: &mut BufWriter<OwnedWriteHalf>, addr: SocketAddr) -> Result<(), telnet::Error> {
-    telnet::write_all(writer, addr, b"\x1B[1;10H").await?;
-    telnet::write_all(writer, addr, b" ").await?;
+fn frame1() -> String {
+    let mut ret = String::new();
 
-    telnet::write_all(writer, addr, b"\x1B[2;9H").await?;
-    telnet::write_all(writer, addr, b" /_ ").await?;
+    ret.push_str("\x1B[1;10H");
+    ret.push(' ');
 
-    telnet::write_all(writer, addr, b"\x1B[3;11H").await?;
-    telnet::write_all(writer, addr, b"\\").await?;
+    ret.push_str("\x1B[2;9H");
+    ret.push_str(" /_ ");
 
-    telnet::write_all(writer, addr, b"\x1B[4;10H").await?;
-    telnet::write_all(writer, addr, b" |").await?;
+    ret.push_str("\x1B[3;11H");
+    ret.push('\\');
 
-    telnet::write_all(writer, addr, b"\x1B[5;9H").await?;
-    telnet::write_all(writer, addr, b"OLLOL").await?;
+    ret.push_str("\x1B[4;10H");
+    ret.push_str(" |");
 
-    telnet::flush(writer, addr).await
+    ret.push_str("\x1B[5;9H");
+    ret.push_str("OLLOL");
+
+    ret
 }
 
-async fn frame2(writer: &mut BufWriter<OwnedWriteHalf>, addr: SocketAddr) -> Result<(), telnet::Error> {
-    telnet::write_all(writer, addr, b"\x1B[1;9H").await?;
-    telnet::write_all(writer, addr, b"/\\").await?;
+fn frame2() -> String {
+    let mut ret = String::new();
+
+    ret.push_str("\x1B[1;9H");
+    ret.push_str("/\\");
 
-    telnet::write_all(writer, addr, b"\x1B[2;11H").await?;
-    telnet::write_all(writer, addr, b"\\/").await?;
+    ret.push_str("\x1B[2;11H");
+    ret.push_str("\\/");
 
-    telnet::write_all(writer, addr, b"\x1B[3;9H").await?;
-    telnet::write_all(writer, addr, b"/\\ ").await?;
+    ret.push_str("\x1B[3;9H");
+    ret.push_str("/\\ ");
 
-    telnet::write_all(writer, addr, b"\x1B[4;8H").await?;
-    telnet::write_all(writer, addr, b"/  \\").await?;
+    ret.push_str("\x1B[4;8H");
+    ret.push_str("/  \\");
 
-    telnet::write_all(writer, addr, b"\x1B[5;7H").await?;
-    telnet::write_all(writer, addr, b"LOL LOL").await?;
+    ret.push_str("\x1B[5;7H");
+    ret.push_str("LOL LOL");
 
-    telnet::flush(writer, addr).await
+    ret
 }
 
-pub(crate) async fn run(writer: Arc<Mutex<BufWriter<OwnedWriteHalf>>>, addr: SocketAddr) -> Result<(), telnet::Error> {
-    {
-        let mut writer_guard = writer.lock().await;
-        base_frame(&mut *writer_guard, addr).await?;
-    }
 
-    loop {
-        {
-            let mut writer_guard = writer.lock().await;
-            frame0(&mut *writer_guard, addr).await?;
+pub(crate) const INFO: AnimationInfo = AnimationInfo {
+    name: "lollerskates",
+    description: "A stick figure skating on LOLs.",
+    default_frame_ms: 100,
+    size: (20, 6),
+    create: |config, _context| Ok(Box::new(Lollerskates::new(config.parse_params()?))),
+    params_schema: animations::params_schema::<Params>,
+    default_params: animations::default_params::<Params>,
+    needs_params: false,
+};
+
+
+/// Parameters of the lollerskates animation.
+#[derive(Clone, Debug, Deserialize, Eq, Hash, JsonSchema, Ord, PartialEq, PartialOrd, Serialize)]
+#[serde(default, deny_unknown_fields)]
+pub(crate) struct Params {
+    /// How long each frame is shown, in milliseconds.
+    pub frame_ms: u64,
+
+    /// The color of the lollerskater.
+    pub color: Option<Color>,
+}
+impl Default for Params {
+    fn default() -> Self {
+        Self {
+            frame_ms: INFO.default_frame_ms,
+            color: None,
         }
-        sleep(SLEEP_DURATION).await;
+    }
+}
+
 
-        {
-            let mut writer_guard = writer.lock().await;
-            frame1(&mut *writer_guard, addr).await?;
+#[derive(Clone, Debug, Default, Eq, Hash, Ord, PartialEq, PartialOrd)]
+pub(crate) struct Lollerskates {
+    params: Params,
+    frame_index: usize,
+    cycle_complete: bool,
+}
+impl Lollerskates {
+    pub fn new(params: Params) -> Self {
+        Self {
+            params,
+            frame_index: 0,
+            cycle_complete: false,
+        }
+    }
+}
+impl Animation for Lollerskates {
+    fn next_frame(&mut self, _session: &SessionInfo) -> Option<Frame> {
+        let mut commands = String::new();
+        if let Some(color) = self.params.color {
+            commands.push_str(color.foreground());
         }
-        sleep(SLEEP_DURATION).await;
 
-        {
-            let mut writer_guard = writer.lock().await;
-            frame2(&mut *writer_guard, addr).await?;
+        let frame_duration = Duration::from_millis(self.params.frame_ms);
+        let delay = match self.frame_index {
+            0 => {
+                commands.push_str(&base_frame());
+                Duration::ZERO
+            },
+            1 => {
+                commands.push_str(&frame0());
+                frame_duration
+            },
+            2 => {
+                commands.push_str(&frame1());
+                frame_duration
+            },
+            _ => {
+                commands.push_str(&frame2());
+                frame_duration
+            },
+        };
+        let starts_cycle = self.frame_index == 0 || self.cycle_complete;
+        self.cycle_complete = false;
+        self.frame_index += 1;
+        if self.frame_index > 3 {
+            // skip the base frame
+            self.frame_index = 1;
+            self.cycle_complete = true;
         }
-        sleep(SLEEP_DURATION).await;
+        let mut frame = Frame::new(commands, delay);
+        frame.starts_cycle = starts_cycle;
+        Some(frame)
     }
 }
@@ -0,0 +1,247 @@
+//! A zoom into the Mandelbrot set.
+
+
+use std::time::Duration;
+
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+
+use crate::animations::{self, Animation, AnimationInfo, Color, Frame};
+use crate::animations::canvas::{Canvas, Cell};
+use crate::telnet::SessionInfo;
+
+
+/// The points the animation zooms into, one after the other, as real and imaginary parts.
+const TARGETS: [(f64, f64); 5] = [
+    // Seahorse Valley
+    (-0.743643887037151, 0.131825904205330),
+    // the tip of a spiral arm near the top
+    (-0.101096363845622, 0.956286510809142),
+    // a point on the antenna
+    (-1.543689012692076, 0.0),
+    // a spiral near the bottom
+    (0.001643721971153, -0.822467633298876),
+    // deep in the valley between the main body and the biggest bulb
+    (-0.775683770, 0.136467370),
+];
+
+/// How wide the view is before zooming in, on the complex plane.
+const START_WIDTH: f64 = 3.5;
+
+/// How narrow the view gets before moving on to the next target; any narrower and the precision of
+/// the computations runs out.
+const END_WIDTH: f64 = 1e-12;
+
+/// Where the view is centered before zooming in.
+const OVERVIEW_CENTER: (f64, f64) = (-0.6, 0.0);
+
+/// How many times taller than wide a character cell is.
+const CELL_ASPECT: f64 = 2.0;
+
+const RAINBOW: [Cell; 8] = [
+    ('.', Some(Color::Blue)),
+    (':', Some(Color::BrightBlue)),
+    ('-', Some(Color::Cyan)),
+    ('=', Some(Color::Green)),
+    ('+', Some(Color::BrightYellow)),
+    ('*', Some(Color::Yellow)),
+    ('#', Some(Color::Red)),
+    ('%', Some(Color::Magenta)),
+];
+
+const FIRE: [Cell; 8] = [
+    ('.', Some(Color::Red)),
+    (':', Some(Color::Red)),
+    ('-', Some(Color::BrightRed)),
+    ('=', Some(Color::Yellow)),
+    ('+', Some(Color::Yellow)),
+    ('*', Some(Color::BrightYellow)),
+    ('#', Some(Color::BrightYellow)),
+    ('%', Some(Color::BrightWhite)),
+];
+
+const OCEAN: [Cell; 8] = [
+    ('.', Some(Color::Blue)),
+    (':', Some(Color::Blue)),
+    ('-', Some(Color::BrightBlue)),
+    ('=', Some(Color::BrightBlue)),
+    ('+', Some(Color::Cyan)),
+    ('*', Some(Color::BrightCyan)),
+    ('#', Some(Color::BrightCyan)),
+    ('%', Some(Color::BrightWhite)),
+];
+
+const MONO: [Cell; 8] = [
+    ('.', None),
+    (':', None),
+    ('-', None),
+    ('=', None),
+    ('+', None),
+    ('*', None),
+    ('#', None),
+    ('%', None),
+];
+
+
+pub(crate) const INFO: AnimationInfo = AnimationInfo {
+    name: "mandelbrot",
+    description: "A never-ending zoom into the Mandelbrot set.",
+    default_frame_ms: 100,
+    size: (80, 24),
+    create: |config, _context| Ok(Box::new(Mandelbrot::new(config.parse_params()?))),
+    params_schema: animations::params_schema::<Params>,
+    default_params: animations::default_params::<Params>,
+    needs_params: false,
+};
+
+
+/// Parameters of the Mandelbrot zoom.
+#[derive(Clone, Debug, Deserialize, Eq, Hash, JsonSchema, Ord, PartialEq, PartialOrd, Serialize)]
+#[serde(default, deny_unknown_fields)]
+pub(crate) struct Params {
+    /// How long each frame is shown, in milliseconds.
+    pub frame_ms: u64,
+
+    /// How much closer each frame gets, as a percentage of the width of the view (1 to 50).
+    pub zoom_percent: u8,
+
+    /// The characters and colors the set is drawn with; by default `rainbow`, or `mono` if the
+    /// client's terminal type suggests that it cannot show colors.
+    pub palette: Option<Palette>,
+}
+impl Default for Params {
+    fn default() -> Self {
+        Self {
+            frame_ms: INFO.default_frame_ms,
+            zoom_percent: 4,
+            palette: None,
+        }
+    }
+}
+
+
+/// The characters and colors the points outside the set are drawn with, depending on how quickly
+/// they escape.
+#[derive(Clone, Copy, Debug, Default, Deserialize, Eq, Hash, JsonSchema, Ord, PartialEq, PartialOrd, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub(crate) enum Palette {
+    #[default]
+    Rainbow,
+    Fire,
+    Ocean,
+
+    /// Characters alone, without colors.
+    Mono,
+}
+impl Palette {
+    fn cells(self) -> &'static [Cell] {
+        match self {
+            Self::Rainbow => &RAINBOW,
+            Self::Fire => &FIRE,
+            Self::Ocean => &OCEAN,
+            Self::Mono => &MONO,
+        }
+    }
+}
+
+
+/// How many iterations it takes the point `c` to escape, or `None` if it does not within
+/// `max_iterations` and is therefore taken to be part of the set.
+fn escape_time((c_re, c_im): (f64, f64), max_iterations: u32) -> Option<u32> {
+    let (mut re, mut im) = (0.0_f64, 0.0_f64);
+    for iteration in 0..max_iterations {
+        let (re_squared, im_squared) = (re * re, im * im);
+        if re_squared + im_squared > 4.0 {
+            return Some(iteration);
+        }
+        im = 2.0 * re * im + c_im;
+        re = re_squared - im_squared + c_re;
+    }
+    None
+}
+
+
+#[derive(Clone, Debug)]
+pub(crate) struct Mandelbrot {
+    params: Params,
+
+    /// Which of the targets is being zoomed into.
+    target: usize,
+
+    /// How many frames of the zoom into the current target have been shown.
+    step: u64,
+
+    /// What is on the client's screen.
+    shown: Option<Canvas>,
+}
+impl Mandelbrot {
+    pub fn new(params: Params) -> Self {
+        Self {
+            params,
+            target: 0,
+            step: 0,
+            shown: None,
+        }
+    }
+}
+impl Animation for Mandelbrot {
+    fn next_frame(&mut self, session: &SessionInfo) -> Option<Frame> {
+        let size = session.window_size.unwrap_or(INFO.size);
+        let (cols, rows) = (f64::from(size.0), f64::from(size.1));
+
+        let shrink = 1.0 - f64::from(self.params.zoom_percent.clamp(1, 50)) / 100.0;
+        let width = START_WIDTH * shrink.powf(self.step as f64);
+
+        // drift from the overview towards the target quickly enough for the target to end up in
+        // the middle of the screen
+        let (target_re, target_im) = TARGETS[self.target];
+        let drift = (width / START_WIDTH).powi(2);
+        let center_re = target_re + (OVERVIEW_CENTER.0 - target_re) * drift;
+        let center_im = target_im + (OVERVIEW_CENTER.1 - target_im) * drift;
+
+        // the closer the view, the longer it takes to tell whether a point belongs to the set
+        let max_iterations = 100 + (100.0 * (START_WIDTH / width).log2()) as u32;
+
+        let palette = self.params.palette
+            .unwrap_or(if session.is_basic_terminal() { Palette::Mono } else { Palette::Rainbow });
+        let cells = palette.cells();
+        let cell_width = width / cols;
+        let escapes: Vec<Option<u32>> = (0..size.1)
+            .flat_map(|row| (0..size.0).map(move |col| (col, row)))
+            .map(|(col, row)| {
+                let re = center_re + (f64::from(col) + 0.5 - cols / 2.0) * cell_width;
+                let im = center_im + (f64::from(row) + 0.5 - rows / 2.0) * cell_width * CELL_ASPECT;
+                escape_time((re, im), max_iterations)
+            })
+            .collect();
+
+        // spread the palette over the escape times actually on the screen, on a logarithmic scale
+        // as the points close to the set take ever longer to escape
+        let fastest = escapes.iter().flatten().min().copied().unwrap_or(0);
+        let slowest = escapes.iter().flatten().max().copied().unwrap_or(0);
+        let range = f64::from(slowest - fastest + 1).ln().max(f64::EPSILON);
+        let mut canvas = Canvas::new(size);
+        for (index, escape) in escapes.iter().enumerate() {
+            if let Some(iterations) = escape {
+                let level = f64::from(iterations - fastest + 1).ln() / range;
+                let cell = cells[((level * cells.len() as f64) as usize).min(cells.len() - 1)];
+                let (col, row) = (index % usize::from(size.0), index / usize::from(size.0));
+                canvas.set(col as i64, row as i64, cell);
+            }
+        }
+        let commands = canvas.draw_over(self.shown.as_ref());
+        self.shown = Some(canvas);
+
+        let starts_cycle = self.step == 0;
+        if width * shrink < END_WIDTH {
+            self.target = (self.target + 1) % TARGETS.len();
+            self.step = 0;
+        } else {
+            self.step += 1;
+        }
+
+        let mut frame = Frame::new(commands, Duration::from_millis(self.params.frame_ms));
+        frame.starts_cycle = starts_cycle;
+        Some(frame)
+    }
+}
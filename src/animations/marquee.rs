@@ -0,0 +1,202 @@
+//! Text scrolling across a row of the screen, like a news ticker.
+
+
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use log::warn;
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use unicode_width::UnicodeWidthChar;
+
+use crate::animations::{self, Animation, AnimationInfo, Color, CreateError, Frame};
+use crate::animations::canvas::Canvas;
+use crate::animations::deck::clean_line;
+use crate::telnet::SessionInfo;
+
+
+/// What separates the lines of the text when they are put in a row.
+const LINE_SEPARATOR: &str = "   ";
+
+
+pub(crate) const INFO: AnimationInfo = AnimationInfo {
+    name: "marquee",
+    description: "Text scrolling across the screen.",
+    default_frame_ms: 100,
+    size: (80, 24),
+    create: |config, _context| {
+        let params: Params = config.parse_params()?;
+        let text = match &params.file {
+            Some(path) => read_text(path)
+                .map_err(|error| CreateError::LoadFailed { name: config.name.clone(), path: path.clone(), error })?,
+            None => prepare_text(&params.text),
+        };
+        Ok(Box::new(Marquee::new(params, text)))
+    },
+    params_schema: animations::params_schema::<Params>,
+    default_params: animations::default_params::<Params>,
+    needs_params: false,
+};
+
+
+/// Parameters of the marquee.
+#[derive(Clone, Debug, Deserialize, Eq, Hash, JsonSchema, Ord, PartialEq, PartialOrd, Serialize)]
+#[serde(default, deny_unknown_fields)]
+pub(crate) struct Params {
+    /// How long it takes the text to move by one column, in milliseconds.
+    pub frame_ms: u64,
+
+    /// The text to scroll. Line breaks are replaced by a few spaces.
+    pub text: String,
+
+    /// A text file to take the text from instead, read again every `reread_secs` seconds; the new
+    /// text is shown from the next pass on. Relative paths are resolved against the working
+    /// directory.
+    pub file: Option<PathBuf>,
+
+    /// How often `file` is read again, in seconds.
+    pub reread_secs: u64,
+
+    /// The row the text scrolls along, counting from 0; by default, the middle row.
+    pub row: Option<u16>,
+
+    /// The color of the text.
+    pub color: Option<Color>,
+
+    /// Instead of scrolling the text off one side and bringing it back in on the other, move it
+    /// back and forth between the edges of the screen.
+    pub bounce: bool,
+}
+impl Default for Params {
+    fn default() -> Self {
+        Self {
+            frame_ms: INFO.default_frame_ms,
+            text: "Welcome to telnet-animations!".to_owned(),
+            file: None,
+            reread_secs: 60,
+            row: None,
+            color: None,
+            bounce: false,
+        }
+    }
+}
+
+
+/// Turns text into the characters shown in a single row.
+pub(crate) fn prepare_text(text: &str) -> Vec<char> {
+    let lines: Vec<String> = text.lines()
+        .map(|line| clean_line(line).trim().to_owned())
+        .filter(|line| !line.is_empty())
+        .collect();
+    lines.join(LINE_SEPARATOR).chars()
+        .filter(|c| c.width() == Some(1))
+        .collect()
+}
+
+
+fn read_text(path: &Path) -> io::Result<Vec<char>> {
+    let text = fs::read_to_string(path)?;
+    Ok(prepare_text(&text))
+}
+
+
+#[derive(Debug)]
+pub(crate) struct Marquee {
+    params: Params,
+    text: Vec<char>,
+
+    /// Text read from the file that is yet to be shown, from the next pass on.
+    next_text: Option<Vec<char>>,
+
+    /// How many steps of the current pass have been shown.
+    step: u64,
+
+    /// How long the animation has been running, in milliseconds.
+    now_ms: u64,
+
+    /// When to read the file again, in milliseconds since the start.
+    next_read_ms: u64,
+
+    /// What is on the client's screen.
+    shown: Option<Canvas>,
+}
+impl Marquee {
+    fn new(params: Params, text: Vec<char>) -> Self {
+        let next_read_ms = params.reread_secs.saturating_mul(1000);
+        Self {
+            params,
+            text,
+            next_text: None,
+            step: 0,
+            now_ms: 0,
+            next_read_ms,
+            shown: None,
+        }
+    }
+
+    /// Reads the file again if it is time to.
+    fn reread(&mut self) {
+        let path = match &self.params.file {
+            Some(p) if self.now_ms >= self.next_read_ms => p,
+            _ => return,
+        };
+        match read_text(path) {
+            Ok(text) => self.next_text = Some(text),
+            Err(e) => warn!("failed to read marquee text from {}, keeping the previous text: {}", path.display(), e),
+        }
+        self.next_read_ms = self.now_ms.saturating_add(self.params.reread_secs.saturating_mul(1000).max(1));
+    }
+
+    /// The number of steps in a pass of the text over a screen of the given width, and the column
+    /// of the text's first character at the given step.
+    fn pass(&self, width: i64, step: u64) -> (u64, i64) {
+        let length = self.text.len() as i64;
+        if self.params.bounce {
+            // there and back again
+            let range = (width - length).abs();
+            if range == 0 {
+                return (1, 0);
+            }
+            let position = (step % (2 * range) as u64) as i64;
+            let distance = if position <= range { position } else { 2 * range - position };
+            let column = if length <= width { distance } else { -distance };
+            (2 * range as u64, column)
+        } else {
+            // in from the right, out to the left, from the first character entering the screen to
+            // the last one leaving it
+            let steps = (width + length - 1).max(1) as u64;
+            (steps, width - 1 - (step % steps) as i64)
+        }
+    }
+}
+impl Animation for Marquee {
+    fn next_frame(&mut self, session: &SessionInfo) -> Option<Frame> {
+        let size = session.window_size.unwrap_or(INFO.size);
+        self.reread();
+
+        let starts_cycle = self.step == 0;
+        if starts_cycle {
+            if let Some(text) = self.next_text.take() {
+                self.text = text;
+            }
+        }
+
+        let width = i64::from(size.0);
+        let (steps, column) = self.pass(width, self.step);
+        let row = self.params.row.unwrap_or(size.1 / 2);
+        let mut canvas = Canvas::new(size);
+        for (offset, c) in self.text.iter().enumerate() {
+            canvas.set(column + offset as i64, row.into(), (*c, self.params.color));
+        }
+        let commands = canvas.draw_over(self.shown.as_ref());
+        self.shown = Some(canvas);
+
+        self.step = (self.step + 1) % steps;
+        self.now_ms = self.now_ms.saturating_add(self.params.frame_ms);
+        let mut frame = Frame::new(commands, Duration::from_millis(self.params.frame_ms));
+        frame.starts_cycle = starts_cycle;
+        Some(frame)
+    }
+}
@@ -1,3 +1,363 @@
+pub(crate) mod ansi_art;
+pub(crate) mod asciimation;
+pub(crate) mod bonus;
+pub(crate) mod bouncing_ball;
+pub(crate) mod canvas;
+pub(crate) mod chatwall;
+pub(crate) mod clock;
+pub(crate) mod composite;
+pub(crate) mod deck;
+pub(crate) mod figlet;
+pub(crate) mod fire;
+pub(crate) mod gif;
+pub(crate) mod guestbook;
 pub(crate) mod lollercoaster;
 pub(crate) mod lollerskates;
+pub(crate) mod mandelbrot;
+pub(crate) mod marquee;
+pub(crate) mod raster;
 pub(crate) mod roflcopter;
+pub(crate) mod roflpilot;
+pub(crate) mod script;
+pub(crate) mod sine_scroller;
+pub(crate) mod sl;
+pub(crate) mod slideshow;
+pub(crate) mod snake;
+pub(crate) mod tetris;
+pub(crate) mod wasm;
+pub(crate) mod weather;
+#[cfg(test)]
+mod snapshots;
+
+
+use std::fmt;
+use std::io;
+use std::net::SocketAddr;
+use std::path::PathBuf;
+use std::time::Duration;
+
+use schemars::{JsonSchema, schema_for};
+use schemars::gen::SchemaGenerator;
+use schemars::schema::{InstanceType, Metadata, RootSchema, Schema, SchemaObject};
+use serde::{Deserialize, Serialize};
+
+use crate::telnet::{Event, SessionInfo};
+
+
+/// A single frame of an animation.
+#[derive(Clone, Debug, Eq, Hash, Ord, PartialEq, PartialOrd)]
+pub(crate) struct Frame {
+    /// The text and escape sequences to output.
+    pub commands: String,
+
+    /// How long to wait after outputting this frame.
+    pub delay: Duration,
+
+    /// Whether this frame is the first of a cycle of the animation (e.g. the first frame after the
+    /// coaster has been reset).
+    pub starts_cycle: bool,
+}
+impl Frame {
+    pub fn new<C: Into<String>>(commands: C, delay: Duration) -> Self {
+        Self {
+            commands: commands.into(),
+            delay,
+            starts_cycle: false,
+        }
+    }
+
+    /// Marks this frame as the first of a cycle.
+    pub fn starting_cycle(mut self) -> Self {
+        self.starts_cycle = true;
+        self
+    }
+}
+
+
+/// An animation, producing a sequence of frames.
+pub(crate) trait Animation: Send {
+    /// Returns the next frame of the animation, or `None` if the animation is over.
+    ///
+    /// The session information can be used to adapt the output to the client.
+    fn next_frame(&mut self, session: &SessionInfo) -> Option<Frame>;
+
+    /// Processes input from the client; returns whether the animation has made use of it.
+    ///
+    /// Most animations are not interactive and ignore all input.
+    fn handle_event(&mut self, _event: &Event) -> bool {
+        false
+    }
+}
+
+
+/// A color that can be configured for an animation.
+#[derive(Clone, Copy, Debug, Deserialize, Eq, Hash, JsonSchema, Ord, PartialEq, PartialOrd, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub(crate) enum Color {
+    Black,
+    Red,
+    Green,
+    Yellow,
+    Blue,
+    Magenta,
+    Cyan,
+    White,
+    BrightBlack,
+    BrightRed,
+    BrightGreen,
+    BrightYellow,
+    BrightBlue,
+    BrightMagenta,
+    BrightCyan,
+    BrightWhite,
+}
+impl Color {
+    /// The escape sequence switching the foreground to this color.
+    pub fn foreground(self) -> &'static str {
+        match self {
+            Self::Black => "\x1B[30m",
+            Self::Red => "\x1B[31m",
+            Self::Green => "\x1B[32m",
+            Self::Yellow => "\x1B[33m",
+            Self::Blue => "\x1B[34m",
+            Self::Magenta => "\x1B[35m",
+            Self::Cyan => "\x1B[36m",
+            Self::White => "\x1B[37m",
+            Self::BrightBlack => "\x1B[90m",
+            Self::BrightRed => "\x1B[91m",
+            Self::BrightGreen => "\x1B[92m",
+            Self::BrightYellow => "\x1B[93m",
+            Self::BrightBlue => "\x1B[94m",
+            Self::BrightMagenta => "\x1B[95m",
+            Self::BrightCyan => "\x1B[96m",
+            Self::BrightWhite => "\x1B[97m",
+        }
+    }
+}
+
+
+/// The configuration of an animation: its name and its parameters.
+///
+/// In the configuration file, this is either just the name of the animation or a table containing
+/// the name and any parameters, e.g. `{ name = "lollercoaster", frame_ms = 30, train = "WHEE" }`.
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+#[serde(from = "AnimationConfigRepr", into = "AnimationConfigRepr")]
+pub(crate) struct AnimationConfig {
+    pub name: String,
+    pub params: toml::Table,
+}
+impl AnimationConfig {
+    /// The configuration of the animation with the given name and default parameters.
+    pub fn named<N: Into<String>>(name: N) -> Self {
+        Self { name: name.into(), params: toml::Table::new() }
+    }
+
+    /// Deserializes the parameters into the animation-specific parameter structure.
+    pub fn parse_params<P: for<'de> Deserialize<'de>>(&self) -> Result<P, CreateError> {
+        toml::Value::Table(self.params.clone()).try_into()
+            .map_err(|error| CreateError::InvalidParameters { name: self.name.clone(), error })
+    }
+}
+impl JsonSchema for AnimationConfig {
+    fn schema_name() -> String {
+        "AnimationConfig".to_owned()
+    }
+
+    fn json_schema(_gen: &mut SchemaGenerator) -> Schema {
+        SchemaObject {
+            metadata: Some(Box::new(Metadata {
+                description: Some("The name of an animation or a table containing the name and parameters.".to_owned()),
+                ..Default::default()
+            })),
+            instance_type: Some(vec![InstanceType::String, InstanceType::Object].into()),
+            ..Default::default()
+        }.into()
+    }
+}
+impl From<AnimationConfigRepr> for AnimationConfig {
+    fn from(repr: AnimationConfigRepr) -> Self {
+        match repr {
+            AnimationConfigRepr::Name(name) => Self { name, params: toml::Table::new() },
+            AnimationConfigRepr::Table { name, params } => Self { name, params },
+        }
+    }
+}
+impl From<AnimationConfig> for AnimationConfigRepr {
+    fn from(config: AnimationConfig) -> Self {
+        if config.params.is_empty() {
+            Self::Name(config.name)
+        } else {
+            Self::Table { name: config.name, params: config.params }
+        }
+    }
+}
+
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+#[serde(untagged)]
+enum AnimationConfigRepr {
+    Name(String),
+    Table {
+        name: String,
+
+        #[serde(flatten)]
+        params: toml::Table,
+    },
+}
+
+
+/// An error that may occur when creating an animation.
+#[derive(Debug)]
+#[non_exhaustive]
+pub(crate) enum CreateError {
+    #[non_exhaustive]
+    UnknownAnimation { name: String },
+
+    #[non_exhaustive]
+    InvalidParameters { name: String, error: toml::de::Error },
+
+    #[non_exhaustive]
+    LoadFailed { name: String, path: PathBuf, error: io::Error },
+
+    #[non_exhaustive]
+    NoFrames { name: String, path: PathBuf },
+}
+impl fmt::Display for CreateError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::UnknownAnimation { name }
+                => write!(f, "unknown animation {:?}", name),
+            Self::InvalidParameters { name, error }
+                => write!(f, "invalid parameters for animation {:?}: {}", name, error.message()),
+            Self::LoadFailed { name, path, error }
+                => write!(f, "failed to load {} for animation {:?}: {}", path.display(), name, error),
+            Self::NoFrames { name, path }
+                => write!(f, "{} contains no frames for animation {:?}", path.display(), name),
+        }
+    }
+}
+impl std::error::Error for CreateError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Self::UnknownAnimation { .. } => None,
+            Self::InvalidParameters { error, .. } => Some(error),
+            Self::LoadFailed { error, .. } => Some(error),
+            Self::NoFrames { .. } => None,
+        }
+    }
+}
+
+
+/// What an animation may know about where it is shown.
+#[derive(Clone, Debug, Default)]
+pub(crate) struct Context {
+    /// The address of the client, unless the animation is shown locally or to many clients at once.
+    pub client_addr: Option<SocketAddr>,
+
+    /// The chat wall shared by all clients of the socket, if the animation is shown to a client.
+    pub chat_wall: Option<chatwall::Wall>,
+
+    /// The guestbooks shared by all clients of the server, if the animation is shown to a client.
+    pub guestbooks: Option<guestbook::Guestbooks>,
+}
+
+
+/// Creates an animation from its configuration, to be shown in the given context.
+pub(crate) type CreateFn = fn(&AnimationConfig, &Context) -> Result<Box<dyn Animation>, CreateError>;
+
+
+/// Information about an animation that can be configured.
+#[derive(Clone, Copy, Debug)]
+pub(crate) struct AnimationInfo {
+    /// The name used to configure the animation.
+    pub name: &'static str,
+
+    /// A one-line description of the animation.
+    pub description: &'static str,
+
+    /// How long each frame is shown by default, in milliseconds; 0 means as fast as possible.
+    pub default_frame_ms: u64,
+
+    /// The terminal size required to show the animation, as (columns, rows).
+    pub size: (u16, u16),
+
+    /// Creates the animation from its configuration.
+    pub create: CreateFn,
+
+    /// Returns the schema of the animation's parameters.
+    pub params_schema: fn() -> RootSchema,
+
+    /// Returns the animation's default parameters.
+    pub default_params: fn() -> toml::Table,
+
+    /// Whether the animation cannot be shown with its default parameters (e.g. because it needs to
+    /// be told which files to load); such animations are left out when all animations are offered.
+    pub needs_params: bool,
+}
+
+
+/// All the animations that can be configured.
+pub(crate) const ANIMATIONS: &[AnimationInfo] = &[
+    ansi_art::INFO,
+    asciimation::INFO,
+    bouncing_ball::INFO,
+    chatwall::INFO,
+    clock::INFO,
+    composite::INFO,
+    deck::INFO,
+    figlet::INFO,
+    fire::INFO,
+    gif::INFO,
+    guestbook::INFO,
+    lollercoaster::INFO,
+    lollerskates::INFO,
+    mandelbrot::INFO,
+    marquee::INFO,
+    roflcopter::INFO,
+    roflpilot::INFO,
+    script::INFO,
+    sine_scroller::INFO,
+    sl::INFO,
+    slideshow::INFO,
+    snake::INFO,
+    tetris::INFO,
+    wasm::INFO,
+    weather::INFO,
+];
+
+
+/// Returns the schema of an animation's parameters; used for [`AnimationInfo::params_schema`].
+pub(crate) fn params_schema<P: JsonSchema>() -> RootSchema {
+    schema_for!(P)
+}
+
+/// Returns an animation's default parameters; used for [`AnimationInfo::default_params`].
+pub(crate) fn default_params<P: Default + Serialize>() -> toml::Table {
+    match toml::Value::try_from(P::default()) {
+        Ok(toml::Value::Table(table)) => table,
+        _ => panic!("parameters do not serialize into a table"),
+    }
+}
+
+
+/// Returns information about the animation with the given name, if there is one.
+pub(crate) fn info(name: &str) -> Option<&'static AnimationInfo> {
+    ANIMATIONS.iter()
+        .find(|info| info.name == name)
+}
+
+/// Creates the animation with the given name and default parameters.
+pub(crate) fn by_name(name: &str) -> Result<Box<dyn Animation>, CreateError> {
+    create(&AnimationConfig::named(name))
+}
+
+/// Creates the configured animation.
+pub(crate) fn create(config: &AnimationConfig) -> Result<Box<dyn Animation>, CreateError> {
+    create_in(config, &Context::default())
+}
+
+/// Creates the configured animation, to be shown in the given context.
+pub(crate) fn create_in(config: &AnimationConfig, context: &Context) -> Result<Box<dyn Animation>, CreateError> {
+    let info = info(&config.name)
+        .ok_or_else(|| CreateError::UnknownAnimation { name: config.name.clone() })?;
+    (info.create)(config, context)
+}
@@ -0,0 +1,262 @@
+//! Showing raster images on a terminal, as colored half blocks or as ASCII characters.
+
+
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex, Weak};
+
+use image::{ImageError, Rgb, RgbImage, RgbaImage};
+use image::imageops::{self, FilterType};
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+
+use crate::reload;
+use crate::telnet::SessionInfo;
+
+
+/// The characters used for the ASCII style, from the darkest to the brightest.
+const ASCII_RAMP: &[u8] = b" .:-=+*#%@";
+
+/// The levels of each channel in the color cube of the 256-color palette.
+const CUBE_LEVELS: [u8; 6] = [0, 95, 135, 175, 215, 255];
+
+/// Images are kept no larger than this (in pixels), which is enough to fill a terminal of 160
+/// columns and 50 rows.
+const MAX_STORED_SIZE: (u32, u32) = (160, 100);
+
+/// Images are drawn no larger than this (in columns and rows), twice the size they are kept at;
+/// beyond that, drawing takes ever more time without showing any more detail.
+const MAX_RENDERED_SIZE: (u16, u16) = (320, 100);
+
+/// How much memory decoding an image may take at most.
+pub(crate) const MAX_DECODE_ALLOCATION: u64 = 64 * 1024 * 1024;
+
+
+/// How images are turned into text.
+#[derive(Clone, Copy, Debug, Default, Deserialize, Eq, Hash, JsonSchema, Ord, PartialEq, PartialOrd, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub(crate) enum Style {
+    /// Blocks, unless the client's terminal type suggests that it cannot show colors.
+    #[default]
+    Auto,
+
+    /// Half blocks in the 256-color palette, each character cell showing two pixels.
+    Blocks,
+
+    /// Characters of increasing density, without colors, for terminals that cannot do better.
+    Ascii,
+}
+impl Style {
+    /// Decides on a style for the client.
+    pub fn for_session(self, session: &SessionInfo) -> Self {
+        match self {
+            Self::Auto => if session.is_basic_terminal() { Self::Ascii } else { Self::Blocks },
+            other => other,
+        }
+    }
+}
+
+
+/// Shares what has been decoded from files among all sessions showing it, by path; each value is
+/// only kept while it is in use, and only handed out until the files change.
+pub(crate) struct Cache<T> {
+    /// The values by path, with the generation of the files they were loaded at.
+    entries: Mutex<BTreeMap<PathBuf, (u64, Weak<T>)>>,
+}
+impl<T> Cache<T> {
+    pub const fn new() -> Self {
+        Self {
+            entries: Mutex::new(BTreeMap::new()),
+        }
+    }
+
+    /// Returns the value for the given path, loading it unless it is already in use.
+    pub fn get_or_load<F: FnOnce(&Path) -> Result<T, ImageError>>(&self, path: &Path, load: F) -> io::Result<Arc<T>> {
+        // loading with the lock held keeps sessions connecting at the same time from decoding the
+        // same file over and over
+        let mut entries = self.entries.lock().unwrap();
+        let generation = reload::generation();
+        entries.retain(|_path, (loaded_generation, value)| *loaded_generation == generation && value.strong_count() > 0);
+        if let Some(value) = entries.get(path).and_then(|(_generation, value)| value.upgrade()) {
+            return Ok(value);
+        }
+
+        let value = Arc::new(load(path).map_err(|e| match e {
+            ImageError::IoError(error) => error,
+            other => io::Error::new(io::ErrorKind::InvalidData, other),
+        })?);
+        entries.insert(path.to_owned(), (generation, Arc::downgrade(&value)));
+        Ok(value)
+    }
+}
+
+
+/// Prepares a decoded image to be kept around: composites it onto black and shrinks it to a size
+/// that is still enough for any terminal.
+pub(crate) fn prepare(image: &RgbaImage) -> RgbImage {
+    let flat = flatten(image);
+    let size = fit(flat.dimensions(), MAX_STORED_SIZE, false);
+    resize(&flat, size)
+}
+
+
+/// Composites an image with transparency onto black.
+fn flatten(image: &RgbaImage) -> RgbImage {
+    RgbImage::from_fn(image.width(), image.height(), |x, y| {
+        let [r, g, b, a] = image.get_pixel(x, y).0;
+        let blend = |c: u8| ((u16::from(c) * u16::from(a)) / 255) as u8;
+        Rgb([blend(r), blend(g), blend(b)])
+    })
+}
+
+
+/// Scales a size to fit within a maximum size, keeping the aspect ratio. Sizes that already fit
+/// are only scaled up if `enlarge` is set.
+pub(crate) fn fit(size: (u32, u32), max_size: (u32, u32), enlarge: bool) -> (u32, u32) {
+    let (width, height) = (f64::from(size.0.max(1)), f64::from(size.1.max(1)));
+    let mut scale = (f64::from(max_size.0) / width).min(f64::from(max_size.1) / height);
+    if !enlarge {
+        scale = scale.min(1.0);
+    }
+    let scaled = |length: f64, max_length: u32| ((length * scale).round() as u32).clamp(1, max_length.max(1));
+    (scaled(width, max_size.0), scaled(height, max_size.1))
+}
+
+
+/// Scales an image to a size, unless it already has that size.
+pub(crate) fn resize(image: &RgbImage, size: (u32, u32)) -> RgbImage {
+    if image.dimensions() == size {
+        image.clone()
+    } else {
+        imageops::resize(image, size.0, size.1, FilterType::Triangle)
+    }
+}
+
+
+/// Renders an image as large as fits into a terminal of the given size (up to a limit),
+/// horizontally centered.
+///
+/// The image is drawn over whatever is on the screen without clearing anything else.
+pub(crate) fn render(image: &RgbImage, cols: u16, rows: u16, style: Style) -> String {
+    render_rows(image, cols, rows, style).concat()
+}
+
+
+/// Renders an image like [`render`], but returns the commands drawing each row of the screen
+/// separately.
+pub(crate) fn render_rows(image: &RgbImage, cols: u16, rows: u16, style: Style) -> Vec<String> {
+    if cols == 0 || rows == 0 {
+        return Vec::new();
+    }
+
+    // a character cell is about twice as high as it is wide, so it takes two (square) pixels
+    let max_size = (cols.min(MAX_RENDERED_SIZE.0), rows.min(MAX_RENDERED_SIZE.1));
+    let (width, height) = fit(image.dimensions(), (u32::from(max_size.0), 2 * u32::from(max_size.1)), true);
+    let scaled = resize(image, (width, height));
+    let left = u32::from(cols).saturating_sub(width) / 2 + 1;
+
+    let mut rendered_rows = Vec::with_capacity(height.div_ceil(2) as usize);
+    for row in 0..height.div_ceil(2) {
+        let mut ret = String::new();
+        write!(ret, "\x1B[{};{}H", row + 1, left).unwrap();
+        let mut colors = None;
+        for x in 0..width {
+            let top = scaled.get_pixel(x, 2 * row).0;
+            let bottom = if 2 * row + 1 < height {
+                scaled.get_pixel(x, 2 * row + 1).0
+            } else {
+                [0, 0, 0]
+            };
+            match style {
+                Style::Auto | Style::Blocks => {
+                    let cell_colors = (palette_index(top), palette_index(bottom));
+                    if colors != Some(cell_colors) {
+                        write!(ret, "\x1B[38;5;{};48;5;{}m", cell_colors.0, cell_colors.1).unwrap();
+                        colors = Some(cell_colors);
+                    }
+                    ret.push('▀');
+                },
+                Style::Ascii => {
+                    let brightness = (luma(top) + luma(bottom)) / 2;
+                    let index = usize::from(brightness) * (ASCII_RAMP.len() - 1) / 255;
+                    ret.push(char::from(ASCII_RAMP[index]));
+                },
+            }
+        }
+        if style != Style::Ascii {
+            ret.push_str("\x1B[0m");
+        }
+        rendered_rows.push(ret);
+    }
+    rendered_rows
+}
+
+
+/// The perceived brightness of a color.
+fn luma([r, g, b]: [u8; 3]) -> u16 {
+    ((299 * u32::from(r) + 587 * u32::from(g) + 114 * u32::from(b)) / 1000) as u16
+}
+
+
+/// Finds the nearest color in the 256-color palette, leaving out the 16 system colors (whose
+/// actual colors differ between terminals).
+fn palette_index(color: [u8; 3]) -> u8 {
+    let distance = |other: [u8; 3]| -> u32 {
+        color.iter().zip(other.iter())
+            .map(|(a, b)| u32::from(a.abs_diff(*b)).pow(2))
+            .sum()
+    };
+    let nearest_level = |value: u8| -> usize {
+        (0..CUBE_LEVELS.len())
+            .min_by_key(|i| CUBE_LEVELS[*i].abs_diff(value))
+            .unwrap()
+    };
+
+    let [r, g, b] = color.map(nearest_level);
+    let cube_color = [CUBE_LEVELS[r], CUBE_LEVELS[g], CUBE_LEVELS[b]];
+    let cube_index = 16 + 36 * r + 6 * g + b;
+
+    // the gray ramp goes from 8 to 238 in steps of 10
+    let average = (color.iter().map(|c| u16::from(*c)).sum::<u16>() / 3) as u8;
+    let gray_step = (average.saturating_sub(3) / 10).min(23);
+    let gray = 8 + 10 * gray_step;
+    let gray_index = 232 + usize::from(gray_step);
+
+    if distance([gray, gray, gray]) < distance(cube_color) {
+        gray_index as u8
+    } else {
+        cube_index as u8
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn render_sizes() {
+        let image = RgbImage::from_pixel(16, 8, Rgb([255, 255, 255]));
+
+        // images are scaled up to fill the terminal
+        let rows = render_rows(&image, 32, 24, Style::Ascii);
+        assert_eq!(rows.len(), 8);
+        assert_eq!(rows[0], format!("\x1B[1;1H{}", "@".repeat(32)));
+
+        // narrower images are centered
+        let rows = render_rows(&image, 40, 4, Style::Ascii);
+        assert_eq!(rows.len(), 4);
+        assert_eq!(rows[0], format!("\x1B[1;13H{}", "@".repeat(16)));
+
+        // nothing fits into nothing
+        assert!(render_rows(&image, 0, 24, Style::Blocks).is_empty());
+        assert!(render_rows(&image, 80, 0, Style::Blocks).is_empty());
+
+        // huge terminals get an image of limited size
+        let rows = render_rows(&image, u16::MAX, u16::MAX, Style::Ascii);
+        assert_eq!(rows.len(), 80);
+        assert_eq!(rows[0], format!("\x1B[1;{}H{}", (u32::from(u16::MAX) - 320) / 2 + 1, "@".repeat(320)));
+    }
+}
@@ -1,11 +1,10 @@
-use std::net::SocketAddr;
-use std::sync::Arc;
+use std::time::Duration;
 
-use tokio::io::BufWriter;
-use tokio::net::tcp::OwnedWriteHalf;
-use tokio::sync::Mutex;
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
 
-use crate::telnet;
+use crate::animations::{self, Animation, AnimationInfo, Color, Frame};
+use crate::telnet::SessionInfo;
 
 
 const ROFLCOPTER_BASE: &str = concat!(
@@ -20,78 +19,135 @@ const ROFLCOPTER_BASE: &str = concat!(
 );
 
 
-async fn base_frame(writer: &mut BufWriter<OwnedWriteHalf>, addr: SocketAddr) -> Result<(), telnet::Error> {
+fn base_frame() -> String {
+    let mut ret = String::new();
+
     // clear screen
-    telnet::write_all(writer, addr, b"\x1B[2J").await?;
+    ret.push_str("\x1B[2J");
 
     // go to top left
-    telnet::write_all(writer, addr, b"\x1B[H").await?;
+    ret.push_str("\x1B[H");
 
     // output roflcopter
-    telnet::write_all(writer, addr, ROFLCOPTER_BASE.as_bytes()).await?;
+    ret.push_str(ROFLCOPTER_BASE);
 
-    telnet::flush(writer, addr).await
+    ret
 }
 
-async fn frame0(writer: &mut BufWriter<OwnedWriteHalf>, addr: SocketAddr) -> Result<(), telnet::Error> {
+fn frame0() -> String {
+    let mut ret = String::new();
+
     // remove upper rotors
     // => top left
-    telnet::write_all(writer, addr, b"\x1B[H").await?;
+    ret.push_str("\x1B[H");
     // => space over
-    telnet::write_all(writer, addr, b"     ").await?;
+    ret.push_str("     ");
     // => top right
-    telnet::write_all(writer, addr, b"\x1B[1;19H").await?;
+    ret.push_str("\x1B[1;19H");
     // => space over
-    telnet::write_all(writer, addr, b"     ").await?;
+    ret.push_str("     ");
 
     // vertical blades
-    telnet::write_all(writer, addr, b"\x1B[3;2H").await?;
-    telnet::write_all(writer, addr, b" L ").await?;
-    telnet::write_all(writer, addr, b"\x1B[4;2H").await?;
-    telnet::write_all(writer, addr, b" O ").await?;
-    telnet::write_all(writer, addr, b"\x1B[5;2H").await?;
-    telnet::write_all(writer, addr, b" L ").await?;
-
-    telnet::flush(writer, addr).await
+    ret.push_str("\x1B[3;2H");
+    ret.push_str(" L ");
+    ret.push_str("\x1B[4;2H");
+    ret.push_str(" O ");
+    ret.push_str("\x1B[5;2H");
+    ret.push_str(" L ");
+
+    ret
 }
 
-async fn frame1(writer: &mut BufWriter<OwnedWriteHalf>, addr: SocketAddr) -> Result<(), telnet::Error> {
+fn frame1() -> String {
+    let mut ret = String::new();
+
     // add upper rotors
     // => top left
-    telnet::write_all(writer, addr, b"\x1B[H").await?;
+    ret.push_str("\x1B[H");
     // => space over
-    telnet::write_all(writer, addr, b"ROFL:").await?;
+    ret.push_str("ROFL:");
     // => top right
-    telnet::write_all(writer, addr, b"\x1B[1;19H").await?;
+    ret.push_str("\x1B[1;19H");
     // => space over
-    telnet::write_all(writer, addr, b":ROFL").await?;
+    ret.push_str(":ROFL");
 
     // horizontal blades
-    telnet::write_all(writer, addr, b"\x1B[3;2H").await?;
-    telnet::write_all(writer, addr, b"   ").await?;
-    telnet::write_all(writer, addr, b"\x1B[4;2H").await?;
-    telnet::write_all(writer, addr, b"LOL").await?;
-    telnet::write_all(writer, addr, b"\x1B[5;2H").await?;
-    telnet::write_all(writer, addr, b"   ").await?;
-
-    telnet::flush(writer, addr).await
+    ret.push_str("\x1B[3;2H");
+    ret.push_str("   ");
+    ret.push_str("\x1B[4;2H");
+    ret.push_str("LOL");
+    ret.push_str("\x1B[5;2H");
+    ret.push_str("   ");
+
+    ret
+}
+
+
+pub(crate) const INFO: AnimationInfo = AnimationInfo {
+    name: "roflcopter",
+    description: "A helicopter whose rotors are made of ROFLs and LOLs.",
+    default_frame_ms: 0,
+    size: (23, 8),
+    create: |config, _context| Ok(Box::new(Roflcopter::new(config.parse_params()?))),
+    params_schema: animations::params_schema::<Params>,
+    default_params: animations::default_params::<Params>,
+    needs_params: false,
+};
+
+
+/// Parameters of the roflcopter animation.
+#[derive(Clone, Debug, Default, Deserialize, Eq, Hash, JsonSchema, Ord, PartialEq, PartialOrd, Serialize)]
+#[serde(default, deny_unknown_fields)]
+pub(crate) struct Params {
+    /// How long each frame is shown, in milliseconds. The default of 0 spins the rotors as fast as
+    /// the connection allows.
+    pub frame_ms: u64,
+
+    /// The color of the roflcopter.
+    pub color: Option<Color>,
 }
 
-pub(crate) async fn run(writer: Arc<Mutex<BufWriter<OwnedWriteHalf>>>, addr: SocketAddr) -> Result<(), telnet::Error> {
-    {
-        let mut writer_guard = writer.lock().await;
-        base_frame(&mut *writer_guard, addr).await?;
-    }
 
-    loop {
-        {
-            let mut writer_guard = writer.lock().await;
-            frame0(&mut *writer_guard, addr).await?;
+#[derive(Clone, Debug, Default, Eq, Hash, Ord, PartialEq, PartialOrd)]
+pub(crate) struct Roflcopter {
+    params: Params,
+    frame_index: usize,
+    cycle_complete: bool,
+}
+impl Roflcopter {
+    pub fn new(params: Params) -> Self {
+        Self {
+            params,
+            frame_index: 0,
+            cycle_complete: false,
+        }
+    }
+}
+impl Animation for Roflcopter {
+    fn next_frame(&mut self, _session: &SessionInfo) -> Option<Frame> {
+        let mut commands = String::new();
+        if let Some(color) = self.params.color {
+            commands.push_str(color.foreground());
         }
 
-        {
-            let mut writer_guard = writer.lock().await;
-            frame1(&mut *writer_guard, addr).await?;
+        if self.frame_index == 0 {
+            commands.push_str(&base_frame());
+        } else if self.frame_index % 2 == 1 {
+            commands.push_str(&frame0());
+        } else {
+            commands.push_str(&frame1());
+        }
+        let starts_cycle = self.frame_index == 0 || self.cycle_complete;
+        self.cycle_complete = false;
+        self.frame_index += 1;
+        if self.frame_index > 2 {
+            // skip the base frame
+            self.frame_index = 1;
+            self.cycle_complete = true;
         }
+
+        let mut frame = Frame::new(commands, Duration::from_millis(self.params.frame_ms));
+        frame.starts_cycle = starts_cycle;
+        Some(frame)
     }
 }
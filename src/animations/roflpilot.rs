@@ -0,0 +1,193 @@
+use std::fmt::Write;
+use std::time::Duration;
+
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+
+use crate::animations::{self, Animation, AnimationInfo, Color, Frame};
+use crate::keys::Key;
+use crate::telnet::{Event, SessionInfo};
+
+
+/// The roflcopter with its rotors in the first position.
+const ROFLCOPTER_VERTICAL: [&str; 8] = [
+    "     ROFL:LOL:ROFL     ",
+    "           ^",
+    "  L  /-----------",
+    "  O ===       [] \\",
+    "  L    \\          \\",
+    "        \\__________]",
+    "            I   I",
+    "         -----------/",
+];
+
+/// The roflcopter with its rotors in the second position.
+const ROFLCOPTER_HORIZONTAL: [&str; 8] = [
+    "ROFL:ROFL:LOL:ROFL:ROFL",
+    "           ^",
+    "     /-----------",
+    " LOL===       [] \\",
+    "       \\          \\",
+    "        \\__________]",
+    "            I   I",
+    "         -----------/",
+];
+
+const ROFLCOPTER_WIDTH: u16 = 23;
+const ROFLCOPTER_HEIGHT: u16 = 8;
+
+/// How many columns the roflcopter moves per press of Left or Right; terminal cells are about
+/// twice as high as they are wide.
+const HORIZONTAL_STEP: u16 = 2;
+
+
+pub(crate) const INFO: AnimationInfo = AnimationInfo {
+    name: "roflpilot",
+    description: "A roflcopter you can fly around using the arrow keys.",
+    default_frame_ms: 100,
+    size: (ROFLCOPTER_WIDTH, ROFLCOPTER_HEIGHT + 1),
+    create: |config, _context| Ok(Box::new(Roflpilot::new(config.parse_params()?))),
+    params_schema: animations::params_schema::<Params>,
+    default_params: animations::default_params::<Params>,
+    needs_params: false,
+};
+
+
+/// Parameters of the roflpilot animation.
+#[derive(Clone, Debug, Deserialize, Eq, Hash, JsonSchema, Ord, PartialEq, PartialOrd, Serialize)]
+#[serde(default, deny_unknown_fields)]
+pub(crate) struct Params {
+    /// How long each frame is shown, in milliseconds.
+    pub frame_ms: u64,
+
+    /// The color of the roflcopter.
+    pub color: Option<Color>,
+}
+impl Default for Params {
+    fn default() -> Self {
+        Self {
+            frame_ms: INFO.default_frame_ms,
+            color: None,
+        }
+    }
+}
+
+
+/// A roflcopter piloted by the client.
+///
+/// The roflcopter can go anywhere within the client's terminal, except for the bottom line, which
+/// shows the altitude.
+#[derive(Clone, Debug, Default, Eq, Hash, Ord, PartialEq, PartialOrd)]
+pub(crate) struct Roflpilot {
+    params: Params,
+    frame_index: usize,
+
+    /// The terminal size as of the last frame, as (columns, rows).
+    screen_size: (u16, u16),
+
+    /// The position of the top left corner of the roflcopter as (column, row), both zero-based,
+    /// or `None` if it is yet to be placed.
+    position: Option<(u16, u16)>,
+
+    /// Whether the roflcopter has moved since the last frame, meaning that the screen must be
+    /// cleared.
+    moved: bool,
+}
+impl Roflpilot {
+    pub fn new(params: Params) -> Self {
+        Self {
+            params,
+            frame_index: 0,
+            screen_size: (80, 24),
+            position: None,
+            moved: false,
+        }
+    }
+
+    /// The furthest the roflcopter may go to the right and to the bottom, as (column, row).
+    fn max_position(&self) -> (u16, u16) {
+        let (cols, rows) = self.screen_size;
+        (
+            cols.saturating_sub(ROFLCOPTER_WIDTH),
+            // keep the bottom line free for the altitude
+            rows.saturating_sub(ROFLCOPTER_HEIGHT + 1),
+        )
+    }
+
+    fn move_to(&mut self, column: u16, row: u16) {
+        let (max_column, max_row) = self.max_position();
+        let new_position = Some((column.min(max_column), row.min(max_row)));
+        if new_position != self.position {
+            self.position = new_position;
+            self.moved = true;
+        }
+    }
+}
+impl Animation for Roflpilot {
+    fn next_frame(&mut self, session: &SessionInfo) -> Option<Frame> {
+        let mut commands = String::new();
+
+        self.screen_size = session.window_size.unwrap_or((80, 24));
+        let (column, row) = match self.position {
+            // the terminal may have become smaller
+            Some((column, row)) => (column, row),
+            None => {
+                // start out at the bottom center
+                let (max_column, max_row) = self.max_position();
+                (max_column / 2, max_row)
+            },
+        };
+        self.move_to(column, row);
+        let (column, row) = self.position.unwrap();
+
+        if self.frame_index == 0 || self.moved {
+            // clear screen
+            commands.push_str("\x1B[2J");
+            self.moved = false;
+        }
+        if let Some(color) = self.params.color {
+            commands.push_str(color.foreground());
+        }
+
+        // a cycle is a full turn of the rotors
+        let starts_cycle = self.frame_index.is_multiple_of(2);
+        let picture = if starts_cycle {
+            ROFLCOPTER_HORIZONTAL
+        } else {
+            ROFLCOPTER_VERTICAL
+        };
+        for (i, line) in picture.iter().enumerate() {
+            // escape sequences count from 1
+            write!(commands, "\x1B[{};{}H{}", row + 1 + i as u16, column + 1, line).unwrap();
+        }
+
+        // altitude in the bottom line
+        let (_max_column, max_row) = self.max_position();
+        write!(
+            commands,
+            "\x1B[0m\x1B[{};1H\x1B[2KALT {:>3}  (arrow keys to fly)",
+            self.screen_size.1, max_row - row,
+        ).unwrap();
+
+        self.frame_index += 1;
+
+        let mut frame = Frame::new(commands, Duration::from_millis(self.params.frame_ms));
+        frame.starts_cycle = starts_cycle;
+        Some(frame)
+    }
+
+    fn handle_event(&mut self, event: &Event) -> bool {
+        let (column, row) = match self.position {
+            Some(p) => p,
+            None => return false,
+        };
+        match event {
+            Event::Key(Key::Up) => self.move_to(column, row.saturating_sub(1)),
+            Event::Key(Key::Down) => self.move_to(column, row.saturating_add(1)),
+            Event::Key(Key::Left) => self.move_to(column.saturating_sub(HORIZONTAL_STEP), row),
+            Event::Key(Key::Right) => self.move_to(column.saturating_add(HORIZONTAL_STEP), row),
+            _ => return false,
+        }
+        true
+    }
+}
@@ -0,0 +1,298 @@
+//! Animations written as Rhai scripts, so that animations can be contributed without touching the
+//! Rust code.
+//!
+//! A script defines a `frame()` function, called for each frame, which draws onto a canvas the
+//! size of the client's terminal using the functions below; only what has changed since the
+//! previous frame is sent to the client. The value returned by `frame()` is how long the frame is
+//! shown in milliseconds (by default, `frame_ms`). A script may also define `init()`, called once
+//! before the first frame, and `key(name)`, called with the name of each key pressed by the client
+//! (`up`, `down`, `left`, `right`, `home`, `end`, `enter` or the character typed), which returns
+//! whether it has made use of the key. The functions keep their state in the properties of
+//! `this`, e.g. `this.x += 1`.
+//!
+//! * `width()` and `height()`: the size of the canvas
+//! * `elapsed_ms()`: how long the animation has been running
+//! * `clear()`: empties the canvas
+//! * `put(x, y, text)`: writes text at the given column and row, counting from 0
+//! * `color(name)`: sets the color of the text written from now on (e.g. `"bright_red"`);
+//!   `color()` goes back to the terminal's default
+//! * `new_cycle()`: marks the frame as the beginning of another cycle of the animation
+//! * `stop()`: ends the animation after this frame
+
+
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use log::{debug, warn};
+use rhai::{AST, CallFnOptions, Dynamic, Engine, EvalAltResult, ImmutableString, Scope};
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use serde::de::IntoDeserializer;
+
+use crate::animations::{self, Animation, AnimationInfo, Color, CreateError, Frame};
+use crate::animations::canvas::Canvas;
+use crate::keys::Key;
+use crate::telnet::{Event, SessionInfo};
+
+
+/// How many operations a single call of a script function may perform, so that a script stuck in
+/// a loop cannot hold up the server.
+const MAX_OPERATIONS: u64 = 1_000_000;
+
+/// How deeply script functions may call each other.
+const MAX_CALL_LEVELS: usize = 32;
+
+/// The longest string a script may build, in bytes.
+const MAX_STRING_SIZE: usize = 64 * 1024;
+
+/// The most elements an array or object map built by a script may have.
+const MAX_COLLECTION_SIZE: usize = 64 * 1024;
+
+
+pub(crate) const INFO: AnimationInfo = AnimationInfo {
+    name: "script",
+    description: "An animation written as a Rhai script.",
+    default_frame_ms: 100,
+    size: (80, 24),
+    create: |config, _context| {
+        let params: Params = config.parse_params()?;
+        let script = ScriptAnimation::load(params)
+            .map_err(|(path, error)| CreateError::LoadFailed { name: config.name.clone(), path, error })?;
+        Ok(Box::new(script))
+    },
+    params_schema: animations::params_schema::<Params>,
+    default_params: animations::default_params::<Params>,
+    needs_params: true,
+};
+
+
+/// Parameters of the script animation.
+#[derive(Clone, Debug, Deserialize, Eq, Hash, JsonSchema, Ord, PartialEq, PartialOrd, Serialize)]
+#[serde(default, deny_unknown_fields)]
+pub(crate) struct Params {
+    /// How long each frame is shown, in milliseconds, unless the script's `frame()` function
+    /// returns a duration of its own.
+    pub frame_ms: u64,
+
+    /// The Rhai script. Relative paths are resolved against the working directory.
+    pub path: PathBuf,
+}
+impl Default for Params {
+    fn default() -> Self {
+        Self {
+            frame_ms: INFO.default_frame_ms,
+            path: PathBuf::from("animation.rhai"),
+        }
+    }
+}
+
+
+/// What the functions called by the script work on.
+#[derive(Debug)]
+struct Drawing {
+    canvas: Canvas,
+    color: Option<Color>,
+    elapsed_ms: u64,
+    starts_cycle: bool,
+    over: bool,
+}
+
+
+/// Creates an engine whose functions draw onto the given drawing.
+fn make_engine(drawing: &Arc<Mutex<Drawing>>, path: &Path) -> Engine {
+    let mut engine = Engine::new();
+    engine.set_max_operations(MAX_OPERATIONS);
+    engine.set_max_call_levels(MAX_CALL_LEVELS);
+    engine.set_max_string_size(MAX_STRING_SIZE);
+    engine.set_max_array_size(MAX_COLLECTION_SIZE);
+    engine.set_max_map_size(MAX_COLLECTION_SIZE);
+
+    // the server's standard output is no place for the script's
+    let print_path = path.display().to_string();
+    engine.on_print(move |text| debug!("{}: {}", print_path, text));
+    let debug_path = path.display().to_string();
+    engine.on_debug(move |text, _source, position| debug!("{} {}: {}", debug_path, position, text));
+
+    let d = Arc::clone(drawing);
+    engine.register_fn("width", move || i64::from(d.lock().unwrap().canvas.size().0));
+    let d = Arc::clone(drawing);
+    engine.register_fn("height", move || i64::from(d.lock().unwrap().canvas.size().1));
+    let d = Arc::clone(drawing);
+    engine.register_fn("elapsed_ms", move || i64::try_from(d.lock().unwrap().elapsed_ms).unwrap_or(i64::MAX));
+    let d = Arc::clone(drawing);
+    engine.register_fn("clear", move || d.lock().unwrap().canvas.clear());
+    let d = Arc::clone(drawing);
+    engine.register_fn("put", move |x: i64, y: i64, text: ImmutableString| {
+        let mut drawing = d.lock().unwrap();
+        let color = drawing.color;
+        drawing.canvas.put(x, y, &text, color);
+    });
+    let d = Arc::clone(drawing);
+    engine.register_fn("color", move |name: ImmutableString| -> Result<(), Box<EvalAltResult>> {
+        let color = Color::deserialize(name.as_str().into_deserializer())
+            .map_err(|_: serde::de::value::Error| format!("unknown color {:?}", name.as_str()))?;
+        d.lock().unwrap().color = Some(color);
+        Ok(())
+    });
+    let d = Arc::clone(drawing);
+    engine.register_fn("color", move || d.lock().unwrap().color = None);
+    let d = Arc::clone(drawing);
+    engine.register_fn("new_cycle", move || d.lock().unwrap().starts_cycle = true);
+    let d = Arc::clone(drawing);
+    engine.register_fn("stop", move || d.lock().unwrap().over = true);
+    engine
+}
+
+
+/// The name passed to the script's `key()` function for an event, if it is a key press.
+fn key_name(event: &Event) -> Option<String> {
+    let name = match event {
+        Event::Key(Key::Up) => "up",
+        Event::Key(Key::Down) => "down",
+        Event::Key(Key::Left) => "left",
+        Event::Key(Key::Right) => "right",
+        Event::Key(Key::Home) => "home",
+        Event::Key(Key::End) => "end",
+        Event::Newline => "enter",
+        Event::Data(b) if b.is_ascii_graphic() || *b == b' ' => return Some(char::from(*b).to_string()),
+        _ => return None,
+    };
+    Some(name.to_owned())
+}
+
+
+pub(crate) struct ScriptAnimation {
+    params: Params,
+    engine: Engine,
+    ast: AST,
+    scope: Scope<'static>,
+
+    /// The object the script's functions see as `this`.
+    state: Dynamic,
+
+    drawing: Arc<Mutex<Drawing>>,
+    has_init: bool,
+    has_key: bool,
+    initialized: bool,
+
+    /// What is on the client's screen.
+    shown: Option<Canvas>,
+}
+impl ScriptAnimation {
+    /// Loads and compiles the script; on failure, returns the path of the script along with the
+    /// error.
+    fn load(params: Params) -> Result<Self, (PathBuf, io::Error)> {
+        let fail = |error| (params.path.clone(), error);
+        let source = fs::read_to_string(&params.path)
+            .map_err(fail)?;
+
+        let drawing = Arc::new(Mutex::new(Drawing {
+            canvas: Canvas::new(INFO.size),
+            color: None,
+            elapsed_ms: 0,
+            starts_cycle: false,
+            over: false,
+        }));
+        let engine = make_engine(&drawing, &params.path);
+        let ast = engine.compile(&source)
+            .map_err(|e| fail(io::Error::new(io::ErrorKind::InvalidData, e.to_string())))?;
+        let has_function = |name: &str, arity: usize| ast.iter_functions()
+            .any(|f| f.name == name && f.params.len() == arity);
+        if !has_function("frame", 0) {
+            return Err(fail(io::Error::new(io::ErrorKind::InvalidData, "the script has no frame() function")));
+        }
+        let has_init = has_function("init", 0);
+        let has_key = has_function("key", 1);
+
+        Ok(Self {
+            params,
+            engine,
+            ast,
+            scope: Scope::new(),
+            state: Dynamic::from_map(rhai::Map::new()),
+            drawing,
+            has_init,
+            has_key,
+            initialized: false,
+            shown: None,
+        })
+    }
+
+    /// Calls a function of the script.
+    fn call(&mut self, name: &str, args: impl rhai::FuncArgs) -> Result<Dynamic, Box<EvalAltResult>> {
+        let options = CallFnOptions::new()
+            .eval_ast(false)
+            .bind_this_ptr(&mut self.state);
+        self.engine.call_fn_with_options(options, &mut self.scope, &self.ast, name, args)
+    }
+
+    /// Runs the script's top-level statements and its `init()` function.
+    fn initialize(&mut self) -> Result<(), Box<EvalAltResult>> {
+        self.engine.run_ast_with_scope(&mut self.scope, &self.ast)?;
+        if self.has_init {
+            let _ = self.call("init", ())?;
+        }
+        Ok(())
+    }
+}
+impl Animation for ScriptAnimation {
+    fn next_frame(&mut self, session: &SessionInfo) -> Option<Frame> {
+        let size = session.window_size.unwrap_or(INFO.size);
+        {
+            let mut drawing = self.drawing.lock().unwrap();
+            if drawing.over {
+                return None;
+            }
+            if drawing.canvas.size() != size {
+                drawing.canvas = Canvas::new(size);
+            }
+            drawing.starts_cycle = !self.initialized;
+        }
+
+        let result = if self.initialized {
+            self.call("frame", ())
+        } else {
+            self.initialized = true;
+            self.initialize().and_then(|()| self.call("frame", ()))
+        };
+        let delay_ms = match result {
+            Ok(value) => value.as_int().ok()
+                .and_then(|ms| u64::try_from(ms).ok())
+                .unwrap_or(self.params.frame_ms),
+            Err(e) => {
+                warn!("script {} failed: {}", self.params.path.display(), e);
+                return None;
+            },
+        };
+
+        let mut drawing = self.drawing.lock().unwrap();
+        let commands = drawing.canvas.draw_over(self.shown.as_ref());
+        self.shown = Some(drawing.canvas.clone());
+        drawing.elapsed_ms = drawing.elapsed_ms.saturating_add(delay_ms);
+
+        let mut frame = Frame::new(commands, Duration::from_millis(delay_ms));
+        frame.starts_cycle = drawing.starts_cycle;
+        Some(frame)
+    }
+
+    fn handle_event(&mut self, event: &Event) -> bool {
+        if !self.has_key || !self.initialized {
+            return false;
+        }
+        let name = match key_name(event) {
+            Some(n) => n,
+            None => return false,
+        };
+        match self.call("key", (name,)) {
+            Ok(used) => used.as_bool().unwrap_or(false),
+            Err(e) => {
+                warn!("script {} failed: {}", self.params.path.display(), e);
+                self.drawing.lock().unwrap().over = true;
+                true
+            },
+        }
+    }
+}
@@ -0,0 +1,135 @@
+//! Text scrolling across the screen on a sine wave in the colors of the rainbow, as in the demos
+//! of old.
+
+
+use std::f64::consts::TAU;
+use std::time::Duration;
+
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+
+use crate::animations::{self, Animation, AnimationInfo, Color, Frame};
+use crate::animations::canvas::Canvas;
+use crate::animations::marquee::prepare_text;
+use crate::telnet::SessionInfo;
+
+
+pub(crate) const INFO: AnimationInfo = AnimationInfo {
+    name: "sine_scroller",
+    description: "Rainbow text scrolling across the screen on a sine wave.",
+    default_frame_ms: 60,
+    size: (80, 24),
+    create: |config, _context| Ok(Box::new(SineScroller::new(config.parse_params()?))),
+    params_schema: animations::params_schema::<Params>,
+    default_params: animations::default_params::<Params>,
+    needs_params: false,
+};
+
+
+/// Parameters of the sine scroller.
+#[derive(Clone, Debug, Deserialize, Eq, Hash, JsonSchema, Ord, PartialEq, PartialOrd, Serialize)]
+#[serde(default, deny_unknown_fields)]
+pub(crate) struct Params {
+    /// How long each frame is shown, in milliseconds.
+    pub frame_ms: u64,
+
+    /// The text to scroll. Line breaks are replaced by a few spaces.
+    pub text: String,
+
+    /// How many columns the text moves with each frame.
+    pub speed: u16,
+
+    /// How many rows the wave rises above and sinks below its middle.
+    pub amplitude: u16,
+
+    /// How many full waves fit across the screen.
+    pub frequency: u16,
+
+    /// The row in the middle of the wave, counting from 0; by default, the middle row.
+    pub row: Option<u16>,
+
+    /// The colors the characters take on in turn.
+    pub colors: Vec<Color>,
+}
+impl Default for Params {
+    fn default() -> Self {
+        Self {
+            frame_ms: INFO.default_frame_ms,
+            text: "*** Greetings from telnet-animations! ***".to_owned(),
+            speed: 1,
+            amplitude: 4,
+            frequency: 3,
+            row: None,
+            colors: vec![
+                Color::BrightRed, Color::BrightYellow, Color::BrightGreen,
+                Color::BrightCyan, Color::BrightBlue, Color::BrightMagenta,
+            ],
+        }
+    }
+}
+
+
+#[derive(Debug)]
+pub(crate) struct SineScroller {
+    params: Params,
+    text: Vec<char>,
+
+    /// How many steps of the current pass have been shown.
+    step: u64,
+
+    /// What is on the client's screen.
+    shown: Option<Canvas>,
+}
+impl SineScroller {
+    fn new(params: Params) -> Self {
+        let text = prepare_text(&params.text);
+        Self {
+            params,
+            text,
+            step: 0,
+            shown: None,
+        }
+    }
+}
+impl Animation for SineScroller {
+    fn next_frame(&mut self, session: &SessionInfo) -> Option<Frame> {
+        let size = session.window_size.unwrap_or(INFO.size);
+        let width = i64::from(size.0);
+        let length = self.text.len() as i64;
+        let speed = i64::from(self.params.speed.max(1));
+
+        // in from the right, out to the left, from the first character entering the screen to the
+        // last one leaving it
+        let steps = ((width + length - 1).max(1) as u64).div_ceil(speed as u64);
+        let column = width - 1 - self.step as i64 * speed;
+        let middle = f64::from(self.params.row.unwrap_or(size.1 / 2));
+        let amplitude = f64::from(self.params.amplitude);
+        let frequency = f64::from(self.params.frequency);
+
+        let mut canvas = Canvas::new(size);
+        for (offset, c) in self.text.iter().enumerate() {
+            let col = column + offset as i64;
+            if col < 0 || col >= width {
+                continue;
+            }
+
+            // the wave stays put while the characters ride it
+            let angle = TAU * frequency * col as f64 / width as f64;
+            let row = (middle - amplitude * angle.sin()).round() as i64;
+            let color = if self.params.colors.is_empty() {
+                None
+            } else {
+                Some(self.params.colors[offset % self.params.colors.len()])
+            };
+            canvas.set(col, row, (*c, color));
+        }
+        let commands = canvas.draw_over(self.shown.as_ref());
+        self.shown = Some(canvas);
+
+        let starts_cycle = self.step == 0;
+        self.step = (self.step + 1) % steps;
+        let mut frame = Frame::new(commands, Duration::from_millis(self.params.frame_ms));
+        frame.starts_cycle = starts_cycle;
+        Some(frame)
+    }
+}
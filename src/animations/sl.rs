@@ -0,0 +1,156 @@
+//! A steam locomotive crossing the screen, in the spirit of the `sl` command that punishes typing
+//! `ls` too quickly.
+
+
+use std::time::Duration;
+
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+
+use crate::animations::{self, Animation, AnimationInfo, Color, Frame};
+use crate::animations::canvas::Canvas;
+use crate::telnet::SessionInfo;
+
+
+/// The locomotive, driving to the left; each `W` is the hub of a wheel.
+const LOCOMOTIVE: [&str; 8] = [
+    "     _____",
+    "     |   |               ______________",
+    "  ___|   |______________|  __________  |",
+    " |  ______    ______    | |          | |",
+    " | |______|  |______|   | |__________| |",
+    " |_____________________________________|",
+    "  \\__  _____  _____  _____  ______  ___|",
+    " <___(W)___(W)___(W)___(W)____(W)___(W)|",
+];
+
+/// The column of the chimney within the locomotive.
+const CHIMNEY_COLUMN: i64 = 7;
+
+/// The spokes of the wheels as they turn.
+const SPOKES: [char; 4] = ['-', '\\', '|', '/'];
+
+/// The shapes of a puff of smoke as it gets older, centered on where it was puffed out.
+const PUFFS: [&str; 8] = ["o", "oO", "(O)", "(  )", "( )( )", "(    )", "(  ) ( )", "(      )"];
+
+/// How many frames pass between two puffs of smoke.
+const PUFF_INTERVAL: u64 = 4;
+
+/// How many rows above the locomotive the smoke rises at most.
+const SMOKE_ROWS: i64 = 5;
+
+
+pub(crate) const INFO: AnimationInfo = AnimationInfo {
+    name: "sl",
+    description: "A steam locomotive puffing across the screen.",
+    default_frame_ms: 40,
+    size: (80, 24),
+    create: |config, _context| Ok(Box::new(Sl::new(config.parse_params()?))),
+    params_schema: animations::params_schema::<Params>,
+    default_params: animations::default_params::<Params>,
+    needs_params: false,
+};
+
+
+/// Parameters of the steam locomotive.
+#[derive(Clone, Debug, Deserialize, Eq, Hash, JsonSchema, Ord, PartialEq, PartialOrd, Serialize)]
+#[serde(default, deny_unknown_fields)]
+pub(crate) struct Params {
+    /// How long it takes the locomotive to move by one column, in milliseconds.
+    pub frame_ms: u64,
+
+    /// The color of the locomotive.
+    pub color: Option<Color>,
+
+    /// The color of the smoke.
+    pub smoke_color: Option<Color>,
+}
+impl Default for Params {
+    fn default() -> Self {
+        Self {
+            frame_ms: INFO.default_frame_ms,
+            color: None,
+            smoke_color: None,
+        }
+    }
+}
+
+
+/// A puff of smoke, staying where it was puffed out while rising and spreading.
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+struct Puff {
+    column: i64,
+
+    /// How many frames ago it was puffed out.
+    age: usize,
+}
+
+
+#[derive(Clone, Debug)]
+pub(crate) struct Sl {
+    params: Params,
+
+    /// How many frames of the current run across the screen have been shown.
+    step: u64,
+
+    puffs: Vec<Puff>,
+
+    /// What is on the client's screen.
+    shown: Option<Canvas>,
+}
+impl Sl {
+    pub fn new(params: Params) -> Self {
+        Self {
+            params,
+            step: 0,
+            puffs: Vec::new(),
+            shown: None,
+        }
+    }
+}
+impl Animation for Sl {
+    fn next_frame(&mut self, session: &SessionInfo) -> Option<Frame> {
+        let size = session.window_size.unwrap_or(INFO.size);
+        let locomotive_width = LOCOMOTIVE.iter().map(|row| row.len()).max().unwrap_or(0) as i64;
+        let locomotive_height = LOCOMOTIVE.len() as i64;
+
+        // in from the right edge, honoring the width of the terminal
+        let left = i64::from(size.0) - 1 - self.step as i64;
+        let top = ((i64::from(size.1) - locomotive_height - SMOKE_ROWS) / 2).max(0) + SMOKE_ROWS;
+
+        self.puffs.retain_mut(|puff| {
+            puff.age += 1;
+            puff.age < PUFFS.len() * 2
+        });
+        if self.step.is_multiple_of(PUFF_INTERVAL) && left + locomotive_width > 0 {
+            self.puffs.push(Puff { column: left + CHIMNEY_COLUMN, age: 0 });
+        }
+
+        let mut canvas = Canvas::new(size);
+        for puff in &self.puffs {
+            let shape = PUFFS[(puff.age / 2).min(PUFFS.len() - 1)];
+            let rise = (puff.age as i64 / 2).min(SMOKE_ROWS - 1) + 1;
+            let column = puff.column - shape.len() as i64 / 2;
+            canvas.put(column, top - rise, shape, self.params.smoke_color);
+        }
+        let spoke = SPOKES[(self.step as usize) % SPOKES.len()].to_string();
+        for (row_offset, row) in LOCOMOTIVE.iter().enumerate() {
+            let row = row.replace('W', &spoke);
+            canvas.put(left, top + row_offset as i64, &row, self.params.color);
+        }
+        let commands = canvas.draw_over(self.shown.as_ref());
+        self.shown = Some(canvas);
+
+        // start over once the locomotive and its smoke are gone
+        let starts_cycle = self.step == 0;
+        if left + locomotive_width <= 0 && self.puffs.is_empty() {
+            self.step = 0;
+        } else {
+            self.step += 1;
+        }
+
+        let mut frame = Frame::new(commands, Duration::from_millis(self.params.frame_ms));
+        frame.starts_cycle = starts_cycle;
+        Some(frame)
+    }
+}
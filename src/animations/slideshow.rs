@@ -0,0 +1,295 @@
+//! Still images (PNG or JPEG) shown one after the other, with a transition in between.
+
+
+use std::fmt::Write;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::Duration;
+
+use image::{ImageError, ImageReader, Limits, RgbImage};
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use serde::de::Error as _;
+
+use crate::animations::{self, Animation, AnimationInfo, CreateError, Frame};
+use crate::animations::raster::{self, Cache, Style};
+use crate::telnet::SessionInfo;
+
+
+/// The extensions of the files picked up by patterns.
+const IMAGE_EXTENSIONS: [&str; 3] = ["jpeg", "jpg", "png"];
+
+
+/// The commands drawing each row of an image on the screen.
+type RenderedImage = Vec<String>;
+
+
+/// The images that have been decoded, shared among the sessions showing them.
+static DECODED: Cache<RgbImage> = Cache::new();
+
+
+pub(crate) const INFO: AnimationInfo = AnimationInfo {
+    name: "slideshow",
+    description: "Still images (PNG or JPEG), drawn with colored blocks or ASCII characters.",
+    default_frame_ms: 40,
+    size: (80, 24),
+    create: |config, _context| {
+        let params: Params = config.parse_params()?;
+        if params.images.is_empty() {
+            let error = toml::de::Error::custom("at least one image is required");
+            return Err(CreateError::InvalidParameters { name: config.name.clone(), error });
+        }
+
+        let mut images = Vec::new();
+        for pattern in &params.images {
+            let paths = expand_pattern(pattern)
+                .map_err(|error| CreateError::LoadFailed { name: config.name.clone(), path: pattern.clone(), error })?;
+            if paths.is_empty() {
+                return Err(CreateError::NoFrames { name: config.name.clone(), path: pattern.clone() });
+            }
+            for path in paths {
+                let image = DECODED.get_or_load(&path, decode)
+                    .map_err(|error| CreateError::LoadFailed { name: config.name.clone(), path: path.clone(), error })?;
+                images.push(image);
+            }
+        }
+        Ok(Box::new(Slideshow::new(params, images)))
+    },
+    params_schema: animations::params_schema::<Params>,
+    default_params: animations::default_params::<Params>,
+    needs_params: true,
+};
+
+
+/// Parameters of the slideshow.
+#[derive(Clone, Debug, Deserialize, Eq, Hash, JsonSchema, Ord, PartialEq, PartialOrd, Serialize)]
+#[serde(default, deny_unknown_fields)]
+pub(crate) struct Params {
+    /// How long each step of a transition is shown, in milliseconds.
+    pub frame_ms: u64,
+
+    /// The images, shown in this order. Each entry is either the path of an image or a pattern
+    /// (containing `*`, `?` or `[`) matching any number of them, which are shown in the order of
+    /// their names; patterns only pick up files ending in .png, .jpg or .jpeg. Relative paths are
+    /// resolved against the working directory.
+    pub images: Vec<PathBuf>,
+
+    /// How long each image stays on the screen, in milliseconds.
+    pub dwell_ms: u64,
+
+    /// How one image gives way to the next.
+    pub transition: Transition,
+
+    /// How long a transition takes, in milliseconds.
+    pub transition_ms: u64,
+
+    /// How the images are drawn.
+    pub style: Style,
+}
+impl Params {
+    /// The paths that have to stay readable for the images to be shown: the images named
+    /// explicitly and the directories that patterns are matched within.
+    pub fn read_paths(&self) -> Vec<PathBuf> {
+        self.images.iter()
+            .map(|pattern| {
+                if !is_pattern(pattern) {
+                    return pattern.clone();
+                }
+                let fixed: PathBuf = pattern.components()
+                    .take_while(|component| !is_pattern(Path::new(component)))
+                    .collect();
+                if fixed.as_os_str().is_empty() { PathBuf::from(".") } else { fixed }
+            })
+            .collect()
+    }
+}
+impl Default for Params {
+    fn default() -> Self {
+        Self {
+            frame_ms: INFO.default_frame_ms,
+            images: vec![PathBuf::from("slides/*")],
+            dwell_ms: 5000,
+            transition: Transition::default(),
+            transition_ms: 500,
+            style: Style::default(),
+        }
+    }
+}
+
+
+/// How one image of a slideshow gives way to the next.
+#[derive(Clone, Copy, Debug, Default, Deserialize, Eq, Hash, JsonSchema, Ord, PartialEq, PartialOrd, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub(crate) enum Transition {
+    /// The next image replaces the previous one at once.
+    Cut,
+
+    /// The next image is drawn over the previous one from the top down.
+    #[default]
+    Wipe,
+}
+
+
+/// Whether an entry of the image list is a pattern rather than a path.
+fn is_pattern(path: &Path) -> bool {
+    path.to_string_lossy().contains(['*', '?', '['])
+}
+
+
+/// Turns an entry of the image list into the paths of the images it stands for.
+fn expand_pattern(pattern: &Path) -> io::Result<Vec<PathBuf>> {
+    if !is_pattern(pattern) {
+        return Ok(vec![pattern.to_owned()]);
+    }
+
+    let entries = glob::glob(&pattern.to_string_lossy())
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?;
+    let mut paths = Vec::new();
+    for entry in entries {
+        let path = entry.map_err(io::Error::from)?;
+        let is_image = path.extension()
+            .is_some_and(|e| IMAGE_EXTENSIONS.iter().any(|ie| e.eq_ignore_ascii_case(ie)));
+        if is_image && path.is_file() {
+            paths.push(path);
+        }
+    }
+    paths.sort_unstable();
+    Ok(paths)
+}
+
+
+fn decode(path: &Path) -> Result<RgbImage, ImageError> {
+    let mut reader = ImageReader::open(path)?
+        .with_guessed_format()?;
+    let mut limits = Limits::default();
+    limits.max_alloc = Some(raster::MAX_DECODE_ALLOCATION);
+    reader.limits(limits);
+    Ok(raster::prepare(&reader.decode()?.to_rgba8()))
+}
+
+
+#[derive(Debug)]
+pub(crate) struct Slideshow {
+    params: Params,
+    images: Vec<Arc<RgbImage>>,
+
+    /// The index of the image to show next.
+    image_index: usize,
+
+    /// How many steps of the transition to the next image have been shown, or `None` if the screen
+    /// has to be redrawn from scratch.
+    step: Option<u64>,
+
+    /// The terminal size (columns and rows) and the style the images have been rendered for, and
+    /// the rows of each rendered image.
+    rendered: Option<((u16, u16), Style, Vec<RenderedImage>)>,
+}
+impl Slideshow {
+    fn new(params: Params, images: Vec<Arc<RgbImage>>) -> Self {
+        Self {
+            params,
+            images,
+            image_index: 0,
+            step: None,
+            rendered: None,
+        }
+    }
+
+    /// How many frames a transition takes.
+    fn transition_steps(&self) -> u64 {
+        match self.params.transition {
+            Transition::Cut => 1,
+            Transition::Wipe => (self.params.transition_ms / self.params.frame_ms.max(1)).max(1),
+        }
+    }
+}
+impl Animation for Slideshow {
+    fn next_frame(&mut self, session: &SessionInfo) -> Option<Frame> {
+        let size = session.window_size.unwrap_or((80, 24));
+        let style = self.params.style.for_session(session);
+        let up_to_date = self.rendered.as_ref()
+            .is_some_and(|(rendered_size, rendered_style, _images)| *rendered_size == size && *rendered_style == style);
+        if !up_to_date {
+            // render every image once instead of each time it is shown
+            let images = self.images.iter()
+                .map(|image| raster::render_rows(image, size.0, size.1, style))
+                .collect();
+            self.rendered = Some((size, style, images));
+            self.step = None;
+        }
+        let (_size, _style, images) = self.rendered.as_ref().unwrap();
+        let rows = &images[self.image_index];
+        let starts_cycle = self.image_index == 0 && self.step.unwrap_or(0) == 0;
+
+        let mut commands = String::new();
+        let steps = self.transition_steps();
+        let done = match self.step {
+            None => {
+                commands.push_str("\x1B[0m\x1B[2J");
+                commands.push_str(&rows.concat());
+                true
+            },
+            Some(_) if self.images.len() == 1 => {
+                // nothing to transition to; leave the image on the screen
+                true
+            },
+            Some(step) => {
+                // uncover the screen rows belonging to this step
+                let screen_rows = u64::from(size.1);
+                let start = screen_rows * step / steps;
+                let end = screen_rows * (step + 1) / steps;
+                for row in start..end {
+                    write!(commands, "\x1B[{};1H\x1B[2K", row + 1).unwrap();
+                    if let Some(image_row) = rows.get(row as usize) {
+                        commands.push_str(image_row);
+                    }
+                }
+                self.step = Some(step + 1);
+                step + 1 == steps
+            },
+        };
+
+        let delay = if done {
+            self.step = Some(0);
+            self.image_index = (self.image_index + 1) % self.images.len();
+            Duration::from_millis(self.params.dwell_ms)
+        } else {
+            Duration::from_millis(self.params.frame_ms)
+        };
+        let mut frame = Frame::new(commands, delay);
+        frame.starts_cycle = starts_cycle;
+        Some(frame)
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use image::Rgb;
+
+    #[test]
+    fn window_sizes() {
+        let images = vec![
+            Arc::new(RgbImage::from_pixel(160, 100, Rgb([255, 0, 0]))),
+            Arc::new(RgbImage::from_pixel(100, 160, Rgb([0, 0, 255]))),
+        ];
+        let params = Params {
+            transition: Transition::Wipe,
+            ..Params::default()
+        };
+        for size in [(0, 0), (0, 24), (80, 0), (1, 1), (80, 24), (1000, 1000), (u16::MAX, u16::MAX)] {
+            let mut slideshow = Slideshow::new(params.clone(), images.clone());
+            let session = SessionInfo {
+                window_size: Some(size),
+                ..SessionInfo::default()
+            };
+            for _ in 0..2 * slideshow.transition_steps() + 2 {
+                let frame = slideshow.next_frame(&session).unwrap();
+                assert!(frame.commands.len() < 4 * 1024 * 1024, "{} bytes at {:?}", frame.commands.len(), size);
+            }
+        }
+    }
+}
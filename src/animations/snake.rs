@@ -0,0 +1,405 @@
+use std::collections::VecDeque;
+use std::fmt::Write;
+use std::time::{Duration, Instant};
+
+use rand::{Rng, SeedableRng};
+use rand::rngs::SmallRng;
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+
+use crate::animations::{self, Animation, AnimationInfo, Color, Frame};
+use crate::keys::Key;
+use crate::telnet::{Event, SessionInfo};
+
+
+/// How long the snake is at the start of a game.
+const INITIAL_LENGTH: usize = 3;
+
+/// How many turns may be queued up by pressing arrow keys in quick succession.
+const MAX_QUEUED_TURNS: usize = 2;
+
+/// How much faster each piece of food makes the game, in milliseconds per step.
+const SPEEDUP_MS: u64 = 5;
+
+
+pub(crate) const INFO: AnimationInfo = AnimationInfo {
+    name: "snake",
+    description: "The classic game; steer the snake to the food using the arrow keys.",
+    default_frame_ms: 200,
+    size: (20, 10),
+    create: |config, _context| Ok(Box::new(Snake::new(config.parse_params()?))),
+    params_schema: animations::params_schema::<Params>,
+    default_params: animations::default_params::<Params>,
+    needs_params: false,
+};
+
+
+/// Parameters of the snake game.
+#[derive(Clone, Debug, Deserialize, Eq, Hash, JsonSchema, Ord, PartialEq, PartialOrd, Serialize)]
+#[serde(default, deny_unknown_fields)]
+pub(crate) struct Params {
+    /// How long each step of the snake takes at the start of a game, in milliseconds.
+    pub frame_ms: u64,
+
+    /// How long each step takes at the least, however much the snake has eaten, in
+    /// milliseconds.
+    pub min_frame_ms: u64,
+
+    /// The color of the snake.
+    pub color: Option<Color>,
+
+    /// The color of the food.
+    pub food_color: Option<Color>,
+
+    /// Seeds the placement of the food, making games reproducible; by default, each game is
+    /// different.
+    pub seed: Option<u64>,
+}
+impl Default for Params {
+    fn default() -> Self {
+        Self {
+            frame_ms: INFO.default_frame_ms,
+            min_frame_ms: 60,
+            color: Some(Color::BrightGreen),
+            food_color: Some(Color::BrightRed),
+            seed: None,
+        }
+    }
+}
+
+
+#[derive(Clone, Copy, Debug, Eq, Hash, Ord, PartialEq, PartialOrd)]
+enum Direction {
+    Up,
+    Down,
+    Left,
+    Right,
+}
+impl Direction {
+    fn opposite(self) -> Self {
+        match self {
+            Self::Up => Self::Down,
+            Self::Down => Self::Up,
+            Self::Left => Self::Right,
+            Self::Right => Self::Left,
+        }
+    }
+}
+
+
+/// A game of snake.
+///
+/// The playing field covers the client's terminal (as of the start of the game) within a border,
+/// except for the bottom line, which shows the score.
+#[derive(Clone, Debug)]
+pub(crate) struct Snake {
+    params: Params,
+    rng: SmallRng,
+
+    /// The size of the playing field (within the border) as (columns, rows).
+    field_size: (u16, u16),
+
+    /// The cells occupied by the snake as (column, row), both zero-based within the playing
+    /// field; the head comes first.
+    body: VecDeque<(u16, u16)>,
+
+    direction: Direction,
+
+    /// Turns requested by the client but not yet taken.
+    turns: VecDeque<Direction>,
+
+    food: Option<(u16, u16)>,
+    score: u64,
+    high_score: u64,
+
+    /// When the snake takes its next step, or `None` if a new game is to be started.
+    next_step_at: Option<Instant>,
+
+    /// Whether the game is over and waiting for the client to start a new one.
+    over: bool,
+}
+impl Snake {
+    pub fn new(params: Params) -> Self {
+        let rng = match params.seed {
+            Some(seed) => SmallRng::seed_from_u64(seed),
+            None => SmallRng::from_os_rng(),
+        };
+        Self {
+            params,
+            rng,
+            field_size: (0, 0),
+            body: VecDeque::new(),
+            direction: Direction::Right,
+            turns: VecDeque::new(),
+            food: None,
+            score: 0,
+            high_score: 0,
+            next_step_at: None,
+            over: false,
+        }
+    }
+
+    /// How long each step takes at the current score.
+    fn step_duration(&self) -> Duration {
+        let speedup = self.score.saturating_mul(SPEEDUP_MS);
+        let ms = self.params.frame_ms.saturating_sub(speedup).max(self.params.min_frame_ms);
+        Duration::from_millis(ms)
+    }
+
+    /// Sets up a new game, returning the commands drawing it.
+    fn start_game(&mut self, session: &SessionInfo) -> String {
+        let (cols, rows) = session.window_size.unwrap_or((80, 24));
+        // leave room for the border and the score
+        self.field_size = (cols.saturating_sub(2).max(1), rows.saturating_sub(3).max(1));
+
+        // start out in the middle, heading right
+        let (width, height) = self.field_size;
+        let head = (width / 2, height / 2);
+        self.body.clear();
+        self.turns.clear();
+        self.food = None;
+        if usize::from(head.0) + 1 < INITIAL_LENGTH {
+            // the snake does not fit; wait for the client to make room
+            self.over = true;
+            return "\x1B[0m\x1B[2J\x1B[1;1HTerminal too small; press Enter to try again".to_owned();
+        }
+        for i in 0..INITIAL_LENGTH {
+            self.body.push_back((head.0.saturating_sub(i as u16), head.1));
+        }
+        self.direction = Direction::Right;
+        self.score = 0;
+        self.over = false;
+
+        let mut commands = String::new();
+
+        // reset attributes, clear screen, then the border
+        commands.push_str("\x1B[0m\x1B[2J");
+        let horizontal = "#".repeat(usize::from(width) + 2);
+        write!(commands, "\x1B[1;1H{}", horizontal).unwrap();
+        for row in 0..height {
+            write!(commands, "\x1B[{};1H#\x1B[{};{}H#", row + 2, row + 2, width + 2).unwrap();
+        }
+        write!(commands, "\x1B[{};1H{}", height + 2, horizontal).unwrap();
+
+        if let Some(color) = self.params.color {
+            commands.push_str(color.foreground());
+        }
+        for (i, &cell) in self.body.iter().enumerate() {
+            draw_cell(&mut commands, cell, if i == 0 { '@' } else { 'o' });
+        }
+        commands.push_str("\x1B[0m");
+        self.place_food(&mut commands);
+        self.draw_score(&mut commands);
+        commands
+    }
+
+    /// Puts food on a random free cell; if there is none, the game is won.
+    fn place_food(&mut self, commands: &mut String) {
+        let (width, height) = self.field_size;
+        let free_cells = usize::from(width) * usize::from(height) - self.body.len();
+        if free_cells == 0 {
+            self.food = None;
+            return;
+        }
+
+        // pick the n-th free cell
+        let mut n = self.rng.random_range(0..free_cells);
+        for row in 0..height {
+            for column in 0..width {
+                if self.body.contains(&(column, row)) {
+                    continue;
+                }
+                if n == 0 {
+                    self.food = Some((column, row));
+                    if let Some(color) = self.params.food_color {
+                        commands.push_str(color.foreground());
+                    }
+                    draw_cell(commands, (column, row), '*');
+                    commands.push_str("\x1B[0m");
+                    return;
+                }
+                n -= 1;
+            }
+        }
+    }
+
+    fn draw_score(&self, commands: &mut String) {
+        let status_row = self.field_size.1 + 3;
+        write!(
+            commands,
+            "\x1B[0m\x1B[{};1H\x1B[2KScore: {}  High score: {}  (arrow keys to steer)",
+            status_row, self.score, self.high_score,
+        ).unwrap();
+    }
+
+    /// Moves the snake by one cell, returning the commands drawing the change.
+    fn step(&mut self) -> String {
+        let mut commands = String::new();
+
+        while let Some(turn) = self.turns.pop_front() {
+            if turn != self.direction && turn != self.direction.opposite() {
+                self.direction = turn;
+                break;
+            }
+        }
+
+        let (width, height) = self.field_size;
+        let (column, row) = self.body[0];
+        let new_head = match self.direction {
+            Direction::Up => row.checked_sub(1).map(|r| (column, r)),
+            Direction::Down => Some(row + 1).filter(|&r| r < height).map(|r| (column, r)),
+            Direction::Left => column.checked_sub(1).map(|c| (c, row)),
+            Direction::Right => Some(column + 1).filter(|&c| c < width).map(|c| (c, row)),
+        };
+        let eats = new_head.is_some() && new_head == self.food;
+        if !eats {
+            // the tail moves out of the way before the head arrives
+            let tail = self.body.pop_back().unwrap();
+            draw_cell(&mut commands, tail, ' ');
+        }
+        let new_head = match new_head {
+            Some(h) if !self.body.contains(&h) => h,
+            _ => {
+                self.game_over(&mut commands, "GAME OVER");
+                return commands;
+            },
+        };
+
+        if let Some(color) = self.params.color {
+            commands.push_str(color.foreground());
+        }
+        draw_cell(&mut commands, self.body[0], 'o');
+        draw_cell(&mut commands, new_head, '@');
+        commands.push_str("\x1B[0m");
+        self.body.push_front(new_head);
+
+        if eats {
+            self.score += 1;
+            self.high_score = self.high_score.max(self.score);
+            self.place_food(&mut commands);
+            self.draw_score(&mut commands);
+            if self.food.is_none() {
+                self.game_over(&mut commands, "YOU WIN");
+            }
+        }
+        commands
+    }
+
+    fn game_over(&mut self, commands: &mut String, headline: &str) {
+        self.over = true;
+        self.high_score = self.high_score.max(self.score);
+
+        let lines = [
+            headline.to_owned(),
+            format!("Score: {}", self.score),
+            "Press Enter to play again".to_owned(),
+        ];
+        let (width, height) = self.field_size;
+        let top = (height + 2).saturating_sub(lines.len() as u16) / 2 + 1;
+        for (i, line) in lines.iter().enumerate() {
+            let column = (width + 2).saturating_sub(line.len() as u16) / 2 + 1;
+            write!(commands, "\x1B[{};{}H\x1B[7m{}\x1B[0m", top + i as u16, column, line).unwrap();
+        }
+        self.draw_score(commands);
+    }
+}
+impl Animation for Snake {
+    fn next_frame(&mut self, session: &SessionInfo) -> Option<Frame> {
+        let now = Instant::now();
+        let next_step_at = match self.next_step_at {
+            Some(at) => at,
+            None => {
+                let commands = self.start_game(session);
+                let delay = self.step_duration();
+                self.next_step_at = Some(now + delay);
+                return Some(Frame::new(commands, delay).starting_cycle());
+            },
+        };
+        if self.over {
+            // wait for the client
+            return Some(Frame::new("", Duration::from_millis(self.params.frame_ms)));
+        }
+        if now < next_step_at {
+            // woken up early by input; steps happen at their own pace
+            return Some(Frame::new("", next_step_at - now));
+        }
+
+        let commands = self.step();
+        let delay = self.step_duration();
+        self.next_step_at = Some(now + delay);
+        Some(Frame::new(commands, delay))
+    }
+
+    fn handle_event(&mut self, event: &Event) -> bool {
+        if self.over {
+            if let Event::Newline = event {
+                // start a new game with the next frame
+                self.next_step_at = None;
+                return true;
+            }
+            return false;
+        }
+
+        let turn = match event {
+            Event::Key(Key::Up) => Direction::Up,
+            Event::Key(Key::Down) => Direction::Down,
+            Event::Key(Key::Left) => Direction::Left,
+            Event::Key(Key::Right) => Direction::Right,
+            _ => return false,
+        };
+        if self.turns.len() < MAX_QUEUED_TURNS {
+            self.turns.push_back(turn);
+        }
+        true
+    }
+}
+
+
+/// Outputs a character in a cell of the playing field.
+fn draw_cell(commands: &mut String, (column, row): (u16, u16), c: char) {
+    // escape sequences count from 1, and the border comes first
+    write!(commands, "\x1B[{};{}H{}", row + 2, column + 2, c).unwrap();
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn session(size: (u16, u16)) -> SessionInfo {
+        SessionInfo {
+            window_size: Some(size),
+            ..SessionInfo::default()
+        }
+    }
+
+    #[test]
+    fn tiny_fields() {
+        let params = Params {
+            seed: Some(1),
+            ..Params::default()
+        };
+        for size in [(0, 0), (1, 1), (4, 4), (5, 24)] {
+            let mut snake = Snake::new(params.clone());
+            let commands = snake.start_game(&session(size));
+            assert!(snake.over, "{:?}", size);
+            assert!(commands.contains("Terminal too small"), "{:?}", size);
+
+            // trying again at the same size changes nothing
+            assert!(snake.handle_event(&Event::Newline));
+            snake.next_frame(&session(size)).unwrap();
+            assert!(snake.over, "{:?}", size);
+        }
+
+        // the smallest field the snake fits into: four columns, one row
+        let mut snake = Snake::new(params);
+        snake.start_game(&session((5, 4)));
+        assert!(snake.over);
+        snake.start_game(&session((6, 4)));
+        assert!(!snake.over);
+        assert_eq!(snake.field_size, (4, 1));
+        while !snake.over {
+            snake.step();
+        }
+    }
+}
@@ -0,0 +1,88 @@
+//! Compares the screens of animations, as rendered by a virtual terminal, to checked-in snapshots.
+//!
+//! After an intentional change to an animation, run the tests with `UPDATE_SNAPSHOTS=1` to rewrite
+//! the snapshots, then review the differences before committing them.
+
+
+use std::path::PathBuf;
+
+use crate::animations;
+use crate::export;
+
+
+/// Renders the first `frame_count` frames of the animation and compares them to its snapshot.
+fn check_snapshot(name: &str, frame_count: usize) {
+    let mut animation = animations::by_name(name)
+        .expect("failed to create animation");
+    let mut rendered = Vec::new();
+    export::write_screens(&mut rendered, &mut *animation, 80, 24, frame_count)
+        .expect("failed to render animation");
+    let rendered = String::from_utf8(rendered)
+        .expect("rendered screens are not UTF-8");
+
+    let path: PathBuf = [env!("CARGO_MANIFEST_DIR"), "src", "animations", "snapshots", &format!("{}.txt", name)]
+        .iter()
+        .collect();
+    if std::env::var_os("UPDATE_SNAPSHOTS").is_some() {
+        std::fs::write(&path, &rendered)
+            .expect("failed to write snapshot");
+        return;
+    }
+    let expected = std::fs::read_to_string(&path)
+        .unwrap_or_else(|e| panic!("failed to read {} (run with UPDATE_SNAPSHOTS=1 to create it): {}", path.display(), e));
+    if rendered != expected {
+        // point out the first difference; the whole screens are too much to take in
+        let (index, (rendered_line, expected_line)) = rendered.lines()
+            .zip(expected.lines())
+            .enumerate()
+            .find(|(_i, (r, e))| r != e)
+            .unwrap_or((rendered.lines().count().min(expected.lines().count()), ("<end>", "<end>")));
+        panic!(
+            "{} differs from {} at line {}:\n  rendered: {:?}\n  expected: {:?}",
+            name, path.display(), index + 1, rendered_line, expected_line,
+        );
+    }
+}
+
+
+#[test]
+fn composite() {
+    // long enough for the roflcopter to fly onto the screen
+    check_snapshot("composite", 40);
+}
+
+#[test]
+fn figlet() {
+    check_snapshot("figlet", 12);
+}
+
+#[test]
+fn lollercoaster() {
+    // the car takes a while to get going
+    check_snapshot("lollercoaster", 30);
+}
+
+#[test]
+fn lollerskates() {
+    check_snapshot("lollerskates", 12);
+}
+
+#[test]
+fn marquee() {
+    check_snapshot("marquee", 12);
+}
+
+#[test]
+fn roflcopter() {
+    check_snapshot("roflcopter", 12);
+}
+
+#[test]
+fn sine_scroller() {
+    check_snapshot("sine_scroller", 30);
+}
+
+#[test]
+fn sl() {
+    check_snapshot("sl", 50);
+}
@@ -0,0 +1,507 @@
+//! A bot playing Tetris, like a game showing off in attract mode.
+
+
+use std::time::Duration;
+
+use rand::{Rng, SeedableRng};
+use rand::rngs::SmallRng;
+use rand::seq::SliceRandom;
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+
+use crate::animations::{self, Animation, AnimationInfo, Color, Frame};
+use crate::animations::canvas::{Canvas, Cell};
+use crate::telnet::SessionInfo;
+
+
+/// The width of the well, in blocks.
+const WELL_WIDTH: usize = 10;
+
+/// The height of the well, in blocks.
+const WELL_HEIGHT: usize = 20;
+
+/// How many character cells wide each block is, to make up for cells being taller than they are
+/// wide.
+const BLOCK_WIDTH: i64 = 2;
+
+/// How many columns separate the well from the preview of the next piece.
+const SIDEBAR_GAP: i64 = 3;
+
+/// How wide the preview of the next piece and the statistics below it are.
+const SIDEBAR_WIDTH: i64 = 8;
+
+/// How many frames cleared lines flash before they vanish.
+const FLASH_FRAMES: u8 = 6;
+
+/// How long "GAME OVER" is shown before the next game starts.
+const GAME_OVER_MS: u64 = 3000;
+
+
+pub(crate) const INFO: AnimationInfo = AnimationInfo {
+    name: "tetris",
+    description: "A bot playing Tetris.",
+    default_frame_ms: 100,
+    size: (80, 24),
+    create: |config, _context| Ok(Box::new(Tetris::new(config.parse_params()?))),
+    params_schema: animations::params_schema::<Params>,
+    default_params: animations::default_params::<Params>,
+    needs_params: false,
+};
+
+
+/// Parameters of the Tetris bot.
+#[derive(Clone, Debug, Deserialize, Eq, Hash, JsonSchema, Ord, PartialEq, PartialOrd, Serialize)]
+#[serde(default, deny_unknown_fields)]
+pub(crate) struct Params {
+    /// How long each step of a falling piece takes, in milliseconds.
+    pub frame_ms: u64,
+
+    /// How often the bot puts a piece in the best place it can find, in percent; otherwise, it
+    /// picks a place at random, so that games come to an end sooner or later.
+    pub skill_percent: u8,
+
+    /// Draw the pieces as letters instead of colored blocks; by default, this is only done if the
+    /// client's terminal type suggests that it can show neither colors nor blocks.
+    pub letters: Option<bool>,
+
+    /// Seeds the order of the pieces and the mistakes of the bot, making games reproducible; by
+    /// default, each game is different.
+    pub seed: Option<u64>,
+}
+impl Default for Params {
+    fn default() -> Self {
+        Self {
+            frame_ms: INFO.default_frame_ms,
+            skill_percent: 95,
+            letters: None,
+            seed: None,
+        }
+    }
+}
+
+
+/// The seven tetrominoes.
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+enum Kind {
+    I,
+    O,
+    T,
+    S,
+    Z,
+    J,
+    L,
+}
+impl Kind {
+    const ALL: [Kind; 7] = [Kind::I, Kind::O, Kind::T, Kind::S, Kind::Z, Kind::J, Kind::L];
+
+    /// The blocks of the piece before it is rotated, as columns and rows within a square box, and
+    /// the size of that box.
+    fn shape(self) -> ([(i64, i64); 4], i64) {
+        match self {
+            Kind::I => ([(0, 1), (1, 1), (2, 1), (3, 1)], 4),
+            Kind::O => ([(0, 0), (1, 0), (0, 1), (1, 1)], 2),
+            Kind::T => ([(1, 0), (0, 1), (1, 1), (2, 1)], 3),
+            Kind::S => ([(1, 0), (2, 0), (0, 1), (1, 1)], 3),
+            Kind::Z => ([(0, 0), (1, 0), (1, 1), (2, 1)], 3),
+            Kind::J => ([(0, 0), (0, 1), (1, 1), (2, 1)], 3),
+            Kind::L => ([(2, 0), (0, 1), (1, 1), (2, 1)], 3),
+        }
+    }
+
+    fn letter(self) -> char {
+        match self {
+            Kind::I => 'I',
+            Kind::O => 'O',
+            Kind::T => 'T',
+            Kind::S => 'S',
+            Kind::Z => 'Z',
+            Kind::J => 'J',
+            Kind::L => 'L',
+        }
+    }
+
+    fn color(self) -> Color {
+        match self {
+            Kind::I => Color::BrightCyan,
+            Kind::O => Color::BrightYellow,
+            Kind::T => Color::BrightMagenta,
+            Kind::S => Color::BrightGreen,
+            Kind::Z => Color::BrightRed,
+            Kind::J => Color::BrightBlue,
+            Kind::L => Color::Yellow,
+        }
+    }
+}
+
+
+/// A piece in the well.
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+struct Piece {
+    kind: Kind,
+
+    /// How many times the piece has been turned clockwise, from 0 to 3.
+    rotation: u8,
+
+    /// The column and row of the top left corner of the piece's box.
+    column: i64,
+    row: i64,
+}
+impl Piece {
+    fn spawn(kind: Kind) -> Self {
+        let (_blocks, size) = kind.shape();
+        Self {
+            kind,
+            rotation: 0,
+            column: (WELL_WIDTH as i64 - size) / 2,
+            row: 0,
+        }
+    }
+
+    /// The columns and rows of the blocks of the piece in the well.
+    fn blocks(&self) -> [(i64, i64); 4] {
+        let (mut blocks, size) = self.kind.shape();
+        for _ in 0..self.rotation {
+            for block in &mut blocks {
+                *block = (size - 1 - block.1, block.0);
+            }
+        }
+        blocks.map(|(column, row)| (self.column + column, self.row + row))
+    }
+}
+
+
+/// The blocks that have come to rest in the well, by row and column.
+type Well = [[Option<Kind>; WELL_WIDTH]; WELL_HEIGHT];
+
+
+fn fits(well: &Well, piece: &Piece) -> bool {
+    piece.blocks().iter().all(|&(column, row)| {
+        (0..WELL_WIDTH as i64).contains(&column)
+            && (0..WELL_HEIGHT as i64).contains(&row)
+            && well[row as usize][column as usize].is_none()
+    })
+}
+
+
+/// Moves the piece down as far as it goes.
+fn dropped(well: &Well, mut piece: Piece) -> Piece {
+    loop {
+        let below = Piece { row: piece.row + 1, ..piece };
+        if !fits(well, &below) {
+            return piece;
+        }
+        piece = below;
+    }
+}
+
+
+fn lock(well: &mut Well, piece: &Piece) {
+    for (column, row) in piece.blocks() {
+        well[row as usize][column as usize] = Some(piece.kind);
+    }
+}
+
+
+fn full_rows(well: &Well) -> Vec<usize> {
+    (0..WELL_HEIGHT)
+        .filter(|&row| well[row].iter().all(Option::is_some))
+        .collect()
+}
+
+
+/// The well with the given rows removed and the ones above them moved down.
+fn without_rows(well: &Well, rows: &[usize]) -> Well {
+    let mut remaining = [[None; WELL_WIDTH]; WELL_HEIGHT];
+    let kept = (0..WELL_HEIGHT).rev().filter(|row| !rows.contains(row));
+    for (target, row) in (0..WELL_HEIGHT).rev().zip(kept) {
+        remaining[target] = well[row];
+    }
+    remaining
+}
+
+
+/// How good the well looks to the bot once full rows are cleared, using the weights found by
+/// Yiyuan Lee's genetic algorithm: it likes cleared lines and dislikes height, holes and bumpy
+/// surfaces.
+fn rate(well: &Well) -> f64 {
+    let rows = full_rows(well);
+    let well = without_rows(well, &rows);
+    let mut heights = [0usize; WELL_WIDTH];
+    let mut holes = 0;
+    for (column, height) in heights.iter_mut().enumerate() {
+        let top = (0..WELL_HEIGHT).find(|&row| well[row][column].is_some());
+        if let Some(top) = top {
+            *height = WELL_HEIGHT - top;
+            holes += (top..WELL_HEIGHT).filter(|&row| well[row][column].is_none()).count();
+        }
+    }
+    let aggregate_height: usize = heights.iter().sum();
+    let bumpiness: usize = heights.windows(2).map(|pair| pair[0].abs_diff(pair[1])).sum();
+    let lines = rows.len();
+
+    -0.510066 * aggregate_height as f64
+        + 0.760666 * lines as f64
+        - 0.35663 * holes as f64
+        - 0.184483 * bumpiness as f64
+}
+
+
+/// What is going on in the game.
+#[derive(Clone, Debug, Eq, PartialEq)]
+enum Phase {
+    /// A piece is falling into the well.
+    Falling(Piece),
+
+    /// Full rows are flashing; the number is how many frames remain until they vanish.
+    Clearing(Vec<usize>, u8),
+
+    /// The well has filled up.
+    GameOver,
+}
+
+
+#[derive(Clone, Debug)]
+pub(crate) struct Tetris {
+    params: Params,
+    rng: SmallRng,
+    well: Well,
+
+    /// None before the first game.
+    phase: Option<Phase>,
+
+    /// Where the bot wants to put the falling piece, as rotation and column.
+    target: (u8, i64),
+
+    next: Kind,
+
+    /// The pieces yet to come, drawn from a shuffled set of all seven.
+    bag: Vec<Kind>,
+
+    lines: u32,
+    score: u32,
+
+    /// What is on the client's screen.
+    shown: Option<Canvas>,
+}
+impl Tetris {
+    pub fn new(params: Params) -> Self {
+        let rng = match params.seed {
+            Some(seed) => SmallRng::seed_from_u64(seed),
+            None => SmallRng::from_os_rng(),
+        };
+        Self {
+            params,
+            rng,
+            well: [[None; WELL_WIDTH]; WELL_HEIGHT],
+            phase: None,
+            target: (0, 0),
+            next: Kind::I,
+            bag: Vec::new(),
+            lines: 0,
+            score: 0,
+            shown: None,
+        }
+    }
+
+    fn draw_from_bag(&mut self) -> Kind {
+        if self.bag.is_empty() {
+            self.bag.extend(Kind::ALL);
+            self.bag.shuffle(&mut self.rng);
+        }
+        self.bag.pop().unwrap_or(Kind::I)
+    }
+
+    fn new_game(&mut self) {
+        self.well = [[None; WELL_WIDTH]; WELL_HEIGHT];
+        self.bag.clear();
+        self.lines = 0;
+        self.score = 0;
+        self.next = self.draw_from_bag();
+        self.spawn();
+    }
+
+    /// Brings the next piece into the well, and decides where to put it.
+    fn spawn(&mut self) {
+        let piece = Piece::spawn(self.next);
+        self.next = self.draw_from_bag();
+        if !fits(&self.well, &piece) {
+            self.phase = Some(Phase::GameOver);
+            return;
+        }
+
+        let mut placements = Vec::new();
+        for rotation in 0..4 {
+            for column in -2..WELL_WIDTH as i64 {
+                let candidate = Piece { rotation, column, ..piece };
+                if !fits(&self.well, &candidate) {
+                    continue;
+                }
+                let mut well = self.well;
+                lock(&mut well, &dropped(&self.well, candidate));
+                placements.push(((rotation, column), rate(&well)));
+            }
+        }
+        let skilled = self.rng.random_ratio(u32::from(self.params.skill_percent.min(100)), 100);
+        let placement = if skilled || placements.is_empty() {
+            placements.iter()
+                .max_by(|(_a, a_rating), (_b, b_rating)| a_rating.total_cmp(b_rating))
+                .map(|(placement, _rating)| *placement)
+        } else {
+            Some(placements[self.rng.random_range(0..placements.len())].0)
+        };
+        self.target = placement.unwrap_or((0, piece.column));
+        self.phase = Some(Phase::Falling(piece));
+    }
+
+    /// Turns or shifts the piece towards where the bot wants it, then lets it fall by a row.
+    fn step(&mut self, mut piece: Piece) {
+        let (rotation, column) = self.target;
+        let turned = Piece { rotation: (piece.rotation + 1) % 4, ..piece };
+        let shifted = Piece { column: piece.column + (column - piece.column).signum(), ..piece };
+        if piece.rotation != rotation && fits(&self.well, &turned) {
+            piece = turned;
+        } else if piece.column != column && fits(&self.well, &shifted) {
+            piece = shifted;
+        }
+
+        let fallen = Piece { row: piece.row + 1, ..piece };
+        if fits(&self.well, &fallen) {
+            self.phase = Some(Phase::Falling(fallen));
+            return;
+        }
+
+        lock(&mut self.well, &piece);
+        let rows = full_rows(&self.well);
+        if rows.is_empty() {
+            self.spawn();
+        } else {
+            self.phase = Some(Phase::Clearing(rows, FLASH_FRAMES));
+        }
+    }
+
+    /// Removes the cleared rows and scores them.
+    fn clear(&mut self, rows: &[usize]) {
+        self.well = without_rows(&self.well, rows);
+
+        let points = match rows.len() {
+            1 => 40,
+            2 => 100,
+            3 => 300,
+            _ => 1200,
+        };
+        self.score = self.score.saturating_add(points * (self.lines / 10 + 1));
+        self.lines = self.lines.saturating_add(rows.len() as u32);
+    }
+
+    fn draw(&self, size: (u16, u16), letters: bool) -> Canvas {
+        let well_width = WELL_WIDTH as i64 * BLOCK_WIDTH + 2;
+        let total_width = well_width + SIDEBAR_GAP + SIDEBAR_WIDTH;
+        let left = ((i64::from(size.0) - total_width) / 2).max(0);
+        let top = ((i64::from(size.1) - WELL_HEIGHT as i64 - 1) / 2).max(0);
+
+        let block = |kind: Kind| -> Cell {
+            if letters {
+                (kind.letter(), None)
+            } else {
+                ('\u{2588}', Some(kind.color()))
+            }
+        };
+        let mut canvas = Canvas::new(size);
+        let put_block = |canvas: &mut Canvas, column: i64, row: i64, cell: Cell| {
+            for offset in 0..BLOCK_WIDTH {
+                canvas.set(left + 1 + column * BLOCK_WIDTH + offset, top + row, cell);
+            }
+        };
+
+        for row in 0..WELL_HEIGHT as i64 {
+            canvas.set(left, top + row, ('|', None));
+            canvas.set(left + well_width - 1, top + row, ('|', None));
+        }
+        let floor = format!("+{}+", "-".repeat((well_width - 2) as usize));
+        canvas.put(left, top + WELL_HEIGHT as i64, &floor, None);
+
+        for (row, blocks) in self.well.iter().enumerate() {
+            for (column, kind) in blocks.iter().enumerate() {
+                if let Some(kind) = kind {
+                    put_block(&mut canvas, column as i64, row as i64, block(*kind));
+                }
+            }
+        }
+        match &self.phase {
+            Some(Phase::Falling(piece)) => {
+                for (column, row) in piece.blocks() {
+                    put_block(&mut canvas, column, row, block(piece.kind));
+                }
+            },
+            Some(Phase::Clearing(rows, frames_left)) => {
+                // alternate between lit up and gone
+                let cell = match (frames_left % 2 == 0, letters) {
+                    (true, true) => ('=', None),
+                    (true, false) => ('\u{2588}', Some(Color::BrightWhite)),
+                    (false, _) => (' ', None),
+                };
+                for row in rows {
+                    for column in 0..WELL_WIDTH as i64 {
+                        put_block(&mut canvas, column, *row as i64, cell);
+                    }
+                }
+            },
+            Some(Phase::GameOver) => {
+                let message = " GAME OVER ";
+                let column = left + (well_width - message.len() as i64) / 2;
+                canvas.put(column, top + WELL_HEIGHT as i64 / 2, message, Some(Color::BrightWhite));
+            },
+            None => {},
+        }
+
+        let sidebar = left + well_width + SIDEBAR_GAP;
+        canvas.put(sidebar, top, "NEXT", None);
+        let preview = Piece { kind: self.next, rotation: 0, column: 0, row: 0 };
+        for (column, row) in preview.blocks() {
+            let cell = block(self.next);
+            for offset in 0..BLOCK_WIDTH {
+                canvas.set(sidebar + column * BLOCK_WIDTH + offset, top + 2 + row, cell);
+            }
+        }
+        canvas.put(sidebar, top + 6, "LINES", None);
+        canvas.put(sidebar, top + 7, &self.lines.to_string(), None);
+        canvas.put(sidebar, top + 9, "SCORE", None);
+        canvas.put(sidebar, top + 10, &self.score.to_string(), None);
+        canvas
+    }
+}
+impl Animation for Tetris {
+    fn next_frame(&mut self, session: &SessionInfo) -> Option<Frame> {
+        let size = session.window_size.unwrap_or(INFO.size);
+
+        let mut starts_cycle = false;
+        let mut delay_ms = self.params.frame_ms;
+        match self.phase.take() {
+            None|Some(Phase::GameOver) => {
+                starts_cycle = true;
+                self.new_game();
+            },
+            Some(Phase::Falling(piece)) => self.step(piece),
+            Some(Phase::Clearing(rows, frames_left)) => {
+                if frames_left > 1 {
+                    self.phase = Some(Phase::Clearing(rows, frames_left - 1));
+                } else {
+                    self.clear(&rows);
+                    self.spawn();
+                }
+            },
+        }
+        if self.phase == Some(Phase::GameOver) {
+            delay_ms = GAME_OVER_MS;
+        }
+
+        let letters = self.params.letters.unwrap_or_else(|| session.is_basic_terminal());
+        let canvas = self.draw(size, letters);
+        let commands = canvas.draw_over(self.shown.as_ref());
+        self.shown = Some(canvas);
+
+        let mut frame = Frame::new(commands, Duration::from_millis(delay_ms));
+        frame.starts_cycle = starts_cycle;
+        Some(frame)
+    }
+}
@@ -0,0 +1,277 @@
+//! Animations compiled to WebAssembly, so that they can be distributed as a single file.
+//!
+//! Each session runs its own instance of the module, which can only reach the functions below: no
+//! files, no network, a bounded amount of memory and a bounded number of instructions per call.
+//!
+//! The module exports:
+//!
+//! * `tick(dt_ms: i32) -> i32` (required): draws the next frame, `dt_ms` milliseconds after the
+//!   previous one (0 for the first frame), and returns how long the frame is shown in milliseconds,
+//!   or a negative number to end the animation
+//! * `on_resize(cols: i32, rows: i32)`: called before the first frame and whenever the client's
+//!   terminal changes size; the canvas is emptied beforehand
+//! * `on_key(key: i32) -> i32`: called with each key pressed by the client, as a Unicode code
+//!   point for characters, 10 for Enter, or -1 to -6 for the cursor keys up, down, left and right,
+//!   Home and End; returns nonzero if the module has made use of the key
+//! * `memory`, if it calls `put`
+//!
+//! and may import from the module `telnet_animations`:
+//!
+//! * `clear()`: empties the canvas
+//! * `put(x: i32, y: i32, text: i32, len: i32)`: writes the UTF-8 text of `len` bytes at `text` in
+//!   the module's memory at the given column and row, counting from 0
+//! * `color(color: i32)`: sets the color of the text written from now on, from 0 (black) to 7
+//!   (white) and 8 (bright black) to 15 (bright white) in the usual terminal order; any other value
+//!   goes back to the terminal's default
+//! * `new_cycle()`: marks the frame as the beginning of another cycle of the animation
+//!
+//! Only what has changed on the canvas since the previous frame is sent to the client.
+
+
+use std::fs;
+use std::io;
+use std::path::PathBuf;
+use std::time::Duration;
+
+use log::warn;
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use wasmi::{
+    Caller, Config, Engine, Error, Extern, Linker, Module, Store, StoreLimits, StoreLimitsBuilder,
+    TypedFunc,
+};
+
+use crate::animations::{self, Animation, AnimationInfo, Color, CreateError, Frame};
+use crate::animations::canvas::Canvas;
+use crate::keys::Key;
+use crate::telnet::{Event, SessionInfo};
+
+
+/// The module from which animations import the host functions.
+const HOST_MODULE: &str = "telnet_animations";
+
+/// How many instructions (roughly) a single call of an exported function may execute, so that a
+/// module stuck in a loop cannot hold up the server.
+const FUEL_PER_CALL: u64 = 1_000_000;
+
+/// How large the memory of an instance may grow, in bytes.
+const MAX_MEMORY_SIZE: usize = 16 * 1024 * 1024;
+
+/// The colors selected by the values passed to `color`.
+const COLORS: [Color; 16] = [
+    Color::Black, Color::Red, Color::Green, Color::Yellow,
+    Color::Blue, Color::Magenta, Color::Cyan, Color::White,
+    Color::BrightBlack, Color::BrightRed, Color::BrightGreen, Color::BrightYellow,
+    Color::BrightBlue, Color::BrightMagenta, Color::BrightCyan, Color::BrightWhite,
+];
+
+
+pub(crate) const INFO: AnimationInfo = AnimationInfo {
+    name: "wasm",
+    description: "An animation compiled to WebAssembly.",
+    default_frame_ms: 100,
+    size: (80, 24),
+    create: |config, _context| {
+        let params: Params = config.parse_params()?;
+        let plugin = Plugin::load(&params)
+            .map_err(|error| CreateError::LoadFailed { name: config.name.clone(), path: params.path.clone(), error })?;
+        Ok(Box::new(plugin))
+    },
+    params_schema: animations::params_schema::<Params>,
+    default_params: animations::default_params::<Params>,
+    needs_params: true,
+};
+
+
+/// Parameters of the WebAssembly animation.
+#[derive(Clone, Debug, Deserialize, Eq, Hash, JsonSchema, Ord, PartialEq, PartialOrd, Serialize)]
+#[serde(default, deny_unknown_fields)]
+pub(crate) struct Params {
+    /// The WebAssembly module. Relative paths are resolved against the working directory.
+    pub path: PathBuf,
+}
+impl Default for Params {
+    fn default() -> Self {
+        Self {
+            path: PathBuf::from("animation.wasm"),
+        }
+    }
+}
+
+
+/// What the host functions called by an instance work on.
+struct Host {
+    canvas: Canvas,
+    color: Option<Color>,
+    starts_cycle: bool,
+    limits: StoreLimits,
+}
+
+
+/// The value passed to `on_key` for an event, if it is a key press.
+fn key_code(event: &Event) -> Option<i32> {
+    match event {
+        Event::Key(Key::Up) => Some(-1),
+        Event::Key(Key::Down) => Some(-2),
+        Event::Key(Key::Left) => Some(-3),
+        Event::Key(Key::Right) => Some(-4),
+        Event::Key(Key::Home) => Some(-5),
+        Event::Key(Key::End) => Some(-6),
+        Event::Newline => Some(10),
+        Event::Data(b) if b.is_ascii_graphic() || *b == b' ' => Some(i32::from(*b)),
+        _ => None,
+    }
+}
+
+
+fn put(mut caller: Caller<'_, Host>, x: i32, y: i32, text: i32, len: i32) -> Result<(), Error> {
+    let memory = caller.get_export("memory")
+        .and_then(Extern::into_memory)
+        .ok_or_else(|| Error::new("put called by a module without memory"))?;
+    let (data, host) = memory.data_and_store_mut(&mut caller);
+    let bytes = usize::try_from(text).ok()
+        .zip(usize::try_from(len).ok())
+        .and_then(|(start, len)| data.get(start..start.checked_add(len)?))
+        .ok_or_else(|| Error::new("put called with text outside of memory"))?;
+    let color = host.color;
+    host.canvas.put(x.into(), y.into(), &String::from_utf8_lossy(bytes), color);
+    Ok(())
+}
+
+
+fn make_linker(engine: &Engine) -> Result<Linker<Host>, Error> {
+    let mut linker = Linker::new(engine);
+    linker.func_wrap(HOST_MODULE, "clear", |mut caller: Caller<'_, Host>| caller.data_mut().canvas.clear())?;
+    linker.func_wrap(HOST_MODULE, "put", put)?;
+    linker.func_wrap(HOST_MODULE, "color", |mut caller: Caller<'_, Host>, color: i32| {
+        caller.data_mut().color = usize::try_from(color).ok()
+            .and_then(|index| COLORS.get(index).copied());
+    })?;
+    linker.func_wrap(HOST_MODULE, "new_cycle", |mut caller: Caller<'_, Host>| caller.data_mut().starts_cycle = true)?;
+    Ok(linker)
+}
+
+
+pub(crate) struct Plugin {
+    path: PathBuf,
+    store: Store<Host>,
+    tick: TypedFunc<i32, i32>,
+    on_resize: Option<TypedFunc<(i32, i32), ()>>,
+    on_key: Option<TypedFunc<i32, i32>>,
+
+    /// How long the previous frame was shown, in milliseconds.
+    last_delay_ms: i32,
+
+    /// What is on the client's screen.
+    shown: Option<Canvas>,
+
+    over: bool,
+}
+impl Plugin {
+    /// Loads, checks and instantiates the module.
+    fn load(params: &Params) -> io::Result<Self> {
+        let invalid = |e: Error| io::Error::new(io::ErrorKind::InvalidData, e.to_string());
+        let bytes = fs::read(&params.path)?;
+
+        let mut config = Config::default();
+        config.consume_fuel(true);
+        let engine = Engine::new(&config);
+        let module = Module::new(&engine, &bytes)
+            .map_err(invalid)?;
+        let host = Host {
+            canvas: Canvas::new(INFO.size),
+            color: None,
+            starts_cycle: false,
+            limits: StoreLimitsBuilder::new()
+                .memory_size(MAX_MEMORY_SIZE)
+                .instances(1)
+                .build(),
+        };
+        let mut store = Store::new(&engine, host);
+        store.limiter(|host| &mut host.limits);
+        store.set_fuel(FUEL_PER_CALL)
+            .map_err(invalid)?;
+
+        let instance = make_linker(&engine)
+            .and_then(|linker| linker.instantiate_and_start(&mut store, &module))
+            .map_err(invalid)?;
+        let tick = instance.get_typed_func(&store, "tick")
+            .map_err(invalid)?;
+        let on_resize = instance.get_typed_func(&store, "on_resize").ok();
+        let on_key = instance.get_typed_func(&store, "on_key").ok();
+
+        Ok(Self {
+            path: params.path.clone(),
+            store,
+            tick,
+            on_resize,
+            on_key,
+            last_delay_ms: 0,
+            shown: None,
+            over: false,
+        })
+    }
+
+    /// Gives the instance a fresh allowance of instructions for the next call.
+    fn refuel(&mut self) -> Result<(), Error> {
+        self.store.set_fuel(FUEL_PER_CALL)
+    }
+
+    /// Lets the instance draw the next frame; returns how long it is shown in milliseconds, or
+    /// `None` if the animation is over.
+    fn tick(&mut self, size: (u16, u16)) -> Result<Option<i32>, Error> {
+        if self.store.data().canvas.size() != size || self.shown.is_none() {
+            self.store.data_mut().canvas = Canvas::new(size);
+            if let Some(on_resize) = self.on_resize {
+                self.refuel()?;
+                on_resize.call(&mut self.store, (size.0.into(), size.1.into()))?;
+            }
+        }
+        self.refuel()?;
+        let delay_ms = self.tick.call(&mut self.store, self.last_delay_ms)?;
+        Ok((delay_ms >= 0).then_some(delay_ms))
+    }
+}
+impl Animation for Plugin {
+    fn next_frame(&mut self, session: &SessionInfo) -> Option<Frame> {
+        if self.over {
+            return None;
+        }
+        let size = session.window_size.unwrap_or(INFO.size);
+        self.store.data_mut().starts_cycle = self.shown.is_none();
+        let delay_ms = match self.tick(size) {
+            Ok(Some(delay_ms)) => delay_ms,
+            Ok(None) => return None,
+            Err(e) => {
+                warn!("WebAssembly animation {} failed: {}", self.path.display(), e);
+                return None;
+            },
+        };
+        self.last_delay_ms = delay_ms;
+
+        let host = self.store.data();
+        let commands = host.canvas.draw_over(self.shown.as_ref());
+        let mut frame = Frame::new(commands, Duration::from_millis(delay_ms as u64));
+        frame.starts_cycle = host.starts_cycle;
+        self.shown = Some(host.canvas.clone());
+        Some(frame)
+    }
+
+    fn handle_event(&mut self, event: &Event) -> bool {
+        let (Some(on_key), Some(code)) = (self.on_key, key_code(event)) else {
+            return false;
+        };
+        if self.shown.is_none() {
+            // not started yet
+            return false;
+        }
+        match self.refuel().and_then(|()| on_key.call(&mut self.store, code)) {
+            Ok(used) => used != 0,
+            Err(e) => {
+                warn!("WebAssembly animation {} failed: {}", self.path.display(), e);
+                self.over = true;
+                true
+            },
+        }
+    }
+}
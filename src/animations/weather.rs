@@ -0,0 +1,783 @@
+//! The current weather at a place, as reported by Open-Meteo, with rain, snow, sunshine and so on
+//! to match.
+
+
+use std::collections::BTreeMap;
+use std::io::{self, Read, Write};
+use std::net::{SocketAddr, TcpStream, ToSocketAddrs};
+use std::sync::{Arc, LazyLock, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use log::warn;
+use rand::{Rng, SeedableRng};
+use rand::rngs::SmallRng;
+use rustls::{ClientConfig, ClientConnection, RootCertStore, StreamOwned};
+use rustls::pki_types::ServerName;
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use serde::de::Error as _;
+
+use crate::animations::{self, Animation, AnimationInfo, Color, CreateError, Frame};
+use crate::animations::canvas::Canvas;
+use crate::telnet::SessionInfo;
+
+
+/// How long to wait before trying again after the weather could not be fetched.
+const RETRY_INTERVAL: Duration = Duration::from_secs(60);
+
+/// How long to wait for the weather service to accept the connection and for each read or write.
+const HTTP_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// The longest response of the weather service that is accepted, headers included.
+const MAX_RESPONSE_LENGTH: u64 = 1024 * 1024;
+
+/// How wide the pictures of the weather are at most.
+const ICON_WIDTH: i64 = 17;
+
+/// How tall the pictures of the weather are.
+const ICON_HEIGHT: i64 = 5;
+
+const SUN_RAYS_LONG: [&str; 5] = [
+    "  \\   |   /",
+    "    .---.",
+    "-- (     ) --",
+    "    '---'",
+    "  /   |   \\",
+];
+
+const SUN_RAYS_SHORT: [&str; 5] = [
+    "    \\ | /",
+    "    .---.",
+    " - (     ) -",
+    "    '---'",
+    "    / | \\",
+];
+
+const MOON: [&str; 5] = [
+    "    .--.",
+    "   /  .-'",
+    "  |  (",
+    "   \\  '-.",
+    "    '--'",
+];
+
+const CLOUD: [&str; 3] = [
+    "     .--.",
+    "  .-(    ).",
+    " (___.__)__)",
+];
+
+const FOG: [&str; 3] = [
+    " _ - _ - _ -",
+    "  _ - _ - _",
+    " _ - _ - _ -",
+];
+
+/// What is drawn in the lower rows of the screen when it is foggy, over and over.
+const FOG_PATTERN: &str = "~  -  ~~ -   ~ --  ";
+
+/// What the weather service's requests go out as.
+const USER_AGENT: &str = concat!("telnet-animations/", env!("CARGO_PKG_VERSION"));
+
+
+pub(crate) const INFO: AnimationInfo = AnimationInfo {
+    name: "weather",
+    description: "The current weather at a place, fetched from Open-Meteo.",
+    default_frame_ms: 100,
+    size: (80, 24),
+    create: |config, _context| {
+        let params: Params = config.parse_params()?;
+        let invalid = |message: String| CreateError::InvalidParameters {
+            name: config.name.clone(),
+            error: toml::de::Error::custom(message),
+        };
+        if params.location.trim().is_empty() {
+            return Err(invalid("no location given".to_owned()));
+        }
+        for url in [&params.forecast_url, &params.geocoding_url] {
+            if !url.starts_with("http://") && !url.starts_with("https://") {
+                return Err(invalid(format!("{:?} is not an http:// or https:// URL", url)));
+            }
+        }
+        Ok(Box::new(Weather::new(params)))
+    },
+    params_schema: animations::params_schema::<Params>,
+    default_params: animations::default_params::<Params>,
+    needs_params: true,
+};
+
+
+/// Parameters of the weather animation.
+#[derive(Clone, Debug, Deserialize, Eq, Hash, JsonSchema, Ord, PartialEq, PartialOrd, Serialize)]
+#[serde(default, deny_unknown_fields)]
+pub(crate) struct Params {
+    /// How long each frame is shown, in milliseconds.
+    pub frame_ms: u64,
+
+    /// The place whose weather is shown: a name to look up (e.g. `Vienna`) or a latitude and
+    /// longitude separated by a comma (e.g. `48.21,16.37`).
+    pub location: String,
+
+    /// Whether temperatures and wind speeds are given in metric or imperial units.
+    pub units: Units,
+
+    /// How often the weather is fetched again, in minutes. All sessions showing the same place
+    /// share what was fetched.
+    pub refresh_minutes: u64,
+
+    /// Where the weather is fetched from; the Open-Meteo forecast API, or a server of your own
+    /// that speaks it, via HTTP or HTTPS.
+    pub forecast_url: String,
+
+    /// Where names of places are looked up; the Open-Meteo geocoding API, or a server of your own
+    /// that speaks it, via HTTP or HTTPS.
+    pub geocoding_url: String,
+}
+impl Default for Params {
+    fn default() -> Self {
+        Self {
+            frame_ms: INFO.default_frame_ms,
+            location: String::new(),
+            units: Units::default(),
+            refresh_minutes: 15,
+            forecast_url: "https://api.open-meteo.com/v1/forecast".to_owned(),
+            geocoding_url: "https://geocoding-api.open-meteo.com/v1/search".to_owned(),
+        }
+    }
+}
+
+
+/// The units of the weather report.
+#[derive(Clone, Copy, Debug, Default, Deserialize, Eq, Hash, JsonSchema, Ord, PartialEq, PartialOrd, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub(crate) enum Units {
+    /// Degrees Celsius and kilometers per hour.
+    #[default]
+    Metric,
+
+    /// Degrees Fahrenheit and miles per hour.
+    Imperial,
+}
+
+
+/// The weather at a place at some point in time.
+#[derive(Clone, Debug, PartialEq)]
+struct Report {
+    place: String,
+
+    /// The local time of the report, as hours and minutes.
+    time: String,
+
+    temperature: f64,
+    temperature_unit: String,
+    humidity_percent: f64,
+    wind_speed: f64,
+    wind_speed_unit: String,
+
+    /// The WMO weather interpretation code.
+    code: u8,
+
+    is_day: bool,
+}
+
+
+/// What the sky looks like, as far as the animation is concerned.
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+enum Sky {
+    Clear,
+    PartlyCloudy,
+    Cloudy,
+    Fog,
+    Drizzle,
+    Rain,
+    Snow,
+    Thunderstorm,
+}
+impl Sky {
+    fn from_code(code: u8) -> Self {
+        match code {
+            0|1 => Self::Clear,
+            2 => Self::PartlyCloudy,
+            45|48 => Self::Fog,
+            51..=57 => Self::Drizzle,
+            61..=67|80..=82 => Self::Rain,
+            71..=77|85|86 => Self::Snow,
+            95..=99 => Self::Thunderstorm,
+            _ => Self::Cloudy,
+        }
+    }
+}
+
+
+/// Describes a WMO weather interpretation code in words.
+fn describe(code: u8) -> &'static str {
+    match code {
+        0 => "Clear sky",
+        1 => "Mainly clear",
+        2 => "Partly cloudy",
+        3 => "Overcast",
+        45 => "Fog",
+        48 => "Depositing rime fog",
+        51 => "Light drizzle",
+        53 => "Moderate drizzle",
+        55 => "Dense drizzle",
+        56 => "Light freezing drizzle",
+        57 => "Dense freezing drizzle",
+        61 => "Slight rain",
+        63 => "Moderate rain",
+        65 => "Heavy rain",
+        66 => "Light freezing rain",
+        67 => "Heavy freezing rain",
+        71 => "Slight snowfall",
+        73 => "Moderate snowfall",
+        75 => "Heavy snowfall",
+        77 => "Snow grains",
+        80 => "Slight rain showers",
+        81 => "Moderate rain showers",
+        82 => "Violent rain showers",
+        85 => "Slight snow showers",
+        86 => "Heavy snow showers",
+        95 => "Thunderstorm",
+        96 => "Thunderstorm with slight hail",
+        99 => "Thunderstorm with heavy hail",
+        _ => "Unknown weather",
+    }
+}
+
+
+/// The TLS configuration for fetching documents over HTTPS.
+static TLS_CONFIG: LazyLock<Arc<ClientConfig>> = LazyLock::new(|| {
+    let roots = RootCertStore { roots: webpki_roots::TLS_SERVER_ROOTS.to_vec() };
+    let config = ClientConfig::builder()
+        .with_root_certificates(roots)
+        .with_no_client_auth();
+    Arc::new(config)
+});
+
+
+/// Fetches the body of a document over HTTP or HTTPS.
+fn http_get(url: &str) -> io::Result<String> {
+    let (secure, rest) = if let Some(rest) = url.strip_prefix("https://") {
+        (true, rest)
+    } else if let Some(rest) = url.strip_prefix("http://") {
+        (false, rest)
+    } else {
+        return Err(io::Error::new(io::ErrorKind::InvalidInput, "only http:// and https:// URLs are supported"));
+    };
+    let (authority, path) = match rest.find('/') {
+        Some(slash) => rest.split_at(slash),
+        None => (rest, "/"),
+    };
+    let default_port = if secure { 443 } else { 80 };
+    let host_port = authority.rsplit_once(':')
+        .filter(|(_host, port)| !authority.ends_with(']') && port.bytes().all(|b| b.is_ascii_digit()));
+    let host = host_port.map_or(authority, |(host, _port)| host)
+        .trim_start_matches('[')
+        .trim_end_matches(']');
+    let addresses: Vec<SocketAddr> = match host_port {
+        Some(_) => authority.to_socket_addrs()?.collect(),
+        None => (host, default_port).to_socket_addrs()?.collect(),
+    };
+
+    let mut last_error = io::Error::new(io::ErrorKind::NotFound, format!("{} has no addresses", authority));
+    let mut stream = None;
+    for address in addresses {
+        match TcpStream::connect_timeout(&address, HTTP_TIMEOUT) {
+            Ok(s) => {
+                stream = Some(s);
+                break;
+            },
+            Err(e) => last_error = e,
+        }
+    }
+    let stream = stream.ok_or(last_error)?;
+    stream.set_read_timeout(Some(HTTP_TIMEOUT))?;
+    stream.set_write_timeout(Some(HTTP_TIMEOUT))?;
+
+    // HTTP/1.0 spares us chunked responses
+    let request = format!(
+        "GET {} HTTP/1.0\r\nHost: {}\r\nUser-Agent: {}\r\nAccept: application/json\r\nConnection: close\r\n\r\n",
+        path, authority, USER_AGENT,
+    );
+    let response = if secure {
+        let server_name = ServerName::try_from(host.to_owned())
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?;
+        let connection = ClientConnection::new(Arc::clone(&TLS_CONFIG), server_name)
+            .map_err(io::Error::other)?;
+        exchange(StreamOwned::new(connection, stream), &request)?
+    } else {
+        exchange(stream, &request)?
+    };
+
+    let invalid = |message: String| io::Error::new(io::ErrorKind::InvalidData, message);
+    let header_end = response.windows(4).position(|window| window == b"\r\n\r\n")
+        .ok_or_else(|| invalid("incomplete response".to_owned()))?;
+    let status_line = String::from_utf8_lossy(&response[..header_end]).lines().next().unwrap_or("").to_owned();
+    if status_line.split_whitespace().nth(1) != Some("200") {
+        return Err(invalid(format!("the server responded with {:?}", status_line)));
+    }
+    String::from_utf8(response[header_end + 4..].to_vec())
+        .map_err(|e| invalid(e.to_string()))
+}
+
+
+/// Sends the request and reads the response until the server closes the connection.
+fn exchange<S: Read + Write>(mut stream: S, request: &str) -> io::Result<Vec<u8>> {
+    stream.write_all(request.as_bytes())?;
+    stream.flush()?;
+    let mut response = Vec::new();
+    match stream.take(MAX_RESPONSE_LENGTH).read_to_end(&mut response) {
+        Ok(_) => Ok(response),
+        // many HTTPS servers close the connection without saying goodbye in TLS first
+        Err(e) if e.kind() == io::ErrorKind::UnexpectedEof && !response.is_empty() => Ok(response),
+        Err(e) => Err(e),
+    }
+}
+
+
+/// Escapes a string for use in the query of a URL.
+fn encode_query(value: &str) -> String {
+    let mut encoded = String::new();
+    for byte in value.bytes() {
+        if byte.is_ascii_alphanumeric() || b"-._~".contains(&byte) {
+            encoded.push(char::from(byte));
+        } else {
+            encoded.push_str(&format!("%{:02X}", byte));
+        }
+    }
+    encoded
+}
+
+
+fn parse_json<T: for<'de> Deserialize<'de>>(body: &str) -> io::Result<T> {
+    serde_json::from_str(body)
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+}
+
+
+#[derive(Deserialize)]
+struct Places {
+    #[serde(default)]
+    results: Vec<Place>,
+}
+
+#[derive(Deserialize)]
+struct Place {
+    name: String,
+    latitude: f64,
+    longitude: f64,
+    country: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct Forecast {
+    current: Current,
+    current_units: CurrentUnits,
+}
+
+#[derive(Deserialize)]
+struct Current {
+    time: String,
+    temperature_2m: f64,
+    relative_humidity_2m: f64,
+    weather_code: u8,
+    wind_speed_10m: f64,
+    is_day: u8,
+}
+
+#[derive(Deserialize)]
+struct CurrentUnits {
+    temperature_2m: String,
+    wind_speed_10m: String,
+}
+
+
+/// Looks up the place if need be, then fetches its current weather.
+fn fetch(params: &Params) -> io::Result<Report> {
+    let location = params.location.trim();
+    let coordinates = location.split_once(',')
+        .and_then(|(latitude, longitude)| Some((latitude.trim().parse().ok()?, longitude.trim().parse().ok()?)));
+    let (place, (latitude, longitude)): (String, (f64, f64)) = match coordinates {
+        Some(coordinates) => (location.to_owned(), coordinates),
+        None => {
+            let url = format!("{}?name={}&count=1&format=json", params.geocoding_url, encode_query(location));
+            let places: Places = parse_json(&http_get(&url)?)?;
+            let place = places.results.into_iter().next()
+                .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, format!("no place called {:?} found", location)))?;
+            let name = match place.country {
+                Some(country) => format!("{}, {}", place.name, country),
+                None => place.name,
+            };
+            (name, (place.latitude, place.longitude))
+        },
+    };
+
+    let units = match params.units {
+        Units::Metric => "",
+        Units::Imperial => "&temperature_unit=fahrenheit&wind_speed_unit=mph",
+    };
+    let url = format!(
+        "{}?latitude={}&longitude={}&current=temperature_2m,relative_humidity_2m,weather_code,wind_speed_10m,is_day&timezone=auto{}",
+        params.forecast_url, latitude, longitude, units,
+    );
+    let forecast: Forecast = parse_json(&http_get(&url)?)?;
+    let current = forecast.current;
+    let time = current.time.split_once('T')
+        .map(|(_date, time)| time.to_owned())
+        .unwrap_or(current.time);
+    Ok(Report {
+        place,
+        time,
+        temperature: current.temperature_2m,
+        temperature_unit: forecast.current_units.temperature_2m,
+        humidity_percent: current.relative_humidity_2m,
+        wind_speed: current.wind_speed_10m,
+        wind_speed_unit: forecast.current_units.wind_speed_10m,
+        code: current.weather_code,
+        is_day: current.is_day != 0,
+    })
+}
+
+
+/// What is known about the weather for some parameters.
+#[derive(Debug, Default)]
+struct CacheEntry {
+    report: Option<Arc<Report>>,
+
+    /// Why the last attempt to fetch the weather failed, if it did.
+    error: Option<String>,
+
+    /// When the last attempt to fetch the weather finished.
+    attempted_at: Option<Instant>,
+
+    fetching: bool,
+}
+
+
+/// The weather fetched so far, shared by all sessions so that the weather service is not asked
+/// more often than necessary.
+static CACHE: Mutex<BTreeMap<Params, CacheEntry>> = Mutex::new(BTreeMap::new());
+
+
+/// Returns the latest weather report and the error that prevented a newer one, and fetches the
+/// weather again in the background if it is time to.
+fn latest(params: &Params) -> (Option<Arc<Report>>, Option<String>) {
+    let mut cache = CACHE.lock().unwrap();
+    let entry = cache.entry(params.clone()).or_default();
+    let interval = if entry.report.is_some() && entry.error.is_none() {
+        Duration::from_secs(params.refresh_minutes.max(1).saturating_mul(60))
+    } else {
+        RETRY_INTERVAL
+    };
+    if !entry.fetching && entry.attempted_at.is_none_or(|at| at.elapsed() >= interval) {
+        entry.fetching = true;
+
+        // the connection and the resolver block, so keep them away from the sessions
+        let thread_params = params.clone();
+        let spawned = thread::Builder::new()
+            .name("weather".to_owned())
+            .spawn(move || {
+                let result = fetch(&thread_params);
+                let mut cache = CACHE.lock().unwrap();
+                let entry = cache.entry(thread_params.clone()).or_default();
+                entry.fetching = false;
+                entry.attempted_at = Some(Instant::now());
+                match result {
+                    Ok(report) => {
+                        entry.report = Some(Arc::new(report));
+                        entry.error = None;
+                    },
+                    Err(e) => {
+                        warn!("failed to fetch the weather for {:?}: {}", thread_params.location, e);
+                        entry.error = Some(e.to_string());
+                    },
+                }
+            });
+        if let Err(e) = spawned {
+            entry.fetching = false;
+            entry.attempted_at = Some(Instant::now());
+            entry.error = Some(e.to_string());
+        }
+    }
+    (entry.report.clone(), entry.error.clone())
+}
+
+
+/// Draws a picture line by line; the spaces within a line hide what is behind them, those before
+/// and after it do not.
+fn draw_art(canvas: &mut Canvas, left: i64, top: i64, art: &[&str], color: Option<Color>) {
+    for (row, line) in art.iter().enumerate() {
+        let indent = line.len() - line.trim_start().len();
+        canvas.put(left + indent as i64, top + row as i64, line.trim(), color);
+    }
+}
+
+
+#[derive(Debug)]
+pub(crate) struct Weather {
+    params: Params,
+    rng: SmallRng,
+
+    /// The report being shown.
+    report: Option<Arc<Report>>,
+
+    /// The sky and the size of the screen the particles were scattered for.
+    scene: Option<(Sky, bool, (u16, u16))>,
+
+    /// The positions of the raindrops, snowflakes, stars or clouds, depending on the sky.
+    particles: Vec<(i64, i64)>,
+
+    /// How many frames remain of the current flash of lightning, and its column.
+    lightning: Option<(u8, i64)>,
+
+    step: u64,
+
+    /// What is on the client's screen.
+    shown: Option<Canvas>,
+}
+impl Weather {
+    fn new(params: Params) -> Self {
+        Self {
+            params,
+            rng: SmallRng::from_os_rng(),
+            report: None,
+            scene: None,
+            particles: Vec::new(),
+            lightning: None,
+            step: 0,
+            shown: None,
+        }
+    }
+
+    /// Scatters the particles over the screen anew.
+    fn scatter(&mut self, sky: Sky, (cols, rows): (u16, u16)) {
+        let area = usize::from(cols) * usize::from(rows);
+        let count = match sky {
+            Sky::Clear => area / 80,
+            Sky::PartlyCloudy => usize::from(cols) / 40 + 1,
+            Sky::Cloudy => usize::from(cols) / 25 + 1,
+            Sky::Fog => 0,
+            Sky::Drizzle => area / 60,
+            Sky::Rain|Sky::Thunderstorm => area / 25,
+            Sky::Snow => area / 40,
+        };
+        let (cols, rows) = (i64::from(cols.max(1)), i64::from(rows.max(1)));
+        self.particles = (0..count)
+            .map(|_| (self.rng.random_range(0..cols), self.rng.random_range(0..rows)))
+            .collect();
+    }
+
+    /// Moves the particles on, bringing back those that have left the screen on the other side.
+    fn move_particles(&mut self, sky: Sky, (cols, rows): (u16, u16)) {
+        let (cols, rows) = (i64::from(cols.max(1)), i64::from(rows.max(1)));
+        let cloud_width = CLOUD.iter().map(|line| line.len()).max().unwrap_or(0) as i64;
+        for (col, row) in &mut self.particles {
+            match sky {
+                Sky::Clear|Sky::Fog => {},
+                Sky::PartlyCloudy|Sky::Cloudy => {
+                    // drifting along the upper part of the screen
+                    if self.step.is_multiple_of(3) {
+                        *col += 1;
+                    }
+                    if *col >= cols {
+                        *col = -cloud_width;
+                        *row = self.rng.random_range(0..(rows / 4).max(1));
+                    }
+                },
+                Sky::Drizzle => *row += 1,
+                Sky::Rain|Sky::Thunderstorm => {
+                    *col -= 1;
+                    *row += 1;
+                },
+                Sky::Snow => {
+                    if self.step.is_multiple_of(2) {
+                        *row += 1;
+                        *col += self.rng.random_range(-1..=1);
+                    }
+                },
+            }
+            if *row >= rows {
+                *row = 0;
+                *col = self.rng.random_range(0..cols);
+            }
+            if *col < 0 && !matches!(sky, Sky::PartlyCloudy|Sky::Cloudy) {
+                *col += cols;
+            }
+        }
+
+        if sky == Sky::Thunderstorm {
+            self.lightning = match self.lightning {
+                Some((frames, col)) if frames > 1 => Some((frames - 1, col)),
+                Some(_) => None,
+                None if self.rng.random_ratio(1, 40) => Some((3, self.rng.random_range(0..cols))),
+                None => None,
+            };
+        } else {
+            self.lightning = None;
+        }
+    }
+
+    fn draw_effects(&self, canvas: &mut Canvas, sky: Sky, is_day: bool) {
+        let (cols, rows) = canvas.size();
+        match sky {
+            Sky::Clear => {
+                if !is_day {
+                    // twinkling stars
+                    for (index, (col, row)) in self.particles.iter().enumerate() {
+                        let c = if (index as u64 + self.step / 4).is_multiple_of(5) { '*' } else { '.' };
+                        canvas.set(*col, *row, (c, Some(Color::White)));
+                    }
+                }
+            },
+            Sky::PartlyCloudy|Sky::Cloudy => {
+                for (col, row) in &self.particles {
+                    draw_art(canvas, *col, *row, &CLOUD, Some(Color::BrightBlack));
+                }
+            },
+            Sky::Fog => {
+                let pattern: Vec<char> = FOG_PATTERN.chars().collect();
+                for row in (i64::from(rows) / 3..i64::from(rows)).step_by(2) {
+                    let shift = (self.step / 2) as usize + row as usize * 7;
+                    for col in 0..i64::from(cols) {
+                        let c = pattern[(col as usize + shift) % pattern.len()];
+                        if c != ' ' {
+                            canvas.set(col, row, (c, Some(Color::BrightBlack)));
+                        }
+                    }
+                }
+            },
+            Sky::Drizzle => {
+                for (col, row) in &self.particles {
+                    canvas.set(*col, *row, (',', Some(Color::Cyan)));
+                }
+            },
+            Sky::Rain|Sky::Thunderstorm => {
+                for (col, row) in &self.particles {
+                    canvas.set(*col, *row, ('/', Some(Color::BrightBlue)));
+                }
+                if let Some((_frames, mut col)) = self.lightning {
+                    for row in 0..i64::from(rows) * 2 / 3 {
+                        let c = if (row / 2) % 2 == 0 { '/' } else { '\\' };
+                        canvas.set(col, row, (c, Some(Color::BrightYellow)));
+                        if row % 2 == 1 {
+                            col += if c == '/' { -1 } else { 1 };
+                        }
+                    }
+                }
+            },
+            Sky::Snow => {
+                for (col, row) in &self.particles {
+                    canvas.set(*col, *row, ('*', Some(Color::BrightWhite)));
+                }
+            },
+        }
+    }
+
+    fn draw_icon(&self, canvas: &mut Canvas, left: i64, top: i64, sky: Sky, is_day: bool) {
+        let sun = if (self.step / 5).is_multiple_of(2) { &SUN_RAYS_LONG } else { &SUN_RAYS_SHORT };
+        let cloud_color = match sky {
+            Sky::Rain|Sky::Thunderstorm => Some(Color::BrightBlack),
+            _ => Some(Color::White),
+        };
+        match (sky, is_day) {
+            (Sky::Clear, true) => draw_art(canvas, left + 2, top, sun, Some(Color::BrightYellow)),
+            (Sky::Clear, false) => draw_art(canvas, left + 4, top, &MOON, Some(Color::BrightWhite)),
+            (Sky::PartlyCloudy, _) => {
+                if is_day {
+                    draw_art(canvas, left, top, &SUN_RAYS_SHORT, Some(Color::BrightYellow));
+                } else {
+                    draw_art(canvas, left, top, &MOON, Some(Color::BrightWhite));
+                }
+                draw_art(canvas, left + 4, top + 2, &CLOUD, cloud_color);
+            },
+            (Sky::Fog, _) => {
+                draw_art(canvas, left + 2, top, &CLOUD, cloud_color);
+                draw_art(canvas, left + 2, top + 2, &FOG, Some(Color::BrightBlack));
+            },
+            _ => {
+                draw_art(canvas, left, top, &CLOUD, cloud_color);
+                draw_art(canvas, left + 4, top + 2, &CLOUD, cloud_color);
+            },
+        }
+    }
+}
+impl Animation for Weather {
+    fn next_frame(&mut self, session: &SessionInfo) -> Option<Frame> {
+        let size = session.window_size.unwrap_or(INFO.size);
+        let (report, error) = latest(&self.params);
+
+        // a cycle per weather report
+        let starts_cycle = match (&self.report, &report) {
+            (Some(old), Some(new)) => !Arc::ptr_eq(old, new),
+            (None, _) => self.shown.is_none() || report.is_some(),
+            (Some(_), None) => false,
+        };
+        self.report = report;
+
+        let mut canvas = Canvas::new(size);
+        let center = |text: &str| ((i64::from(size.0) - text.chars().count() as i64) / 2).max(0);
+        let report = match &self.report {
+            Some(r) => Arc::clone(r),
+            None => {
+                let message = match error {
+                    Some(e) => format!("No weather for {}: {}", self.params.location, e),
+                    None => format!("Looking up the weather for {}...", self.params.location),
+                };
+                canvas.put(center(&message), i64::from(size.1 / 2), &message, None);
+                let commands = canvas.draw_over(self.shown.as_ref());
+                self.shown = Some(canvas);
+                let mut frame = Frame::new(commands, Duration::from_millis(self.params.frame_ms));
+                frame.starts_cycle = starts_cycle;
+                return Some(frame);
+            },
+        };
+
+        let sky = Sky::from_code(report.code);
+        if self.scene != Some((sky, report.is_day, size)) {
+            self.scene = Some((sky, report.is_day, size));
+            self.scatter(sky, size);
+        }
+        self.step += 1;
+        self.move_particles(sky, size);
+        self.draw_effects(&mut canvas, sky, report.is_day);
+
+        let basic = session.is_basic_terminal();
+        let ascii = |unit: &str| -> String {
+            if basic {
+                unit.chars().filter(char::is_ascii).collect()
+            } else {
+                unit.to_owned()
+            }
+        };
+        let mut lines = [
+            report.place.clone(),
+            describe(report.code).to_owned(),
+            format!(
+                "{:.1} {} | humidity {:.0}% | wind {:.1} {}",
+                report.temperature, ascii(&report.temperature_unit), report.humidity_percent,
+                report.wind_speed, ascii(&report.wind_speed_unit),
+            ),
+            format!("as of {}", report.time),
+        ];
+        if error.is_some() {
+            lines[3].push_str(" (update failed)");
+        }
+
+        let height = ICON_HEIGHT + 1 + lines.len() as i64;
+        let top = ((i64::from(size.1) - height) / 2).max(0);
+        self.draw_icon(&mut canvas, (i64::from(size.0) - ICON_WIDTH) / 2, top, sky, report.is_day);
+        for (index, line) in lines.iter().enumerate() {
+            // a space on either side keeps the rain and snow from running into the text
+            let color = if index == 0 { Some(Color::BrightWhite) } else { None };
+            let padded = format!(" {} ", line);
+            canvas.put(center(&padded), top + ICON_HEIGHT + 1 + index as i64, &padded, color);
+        }
+        let commands = canvas.draw_over(self.shown.as_ref());
+        self.shown = Some(canvas);
+
+        let mut frame = Frame::new(commands, Duration::from_millis(self.params.frame_ms));
+        frame.starts_cycle = starts_cycle;
+        Some(frame)
+    }
+}
@@ -0,0 +1,251 @@
+//! Broadcasting a single run of an animation to many clients.
+//!
+//! Instead of every session computing the same frames, a driver task renders each frame once and
+//! fans it out to all viewers. Since frames usually only redraw what has changed, every frame is
+//! also fed to a virtual screen, and viewers joining later (or falling behind) are sent a complete
+//! picture of that screen before the live frames.
+
+
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex};
+
+use log::{debug, error};
+use tokio::sync::{broadcast, watch};
+use tokio::sync::broadcast::error::RecvError;
+use tokio::time::{Duration, Instant, sleep_until};
+
+use crate::animations::{self, AnimationConfig, CreateError, Frame};
+use crate::screen::Screen;
+use crate::task;
+use crate::telnet::SessionInfo;
+
+
+/// How many frames a viewer may fall behind before frames are skipped.
+const CHANNEL_CAPACITY: usize = 64;
+
+
+/// The size of the screen of animations whose size is unknown.
+const DEFAULT_SIZE: (u16, u16) = (80, 24);
+
+
+/// Identifies a broadcast: the socket and the animation with its parameters.
+type BroadcastKey = (SocketAddr, String, String);
+
+
+#[derive(Debug)]
+struct Channel {
+    sender: broadcast::Sender<Arc<Frame>>,
+
+    /// What the viewers see once they have been sent all the frames so far.
+    screen: Screen,
+
+    /// Whether any frames have been sent yet.
+    started: bool,
+
+    viewers: watch::Sender<usize>,
+}
+impl Channel {
+    /// Returns a frame drawing what the viewers currently see.
+    fn redraw(&self) -> Frame {
+        Frame::new(self.screen.redraw(), Duration::ZERO)
+    }
+}
+
+
+/// What happened to a broadcast.
+#[derive(Clone, Debug)]
+pub(crate) enum Update {
+    /// A frame is to be shown.
+    Frame(Arc<Frame>),
+
+    /// A viewer has joined or left (see [`Subscription::viewer_count`]).
+    ViewersChanged,
+
+    /// The animation is over.
+    Over,
+}
+
+
+/// Keeps track of the running broadcasts.
+#[derive(Clone, Debug, Default)]
+pub(crate) struct Broadcaster {
+    channels: Arc<Mutex<HashMap<BroadcastKey, Channel>>>,
+}
+impl Broadcaster {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Starts watching the broadcast of the animation on the socket, starting the broadcast if
+    /// nobody is watching it yet.
+    pub fn subscribe(&self, listen_addr: SocketAddr, config: &AnimationConfig) -> Result<Subscription, CreateError> {
+        let key = (listen_addr, config.name.clone(), config.params.to_string());
+        let mut channels = self.channels.lock().unwrap();
+        if let Some(channel) = channels.get(&key) {
+            channel.viewers.send_modify(|count| *count += 1);
+            // the first frame starts a cycle, so joining late should as well
+            let pending_redraw = channel.started
+                .then(|| Arc::new(channel.redraw().starting_cycle()));
+            return Ok(Subscription {
+                broadcaster: self.clone(),
+                key: key.clone(),
+                pending_redraw,
+                receiver: channel.sender.subscribe(),
+                viewers: channel.viewers.subscribe(),
+                frames_skipped: 0,
+            });
+        }
+
+        let animation = animations::create(config)?;
+        let (sender, receiver) = broadcast::channel(CHANNEL_CAPACITY);
+        let (viewers_sender, viewers) = watch::channel(1);
+
+        // a spare row, since many animations end their last line with a line break, which would
+        // scroll everything up
+        let size = animations::info(&config.name).map_or(DEFAULT_SIZE, |info| info.size);
+        let screen = Screen::new(size.0, size.1.saturating_add(1));
+        channels.insert(key.clone(), Channel { sender, screen, started: false, viewers: viewers_sender });
+        let task_broadcaster = self.clone();
+        let task_key = key.clone();
+        tokio::spawn(async move {
+            if let Err(message) = task::catch_panic(drive(task_broadcaster.clone(), task_key.clone(), animation)).await {
+                error!("broadcast of {} on {} panicked: {}", task_key.1, task_key.0, message);
+                // dropping the sender informs the viewers that the animation is over
+                task_broadcaster.remove(&task_key);
+            }
+        });
+        Ok(Subscription {
+            broadcaster: self.clone(),
+            key,
+            pending_redraw: None,
+            receiver,
+            viewers,
+            frames_skipped: 0,
+        })
+    }
+
+    /// Returns a frame drawing what the viewers currently see, along with a receiver of the frames
+    /// following it, or `None` if the broadcast is over.
+    fn resume(&self, key: &BroadcastKey) -> Option<(Arc<Frame>, broadcast::Receiver<Arc<Frame>>)> {
+        self.channels.lock().unwrap()
+            .get(key)
+            .map(|c| (Arc::new(c.redraw()), c.sender.subscribe()))
+    }
+
+    /// Sends a frame to the viewers of the broadcast.
+    ///
+    /// Returns `false` if nobody is watching anymore, in which case the broadcast is removed.
+    fn send(&self, key: &BroadcastKey, frame: Frame) -> bool {
+        let mut channels = self.channels.lock().unwrap();
+        let channel = match channels.get_mut(key) {
+            Some(c) => c,
+            None => return false,
+        };
+        if channel.sender.receiver_count() == 0 {
+            channels.remove(key);
+            return false;
+        }
+        channel.screen.feed(&frame.commands);
+        channel.started = true;
+        let frame = Arc::new(frame);
+        // only fails if there are no receivers, which we have just ruled out
+        let _ = channel.sender.send(frame);
+        true
+    }
+
+    fn remove(&self, key: &BroadcastKey) {
+        self.channels.lock().unwrap().remove(key);
+    }
+
+    /// Counts a viewer as gone.
+    fn leave(&self, key: &BroadcastKey) {
+        if let Some(channel) = self.channels.lock().unwrap().get(key) {
+            channel.viewers.send_modify(|count| *count = count.saturating_sub(1));
+        }
+    }
+}
+
+
+/// Renders the frames of the animation and sends them to the viewers until the animation ends or
+/// nobody is watching anymore.
+async fn drive(broadcaster: Broadcaster, key: BroadcastKey, mut animation: Box<dyn animations::Animation>) {
+    debug!("starting broadcast of {} on {}", key.1, key.0);
+
+    // the frames are shared, so they cannot be adapted to the terminal of each viewer
+    let session_info = SessionInfo::default();
+    loop {
+        let frame = match animation.next_frame(&session_info) {
+            Some(f) => f,
+            None => {
+                // dropping the sender informs the viewers that the animation is over
+                broadcaster.remove(&key);
+                break;
+            },
+        };
+        let next_frame_at = Instant::now() + frame.delay;
+        if !broadcaster.send(&key, frame) {
+            break;
+        }
+        sleep_until(next_frame_at).await;
+    }
+
+    debug!("stopping broadcast of {} on {}", key.1, key.0);
+}
+
+
+/// A viewer's connection to a broadcast.
+pub(crate) struct Subscription {
+    broadcaster: Broadcaster,
+    key: BroadcastKey,
+    pending_redraw: Option<Arc<Frame>>,
+    receiver: broadcast::Receiver<Arc<Frame>>,
+    viewers: watch::Receiver<usize>,
+
+    /// How many frames have been missed since the last call to `take_frames_skipped`.
+    frames_skipped: u64,
+}
+impl Subscription {
+    /// Waits for the next frame to show or for the number of viewers to change.
+    ///
+    /// This method is cancel-safe.
+    pub async fn next_update(&mut self) -> Update {
+        if let Some(redraw) = self.pending_redraw.take() {
+            return Update::Frame(redraw);
+        }
+        tokio::select! {
+            received = self.receiver.recv() => match received {
+                Ok(frame) => Update::Frame(frame),
+                Err(RecvError::Lagged(count)) => {
+                    // we have missed some changes; start over with a complete picture
+                    self.frames_skipped += count;
+                    match self.broadcaster.resume(&self.key) {
+                        Some((redraw, receiver)) => {
+                            self.receiver = receiver;
+                            Update::Frame(redraw)
+                        },
+                        None => Update::Over,
+                    }
+                },
+                Err(RecvError::Closed) => Update::Over,
+            },
+            Ok(()) = self.viewers.changed() => Update::ViewersChanged,
+        }
+    }
+
+    /// Returns how many frames have been missed (because they were sent faster than the viewer
+    /// received them) since the last call.
+    pub fn take_frames_skipped(&mut self) -> u64 {
+        std::mem::take(&mut self.frames_skipped)
+    }
+
+    /// The number of clients watching the broadcast, including this one.
+    pub fn viewer_count(&self) -> usize {
+        *self.viewers.borrow()
+    }
+}
+impl Drop for Subscription {
+    fn drop(&mut self) {
+        self.broadcaster.leave(&self.key);
+    }
+}
@@ -0,0 +1,185 @@
+//! Embedding the server in other applications.
+
+
+use std::fmt;
+use std::io;
+use std::net::SocketAddr;
+
+use log::warn;
+use tokio_util::sync::CancellationToken;
+
+use crate::{serve, ServeOptions};
+use crate::animations::{self, AnimationConfig};
+use crate::config::{Config, Severity, SocketConfig};
+
+
+/// Why an embedded server could not be started.
+#[derive(Debug)]
+#[non_exhaustive]
+pub enum ServeError {
+    /// The settings are invalid (e.g. an animation does not exist).
+    #[non_exhaustive]
+    InvalidConfig { problems: Vec<String> },
+
+    /// A socket could not be set up (e.g. because its port is already in use).
+    #[non_exhaustive]
+    Listen { error: Box<dyn std::error::Error + Send + Sync> },
+
+    /// No sockets were added.
+    #[non_exhaustive]
+    NoListeners,
+
+    /// The admin interface could not be set up.
+    #[non_exhaustive]
+    Admin { error: io::Error },
+
+    /// The process could not switch to the configured user and group.
+    #[non_exhaustive]
+    DropPrivileges { error: Box<dyn std::error::Error + Send + Sync> },
+}
+impl fmt::Display for ServeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::InvalidConfig { problems }
+                => write!(f, "invalid configuration: {}", problems.join("; ")),
+            Self::Listen { error }
+                => write!(f, "{}", error),
+            Self::NoListeners
+                => write!(f, "no socket could be set up"),
+            Self::Admin { error }
+                => write!(f, "failed to bind admin interface: {}", error),
+            Self::DropPrivileges { error }
+                => write!(f, "failed to drop privileges: {}", error),
+        }
+    }
+}
+impl std::error::Error for ServeError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Self::InvalidConfig { .. } => None,
+            Self::Listen { error } => Some(error.as_ref()),
+            Self::NoListeners => None,
+            Self::Admin { error } => Some(error),
+            Self::DropPrivileges { error } => Some(error.as_ref()),
+        }
+    }
+}
+
+
+/// Limits on the sessions of an embedded server; unset limits do not apply.
+///
+/// Start from `Limits::default()` and set the fields you need.
+#[derive(Clone, Copy, Debug, Default, Eq, Hash, PartialEq)]
+#[non_exhaustive]
+pub struct Limits {
+    /// The maximum number of sessions live at the same time, across all listeners.
+    pub max_total_connections: Option<usize>,
+
+    /// The maximum number of sessions live at the same time from the same client address.
+    pub max_connections_per_ip: Option<usize>,
+
+    /// The maximum number of connections accepted from the same client address within a minute.
+    pub max_new_connections_per_ip_per_minute: Option<usize>,
+
+    /// Sessions are ended after this many seconds.
+    pub max_session_secs: Option<u64>,
+
+    /// Sessions are ended after the client has not sent anything for this many seconds.
+    pub idle_secs: Option<u64>,
+}
+
+
+/// Sets up an animation server within another application.
+///
+/// ```no_run
+/// # async fn example() -> Result<(), telnet_animations::ServeError> {
+/// use telnet_animations::{CancellationToken, ServerBuilder};
+///
+/// let shutdown = CancellationToken::new();
+/// ServerBuilder::new()
+///     .add_listener("127.0.0.1:2323".parse().unwrap(), "lollerskates")
+///     .with_shutdown(shutdown.clone())
+///     .serve()
+///     .await
+/// # }
+/// ```
+///
+/// The server logs via the `log` crate; whatever logger the application has set up receives the
+/// messages.
+#[derive(Clone, Debug, Default)]
+pub struct ServerBuilder {
+    listeners: Vec<(SocketAddr, String)>,
+    limits: Limits,
+    shutdown: CancellationToken,
+}
+impl ServerBuilder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Serves the animation with the given name (as listed by `telnet-animations
+    /// list-animations`) to Telnet clients connecting to the address.
+    pub fn add_listener(mut self, addr: SocketAddr, animation: &str) -> Self {
+        self.listeners.push((addr, animation.to_owned()));
+        self
+    }
+
+    /// Limits the sessions served.
+    pub fn with_limits(mut self, limits: Limits) -> Self {
+        self.limits = limits;
+        self
+    }
+
+    /// Shuts the server down (bidding the clients goodbye) once the token is cancelled.
+    pub fn with_shutdown(mut self, shutdown: CancellationToken) -> Self {
+        self.shutdown = shutdown;
+        self
+    }
+
+    /// Serves until the shutdown token is cancelled.
+    ///
+    /// Returns an error if the server cannot be started, e.g. if an animation does not exist or a
+    /// port is already in use.
+    pub async fn serve(self) -> Result<(), ServeError> {
+        let config = self.to_config()?;
+        let shutdown = self.shutdown;
+        serve(config, ServeOptions::default(), shutdown.cancelled_owned()).await
+    }
+
+    fn to_config(&self) -> Result<Config, ServeError> {
+        if self.listeners.is_empty() {
+            return Err(ServeError::NoListeners);
+        }
+
+        let mut problems = Vec::new();
+        let mut sockets = Vec::with_capacity(self.listeners.len());
+        for (addr, animation) in &self.listeners {
+            if let Err(e) = animations::by_name(animation) {
+                problems.push(format!("{}: {}", addr, e));
+            }
+            let mut socket_config = SocketConfig::new(
+                *addr,
+                AnimationConfig { name: animation.clone(), params: toml::Table::new() },
+            );
+            socket_config.max_session_secs = self.limits.max_session_secs;
+            socket_config.idle_secs = self.limits.idle_secs;
+            sockets.push(socket_config);
+        }
+
+        let mut config = Config::new(sockets);
+        config.max_total_connections = self.limits.max_total_connections;
+        config.max_connections_per_ip = self.limits.max_connections_per_ip;
+        config.max_new_connections_per_ip_per_minute = self.limits.max_new_connections_per_ip_per_minute;
+        for problem in config.validate() {
+            if problem.severity == Severity::Error {
+                problems.push(problem.message);
+            } else {
+                warn!("{}", problem.message);
+            }
+        }
+        if !problems.is_empty() {
+            return Err(ServeError::InvalidConfig { problems });
+        }
+        Ok(config)
+    }
+}
@@ -0,0 +1,99 @@
+//! Ranges of IP addresses in CIDR notation.
+
+
+use std::fmt;
+use std::net::IpAddr;
+use std::str::FromStr;
+
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+
+
+/// A range of IP addresses given as an address and a prefix length, e.g. `192.168.0.0/16`.
+///
+/// A single address without a prefix length matches only itself.
+#[derive(Clone, Copy, Debug, Deserialize, Eq, Hash, JsonSchema, PartialEq, Serialize)]
+#[serde(try_from = "String", into = "String")]
+#[schemars(with = "String")]
+pub(crate) struct Cidr {
+    addr: IpAddr,
+    prefix_len: u8,
+}
+impl Cidr {
+    /// Whether the address lies within this range.
+    ///
+    /// IPv4-mapped IPv6 addresses (`::ffff:a.b.c.d`) are treated as the IPv4 addresses they contain.
+    pub fn contains(&self, addr: IpAddr) -> bool {
+        match (self.addr, addr.to_canonical()) {
+            (IpAddr::V4(network), IpAddr::V4(addr)) => {
+                let mask = u32::MAX.checked_shl(32 - u32::from(self.prefix_len)).unwrap_or(0);
+                u32::from(network) & mask == u32::from(addr) & mask
+            },
+            (IpAddr::V6(network), IpAddr::V6(addr)) => {
+                let mask = u128::MAX.checked_shl(128 - u32::from(self.prefix_len)).unwrap_or(0);
+                u128::from(network) & mask == u128::from(addr) & mask
+            },
+            _ => false,
+        }
+    }
+}
+impl fmt::Display for Cidr {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}/{}", self.addr, self.prefix_len)
+    }
+}
+impl FromStr for Cidr {
+    type Err = ParseCidrError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (addr_str, prefix_len_str) = match s.split_once('/') {
+            Some((a, p)) => (a, Some(p)),
+            None => (s, None),
+        };
+        let addr: IpAddr = addr_str.parse()
+            .map_err(|_| ParseCidrError::InvalidAddress { cidr: s.to_owned() })?;
+        let max_prefix_len = if addr.is_ipv4() { 32 } else { 128 };
+        let prefix_len = match prefix_len_str {
+            Some(p) => p.parse().ok()
+                .filter(|p| *p <= max_prefix_len)
+                .ok_or_else(|| ParseCidrError::InvalidPrefixLength { cidr: s.to_owned() })?,
+            None => max_prefix_len,
+        };
+        Ok(Self { addr, prefix_len })
+    }
+}
+impl TryFrom<String> for Cidr {
+    type Error = ParseCidrError;
+
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        value.parse()
+    }
+}
+impl From<Cidr> for String {
+    fn from(value: Cidr) -> Self {
+        value.to_string()
+    }
+}
+
+
+#[derive(Debug)]
+#[non_exhaustive]
+pub(crate) enum ParseCidrError {
+    #[non_exhaustive]
+    InvalidAddress { cidr: String },
+
+    #[non_exhaustive]
+    InvalidPrefixLength { cidr: String },
+}
+impl fmt::Display for ParseCidrError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::InvalidAddress { cidr }
+                => write!(f, "invalid address in address range {:?}", cidr),
+            Self::InvalidPrefixLength { cidr }
+                => write!(f, "invalid prefix length in address range {:?}", cidr),
+        }
+    }
+}
+impl std::error::Error for ParseCidrError {
+}
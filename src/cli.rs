@@ -0,0 +1,227 @@
+//! Command-line interface.
+
+
+use std::net::SocketAddr;
+use std::path::PathBuf;
+
+use clap::{ArgAction, ArgGroup, Parser, Subcommand};
+
+use crate::config::Format;
+
+
+/// Serves ASCII animations via Telnet.
+#[derive(Clone, Debug, Parser)]
+#[command(version, about)]
+#[command(group(ArgGroup::new("mode").args(["listen", "stdio"])))]
+pub(crate) struct Cli {
+    /// The configuration file to load.
+    #[arg(short, long, value_name = "CONFIG_FILE", conflicts_with_all = ["listen", "animation"])]
+    pub config: Option<PathBuf>,
+
+    /// The configuration file to load (same as --config).
+    #[arg(value_name = "CONFIG_FILE", conflicts_with_all = ["config", "listen", "animation"])]
+    pub config_positional: Option<PathBuf>,
+
+    /// The format of the configuration file (by default, guessed from its extension).
+    #[arg(long, value_name = "FORMAT", global = true)]
+    pub format: Option<Format>,
+
+    /// Serve a single animation on this address instead of loading a configuration file.
+    #[arg(short, long, value_name = "ADDRESS:PORT", requires = "animation")]
+    pub listen: Option<SocketAddr>,
+
+    /// The animation to serve on the address passed to --listen or via --stdio.
+    #[arg(short, long, value_name = "NAME", requires = "mode")]
+    pub animation: Option<String>,
+
+    /// Serve a single session on stdin and stdout (e.g. from inetd) and exit when it ends.
+    ///
+    /// The first socket of the configuration is used for the session settings unless --animation
+    /// is passed. Log messages are still written to stderr.
+    #[arg(long)]
+    pub stdio: bool,
+
+    /// Detach from the terminal and continue in the background (Unix only).
+    ///
+    /// Standard output and error are redirected to the log file if logging to a file, otherwise
+    /// they are discarded.
+    #[arg(long, conflicts_with = "stdio")]
+    pub daemon: bool,
+
+    /// Write the ID of the process to this file (overrides the configuration).
+    #[arg(long, value_name = "PATH")]
+    pub pid_file: Option<PathBuf>,
+
+    /// The number of worker threads of the runtime (overrides the configuration).
+    #[arg(long, value_name = "COUNT", conflicts_with = "current_thread", value_parser = clap::builder::RangedU64ValueParser::<usize>::new().range(1..))]
+    pub worker_threads: Option<usize>,
+
+    /// Run everything on a single thread (overrides the configuration).
+    #[arg(long)]
+    pub current_thread: bool,
+
+    /// Keep going with the sockets that could be set up if others cannot (e.g. because their port
+    /// is already in use), instead of refusing to start.
+    #[arg(long, conflicts_with = "stdio")]
+    pub best_effort: bool,
+
+    /// Output more diagnostics (can be repeated).
+    ///
+    /// -v outputs debug messages regardless of the configured log level, -vv additionally
+    /// hexdumps Telnet negotiation with each client in both directions, and -vvv hexdumps
+    /// everything exchanged with clients.
+    #[arg(short, long, action = ArgAction::Count, global = true)]
+    pub verbose: u8,
+
+    #[command(subcommand)]
+    pub command: Option<Command>,
+}
+impl Cli {
+    /// The configuration file passed on the command line, if any.
+    pub fn config_path(&self) -> Option<&PathBuf> {
+        self.config.as_ref()
+            .or(self.config_positional.as_ref())
+    }
+}
+
+
+#[derive(Clone, Debug, Subcommand)]
+pub(crate) enum Command {
+    /// Connect to a server as a minimal Telnet client and show its animation.
+    Connect {
+        /// The server to connect to.
+        #[arg(value_name = "HOST:PORT")]
+        target: String,
+    },
+
+    /// Play the client side of a recorded session back against a server and show what is sent in
+    /// both directions.
+    ///
+    /// Sessions are recorded using the `record` option of a socket. The server must be reachable
+    /// without TLS.
+    Replay {
+        /// The recording to play back.
+        #[arg(value_name = "RECORDING")]
+        recording: PathBuf,
+
+        /// The server to connect to.
+        #[arg(value_name = "HOST:PORT")]
+        target: String,
+
+        /// How much faster than recorded to send the client's data.
+        #[arg(long, default_value_t = 1.0)]
+        speed: f64,
+    },
+
+    /// Open many sessions to a server at once and report how well it keeps up.
+    ///
+    /// Exits with a non-zero status if any session fails.
+    Stress {
+        /// The server to connect to.
+        #[arg(value_name = "HOST:PORT")]
+        target: String,
+
+        /// How many sessions to open.
+        #[arg(short = 'n', long, default_value_t = 10)]
+        sessions: usize,
+
+        /// How many seconds to keep the sessions open.
+        #[arg(short, long, default_value_t = 10.0)]
+        seconds: f64,
+    },
+
+    /// Check a configuration file for problems and exit.
+    ///
+    /// Exits with a non-zero status if any problems are found that keep the server from starting;
+    /// warnings are only output.
+    Check {
+        /// The configuration file to check.
+        #[arg(value_name = "CONFIG_FILE")]
+        config: Option<PathBuf>,
+    },
+
+    /// List the animations that can be configured.
+    ListAnimations,
+
+    /// Print a commented configuration file containing all options with their default values.
+    DumpDefaultConfig,
+
+    /// Show an animation on the local terminal without opening any sockets.
+    Preview {
+        /// The name of the animation.
+        #[arg(value_name = "NAME")]
+        name: String,
+    },
+
+    /// Run an animation headlessly and save it as a recording.
+    #[command(subcommand)]
+    Export(ExportFormat),
+
+    /// Install the server as a Windows service using the configuration file.
+    #[cfg(windows)]
+    InstallService {
+        /// The configuration file the service is to use.
+        #[arg(value_name = "CONFIG_FILE")]
+        config: Option<PathBuf>,
+    },
+
+    /// Stop and remove the Windows service.
+    #[cfg(windows)]
+    UninstallService,
+
+    /// Run as a Windows service (invoked by the service control manager).
+    #[cfg(windows)]
+    #[command(hide = true)]
+    RunService,
+}
+
+
+#[derive(Clone, Debug, Subcommand)]
+pub(crate) enum ExportFormat {
+    /// Save the animation as an asciinema v2 recording.
+    Asciinema {
+        /// The name of the animation.
+        #[arg(value_name = "NAME")]
+        name: String,
+
+        /// The file to write the recording to.
+        #[arg(value_name = "OUTPUT.CAST")]
+        output: PathBuf,
+
+        /// How many seconds to record (unless the animation ends earlier).
+        #[arg(short, long, default_value_t = 10.0)]
+        seconds: f64,
+
+        /// The width of the recorded terminal (default: 80 or the width the animation requires).
+        #[arg(long)]
+        width: Option<u16>,
+
+        /// The height of the recorded terminal (default: 24 or the height the animation requires).
+        #[arg(long)]
+        height: Option<u16>,
+    },
+
+    /// Save the screen as it looks after each frame as plain text, as rendered by a virtual
+    /// terminal.
+    Screens {
+        /// The name of the animation.
+        #[arg(value_name = "NAME")]
+        name: String,
+
+        /// The file to write the screens to.
+        #[arg(value_name = "OUTPUT.TXT")]
+        output: PathBuf,
+
+        /// How many frames to render (unless the animation ends earlier).
+        #[arg(short, long, default_value_t = 10)]
+        frames: usize,
+
+        /// The width of the virtual terminal (default: 80 or the width the animation requires).
+        #[arg(long)]
+        width: Option<u16>,
+
+        /// The height of the virtual terminal (default: 24 or the height the animation requires).
+        #[arg(long)]
+        height: Option<u16>,
+    },
+}
@@ -0,0 +1,143 @@
+//! A minimal Telnet client that renders animations on the local terminal.
+//!
+//! Mostly useful to check whether a deployment works without installing a separate Telnet client.
+
+
+use std::io::{self, Write};
+use std::net::SocketAddr;
+
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+use tokio::net::TcpStream;
+
+use crate::console::local_terminal_size;
+use crate::telnet::{
+    self, decode_element, option, termtype, DEFAULT_MAX_SUB_NEGOTIATION_LENGTH, DO, DONT, Element, IAC, SB,
+    SE, WILL, WONT,
+};
+
+
+/// The terminal type reported to the server if `TERM` is not set.
+const DEFAULT_TERMINAL_TYPE: &str = "ANSI";
+
+
+/// Encodes a subnegotiation, escaping IAC bytes in its payload.
+fn encode_sub_negotiation(option_byte: u8, payload: &[u8]) -> Vec<u8> {
+    let mut ret = vec![IAC, SB, option_byte];
+    for &b in payload {
+        ret.push(b);
+        if b == IAC {
+            ret.push(IAC);
+        }
+    }
+    ret.push(IAC);
+    ret.push(SE);
+    ret
+}
+
+/// Computes the answer to a single element received from the server and outputs data locally.
+///
+/// The window size is reported as (columns, rows) if the server asks for it.
+pub(crate) fn process_element(element: Element, out: &mut Vec<u8>, window_size: (u16, u16)) -> Vec<u8> {
+    match element {
+        Element::Data(b) => {
+            out.push(b);
+            Vec::new()
+        },
+        Element::Command(_) => Vec::new(),
+        Element::Negotiation { command: DO, option: option::TERMINAL_TYPE } => {
+            vec![IAC, WILL, option::TERMINAL_TYPE]
+        },
+        Element::Negotiation { command: DO, option: option::NEGO_WIN_SIZE } => {
+            let (cols, rows) = window_size;
+            let mut payload = Vec::with_capacity(4);
+            payload.extend_from_slice(&cols.to_be_bytes());
+            payload.extend_from_slice(&rows.to_be_bytes());
+            encode_sub_negotiation(option::NEGO_WIN_SIZE, &payload)
+        },
+        Element::Negotiation { command: DO, option } => vec![IAC, WONT, option],
+        Element::Negotiation { command: WILL, option } => vec![IAC, DONT, option],
+        Element::Negotiation { .. } => Vec::new(),
+        Element::SubNegotiation(buf) => {
+            if buf.len() == 2 && buf[0] == option::TERMINAL_TYPE && buf[1] == termtype::SEND {
+                let term_type = std::env::var("TERM")
+                    .unwrap_or_else(|_| DEFAULT_TERMINAL_TYPE.to_owned());
+                let mut payload = vec![termtype::IS];
+                payload.extend_from_slice(term_type.as_bytes());
+                encode_sub_negotiation(option::TERMINAL_TYPE, &payload)
+            } else {
+                Vec::new()
+            }
+        },
+    }
+}
+
+pub(crate) async fn send<W: AsyncWrite + Unpin>(writer: &mut W, target: SocketAddr, buf: &[u8]) -> Result<(), telnet::Error> {
+    writer.write_all(buf)
+        .await.map_err(|e| telnet::Error::from_io_send(e, target))
+}
+
+/// Renders what the server sends on `reader` to stdout, answering it on `writer`.
+async fn render<R: AsyncRead + Unpin, W: AsyncWrite + Unpin>(mut reader: R, mut writer: W, addr: SocketAddr) -> Result<(), telnet::Error> {
+    // offer to tell the server our window size
+    send(&mut writer, addr, &[IAC, WILL, option::NEGO_WIN_SIZE]).await?;
+
+    let mut read_buf = Vec::new();
+    let mut stdout = io::stdout();
+    loop {
+        let byte_count = reader.read_buf(&mut read_buf)
+            .await.map_err(|e| telnet::Error::from_io_receive(e, addr))?;
+        if byte_count == 0 {
+            // server closed the connection
+            return Ok(());
+        }
+
+        let mut consumed = 0;
+        let mut out = Vec::new();
+        let mut replies = Vec::new();
+        let window_size = local_terminal_size();
+        while let Some((element, length)) = decode_element(&read_buf[consumed..], addr, DEFAULT_MAX_SUB_NEGOTIATION_LENGTH)? {
+            consumed += length;
+            replies.extend(process_element(element, &mut out, window_size));
+        }
+        read_buf.drain(..consumed);
+
+        if !replies.is_empty() {
+            send(&mut writer, addr, &replies).await?;
+        }
+
+        if stdout.write_all(&out).and_then(|_| stdout.flush()).is_err() {
+            // nobody is watching anymore
+            return Ok(());
+        }
+    }
+}
+
+
+/// Connects to the given server and renders whatever it sends to stdout.
+pub(crate) async fn run(target: &str) -> i32 {
+    let stream = match TcpStream::connect(target).await {
+        Ok(s) => s,
+        Err(e) => {
+            eprintln!("error: failed to connect to {}: {}", target, e);
+            return 1;
+        },
+    };
+    let addr = match stream.peer_addr() {
+        Ok(a) => a,
+        Err(e) => {
+            eprintln!("error: failed to obtain the address of {}: {}", target, e);
+            return 1;
+        },
+    };
+    let (reader, writer) = stream.into_split();
+    match render(reader, writer, addr).await {
+        Ok(()) => {
+            eprintln!("connection closed");
+            0
+        },
+        Err(e) => {
+            eprintln!("{}", e);
+            1
+        },
+    }
+}
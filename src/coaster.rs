@@ -1,7 +1,9 @@
 //! Rollercoaster logic.
 
-use std::collections::VecDeque;
-use std::fmt::Write;
+use std::collections::{BTreeSet, VecDeque};
+use std::fmt::{self, Write};
+
+use unicode_width::UnicodeWidthChar;
 
 
 #[derive(Clone, Copy, Debug, Eq, Hash, Ord, PartialEq, PartialOrd)]
@@ -22,7 +24,7 @@ impl Movement {
     /// geometrical coordinates).
     ///
     /// Returned in order (Y, X) to match ANSI escapes.
-    pub fn to_coordinates(&self) -> (isize, isize) {
+    pub fn to_coordinates(self) -> (isize, isize) {
         match self {
             Self::UpLeft => (-1, -1),
             Self::Up => (-1, 0),
@@ -72,17 +74,140 @@ pub(crate) fn decode_movements(movements: &str) -> Option<Vec<Movement>> {
 }
 
 
+/// Splits a string into glyphs, each consisting of a character and the zero-width characters (e.g.
+/// combining diacritics) following it, together with the number of terminal columns it occupies.
+fn split_glyphs(text: &str) -> Vec<(String, usize)> {
+    let mut ret: Vec<(String, usize)> = Vec::new();
+    for c in text.chars() {
+        let width = c.width().unwrap_or(0);
+        if width == 0 {
+            if let Some((last_glyph, _)) = ret.last_mut() {
+                last_glyph.push(c);
+                continue;
+            }
+        }
+        ret.push((c.to_string(), width.max(1)));
+    }
+    ret
+}
+
+
+/// Returns how many steps along the track each car of a train trails the front; each car follows
+/// the one ahead of it by its own width.
+fn car_offsets(train: &[(String, usize)]) -> Vec<usize> {
+    let mut offsets = Vec::with_capacity(train.len());
+    let mut offset = 0;
+    for (i, (_glyph, width)) in train.iter().enumerate() {
+        if i > 0 {
+            offset += width;
+        }
+        offsets.push(offset);
+    }
+    offsets
+}
+
+
+/// Lines up a train just before the given position, with its front at that position.
+///
+/// The train is given as read from left to right; since the front of a train moving right is its
+/// last character, the characters are returned reversed, along with the positions the front has
+/// passed through on its way there (see [`Rollercoaster::new`]).
+pub(crate) fn line_up_train(train: &str, front_row: isize, front_col: isize) -> (String, Vec<(isize, isize)>) {
+    let reversed: Vec<(String, usize)> = split_glyphs(train)
+        .into_iter()
+        .rev()
+        .collect();
+    let length = car_offsets(&reversed).last().map_or(0, |offset| offset + 1);
+    let positions = (0..length)
+        .map(|i| (front_row, front_col - i as isize))
+        .collect();
+    let reversed = reversed.into_iter()
+        .map(|(glyph, _width)| glyph)
+        .collect();
+    (reversed, positions)
+}
+
+
+/// An error that may occur when setting up a rollercoaster.
+#[derive(Clone, Copy, Debug, Eq, Hash, Ord, PartialEq, PartialOrd)]
+#[non_exhaustive]
+pub(crate) enum Error {
+    EmptyBase,
+    EmptyTrain,
+    TrainStartMismatch { train_length: usize, start_length: usize },
+}
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::EmptyBase
+                => write!(f, "the base frame must not be empty"),
+            Self::EmptyTrain
+                => write!(f, "the train must not be empty"),
+            Self::TrainStartMismatch { train_length, start_length }
+                => write!(f, "the train needs {} starting positions but has {}", train_length, start_length),
+        }
+    }
+}
+impl std::error::Error for Error {
+}
+
+
+/// Whether a train contains nothing that would show up on the screen.
+pub(crate) fn is_invisible_train(train: &str) -> bool {
+    train.chars().all(|c| c.width().unwrap_or(0) == 0)
+}
+
+
+/// A single terminal column of the base frame.
+#[derive(Clone, Debug, Eq, Hash, Ord, PartialEq, PartialOrd)]
+enum BaseCell {
+    /// The leftmost column of a glyph, which is stored here.
+    Glyph(String),
+
+    /// A column covered by a wide glyph starting further left.
+    Continuation,
+}
+
+/// Lays out a line of the base frame into terminal columns.
+fn layout_base_line(line: &str) -> Vec<BaseCell> {
+    let mut ret = Vec::new();
+    for (glyph, width) in split_glyphs(line) {
+        ret.push(BaseCell::Glyph(glyph));
+        for _ in 1..width {
+            ret.push(BaseCell::Continuation);
+        }
+    }
+    ret
+}
+
+
+/// A rollercoaster animation.
+///
+/// All positions are measured in terminal columns, so base frames and trains may contain wide
+/// (e.g. CJK or emoji) characters.
 #[derive(Clone, Debug, Eq, Hash, Ord, PartialEq, PartialOrd)]
 pub(crate) struct Rollercoaster {
     base_lines: Vec<String>,
-    train: String,
+    base_cells: Vec<Vec<BaseCell>>,
+    train: Vec<(String, usize)>,
+
+    /// How many positions of the front each car of the train trails it by.
+    car_offsets: Vec<usize>,
+
     train_start: Vec<(isize, isize)>,
     movements: Vec<Movement>,
 
+    /// The positions the front of the train has passed through, the most recent first.
     train_positions: VecDeque<(isize, isize)>,
     frame_index: usize,
 }
 impl Rollercoaster {
+    /// Sets up a rollercoaster.
+    ///
+    /// The train is given front first. Its cars follow the path of the front, each trailing the car
+    /// ahead of it by its own width, so `train_start` lists the positions the front has passed
+    /// through before the ride starts, the most recent first: one more than the combined width of
+    /// all the cars but the front one.
     pub fn new<
         L: Into<Vec<String>>,
         T: Into<String>,
@@ -93,24 +218,42 @@ impl Rollercoaster {
         train: T,
         train_start: S,
         movements: M,
-    ) -> Self {
-        let train_start_vec = train_start.into();
-        let ret = Self {
-            base_lines: base_lines.into(),
-            train: train.into(),
+    ) -> Result<Self, Error> {
+        let base_lines = base_lines.into();
+        let base_cells = base_lines.iter()
+            .map(|bl| layout_base_line(bl))
+            .collect();
+        if base_lines.first().is_none_or(|bl| bl.is_empty()) {
+            return Err(Error::EmptyBase);
+        }
+        let train = train.into();
+        if is_invisible_train(&train) {
+            return Err(Error::EmptyTrain);
+        }
+        let train = split_glyphs(&train);
+        let car_offsets = car_offsets(&train);
+        let train_length = car_offsets.last().map_or(0, |offset| offset + 1);
+        let train_start_vec: Vec<(isize, isize)> = train_start.into();
+        if train_length != train_start_vec.len() {
+            return Err(Error::TrainStartMismatch {
+                train_length,
+                start_length: train_start_vec.len(),
+            });
+        }
+        Ok(Self {
+            base_lines,
+            base_cells,
+            train,
+            car_offsets,
             train_start: train_start_vec.clone(),
             movements: movements.into(),
 
             train_positions: VecDeque::from(train_start_vec),
             frame_index: 0,
-        };
-        assert_ne!(ret.base_lines.len(), 0);
-        assert_ne!(ret.base_lines[0].len(), 0);
-        assert_ne!(ret.train.len(), 0);
-        assert_eq!(ret.train.len(), ret.train_start.len());
-        ret
+        })
     }
 
+    #[allow(dead_code)]
     pub fn get_total_frames(&self) -> usize {
         self.movements.len()
     }
@@ -125,8 +268,8 @@ impl Rollercoaster {
     }
 
     pub fn get_width(&self) -> isize {
-        self.base_lines.iter()
-            .map(|bl| bl.chars().count())
+        self.base_cells.iter()
+            .map(|bc| bc.len())
             .max().unwrap() as isize
     }
 
@@ -143,6 +286,52 @@ impl Rollercoaster {
         }
     }
 
+    /// Outputs the commands that restore the given columns of a row to the base frame.
+    ///
+    /// If the columns cut through a wide glyph, the whole glyph is restored.
+    fn restore_base(&self, ret: &mut String, row: isize, start_col: isize, width: usize) {
+        if row < 0 || row >= self.get_height() {
+            return;
+        }
+        let cells = &self.base_cells[row as usize];
+
+        // if we start in the middle of a wide glyph, restore it from its beginning
+        let mut col = start_col.max(0);
+        while col > 0 && cells.get(col as usize) == Some(&BaseCell::Continuation) {
+            col -= 1;
+        }
+        let end_col = (start_col + width as isize).min(self.get_width());
+        if col >= end_col {
+            return;
+        }
+
+        write!(ret, "\x1B[{};{}H", row+1, col+1).unwrap();
+        while col < end_col || cells.get(col as usize) == Some(&BaseCell::Continuation) {
+            match cells.get(col as usize) {
+                Some(BaseCell::Glyph(glyph)) => ret.push_str(glyph),
+                Some(BaseCell::Continuation) => {},
+                None => ret.push(' '),
+            }
+            col += 1;
+        }
+    }
+
+    /// Whether a car of the train at the given position is drawn; cars are only drawn if they start
+    /// within the base frame.
+    fn is_drawn(&self, row: isize, col: isize) -> bool {
+        row >= 0 && col >= 0 && row < self.get_height() && col < self.get_width()
+    }
+
+    /// Returns the position and width of each car of the train.
+    fn cars(&self) -> impl Iterator<Item = (isize, isize, usize)> + '_ {
+        self.car_offsets.iter()
+            .zip(self.train.iter())
+            .map(|(&offset, (_glyph, width))| {
+                let (row, col) = self.train_positions[offset];
+                (row, col, *width)
+            })
+    }
+
     pub fn advance(&mut self) -> Option<String> {
         let mut ret = String::new();
 
@@ -150,37 +339,36 @@ impl Rollercoaster {
             return None;
         }
 
-        // return the last character of the train to its original state
-        let &(last_seg_row, last_seg_col) = self.train_positions.back().unwrap();
-        if last_seg_row >= 0 && last_seg_col >= 0 && last_seg_row < self.get_height() && last_seg_col < self.get_width() {
-            let base_char = self.base_lines[last_seg_row as usize]
-                .chars()
-                .nth(last_seg_col as usize)
-                .unwrap_or(' ');
-            write!(ret, "\x1B[{};{}H{}", last_seg_row+1, last_seg_col+1, base_char).unwrap();
-        }
-
-        // drop the last train position
+        // move the front according to the movement, the cars follow
+        let old_cars: Vec<(isize, isize, usize)> = self.cars().collect();
         self.train_positions.pop_back();
-
-        // calculate the new position by looking at the movement
         let (cur_row, cur_col) = *self.train_positions.front().unwrap();
         let (move_row, move_col) = self.movements[self.frame_index].to_coordinates();
         self.train_positions.push_front((cur_row + move_row, cur_col + move_col));
 
-        // update the train positions
-        let mut last_pos = None;
-        for (&(pos_row, pos_col), train_char) in self.train_positions.iter().zip(self.train.chars()) {
-            if pos_row < 0 || pos_col < 0 {
-                continue;
+        // return what the train has left to its original state
+        let new_cells: BTreeSet<(isize, isize)> = self.cars()
+            .filter(|&(row, col, _width)| self.is_drawn(row, col))
+            .flat_map(|(row, col, width)| (col..col + width as isize).map(move |c| (row, c)))
+            .collect();
+        for (row, col, width) in old_cars {
+            for c in col..col + width as isize {
+                if !new_cells.contains(&(row, c)) {
+                    self.restore_base(&mut ret, row, c, 1);
+                }
             }
-            if pos_row >= self.get_height() || pos_col >= self.get_width() {
+        }
+
+        // update the train positions
+        let mut last_end = None;
+        for ((pos_row, pos_col, train_width), (train_glyph, _)) in self.cars().zip(self.train.iter()) {
+            if !self.is_drawn(pos_row, pos_col) {
                 continue;
             }
 
             let mut set_new_pos = true;
-            if let Some((last_row, last_col)) = last_pos {
-                if pos_row == last_row && pos_col == last_col + 1 {
+            if let Some((last_row, last_end_col)) = last_end {
+                if pos_row == last_row && pos_col == last_end_col {
                     // it's the next character in the line; we need not reposition the cursor
                     set_new_pos = false;
                 }
@@ -189,9 +377,9 @@ impl Rollercoaster {
             if set_new_pos {
                 write!(ret, "\x1B[{};{}H", pos_row+1, pos_col+1).unwrap();
             }
-            write!(ret, "{}", train_char).unwrap();
+            ret.push_str(train_glyph);
 
-            last_pos = Some((pos_row, pos_col));
+            last_end = Some((pos_row, pos_col + train_width as isize));
         }
 
         // increase the frame index
@@ -200,3 +388,61 @@ impl Rollercoaster {
         Some(ret)
     }
 }
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::screen::Screen;
+
+    /// Rides a train along a straight track and returns the row it is on after each step.
+    fn ride(train: &str, steps: usize) -> Vec<String> {
+        let (train, train_start) = line_up_train(train, 0, -1);
+        let movements = vec![Movement::Right; steps];
+        let mut coaster = Rollercoaster::new(vec![".".repeat(12)], train, train_start, movements)
+            .unwrap();
+        let mut screen = Screen::new(12, 2);
+        screen.feed(&coaster.get_base_frame());
+        let mut rows = Vec::new();
+        while let Some(commands) = coaster.advance() {
+            screen.feed(&commands);
+            rows.push(screen.rows()[0].iter().collect());
+        }
+        rows
+    }
+
+    #[test]
+    fn narrow_train() {
+        let rows = ride("LOL", 5);
+        assert_eq!(rows, [
+            "L...........",
+            "OL..........",
+            "LOL.........",
+            ".LOL........",
+            "..LOL.......",
+        ]);
+    }
+
+    #[test]
+    fn wide_train() {
+        // the screen keeps a space in the right half of a wide character
+        let rows = ride("A\u{6F22}\u{5B57}B", 8);
+        assert_eq!(rows, [
+            "B...........",
+            ".B..........",
+            "\u{5B57} B.........",
+            ".\u{5B57} B........",
+            "\u{6F22} \u{5B57} B.......",
+            "A\u{6F22} \u{5B57} B......",
+            ".A\u{6F22} \u{5B57} B.....",
+            "..A\u{6F22} \u{5B57} B....",
+        ]);
+    }
+
+    #[test]
+    fn invisible_train() {
+        let (train, train_start) = line_up_train("\u{200B}", 0, -1);
+        let result = Rollercoaster::new(vec!["...".to_owned()], train, train_start, vec![Movement::Right]);
+        assert_eq!(result.err(), Some(Error::EmptyTrain));
+    }
+}
@@ -0,0 +1,1022 @@
+//! Configuration of the server.
+
+
+use std::borrow::Cow;
+use std::env;
+use std::fmt;
+use std::fs::File;
+use std::io::{self, Read};
+use std::net::{IpAddr, SocketAddr};
+use std::path::{Path, PathBuf};
+use std::string::FromUtf8Error;
+
+use clap::ValueEnum;
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+
+use crate::animations::{self, AnimationConfig, CreateError};
+use crate::animations::{ansi_art, asciimation, composite, deck, figlet, gif, marquee, script, slideshow, wasm};
+use crate::admin::AdminConfig;
+use crate::cidr::Cidr;
+use crate::logging::{LogConfig, LogTarget};
+use crate::playlist::PlaylistItem;
+use crate::recording::RecordConfig;
+use crate::runtime::{RuntimeConfig, RuntimeFlavor};
+use crate::sandbox::SandboxConfig;
+use crate::telnet;
+use crate::tls::{self, TlsConfig};
+
+
+/// The configuration file loaded if none is passed on the command line.
+pub(crate) const DEFAULT_PATH: &str = "config.toml";
+
+/// The environment variable that may contain the whole configuration (in TOML format).
+pub(crate) const CONFIG_VARIABLE: &str = "TELNET_ANIMATIONS_CONFIG";
+
+/// The prefix of environment variables overriding individual configuration values.
+///
+/// The rest of the variable name is the path to the value, with the components separated by
+/// double underscores, e.g. `TELNET_ANIMATIONS__SOCKETS__0__LISTEN_SOCKET_ADDR`.
+pub(crate) const OVERRIDE_PREFIX: &str = "TELNET_ANIMATIONS__";
+
+
+#[derive(Clone, Debug, Deserialize, JsonSchema, PartialEq, Serialize)]
+pub(crate) struct Config {
+    /// The sockets to listen on and what to serve on each of them.
+    pub sockets: Vec<SocketConfig>,
+
+    /// Name of an animation to briefly show on the console at startup.
+    #[serde(default)]
+    pub startup_banner: Option<String>,
+
+    /// The maximum number of sessions live at the same time, across all sockets.
+    #[serde(default)]
+    pub max_total_connections: Option<usize>,
+
+    /// The maximum number of sessions live at the same time from the same client address.
+    #[serde(default)]
+    pub max_connections_per_ip: Option<usize>,
+
+    /// The maximum number of connections accepted from the same client address within a minute.
+    #[serde(default)]
+    pub max_new_connections_per_ip_per_minute: Option<usize>,
+
+    /// Where and how much to log.
+    #[serde(default)]
+    pub log: LogConfig,
+
+    /// Whether to look up the host names of clients and mention them in the connection logs.
+    #[serde(default)]
+    pub reverse_dns: bool,
+
+    /// How often to log a summary of the connections and traffic, in minutes. No summaries are
+    /// logged if unset.
+    #[serde(default)]
+    pub stats_interval_mins: Option<u64>,
+
+    /// Watch the files the animations are loaded from (frame decks, scripts, images and so on)
+    /// and load them again when they change: new sessions get the new version, running sessions
+    /// switch to it at the start of the next cycle. Broadcasts keep the version they started
+    /// with.
+    #[serde(default)]
+    pub watch_animation_files: bool,
+
+    /// A local interface for listing and controlling sessions.
+    #[serde(default)]
+    pub admin: Option<AdminConfig>,
+
+    /// The user to switch to once all sockets have been bound, e.g. to serve port 23 without
+    /// running as root (Unix only).
+    #[serde(default)]
+    pub user: Option<String>,
+
+    /// The group to switch to once all sockets have been bound; defaults to the primary group of
+    /// `user` (Unix only).
+    #[serde(default)]
+    pub group: Option<String>,
+
+    /// A file to write the ID of the process to; it is removed again when the server exits.
+    #[serde(default)]
+    pub pid_file: Option<PathBuf>,
+
+    /// Restrict what the process may do once it has started (Linux only).
+    #[serde(default)]
+    pub sandbox: Option<SandboxConfig>,
+
+    /// Tuning of the async runtime.
+    #[serde(default)]
+    pub runtime: RuntimeConfig,
+}
+
+#[derive(Clone, Debug, Deserialize, JsonSchema, PartialEq, Serialize)]
+pub(crate) struct SocketConfig {
+    /// The address and port to listen on.
+    pub listen_socket_addr: SocketAddr,
+
+    /// The animation to show.
+    #[serde(default)]
+    pub animation: Option<AnimationConfig>,
+
+    /// Animations to offer in a menu, after `animation` (if any).
+    #[serde(default)]
+    pub animations: Vec<AnimationConfig>,
+
+    /// Instead of letting the client choose, show these animations one after the other (e.g.
+    /// `["roflcopter:30s", "lollercoaster:2loops"]`), starting over after the last one. Animations
+    /// also configured in `animation` or `animations` are shown with the parameters given there,
+    /// others with their defaults. The number keys still switch to the configured animations,
+    /// leaving the playlist.
+    #[serde(default)]
+    pub playlist: Vec<PlaylistItem>,
+
+    /// Always let the client choose from a menu, even if only one animation is configured. If no
+    /// animations are configured at all, all available animations (except those that need
+    /// parameters) are offered. Clients can return to the menu by pressing Escape.
+    #[serde(default)]
+    pub menu: bool,
+
+    /// The title shown above the menu.
+    #[serde(default = "SocketConfig::default_menu_title")]
+    pub menu_title: String,
+
+    /// Instead of showing animations, let clients watch what another live session is being sent,
+    /// chosen by its ID as listed by the admin interface. Access should be restricted using
+    /// `allow`.
+    #[serde(default)]
+    pub spectator: bool,
+
+    /// Instead of the menu, show the names of the animations and let the client type the name of
+    /// one; this works even for clients that cannot show the menu properly or send cursor keys.
+    #[serde(default)]
+    pub name_prompt: bool,
+
+    /// The maximum length of a subnegotiation sent by a client; longer ones abort the session.
+    #[serde(default = "SocketConfig::default_max_sub_negotiation_length")]
+    pub max_sub_negotiation_length: usize,
+
+    /// How long to wait for the client to answer the terminal type query before assuming an ANSI
+    /// terminal and starting the animation anyway, in milliseconds.
+    #[serde(default = "SocketConfig::default_negotiation_timeout_ms")]
+    pub negotiation_timeout_ms: u64,
+
+    /// Disconnect sessions after they have been connected for this many seconds.
+    #[serde(default)]
+    pub max_session_secs: Option<u64>,
+
+    /// During this many seconds before `max_session_secs` is reached, the time left is shown in the
+    /// top right corner of the client's terminal. 0 disables the countdown.
+    #[serde(default = "SocketConfig::default_countdown_secs")]
+    pub countdown_secs: u64,
+
+    /// Disconnect sessions whose client has not sent anything for this many seconds.
+    #[serde(default)]
+    pub idle_secs: Option<u64>,
+
+    /// Disconnect sessions after the animation has run through this many cycles (or, with a
+    /// playlist, after the playlist has been played through this many times).
+    #[serde(default)]
+    pub loops: Option<u64>,
+
+    /// Once the animation is over (or has run through `loops` cycles, one by default), hold the
+    /// final frame and let the client press a key to watch it again instead of disconnecting. With
+    /// a playlist, this happens once it has been played through `loops` times (once by default).
+    #[serde(default)]
+    pub replay_on_key: bool,
+
+    /// Keep showing the animation to clients that have closed their sending side of the
+    /// connection (e.g. `nc -q`) instead of saying goodbye. Sessions that are waiting for input,
+    /// e.g. on a menu, are ended regardless.
+    #[serde(default)]
+    pub play_after_end_of_input: bool,
+
+    /// A text file (e.g. rules or credits) to show before the animation begins. Relative paths
+    /// are resolved against the working directory.
+    #[serde(default)]
+    pub banner_file: Option<PathBuf>,
+
+    /// Whether to wait for a key press after showing the banner file.
+    #[serde(default)]
+    pub banner_press_any_key: bool,
+
+    /// How long to show the banner file before continuing, in seconds, if not waiting for a key
+    /// press.
+    #[serde(default = "SocketConfig::default_banner_secs")]
+    pub banner_secs: u64,
+
+    /// The line of text sent (after resetting the terminal) when the server ends a session.
+    #[serde(default = "SocketConfig::default_goodbye_message")]
+    pub goodbye_message: String,
+
+    /// Whether to disable Nagle's algorithm on accepted connections, sending each frame
+    /// immediately.
+    #[serde(default = "SocketConfig::default_tcp_nodelay")]
+    pub tcp_nodelay: bool,
+
+    /// The size of the send buffer of accepted connections, in bytes; by default, the operating
+    /// system decides.
+    #[serde(default)]
+    pub send_buffer_size: Option<u32>,
+
+    /// Probe idle connections with TCP keepalives, so that sessions whose clients have vanished
+    /// without a trace (e.g. behind a NAT gateway) are ended.
+    #[serde(default)]
+    pub tcp_keepalive: Option<KeepaliveConfig>,
+
+    /// The maximum number of connections waiting to be accepted.
+    #[serde(default = "SocketConfig::default_listen_backlog")]
+    pub listen_backlog: u32,
+
+    /// Whether to set SO_REUSEADDR, allowing the address to be bound again immediately after a
+    /// restart.
+    #[serde(default = "SocketConfig::default_reuse_address")]
+    pub reuse_address: bool,
+
+    /// Whether to set SO_REUSEPORT, allowing multiple processes to listen on the same address
+    /// (Unix only).
+    #[serde(default)]
+    pub reuse_port: bool,
+
+    /// The number of listeners accepting connections on this address, each bound with
+    /// SO_REUSEPORT so that the kernel distributes connections between them (Unix only).
+    #[serde(default = "SocketConfig::default_workers")]
+    pub workers: usize,
+
+    /// If not empty, only clients within these address ranges (e.g. `192.168.0.0/16`) may connect.
+    #[serde(default)]
+    pub allow: Vec<Cidr>,
+
+    /// Clients within these address ranges may not connect, even if they are allowed by `allow`.
+    #[serde(default)]
+    pub deny: Vec<Cidr>,
+
+    /// Whether connections start with a PROXY protocol header (version 1 or 2) from a load
+    /// balancer, which specifies the address of the actual client. Connections without one are
+    /// refused.
+    #[serde(default)]
+    pub proxy_protocol: bool,
+
+    /// The protocol spoken on the socket: `telnet`, `websocket` for web terminals such as xterm.js
+    /// (with its attach addon), `http` for streaming to `curl`, or `raw` for clients that do not
+    /// speak Telnet, such as `nc`.
+    #[serde(default)]
+    pub protocol: Protocol,
+
+    /// Whether to render each animation once and show the same frames to all clients watching it,
+    /// instead of running the animation separately for each of them. Saves resources with many
+    /// viewers, but the frames cannot be adapted to each client's terminal.
+    #[serde(default)]
+    pub broadcast: bool,
+
+    /// Whether to show how many clients are watching a broadcast in the bottom right corner.
+    #[serde(default = "SocketConfig::default_show_viewer_count")]
+    pub show_viewer_count: bool,
+
+    /// Serve Telnet over TLS (telnets) using these certificates.
+    #[serde(default)]
+    pub tls: Option<TlsConfig>,
+
+    /// Record the bytes exchanged with clients (after decryption) to files, e.g. to reproduce
+    /// problems with a particular client using the `replay` subcommand.
+    #[serde(default)]
+    pub record: Option<RecordConfig>,
+}
+impl SocketConfig {
+    /// Creates a socket configuration serving a single animation with default settings.
+    pub fn new(listen_socket_addr: SocketAddr, animation: AnimationConfig) -> Self {
+        Self {
+            listen_socket_addr,
+            animation: Some(animation),
+            animations: Vec::new(),
+            playlist: Vec::new(),
+            menu: false,
+            menu_title: Self::default_menu_title(),
+            spectator: false,
+            name_prompt: false,
+            max_sub_negotiation_length: Self::default_max_sub_negotiation_length(),
+            negotiation_timeout_ms: Self::default_negotiation_timeout_ms(),
+            max_session_secs: None,
+            countdown_secs: Self::default_countdown_secs(),
+            idle_secs: None,
+            loops: None,
+            replay_on_key: false,
+            play_after_end_of_input: false,
+            banner_file: None,
+            banner_press_any_key: false,
+            banner_secs: Self::default_banner_secs(),
+            goodbye_message: Self::default_goodbye_message(),
+            tcp_nodelay: Self::default_tcp_nodelay(),
+            send_buffer_size: None,
+            tcp_keepalive: None,
+            listen_backlog: Self::default_listen_backlog(),
+            reuse_address: Self::default_reuse_address(),
+            reuse_port: false,
+            workers: Self::default_workers(),
+            allow: Vec::new(),
+            deny: Vec::new(),
+            proxy_protocol: false,
+            protocol: Protocol::default(),
+            broadcast: false,
+            show_viewer_count: Self::default_show_viewer_count(),
+            tls: None,
+            record: None,
+        }
+    }
+
+    /// Whether clients from this address may connect according to `allow` and `deny`.
+    pub fn is_allowed(&self, addr: IpAddr) -> bool {
+        let allowed = self.allow.is_empty() || self.allow.iter().any(|c| c.contains(addr));
+        allowed && !self.deny.iter().any(|c| c.contains(addr))
+    }
+
+    /// All animations configured for this socket or, if none are and `menu` is set, all available
+    /// animations that can be shown with their default parameters.
+    pub fn animation_choices(&self) -> Vec<Cow<'_, AnimationConfig>> {
+        let configured: Vec<Cow<'_, AnimationConfig>> = self.animation.iter()
+            .chain(self.animations.iter())
+            .map(Cow::Borrowed)
+            .collect();
+        if !self.menu || !configured.is_empty() {
+            return configured;
+        }
+        animations::ANIMATIONS.iter()
+            .filter(|info| !info.needs_params)
+            .map(|info| Cow::Owned(AnimationConfig::named(info.name)))
+            .collect()
+    }
+
+    /// Whether the client chooses the animation from a menu.
+    pub fn has_menu(&self) -> bool {
+        self.playlist.is_empty() && (self.menu || self.animation_choices().len() > 1)
+    }
+
+    /// The animation shown for an entry of the playlist: the configured animation of that name or,
+    /// if there is none, the animation with its default parameters.
+    pub fn playlist_animation(&self, item: &PlaylistItem) -> Cow<'_, AnimationConfig> {
+        self.animation.iter()
+            .chain(self.animations.iter())
+            .find(|choice| choice.name == item.name)
+            .map(Cow::Borrowed)
+            .unwrap_or_else(|| Cow::Owned(AnimationConfig::named(item.name.clone())))
+    }
+
+    /// The files and directories that the animations shown on this socket are loaded from.
+    pub fn animation_files(&self) -> Vec<PathBuf> {
+        let mut files = Vec::new();
+        for choice in self.all_animations() {
+            let choice_files = match choice.name.as_str() {
+                name if name == ansi_art::INFO.name => choice.parse_params::<ansi_art::Params>().map(|p| vec![p.path]),
+                name if name == asciimation::INFO.name => choice.parse_params::<asciimation::Params>().map(|p| vec![p.path]),
+                name if name == deck::INFO.name => choice.parse_params::<deck::Params>().map(|p| vec![p.path]),
+                name if name == figlet::INFO.name => choice.parse_params::<figlet::Params>().map(|p| p.font_file.into_iter().collect()),
+                name if name == gif::INFO.name => choice.parse_params::<gif::Params>().map(|p| vec![p.path]),
+                name if name == marquee::INFO.name => choice.parse_params::<marquee::Params>().map(|p| p.file.into_iter().collect()),
+                name if name == script::INFO.name => choice.parse_params::<script::Params>().map(|p| vec![p.path]),
+                name if name == slideshow::INFO.name => choice.parse_params::<slideshow::Params>().map(|p| p.read_paths()),
+                name if name == wasm::INFO.name => choice.parse_params::<wasm::Params>().map(|p| vec![p.path]),
+                _ => continue,
+            };
+            files.extend(choice_files.unwrap_or_default());
+        }
+        files
+    }
+
+    /// Whether the animation with the given name may be shown on this socket, be it on its own or
+    /// in a layer of a composite animation.
+    pub fn uses_animation(&self, name: &str) -> bool {
+        self.all_animations().iter().any(|choice| choice.name == name)
+    }
+
+    /// The animations offered and played on this socket, followed by those shown in the layers of
+    /// composite animations among them.
+    fn all_animations(&self) -> Vec<Cow<'_, AnimationConfig>> {
+        let playlist_animations = self.playlist.iter()
+            .map(|item| self.playlist_animation(item));
+        let mut pending: Vec<_> = self.animation_choices().into_iter()
+            .chain(playlist_animations)
+            .collect();
+        let mut all = Vec::new();
+        while let Some(choice) = pending.pop() {
+            if choice.name == composite::INFO.name {
+                if let Ok(params) = choice.parse_params::<composite::Params>() {
+                    pending.extend(params.layers.into_iter().filter_map(|layer| layer.animation).map(Cow::Owned));
+                }
+            }
+            all.push(choice);
+        }
+        all
+    }
+
+    fn default_max_sub_negotiation_length() -> usize { telnet::DEFAULT_MAX_SUB_NEGOTIATION_LENGTH }
+    fn default_menu_title() -> String { "Choose an animation:".to_owned() }
+    fn default_negotiation_timeout_ms() -> u64 { 3000 }
+    fn default_countdown_secs() -> u64 { 10 }
+    fn default_banner_secs() -> u64 { 5 }
+    fn default_goodbye_message() -> String { "Thanks for watching!".to_owned() }
+    fn default_tcp_nodelay() -> bool { true }
+    fn default_show_viewer_count() -> bool { true }
+    fn default_listen_backlog() -> u32 { 1024 }
+    fn default_reuse_address() -> bool { cfg!(unix) }
+    fn default_workers() -> usize { 1 }
+}
+
+
+/// The protocol spoken with clients on a socket.
+#[derive(Clone, Copy, Debug, Default, Deserialize, Eq, Hash, JsonSchema, PartialEq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub(crate) enum Protocol {
+    /// Telnet, with negotiation of the terminal type and window size.
+    #[default]
+    Telnet,
+
+    /// WebSocket; data is exchanged in binary (or text) messages without any negotiation.
+    WebSocket,
+
+    /// HTTP; the animation named in the path is streamed as plain text, and browsers are shown a
+    /// list of the animations.
+    Http,
+
+    /// A plain byte stream without any negotiation, e.g. for `nc` or behind gateways that handle
+    /// the terminal themselves.
+    Raw,
+}
+
+
+/// Settings of TCP keepalive probes.
+#[derive(Clone, Debug, Deserialize, Eq, Hash, JsonSchema, PartialEq, Serialize)]
+pub(crate) struct KeepaliveConfig {
+    /// How long a connection must be idle before the first probe is sent, in seconds.
+    #[serde(default = "KeepaliveConfig::default_idle_secs")]
+    pub idle_secs: u64,
+
+    /// How long to wait between unanswered probes, in seconds (where supported).
+    #[serde(default = "KeepaliveConfig::default_interval_secs")]
+    pub interval_secs: u64,
+
+    /// How many probes may go unanswered before the connection is dropped (where supported).
+    #[serde(default = "KeepaliveConfig::default_retries")]
+    pub retries: u32,
+}
+impl KeepaliveConfig {
+    fn default_idle_secs() -> u64 { 60 }
+    fn default_interval_secs() -> u64 { 10 }
+    fn default_retries() -> u32 { 6 }
+}
+
+
+/// The format of a configuration file.
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq, ValueEnum)]
+pub(crate) enum Format {
+    Toml,
+    Json,
+    Yaml,
+}
+impl Format {
+    /// Guesses the format from the extension of the file name, falling back to TOML.
+    pub fn from_path(path: &Path) -> Self {
+        let extension = path.extension()
+            .and_then(|e| e.to_str())
+            .map(|e| e.to_ascii_lowercase());
+        match extension.as_deref() {
+            Some("json") => Self::Json,
+            Some("yaml") | Some("yml") => Self::Yaml,
+            _ => Self::Toml,
+        }
+    }
+
+    /// Parses the configuration text into a table.
+    fn parse(self, text: &str) -> Result<toml::Table, ParseError> {
+        match self {
+            Self::Toml => toml::from_str(text).map_err(ParseError::Toml),
+            Self::Json => serde_json::from_str(text).map_err(ParseError::Json),
+            Self::Yaml => serde_yaml::from_str(text).map_err(ParseError::Yaml),
+        }
+    }
+}
+
+
+/// An error that may occur while parsing a configuration file.
+#[derive(Debug)]
+#[non_exhaustive]
+pub(crate) enum ParseError {
+    Toml(toml::de::Error),
+    Json(serde_json::Error),
+    Yaml(serde_yaml::Error),
+}
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Toml(e) => write!(f, "{}", e),
+            Self::Json(e) => write!(f, "{}", e),
+            Self::Yaml(e) => write!(f, "{}", e),
+        }
+    }
+}
+impl std::error::Error for ParseError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Self::Toml(e) => Some(e),
+            Self::Json(e) => Some(e),
+            Self::Yaml(e) => Some(e),
+        }
+    }
+}
+
+
+/// An error that may occur while loading the configuration.
+#[derive(Debug)]
+#[non_exhaustive]
+pub(crate) enum Error {
+    #[non_exhaustive]
+    Open { path: PathBuf, error: io::Error },
+
+    #[non_exhaustive]
+    Read { path: PathBuf, error: io::Error },
+
+    #[non_exhaustive]
+    Decode { path: PathBuf, error: FromUtf8Error },
+
+    #[non_exhaustive]
+    Parse { path: PathBuf, error: ParseError },
+
+    #[non_exhaustive]
+    ParseVariable { variable: String, error: ParseError },
+
+    #[non_exhaustive]
+    Include { origin: String, message: String },
+
+    #[non_exhaustive]
+    Override { variable: String, message: String },
+
+    #[non_exhaustive]
+    Deserialize { error: toml::de::Error },
+}
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Open { path, error }
+                => write!(f, "failed to open config file {}: {}", path.display(), error),
+            Self::Read { path, error }
+                => write!(f, "failed to read config file {}: {}", path.display(), error),
+            Self::Decode { path, error }
+                => write!(f, "failed to decode config file {} as UTF-8: {}", path.display(), error),
+            Self::Parse { path, error }
+                => write!(f, "failed to parse config file {}: {}", path.display(), error),
+            Self::ParseVariable { variable, error }
+                => write!(f, "failed to parse configuration in environment variable {}: {}", variable, error),
+            Self::Include { origin, message }
+                => write!(f, "failed to process includes of {}: {}", origin, message),
+            Self::Override { variable, message }
+                => write!(f, "failed to apply environment variable {}: {}", variable, message),
+            Self::Deserialize { error }
+                => write!(f, "invalid configuration: {}", error),
+        }
+    }
+}
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Self::Open { error, .. } => Some(error),
+            Self::Read { error, .. } => Some(error),
+            Self::Decode { error, .. } => Some(error),
+            Self::Parse { error, .. } => Some(error),
+            Self::ParseVariable { error, .. } => Some(error),
+            Self::Include { .. } => None,
+            Self::Override { .. } => None,
+            Self::Deserialize { error } => Some(error),
+        }
+    }
+}
+
+
+/// How severe a problem with the configuration is.
+#[derive(Clone, Copy, Debug, Eq, Hash, Ord, PartialEq, PartialOrd)]
+pub(crate) enum Severity {
+    /// The server can run, but probably not as intended.
+    Warning,
+
+    /// The server cannot run with this configuration.
+    Error,
+}
+
+/// A problem found while validating the configuration.
+#[derive(Clone, Debug, Eq, Hash, Ord, PartialEq, PartialOrd)]
+pub(crate) struct Problem {
+    pub severity: Severity,
+    pub message: String,
+}
+impl Problem {
+    fn warning<M: Into<String>>(message: M) -> Self {
+        Self { severity: Severity::Warning, message: message.into() }
+    }
+
+    fn error<M: Into<String>>(message: M) -> Self {
+        Self { severity: Severity::Error, message: message.into() }
+    }
+}
+impl fmt::Display for Problem {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.severity {
+            Severity::Warning => write!(f, "warning: {}", self.message),
+            Severity::Error => write!(f, "error: {}", self.message),
+        }
+    }
+}
+
+
+/// Reads a configuration file into a table.
+fn read_file(path: &Path, format: Format) -> Result<toml::Table, Error> {
+    let mut f = File::open(path)
+        .map_err(|error| Error::Open { path: path.to_owned(), error })?;
+    let mut buf = Vec::new();
+    f.read_to_end(&mut buf)
+        .map_err(|error| Error::Read { path: path.to_owned(), error })?;
+    let string = String::from_utf8(buf)
+        .map_err(|error| Error::Decode { path: path.to_owned(), error })?;
+    format.parse(&string)
+        .map_err(|error| Error::Parse { path: path.to_owned(), error })
+}
+
+/// Reads a configuration file and the files it includes into a table.
+///
+/// `stack` contains the files currently being read, to detect include cycles.
+fn read_file_with_includes(path: &Path, format: Format, stack: &mut Vec<PathBuf>) -> Result<toml::Table, Error> {
+    let mut table = read_file(path, format)?;
+    let base_dir = path.parent().unwrap_or(Path::new("."));
+    let canonical_path = path.canonicalize()
+        .unwrap_or_else(|_| path.to_owned());
+    stack.push(canonical_path);
+    let result = resolve_includes(&mut table, base_dir, &path.display().to_string(), stack);
+    stack.pop();
+    result?;
+    Ok(table)
+}
+
+/// Replaces the `include` entry of the table with the contents of the files it references.
+///
+/// Relative paths are resolved against `base_dir`. The values in the included files are merged
+/// in order, followed by the values in the table itself; arrays (such as `sockets`) are
+/// concatenated while other values are replaced.
+fn resolve_includes(table: &mut toml::Table, base_dir: &Path, origin: &str, stack: &mut Vec<PathBuf>) -> Result<(), Error> {
+    let include_error = |message: String| Error::Include { origin: origin.to_owned(), message };
+
+    let patterns = match table.remove("include") {
+        None => return Ok(()),
+        Some(toml::Value::String(pattern)) => vec![pattern],
+        Some(toml::Value::Array(values)) => {
+            let mut patterns = Vec::with_capacity(values.len());
+            for value in values {
+                match value {
+                    toml::Value::String(pattern) => patterns.push(pattern),
+                    other => return Err(include_error(format!("expected a path, got {}", other.type_str()))),
+                }
+            }
+            patterns
+        },
+        Some(other) => return Err(include_error(format!("expected a path or a list of paths, got {}", other.type_str()))),
+    };
+
+    let mut merged = toml::Table::new();
+    for pattern in patterns {
+        let full_pattern = base_dir.join(&pattern);
+        let paths: Vec<PathBuf> = if pattern.contains(['*', '?', '[']) {
+            let full_pattern_str = full_pattern.to_str()
+                .ok_or_else(|| include_error(format!("path of pattern {:?} is not valid UTF-8", pattern)))?;
+            let entries = glob::glob(full_pattern_str)
+                .map_err(|e| include_error(format!("invalid pattern {:?}: {}", pattern, e)))?;
+            entries
+                .collect::<Result<_, _>>()
+                .map_err(|e| include_error(e.to_string()))?
+        } else {
+            // a missing file that is named explicitly is an error
+            vec![full_pattern]
+        };
+
+        for path in paths {
+            let canonical_path = path.canonicalize()
+                .unwrap_or_else(|_| path.clone());
+            if stack.contains(&canonical_path) {
+                return Err(include_error(format!("{} includes itself", path.display())));
+            }
+            let included = read_file_with_includes(&path, Format::from_path(&path), stack)?;
+            merge_tables(&mut merged, included);
+        }
+    }
+
+    merge_tables(&mut merged, std::mem::take(table));
+    *table = merged;
+    Ok(())
+}
+
+/// Merges the values from `source` into `target`.
+///
+/// Tables are merged recursively, arrays are concatenated and any other values are replaced.
+fn merge_tables(target: &mut toml::Table, source: toml::Table) {
+    for (key, value) in source {
+        match (target.get_mut(&key), value) {
+            (Some(toml::Value::Table(target_table)), toml::Value::Table(source_table)) => {
+                merge_tables(target_table, source_table);
+            },
+            (Some(toml::Value::Array(target_array)), toml::Value::Array(source_array)) => {
+                target_array.extend(source_array);
+            },
+            (_, value) => {
+                target.insert(key, value);
+            },
+        }
+    }
+}
+
+/// Interprets the value of an override variable.
+///
+/// Anything that is a valid TOML value (number, boolean, array, inline table, quoted string) is
+/// taken as such; everything else is taken as a string.
+fn parse_override_value(value: &str) -> toml::Value {
+    let mut table: toml::Table = match toml::from_str(&format!("value = {}", value)) {
+        Ok(t) => t,
+        Err(_) => return toml::Value::String(value.to_owned()),
+    };
+    table.remove("value")
+        .unwrap_or_else(|| toml::Value::String(value.to_owned()))
+}
+
+/// Sets the value at the given path (lowercase keys or array indexes) within the table, creating
+/// intermediate tables and array entries as necessary.
+fn set_override(table: &mut toml::Table, path: &[String], value: toml::Value) -> Result<(), String> {
+    let (key, rest) = match path.split_first() {
+        Some(kr) => kr,
+        None => return Err("empty path".to_owned()),
+    };
+    if rest.is_empty() {
+        table.insert(key.clone(), value);
+        return Ok(());
+    }
+
+    let next_is_index = rest[0].parse::<usize>().is_ok();
+    let child = table.entry(key.clone())
+        .or_insert_with(|| if next_is_index {
+            toml::Value::Array(Vec::new())
+        } else {
+            toml::Value::Table(toml::Table::new())
+        });
+    set_override_value(child, rest, value)
+}
+
+fn set_override_value(target: &mut toml::Value, path: &[String], value: toml::Value) -> Result<(), String> {
+    match target {
+        toml::Value::Table(table) => set_override(table, path, value),
+        toml::Value::Array(array) => {
+            let index: usize = path[0].parse()
+                .map_err(|_| format!("{:?} is not an array index", path[0]))?;
+            if index == array.len() {
+                // append a new entry
+                array.push(toml::Value::Table(toml::Table::new()));
+            } else if index > array.len() {
+                return Err(format!("array index {} skips entries (array has {} entries)", index, array.len()));
+            }
+
+            if path.len() == 1 {
+                array[index] = value;
+                Ok(())
+            } else {
+                set_override_value(&mut array[index], &path[1..], value)
+            }
+        },
+        _ => Err(format!("cannot set {:?} within a value that is neither a table nor an array", path[0])),
+    }
+}
+
+/// Applies overrides from the given environment variables to the configuration table.
+///
+/// Variables are applied in order of their names, so that array entries are created in order.
+fn apply_overrides<I: IntoIterator<Item = (String, String)>>(table: &mut toml::Table, variables: I) -> Result<(), Error> {
+    let mut overrides: Vec<(String, String)> = variables.into_iter()
+        .filter(|(name, _value)| name.starts_with(OVERRIDE_PREFIX))
+        .collect();
+    overrides.sort_unstable_by_key(|(name, _value)| {
+        // sort numerically by array indexes
+        name[OVERRIDE_PREFIX.len()..].split("__")
+            .map(|piece| match piece.parse::<usize>() {
+                Ok(index) => (0, index, String::new()),
+                Err(_) => (1, 0, piece.to_owned()),
+            })
+            .collect::<Vec<_>>()
+    });
+
+    for (name, value) in overrides {
+        let path: Vec<String> = name[OVERRIDE_PREFIX.len()..]
+            .split("__")
+            .map(|piece| piece.to_lowercase())
+            .collect();
+        set_override(table, &path, parse_override_value(&value))
+            .map_err(|message| Error::Override { variable: name.clone(), message })?;
+    }
+    Ok(())
+}
+
+
+impl Config {
+    /// Creates a configuration serving the given sockets with default settings.
+    pub fn new(sockets: Vec<SocketConfig>) -> Self {
+        Self {
+            sockets,
+            startup_banner: None,
+            max_total_connections: None,
+            max_connections_per_ip: None,
+            max_new_connections_per_ip_per_minute: None,
+            log: LogConfig::default(),
+            reverse_dns: false,
+            stats_interval_mins: None,
+            watch_animation_files: false,
+            admin: None,
+            user: None,
+            group: None,
+            pid_file: None,
+            sandbox: None,
+            runtime: RuntimeConfig::default(),
+        }
+    }
+
+    /// Loads the configuration, applying overrides from environment variables.
+    ///
+    /// The configuration is loaded from the given file; if none is given, it is taken from the
+    /// [`CONFIG_VARIABLE`] environment variable or the file at [`DEFAULT_PATH`]. If none of these
+    /// exist but override variables are set, the configuration is built from the overrides alone.
+    ///
+    /// Unless a format is given, files are parsed according to their extension and the
+    /// environment variable is parsed as TOML. Files listed under `include` are merged into the
+    /// configuration; their format is always taken from their extension.
+    pub fn load(path: Option<&Path>, format: Option<Format>) -> Result<Self, Error> {
+        let variables: Vec<(String, String)> = env::vars().collect();
+        let has_overrides = variables.iter()
+            .any(|(name, _value)| name.starts_with(OVERRIDE_PREFIX));
+
+        let mut table = if let Some(p) = path {
+            read_file_with_includes(p, format.unwrap_or_else(|| Format::from_path(p)), &mut Vec::new())?
+        } else if let Ok(blob) = env::var(CONFIG_VARIABLE) {
+            let mut table = format.unwrap_or(Format::Toml).parse(&blob)
+                .map_err(|error| Error::ParseVariable { variable: CONFIG_VARIABLE.to_owned(), error })?;
+            resolve_includes(&mut table, Path::new("."), CONFIG_VARIABLE, &mut Vec::new())?;
+            table
+        } else if has_overrides && !Path::new(DEFAULT_PATH).exists() {
+            toml::Table::new()
+        } else {
+            read_file_with_includes(Path::new(DEFAULT_PATH), format.unwrap_or(Format::Toml), &mut Vec::new())?
+        };
+
+        apply_overrides(&mut table, variables)?;
+
+        toml::Value::Table(table).try_into()
+            .map_err(|error| Error::Deserialize { error })
+    }
+
+    /// Checks the configuration for problems that deserialization does not catch.
+    pub fn validate(&self) -> Vec<Problem> {
+        let mut problems = Vec::new();
+
+        if self.sockets.is_empty() {
+            problems.push(Problem::warning("no sockets configured"));
+        }
+
+        for (i, socket_config) in self.sockets.iter().enumerate() {
+            let addr = socket_config.listen_socket_addr;
+
+            // is the address one that can be listened on?
+            let unbindable = match addr.ip() {
+                IpAddr::V4(ip) => ip.is_multicast() || ip.is_broadcast(),
+                IpAddr::V6(ip) => ip.is_multicast(),
+            };
+            if unbindable {
+                problems.push(Problem::error(format!("{}: cannot listen on a multicast or broadcast address", addr)));
+            }
+            if addr.port() == 0 {
+                problems.push(Problem::warning(format!("{}: port 0 means a random port will be chosen", addr)));
+            }
+            if addr.port() != 0 && self.sockets[..i].iter().any(|sc| sc.listen_socket_addr == addr) {
+                problems.push(Problem::error(format!("{}: address is configured multiple times", addr)));
+            }
+            if socket_config.max_session_secs == Some(0) {
+                problems.push(Problem::warning(format!("{}: max_session_secs is 0; sessions will end immediately", addr)));
+            }
+            if socket_config.idle_secs == Some(0) {
+                problems.push(Problem::warning(format!("{}: idle_secs is 0; sessions will end immediately", addr)));
+            }
+            if socket_config.loops == Some(0) {
+                problems.push(Problem::warning(format!("{}: loops is 0; sessions will end without showing an animation", addr)));
+            }
+            if socket_config.listen_backlog == 0 {
+                problems.push(Problem::error(format!("{}: listen_backlog must not be 0", addr)));
+            }
+            if socket_config.reuse_port && !cfg!(unix) {
+                problems.push(Problem::error(format!("{}: reuse_port is only supported on Unix", addr)));
+            }
+            if socket_config.workers == 0 {
+                problems.push(Problem::error(format!("{}: workers must be at least 1", addr)));
+            } else if socket_config.workers > 1 && !cfg!(unix) {
+                problems.push(Problem::error(format!("{}: more than one worker is only supported on Unix", addr)));
+            }
+            if let Some(tls_config) = &socket_config.tls {
+                if let Err(e) = tls::build_acceptor(tls_config) {
+                    problems.push(Problem::error(format!("{}: {}", addr, e)));
+                }
+            }
+            if socket_config.send_buffer_size == Some(0) {
+                problems.push(Problem::error(format!("{}: send_buffer_size must not be 0", addr)));
+            }
+            if let Some(keepalive) = &socket_config.tcp_keepalive {
+                if keepalive.idle_secs == 0 || keepalive.interval_secs == 0 || keepalive.retries == 0 {
+                    problems.push(Problem::error(format!("{}: tcp_keepalive settings must not be 0", addr)));
+                }
+            }
+            if let Some(banner_file) = &socket_config.banner_file {
+                if let Err(e) = File::open(banner_file) {
+                    problems.push(Problem::error(format!("{}: cannot open banner file {}: {}", addr, banner_file.display(), e)));
+                }
+            }
+
+            if socket_config.spectator {
+                if socket_config.allow.is_empty() {
+                    problems.push(Problem::warning(format!("{}: spectator socket without allow list; anyone can watch other sessions", addr)));
+                }
+                if socket_config.protocol == Protocol::Http {
+                    problems.push(Problem::error(format!("{}: spectator sockets cannot speak HTTP", addr)));
+                }
+            }
+
+            let mut choices = socket_config.animation_choices();
+            if choices.is_empty() && socket_config.playlist.is_empty() && !socket_config.spectator {
+                problems.push(Problem::error(format!("{}: no animation configured", addr)));
+            }
+            for item in &socket_config.playlist {
+                let animation = socket_config.playlist_animation(item);
+                if !choices.contains(&animation) {
+                    choices.push(animation);
+                }
+            }
+            for choice in choices {
+                if let Err(e) = animations::create(&choice) {
+                    problems.push(Problem::error(format!("{}: {}", addr, e)));
+                }
+            }
+        }
+
+        if let Some(startup_banner) = &self.startup_banner {
+            match animations::by_name(startup_banner) {
+                Ok(_) => {},
+                Err(e @ CreateError::UnknownAnimation { .. }) => {
+                    problems.push(Problem::error(format!("startup banner: {}", e)));
+                },
+                Err(e) => {
+                    problems.push(Problem::warning(format!("startup banner: {}", e)));
+                },
+            }
+        }
+
+        if !cfg!(unix) && matches!(self.log.target, LogTarget::Syslog | LogTarget::Journald) {
+            problems.push(Problem::error("logging to syslog or journald is only supported on Unix"));
+        }
+
+        if self.max_total_connections == Some(0) {
+            problems.push(Problem::warning("max_total_connections is 0; all connections will be refused".to_owned()));
+        }
+        if self.max_connections_per_ip == Some(0) {
+            problems.push(Problem::warning("max_connections_per_ip is 0; all connections will be refused".to_owned()));
+        }
+        if self.max_new_connections_per_ip_per_minute == Some(0) {
+            problems.push(Problem::warning("max_new_connections_per_ip_per_minute is 0; all connections will be refused".to_owned()));
+        }
+
+        if !cfg!(unix) && (self.user.is_some() || self.group.is_some()) {
+            problems.push(Problem::error("switching user or group is only supported on Unix"));
+        }
+
+        if !cfg!(target_os = "linux") && self.sandbox.is_some() {
+            problems.push(Problem::error("sandboxing is only supported on Linux"));
+        }
+
+        if self.stats_interval_mins == Some(0) {
+            problems.push(Problem::error("stats_interval_mins must be at least 1"));
+        }
+
+        if self.runtime.worker_threads == Some(0) {
+            problems.push(Problem::error("runtime: worker_threads must be at least 1"));
+        } else if self.runtime.worker_threads.is_some() && self.runtime.flavor == RuntimeFlavor::CurrentThread {
+            problems.push(Problem::warning("runtime: worker_threads is ignored by the current_thread flavor".to_owned()));
+        }
+
+        if let Some(admin) = &self.admin {
+            if admin.listen_socket_addr.is_none() && admin.unix_socket_path.is_none() {
+                problems.push(Problem::error("admin: neither listen_socket_addr nor unix_socket_path is set"));
+            }
+            if let Some(addr) = admin.listen_socket_addr {
+                if !addr.ip().is_loopback() {
+                    problems.push(Problem::warning(format!("admin: {} is not a loopback address; anyone who can reach it can control the server", addr)));
+                }
+            }
+            if !cfg!(unix) && admin.unix_socket_path.is_some() {
+                problems.push(Problem::error("admin: unix_socket_path is only supported on Unix"));
+            }
+        }
+
+        problems
+    }
+}
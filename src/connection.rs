@@ -0,0 +1,183 @@
+//! Running the Telnet protocol over tokio streams.
+
+
+use std::io;
+use std::net::SocketAddr;
+use std::sync::Arc;
+
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+use tokio::sync::broadcast;
+
+use crate::logging::{self, HexdumpScope};
+use crate::telnet::{Error, Event, SessionInfo, TelnetMachine};
+
+
+/// How many bytes are read from the client at once.
+const READ_CHUNK_SIZE: usize = 4096;
+
+
+/// The receiving end of a connection.
+pub(crate) type Reader = Box<dyn AsyncRead + Send + Unpin>;
+
+/// The sending end of a connection.
+pub(crate) type Writer = Box<dyn AsyncWrite + Send + Unpin>;
+
+/// A bidirectional stream, such as a TCP or TLS connection.
+pub trait Stream: AsyncRead + AsyncWrite + Send + Unpin {}
+impl<S: AsyncRead + AsyncWrite + Send + Unpin> Stream for S {}
+
+
+/// A Telnet connection with a client.
+///
+/// The protocol is handled by a [`TelnetMachine`]; this moves the bytes between it and the client.
+pub struct TelnetConnection {
+    machine: TelnetMachine,
+    reader: Reader,
+    writer: Writer,
+    read_buf: Box<[u8]>,
+
+    /// How many bytes have been sent to the client.
+    bytes_sent: u64,
+
+    /// Receives a copy of every frame sent to the client, for spectators.
+    mirror: Option<broadcast::Sender<Arc<[u8]>>>,
+}
+impl TelnetConnection {
+    /// Creates a connection speaking Telnet over a bidirectional stream (e.g. a TCP or TLS
+    /// connection, or one end of an in-memory pipe).
+    pub fn new<S: Stream + 'static>(stream: S, addr: SocketAddr, max_sub_negotiation_length: usize) -> Self {
+        let (reader, writer) = tokio::io::split(stream);
+        Self::from_parts(Box::new(reader), Box::new(writer), addr, max_sub_negotiation_length)
+    }
+
+    /// Creates a connection speaking Telnet over a pair of streams (e.g. the halves of a TCP
+    /// connection, or stdin and stdout).
+    pub fn from_parts(reader: Reader, writer: Writer, addr: SocketAddr, max_sub_negotiation_length: usize) -> Self {
+        Self {
+            machine: TelnetMachine::new(addr, max_sub_negotiation_length),
+            reader,
+            writer,
+            read_buf: vec![0; READ_CHUNK_SIZE].into_boxed_slice(),
+            bytes_sent: 0,
+            mirror: None,
+        }
+    }
+
+    /// Treats the connection as a plain byte stream: no negotiation takes place, and IAC bytes are
+    /// neither escaped nor interpreted.
+    pub fn disable_telnet(&mut self) {
+        self.machine.disable_telnet();
+    }
+
+    /// Whether the connection speaks Telnet (as opposed to being a plain byte stream).
+    pub fn speaks_telnet(&self) -> bool {
+        self.machine.speaks_telnet()
+    }
+
+    /// Copies every frame sent to the client from now on to the given sender.
+    pub fn set_mirror(&mut self, mirror: broadcast::Sender<Arc<[u8]>>) {
+        self.mirror = Some(mirror);
+    }
+
+    /// Whether the client has closed its sending side of the connection.
+    pub fn input_closed(&self) -> bool {
+        self.machine.input_closed()
+    }
+
+    /// Whether we have agreed to echo the client's input, meaning that the client doesn't.
+    pub fn echoes(&self) -> bool {
+        self.machine.echoes()
+    }
+
+    /// The address of the client.
+    pub fn addr(&self) -> SocketAddr {
+        self.machine.addr()
+    }
+
+    /// How many bytes have been sent to the client so far.
+    pub fn bytes_sent(&self) -> u64 {
+        self.bytes_sent
+    }
+
+    /// The state of the session as negotiated so far.
+    pub fn session_info(&self) -> &SessionInfo {
+        self.machine.session_info()
+    }
+
+    /// Starts negotiation; see [`TelnetMachine::negotiate`].
+    pub async fn negotiate(&mut self) -> Result<(), Error> {
+        self.machine.negotiate();
+        self.flush_output().await
+    }
+
+    /// Reads the next event from the client.
+    ///
+    /// Answers to the client's negotiation requests are sent while waiting for more data.
+    ///
+    /// Once the client has closed its sending side of the connection, [`Event::EndOfInput`] is
+    /// returned once; after that, this method never completes.
+    ///
+    /// This method is cancel-safe: if it is cancelled (e.g. in `tokio::select!`), no received data
+    /// is lost and no answer is sent twice.
+    pub async fn read_event(&mut self) -> Result<Event, Error> {
+        loop {
+            if let Some(event) = self.machine.next_event()? {
+                return Ok(event);
+            }
+            if self.machine.input_closed() {
+                return std::future::pending().await;
+            }
+
+            self.flush_output().await?;
+
+            let byte_count = self.reader.read(&mut self.read_buf)
+                .await.map_err(|e| Error::from_io_receive(e, self.addr()))?;
+            let received = &self.read_buf[..byte_count];
+            if logging::hexdump_scope() == HexdumpScope::Everything {
+                logging::hexdump(self.machine.addr(), "<", received);
+            }
+            if byte_count == 0 {
+                self.machine.receive_end();
+            } else {
+                self.machine.receive(received);
+            }
+        }
+    }
+
+    /// Sends everything queued up for the client, such as answers to negotiation requests.
+    ///
+    /// This method is cancel-safe.
+    pub async fn flush_output(&mut self) -> Result<(), Error> {
+        if self.machine.output().is_empty() {
+            return Ok(());
+        }
+        while !self.machine.output().is_empty() {
+            let byte_count = self.writer.write(self.machine.output())
+                .await.map_err(|e| Error::from_io_send(e, self.addr()))?;
+            if byte_count == 0 {
+                return Err(Error::from_io_send(io::ErrorKind::WriteZero.into(), self.addr()));
+            }
+            if logging::hexdump_scope() == HexdumpScope::Everything {
+                logging::hexdump(self.machine.addr(), ">", &self.machine.output()[..byte_count]);
+            }
+            self.machine.consume_output(byte_count);
+            self.bytes_sent += byte_count as u64;
+        }
+        self.writer.flush()
+            .await.map_err(|e| Error::from_io_send(e, self.addr()))
+    }
+
+    /// Sends a frame of data to the client, escaping it as necessary.
+    ///
+    /// If this method is cancelled, the rest of the frame is sent along with whatever is sent next.
+    pub async fn send_frame(&mut self, frame: &[u8]) -> Result<(), Error> {
+        if let Some(mirror) = &self.mirror {
+            if mirror.receiver_count() > 0 {
+                // nobody might be listening anymore by now; that's fine
+                let _ = mirror.send(Arc::from(frame));
+            }
+        }
+        self.machine.send_data(frame);
+        self.flush_output().await
+    }
+}
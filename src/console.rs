@@ -0,0 +1,80 @@
+//! Rendering of animations on the local console.
+
+
+use std::env;
+use std::time::Duration;
+
+use terminal_size::{Height, Width, terminal_size};
+use tokio::io::{self, AsyncWriteExt};
+use tokio::time::{Instant, sleep_until};
+
+use crate::animations::Animation;
+use crate::telnet::SessionInfo;
+
+
+/// The shortest time a frame is shown locally or in a recording.
+///
+/// Animations that run as fast as the connection allows would otherwise flood the console.
+pub(crate) const MIN_FRAME_DELAY: Duration = Duration::from_millis(16);
+
+
+/// Obtains the size of the local terminal as (columns, rows), assuming 80x24 if it is unknown.
+pub(crate) fn local_terminal_size() -> (u16, u16) {
+    match terminal_size() {
+        Some((Width(cols), Height(rows))) => (cols, rows),
+        None => (80, 24),
+    }
+}
+
+/// Describes the local terminal in the same way a Telnet client would.
+pub(crate) fn local_session_info() -> SessionInfo {
+    SessionInfo {
+        terminal_type: env::var("TERM").ok(),
+        window_size_negotiated: true,
+        window_size: Some(local_terminal_size()),
+        binary: true,
+    }
+}
+
+/// Previews an animation on stdout until it ends or Ctrl+C is pressed.
+pub(crate) async fn preview(animation: &mut dyn Animation) -> io::Result<()> {
+    let result = tokio::select! {
+        result = play(animation, None) => result,
+        _ = tokio::signal::ctrl_c() => Ok(()),
+    };
+
+    // reset attributes and move to a fresh line
+    let mut stdout = io::stdout();
+    stdout.write_all(b"\x1B[0m\r\n").await?;
+    stdout.flush().await?;
+
+    result
+}
+
+/// Plays an animation on stdout, stopping once the given duration has elapsed (if any).
+pub(crate) async fn play(animation: &mut dyn Animation, duration: Option<Duration>) -> io::Result<()> {
+    let mut session = local_session_info();
+    let mut stdout = io::stdout();
+    let end = duration.map(|d| Instant::now() + d);
+
+    loop {
+        // the terminal might have been resized
+        session.window_size = Some(local_terminal_size());
+
+        let frame = match animation.next_frame(&session) {
+            Some(f) => f,
+            None => return Ok(()),
+        };
+        stdout.write_all(frame.commands.as_bytes()).await?;
+        stdout.flush().await?;
+
+        let next_frame_at = Instant::now() + frame.delay.max(MIN_FRAME_DELAY);
+        if let Some(e) = end {
+            if next_frame_at >= e {
+                sleep_until(e).await;
+                return Ok(());
+            }
+        }
+        sleep_until(next_frame_at).await;
+    }
+}
@@ -0,0 +1,75 @@
+//! Running in the background as a classic daemon.
+
+
+use std::fs::File;
+#[cfg(unix)]
+use std::fs::OpenOptions;
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+
+use log::warn;
+#[cfg(unix)]
+use nix::unistd::{ForkResult, dup2_stderr, dup2_stdin, dup2_stdout, fork, setsid};
+
+
+/// A file containing the ID of the process, removed again when dropped.
+#[derive(Debug)]
+pub(crate) struct PidFile {
+    path: PathBuf,
+}
+impl PidFile {
+    /// Writes the ID of the current process into the file at the given path.
+    pub fn create(path: &Path) -> io::Result<Self> {
+        let mut file = File::create(path)?;
+        writeln!(file, "{}", std::process::id())?;
+        Ok(Self { path: path.to_owned() })
+    }
+}
+impl Drop for PidFile {
+    fn drop(&mut self) {
+        // after a restart, the file belongs to the new process
+        let ours = std::fs::read_to_string(&self.path)
+            .is_ok_and(|contents| contents.trim() == std::process::id().to_string());
+        if !ours {
+            return;
+        }
+
+        // may fail if we have dropped the privileges required to remove it
+        if let Err(e) = std::fs::remove_file(&self.path) {
+            warn!("failed to remove PID file {}: {}", self.path.display(), e);
+        }
+    }
+}
+
+
+/// Detaches the process from the terminal and continues in the background.
+///
+/// Standard input is redirected from `/dev/null`; standard output and error are appended to
+/// `output_path` or redirected to `/dev/null` if it is `None`. Must be called before any threads
+/// are started, since only the calling thread survives.
+#[cfg(unix)]
+pub(crate) fn daemonize(output_path: Option<&Path>) -> io::Result<()> {
+    // open everything first so that failures can still be reported to the terminal
+    let null = OpenOptions::new().read(true).write(true).open("/dev/null")?;
+    let output = match output_path {
+        Some(path) => OpenOptions::new().create(true).append(true).open(path)?,
+        None => null.try_clone()?,
+    };
+
+    // fork, start a new session without a controlling terminal, then fork again so that we can
+    // never acquire one
+    // SAFETY: no other threads are running yet
+    if let ForkResult::Parent { .. } = unsafe { fork() }? {
+        std::process::exit(0);
+    }
+    setsid()?;
+    // SAFETY: no other threads are running yet
+    if let ForkResult::Parent { .. } = unsafe { fork() }? {
+        std::process::exit(0);
+    }
+
+    dup2_stdin(&null)?;
+    dup2_stdout(&output)?;
+    dup2_stderr(&output)?;
+    Ok(())
+}
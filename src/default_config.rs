@@ -0,0 +1,260 @@
+//! Generation of a commented default configuration file.
+//!
+//! The options and their descriptions are taken from the schema of the configuration structures,
+//! so the output cannot get out of sync with the code.
+
+
+use std::fmt::Write;
+
+use schemars::schema::{InstanceType, RootSchema, Schema, SchemaObject, SingleOrVec};
+use schemars::schema_for;
+
+use crate::animations::{self, AnimationConfig};
+use crate::config::{Config, SocketConfig};
+
+
+/// The address of the socket in the default configuration.
+const DEFAULT_LISTEN_SOCKET_ADDR: &str = "0.0.0.0:2323";
+
+/// The animation served by the socket in the default configuration.
+const DEFAULT_ANIMATION: &str = "lollercoaster";
+
+/// The width to which comments are wrapped.
+const COMMENT_WIDTH: usize = 100;
+
+
+/// Appends the text as comment lines, wrapped to [`COMMENT_WIDTH`].
+fn write_comment(out: &mut String, prefix: &str, text: &str) {
+    for paragraph in text.split('\n') {
+        let mut line = prefix.to_owned();
+        for word in paragraph.split_whitespace() {
+            if line.len() > prefix.len() && line.len() + 1 + word.len() > COMMENT_WIDTH {
+                out.push_str(line.trim_end());
+                out.push('\n');
+                line = prefix.to_owned();
+            }
+            if line.len() > prefix.len() {
+                line.push(' ');
+            }
+            line.push_str(word);
+        }
+        out.push_str(line.trim_end());
+        out.push('\n');
+    }
+}
+
+
+/// Follows a reference to a definition, if the schema is one.
+fn resolve<'a>(schema: &'a SchemaObject, root: &'a RootSchema) -> &'a SchemaObject {
+    let definition = schema.reference.as_ref()
+        .and_then(|r| r.strip_prefix("#/definitions/"))
+        .and_then(|name| root.definitions.get(name));
+    match definition {
+        Some(Schema::Object(o)) => o,
+        _ => schema,
+    }
+}
+
+/// Describes the type of values accepted by the schema, e.g. `<integer>`.
+fn describe_type(schema: &SchemaObject, root: &RootSchema) -> String {
+    let schema = resolve(schema, root);
+
+    if let Some(values) = &schema.enum_values {
+        let names: Vec<String> = values.iter()
+            .map(|v| v.to_string())
+            .collect();
+        return format!("<one of {}>", names.join(", "));
+    }
+
+    if let Some(subschemas) = &schema.subschemas {
+        // Option<T> and documented references are expressed as anyOf/allOf
+        let alternatives = subschemas.any_of.iter()
+            .chain(subschemas.all_of.iter())
+            .chain(subschemas.one_of.iter())
+            .flatten();
+        for alternative in alternatives {
+            if let Schema::Object(o) = alternative {
+                let description = describe_type(o, root);
+                if description != "<null>" {
+                    return description;
+                }
+            }
+        }
+    }
+
+    let instance_types = match &schema.instance_type {
+        Some(SingleOrVec::Single(t)) => vec![**t],
+        Some(SingleOrVec::Vec(ts)) => ts.clone(),
+        None => Vec::new(),
+    };
+    let mut names: Vec<&str> = instance_types.iter()
+        .filter(|t| **t != InstanceType::Null)
+        .map(|t| match t {
+            InstanceType::Null => "null",
+            InstanceType::Boolean => "boolean",
+            InstanceType::Object => "table",
+            InstanceType::Array => "array",
+            InstanceType::Number => "number",
+            InstanceType::String => "string",
+            InstanceType::Integer => "integer",
+        })
+        .collect();
+    if names.is_empty() {
+        if instance_types.is_empty() {
+            names.push("value");
+        } else {
+            names.push("null");
+        }
+    }
+    format!("<{}>", names.join(" or "))
+}
+
+/// Returns the properties of the object described by the schema, in order of declaration.
+fn properties<'a>(schema: &'a SchemaObject, root: &'a RootSchema) -> Vec<(&'a str, &'a SchemaObject)> {
+    let schema = resolve(schema, root);
+    let mut ret = Vec::new();
+    if let Some(object) = &schema.object {
+        for (name, property) in &object.properties {
+            if let Schema::Object(o) = property {
+                ret.push((name.as_str(), o));
+            }
+        }
+    }
+    ret
+}
+
+/// The documentation of the schema, if any.
+fn description(schema: &SchemaObject) -> Option<&str> {
+    schema.metadata.as_ref()
+        .and_then(|m| m.description.as_deref())
+}
+
+/// Whether the value is an array of tables, which must come after all other values of a table.
+fn is_array_of_tables(value: &toml::Value) -> bool {
+    match value {
+        toml::Value::Array(a) => !a.is_empty() && a.iter().all(|v| v.is_table()),
+        _ => false,
+    }
+}
+
+
+/// Appends the values of a table (except arrays of tables) with their descriptions.
+///
+/// Options without a value are written commented out, with the type of value they take.
+fn write_values(out: &mut String, schema: &SchemaObject, root: &RootSchema, values: &toml::Table) {
+    for (name, property) in properties(schema, root) {
+        let value = values.get(name);
+        if value.map(is_array_of_tables).unwrap_or(false) {
+            continue;
+        }
+
+        if let Some(d) = description(property) {
+            write_comment(out, "# ", d);
+        }
+        match value {
+            Some(v) => writeln!(out, "{} = {}", name, v).unwrap(),
+            None => writeln!(out, "#{} = {}", name, describe_type(property, root)).unwrap(),
+        }
+        out.push('\n');
+    }
+}
+
+
+/// Appends the list of animations with their parameters.
+fn write_animations(out: &mut String) {
+    write_comment(
+        out,
+        "# ",
+        "Available animations. To pass parameters to an animation, configure it as a table, e.g.:",
+    );
+    writeln!(out, "#animation = {{ name = \"{}\", frame_ms = 30 }}", DEFAULT_ANIMATION).unwrap();
+    for info in animations::ANIMATIONS {
+        let root = (info.params_schema)();
+        let defaults = (info.default_params)();
+
+        out.push_str("#\n");
+        write_comment(out, "# ", &format!("{} ({}x{}): {}", info.name, info.size.0, info.size.1, info.description));
+
+        let params = properties(&root.schema, &root);
+        let mut inline = format!("#animation = {{ name = {:?}", info.name);
+        for (name, _property) in &params {
+            if let Some(value) = defaults.get(*name) {
+                write!(inline, ", {} = {}", name, value).unwrap();
+            }
+        }
+        inline.push_str(" }");
+        writeln!(out, "{}", inline).unwrap();
+
+        for (name, property) in params {
+            let mut text = format!("{}: {}", name, description(property).unwrap_or(""));
+            if !defaults.contains_key(name) {
+                write!(text, " {}", describe_type(property, &root)).unwrap();
+            }
+            write_comment(out, "#   ", &text);
+        }
+    }
+}
+
+
+/// Generates a commented configuration file containing the default values of all options.
+pub(crate) fn default_config() -> String {
+    let root = schema_for!(Config);
+    let socket_config = SocketConfig::new(
+        DEFAULT_LISTEN_SOCKET_ADDR.parse().unwrap(),
+        AnimationConfig { name: DEFAULT_ANIMATION.to_owned(), params: toml::Table::new() },
+    );
+    let config = Config::new(vec![socket_config]);
+    let values = match toml::Value::try_from(&config) {
+        Ok(toml::Value::Table(t)) => t,
+        _ => panic!("configuration does not serialize into a table"),
+    };
+
+    let mut out = String::new();
+    write_comment(&mut out, "# ", "Configuration of telnet-animations, generated by `telnet-animations dump-default-config`.");
+    out.push_str("#\n");
+    write_comment(
+        &mut out,
+        "# ",
+        "Options that are commented out directly before their key are not set by default; the value shows what they accept.",
+    );
+    out.push_str("#\n");
+    write_comment(
+        &mut out,
+        "# ",
+        "Further configuration files can be merged into this one. Their paths are relative to this file and may contain wildcards:",
+    );
+    out.push_str("#include = [\"animations/*.toml\"]\n\n");
+
+    write_values(&mut out, &root.schema, &root, &values);
+
+    for (name, property) in properties(&root.schema, &root) {
+        let array = match values.get(name) {
+            Some(toml::Value::Array(a)) if is_array_of_tables(&values[name]) => a,
+            _ => continue,
+        };
+        if let Some(d) = description(property) {
+            write_comment(&mut out, "# ", d);
+        }
+
+        // the schema of the entries
+        let item_schema = property.array.as_ref()
+            .and_then(|a| a.items.as_ref())
+            .and_then(|items| match items {
+                SingleOrVec::Single(s) => Some(&**s),
+                SingleOrVec::Vec(_) => None,
+            });
+        let item_schema = match item_schema {
+            Some(Schema::Object(o)) => o,
+            _ => continue,
+        };
+        for entry in array {
+            writeln!(out, "[[{}]]", name).unwrap();
+            if let toml::Value::Table(t) = entry {
+                write_values(&mut out, item_schema, &root, t);
+            }
+        }
+    }
+
+    write_animations(&mut out);
+    out
+}
@@ -0,0 +1,103 @@
+//! Exporting animations into recording formats.
+
+
+use std::io::{self, Write};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use serde::Serialize;
+
+use crate::animations::Animation;
+use crate::console::MIN_FRAME_DELAY;
+use crate::screen::Screen;
+use crate::telnet::SessionInfo;
+
+
+/// The header of an asciinema v2 recording.
+#[derive(Clone, Debug, Eq, Hash, Ord, PartialEq, PartialOrd, Serialize)]
+struct AsciinemaHeader {
+    version: u32,
+    width: u16,
+    height: u16,
+    timestamp: u64,
+    title: String,
+}
+
+
+/// The session of a headless client whose terminal has the given size.
+fn headless_session(width: u16, height: u16) -> SessionInfo {
+    SessionInfo {
+        terminal_type: None,
+        window_size_negotiated: true,
+        window_size: Some((width, height)),
+        binary: true,
+    }
+}
+
+
+/// Writes an animation as an asciinema v2 recording.
+///
+/// The animation is run headlessly until it ends or until `duration` of animation time has been
+/// recorded.
+pub(crate) fn write_asciinema<W: Write>(
+    writer: &mut W,
+    animation: &mut dyn Animation,
+    title: &str,
+    width: u16,
+    height: u16,
+    duration: Duration,
+) -> io::Result<()> {
+    let header = AsciinemaHeader {
+        version: 2,
+        width,
+        height,
+        timestamp: SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0),
+        title: title.to_owned(),
+    };
+    serde_json::to_writer(&mut *writer, &header)?;
+    writer.write_all(b"\n")?;
+
+    let session = headless_session(width, height);
+    let mut time = Duration::ZERO;
+    while time < duration {
+        let frame = match animation.next_frame(&session) {
+            Some(f) => f,
+            None => break,
+        };
+        if !frame.commands.is_empty() {
+            let event = (time.as_secs_f64(), "o", &frame.commands);
+            serde_json::to_writer(&mut *writer, &event)?;
+            writer.write_all(b"\n")?;
+        }
+        time += frame.delay.max(MIN_FRAME_DELAY);
+    }
+
+    writer.flush()
+}
+
+
+/// Writes the screen as it looks after each of the first `frame_count` frames of an animation as
+/// plain text.
+///
+/// The animation is run headlessly against a virtual terminal, so the result only depends on the
+/// animation, not on the time it takes to run.
+pub(crate) fn write_screens<W: Write>(
+    writer: &mut W,
+    animation: &mut dyn Animation,
+    width: u16,
+    height: u16,
+    frame_count: usize,
+) -> io::Result<()> {
+    let session = headless_session(width, height);
+    let mut screen = Screen::new(width, height);
+    for index in 0..frame_count {
+        let frame = match animation.next_frame(&session) {
+            Some(f) => f,
+            None => break,
+        };
+        screen.feed(&frame.commands);
+        writeln!(writer, "=== frame {} ({} ms) ===", index + 1, frame.delay.as_millis())?;
+        writer.write_all(screen.text().as_bytes())?;
+    }
+
+    writer.flush()
+}
@@ -0,0 +1,146 @@
+//! Restarting without dropping connections by handing the listening sockets over to a new process.
+//!
+//! On SIGUSR2, the server starts its executable anew with the same arguments, passing the
+//! listening sockets along. The new process picks them up instead of binding the addresses again,
+//! while the old one stops accepting and keeps serving its sessions until they have all ended.
+
+
+use std::collections::HashMap;
+use std::io;
+use std::net::{SocketAddr, TcpListener};
+
+use log::{debug, warn};
+
+
+/// The environment variable listing the file descriptors of the listening sockets passed on to the
+/// new process, separated by commas.
+pub(crate) const LISTEN_FDS_VARIABLE: &str = "TELNET_ANIMATIONS_LISTEN_FDS";
+
+
+/// Listening sockets inherited from the previous process, by address.
+#[derive(Debug, Default)]
+pub(crate) struct InheritedListeners {
+    by_addr: HashMap<SocketAddr, Vec<TcpListener>>,
+}
+impl InheritedListeners {
+    /// Picks up the listening sockets named in the environment, if any.
+    #[cfg(unix)]
+    pub fn from_env() -> Self {
+        use std::os::fd::{FromRawFd, RawFd};
+
+        let mut ret = Self::default();
+        let fds = match std::env::var(LISTEN_FDS_VARIABLE) {
+            Ok(f) => f,
+            Err(_) => return ret,
+        };
+        for fd_str in fds.split(',').filter(|s| !s.is_empty()) {
+            let fd: RawFd = match fd_str.parse() {
+                Ok(fd) => fd,
+                Err(_) => {
+                    warn!("ignoring invalid file descriptor {:?} in {}", fd_str, LISTEN_FDS_VARIABLE);
+                    continue;
+                },
+            };
+            // SAFETY: the previous process has passed this descriptor to us and nobody else uses it
+            let listener = unsafe { TcpListener::from_raw_fd(fd) };
+            match listener.local_addr() {
+                Ok(addr) => {
+                    debug!("inherited listener on {} (file descriptor {})", addr, fd);
+                    ret.by_addr.entry(addr).or_default().push(listener);
+                },
+                Err(e) => warn!("ignoring inherited file descriptor {}: {}", fd, e),
+            }
+        }
+        ret
+    }
+
+    #[cfg(not(unix))]
+    pub fn from_env() -> Self {
+        Self::default()
+    }
+
+    /// Takes an inherited listener on the given address, if there is one left.
+    pub fn take(&mut self, addr: SocketAddr) -> Option<TcpListener> {
+        self.by_addr.get_mut(&addr)?.pop()
+    }
+}
+
+
+/// Starts a new instance of the server which takes over the given listening sockets.
+///
+/// Returns the ID of the new process.
+#[cfg(unix)]
+pub(crate) fn spawn_successor(listeners: &[TcpListener]) -> io::Result<u32> {
+    use std::os::fd::AsRawFd;
+    use std::process::Command;
+
+    use nix::fcntl::{FcntlArg, FdFlag, fcntl};
+
+    // the descriptors must survive the exec, but only for this one
+    for listener in listeners {
+        fcntl(listener, FcntlArg::F_SETFD(FdFlag::empty()))?;
+    }
+    let fds: Vec<String> = listeners.iter()
+        .map(|l| l.as_raw_fd().to_string())
+        .collect();
+    let spawned = std::env::current_exe()
+        .and_then(|exe| {
+            Command::new(exe)
+                .args(std::env::args_os().skip(1))
+                .env(LISTEN_FDS_VARIABLE, fds.join(","))
+                .spawn()
+        });
+    for listener in listeners {
+        fcntl(listener, FcntlArg::F_SETFD(FdFlag::FD_CLOEXEC))?;
+    }
+    Ok(spawned?.id())
+}
+
+#[cfg(not(unix))]
+pub(crate) fn spawn_successor(_listeners: &[TcpListener]) -> io::Result<u32> {
+    Err(io::Error::new(io::ErrorKind::Unsupported, "restarting is only supported on Unix"))
+}
+
+
+/// Waits for requests to restart (SIGUSR2 on Unix).
+#[derive(Debug)]
+pub(crate) struct RestartRequests {
+    #[cfg(unix)]
+    signal: Option<tokio::signal::unix::Signal>,
+}
+impl RestartRequests {
+    pub fn new() -> Self {
+        #[cfg(unix)]
+        {
+            use tokio::signal::unix::{SignalKind, signal};
+
+            let signal = signal(SignalKind::user_defined2())
+                .map_err(|e| warn!("failed to register SIGUSR2 handler; restarting is not possible: {}", e))
+                .ok();
+            Self { signal }
+        }
+
+        #[cfg(not(unix))]
+        {
+            Self {}
+        }
+    }
+
+    /// Ignores requests to restart (e.g. because the server is embedded in another application).
+    pub fn disabled() -> Self {
+        Self {
+            #[cfg(unix)]
+            signal: None,
+        }
+    }
+
+    /// Waits for the next request to restart.
+    pub async fn next(&mut self) {
+        #[cfg(unix)]
+        if let Some(signal) = &mut self.signal {
+            signal.recv().await;
+            return;
+        }
+        std::future::pending().await
+    }
+}
@@ -0,0 +1,280 @@
+//! Serving animations over HTTP, e.g. to `curl host:port/roflcopter`.
+//!
+//! Animations are streamed as a never-ending `text/plain` response, one chunk per frame; browsers
+//! are shown a page listing the animations instead.
+
+
+use std::fmt::Write;
+use std::io;
+use std::net::SocketAddr;
+use std::time::Duration;
+
+use log::{error, info};
+use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+use tokio::sync::watch;
+use tokio::time::{Instant, sleep_until};
+
+use crate::animations;
+use crate::config::SocketConfig;
+use crate::connection::Stream;
+use crate::registry::{Registration, SessionCommand};
+use crate::session::sleep_until_opt;
+use crate::telnet::{self, SessionInfo};
+
+
+/// The maximum length of the request line and headers.
+const MAX_REQUEST_LENGTH: usize = 8 * 1024;
+
+
+/// The parts of a request we care about.
+struct Request {
+    method: String,
+    path: String,
+    host: Option<String>,
+    accepts_html: bool,
+}
+
+
+/// Reads the request line and headers. Returns `None` if the request is malformed.
+async fn read_request<R: AsyncBufReadExt + Unpin>(reader: &mut R) -> io::Result<Option<Request>> {
+    let mut lines = Vec::new();
+    let mut total_length = 0;
+    loop {
+        let mut line = String::new();
+        let length = reader.read_line(&mut line).await?;
+        if length == 0 {
+            return Err(io::ErrorKind::UnexpectedEof.into());
+        }
+        total_length += length;
+        if total_length > MAX_REQUEST_LENGTH {
+            return Ok(None);
+        }
+        let line = line.trim_end_matches(['\r', '\n']);
+        if line.is_empty() {
+            break;
+        }
+        lines.push(line.to_owned());
+    }
+
+    let mut request_line = match lines.first() {
+        Some(l) => l.split(' '),
+        None => return Ok(None),
+    };
+    let (method, target) = match (request_line.next(), request_line.next()) {
+        (Some(m), Some(t)) => (m, t),
+        _ => return Ok(None),
+    };
+    let path = target.split('?').next().unwrap_or("");
+
+    let mut host = None;
+    let mut accepts_html = false;
+    for header in &lines[1..] {
+        let (name, value) = match header.split_once(':') {
+            Some(nv) => nv,
+            None => return Ok(None),
+        };
+        if name.eq_ignore_ascii_case("host") {
+            host = Some(value.trim().to_owned());
+        } else if name.eq_ignore_ascii_case("accept") {
+            accepts_html = value.contains("text/html");
+        }
+    }
+
+    Ok(Some(Request {
+        method: method.to_owned(),
+        path: path.to_owned(),
+        host,
+        accepts_html,
+    }))
+}
+
+
+/// Escapes text for inclusion in HTML.
+fn escape_html(text: &str) -> String {
+    let mut ret = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => ret.push_str("&amp;"),
+            '<' => ret.push_str("&lt;"),
+            '>' => ret.push_str("&gt;"),
+            '"' => ret.push_str("&quot;"),
+            other => ret.push(other),
+        }
+    }
+    ret
+}
+
+
+/// Renders the page shown to browsers.
+fn index_page(config: &SocketConfig, host: &str) -> String {
+    let mut page = String::new();
+    page.push_str("<!DOCTYPE html>\n<html>\n<head>\n<meta charset=\"utf-8\">\n<title>telnet-animations</title>\n</head>\n<body>\n");
+    page.push_str("<h1>telnet-animations</h1>\n");
+    page.push_str("<p>These animations are best enjoyed in a terminal:</p>\n<ul>\n");
+    for choice in config.animation_choices() {
+        let name = escape_html(&choice.name);
+        let url = format!("http://{}/{}", escape_html(host), name);
+        writeln!(page, "<li><a href=\"{}\">{}</a>: <code>curl -N {}</code></li>", url, name, url).unwrap();
+    }
+    page.push_str("</ul>\n</body>\n</html>\n");
+    page
+}
+
+
+/// Writes a complete response with the given status and body.
+async fn write_response<W: AsyncWriteExt + Unpin>(writer: &mut W, status: &str, content_type: &str, body: &str, send_body: bool) -> io::Result<()> {
+    let head = format!(
+        "HTTP/1.1 {}\r\nContent-Type: {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+        status, content_type, body.len(),
+    );
+    writer.write_all(head.as_bytes()).await?;
+    if send_body {
+        writer.write_all(body.as_bytes()).await?;
+    }
+    writer.flush().await
+}
+
+
+/// Writes a chunk of a response with chunked transfer encoding.
+async fn write_chunk<W: AsyncWriteExt + Unpin>(writer: &mut W, data: &[u8]) -> io::Result<u64> {
+    if data.is_empty() {
+        // an empty chunk would end the response
+        return Ok(0);
+    }
+    let head = format!("{:x}\r\n", data.len());
+    writer.write_all(head.as_bytes()).await?;
+    writer.write_all(data).await?;
+    writer.write_all(b"\r\n").await?;
+    writer.flush().await?;
+    Ok((head.len() + data.len() + 2) as u64)
+}
+
+
+/// Answers an HTTP request on a newly connected stream.
+pub(crate) async fn serve(
+    stream: Box<dyn Stream>,
+    addr: SocketAddr,
+    config: SocketConfig,
+    mut shutdown: watch::Receiver<bool>,
+    mut registration: Registration,
+) -> Result<(), telnet::Error> {
+    let mut stream = BufReader::new(stream);
+
+    let request_timeout = Duration::from_millis(config.negotiation_timeout_ms);
+    let request = match tokio::time::timeout(request_timeout, read_request(&mut stream)).await {
+        Ok(Ok(Some(r))) => r,
+        Ok(Ok(None)) => {
+            write_response(&mut stream, "400 Bad Request", "text/plain", "Bad request.\n", true)
+                .await.map_err(|e| telnet::Error::from_io_send(e, addr))?;
+            return Ok(());
+        },
+        Ok(Err(e)) => return Err(telnet::Error::from_io_receive(e, addr)),
+        Err(_) => {
+            info!("{} did not send an HTTP request in time", addr);
+            return Ok(());
+        },
+    };
+    let send_body = request.method != "HEAD";
+    if request.method != "GET" && request.method != "HEAD" {
+        return write_response(&mut stream, "405 Method Not Allowed", "text/plain", "Only GET is supported.\n", true)
+            .await.map_err(|e| telnet::Error::from_io_send(e, addr));
+    }
+
+    if request.accepts_html {
+        let host = request.host.clone()
+            .unwrap_or_else(|| config.listen_socket_addr.to_string());
+        let page = index_page(&config, &host);
+        return write_response(&mut stream, "200 OK", "text/html; charset=utf-8", &page, send_body)
+            .await.map_err(|e| telnet::Error::from_io_send(e, addr));
+    }
+
+    // "/" plays the first animation
+    let name = request.path.trim_start_matches('/');
+    let choices = config.animation_choices();
+    let choice = if name.is_empty() {
+        choices.first()
+    } else {
+        choices.iter().find(|c| c.name == name)
+    };
+    let choice = match choice {
+        Some(c) => c,
+        None => {
+            return write_response(&mut stream, "404 Not Found", "text/plain", "No such animation.\n", send_body)
+                .await.map_err(|e| telnet::Error::from_io_send(e, addr));
+        },
+    };
+    let mut animation = match animations::create(choice) {
+        Ok(a) => a,
+        Err(e) => {
+            error!("failed to start animation: {}", e);
+            return write_response(&mut stream, "500 Internal Server Error", "text/plain", "Animation missing.\n", send_body)
+                .await.map_err(|e| telnet::Error::from_io_send(e, addr));
+        },
+    };
+    registration.set_animation(Some(choice.name.clone()));
+
+    let head = "HTTP/1.1 200 OK\r\nContent-Type: text/plain; charset=utf-8\r\nTransfer-Encoding: chunked\r\nCache-Control: no-store\r\nConnection: close\r\n\r\n";
+    stream.write_all(head.as_bytes())
+        .await.map_err(|e| telnet::Error::from_io_send(e, addr))?;
+    stream.flush()
+        .await.map_err(|e| telnet::Error::from_io_send(e, addr))?;
+    registration.add_bytes_sent(head.len() as u64);
+    if !send_body {
+        return Ok(());
+    }
+
+    // we know nothing about the terminal at the other end
+    let session_info = SessionInfo::default();
+    let session_deadline = config.max_session_secs
+        .map(|secs| Instant::now() + Duration::from_secs(secs));
+    let mut cycles_started = 0;
+    let mut next_frame_at = Instant::now();
+    loop {
+        tokio::select! {
+            _ = sleep_until(next_frame_at) => {},
+            _ = sleep_until_opt(session_deadline) => {
+                info!("{} reached the session time limit", addr);
+                break;
+            },
+            _ = shutdown.changed() => break,
+            command = registration.next_command() => match command {
+                Some(SessionCommand::Kick) => {
+                    info!("{} has been kicked", addr);
+                    break;
+                },
+                // there is no good place to show a message in a stream
+                Some(SessionCommand::Message(_)) => continue,
+                None => break,
+            },
+        }
+
+        let render_start = Instant::now();
+        let frame = match animation.next_frame(&session_info) {
+            Some(f) => f,
+            None => break,
+        };
+        if frame.starts_cycle {
+            if config.loops.is_some_and(|loops| cycles_started >= loops) {
+                info!("{} watched {} cycles", addr, cycles_started);
+                break;
+            }
+            cycles_started += 1;
+        }
+        let render_time = render_start.elapsed();
+        let write_start = Instant::now();
+        let byte_count = write_chunk(&mut stream, frame.commands.as_bytes())
+            .await.map_err(|e| telnet::Error::from_io_send(e, addr))?;
+        registration.add_bytes_sent(byte_count);
+        registration.add_frame_sent(Some(render_time), write_start.elapsed(), byte_count);
+        next_frame_at = Instant::now() + frame.delay;
+    }
+
+    let goodbye = format!("\x1B[0m\x1B[2J\x1B[H{}\r\n", config.goodbye_message);
+    write_chunk(&mut stream, goodbye.as_bytes())
+        .await.map_err(|e| telnet::Error::from_io_send(e, addr))?;
+    stream.write_all(b"0\r\n\r\n")
+        .await.map_err(|e| telnet::Error::from_io_send(e, addr))?;
+    stream.flush()
+        .await.map_err(|e| telnet::Error::from_io_send(e, addr))
+}
+
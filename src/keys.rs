@@ -0,0 +1,205 @@
+//! Decoding of the escape sequences terminals send for cursor keys.
+//!
+//! Depending on its mode, a terminal sends either CSI (`ESC [ A`) or SS3 (`ESC O A`) sequences for
+//! the cursor keys; both are understood. Modifiers (e.g. `ESC [ 1 ; 5 A` for Ctrl+Up) are ignored.
+
+
+use crate::telnet::Event;
+
+
+/// ASCII backspace.
+pub(crate) const BS: u8 = 0x08;
+
+/// ASCII escape.
+pub const ESC: u8 = 0x1B;
+
+/// ASCII delete, sent by many terminals for the backspace key.
+pub(crate) const DEL: u8 = 0x7F;
+
+/// The longest escape sequence that is decoded; longer ones are dropped.
+const MAX_SEQUENCE_LENGTH: usize = 16;
+
+
+/// A special key pressed by the client.
+#[derive(Clone, Copy, Debug, Eq, Hash, Ord, PartialEq, PartialOrd)]
+pub enum Key {
+    Up,
+    Down,
+    Left,
+    Right,
+    Home,
+    End,
+}
+
+
+/// The meaning of a byte received from the client.
+#[derive(Clone, Copy, Debug, Eq, Hash, Ord, PartialEq, PartialOrd)]
+pub(crate) enum Decoded {
+    /// An ordinary byte of data.
+    Data(u8),
+
+    /// The final byte of a sequence denoting a special key.
+    Key(Key),
+
+    /// The final byte of the sequence sent by the Enter key of the numeric keypad.
+    Enter,
+
+    /// Part of an escape sequence that is not complete yet, or the end of one that means nothing
+    /// to us.
+    Nothing,
+}
+
+
+/// Collects escape sequences byte by byte.
+#[derive(Clone, Debug, Default, Eq, Hash, Ord, PartialEq, PartialOrd)]
+pub(crate) struct KeyDecoder {
+    /// The escape sequence received so far (including the ESC), or empty if none is in progress.
+    sequence: Vec<u8>,
+}
+impl KeyDecoder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Processes the next byte from the client.
+    pub fn push(&mut self, b: u8) -> Decoded {
+        if self.sequence.is_empty() {
+            if b == ESC {
+                self.sequence.push(b);
+                return Decoded::Nothing;
+            }
+            return Decoded::Data(b);
+        }
+
+        if self.sequence.len() == 1 {
+            return match b {
+                b'['|b'O' => {
+                    self.sequence.push(b);
+                    Decoded::Nothing
+                },
+                ESC => {
+                    // the first one was the Escape key
+                    Decoded::Data(ESC)
+                },
+                other => {
+                    // Alt+key; take it as the key alone
+                    self.sequence.clear();
+                    Decoded::Data(other)
+                },
+            };
+        }
+
+        if self.sequence[1] == b'O' {
+            // SS3: always a single byte
+            self.sequence.clear();
+            return match b {
+                b'M' => Decoded::Enter,
+                other => key_from_final(other, &[]).map_or(Decoded::Nothing, Decoded::Key),
+            };
+        }
+
+        // CSI: parameter and intermediate bytes, then a final byte
+        match b {
+            0x20..=0x3F => {
+                self.sequence.push(b);
+                if self.sequence.len() > MAX_SEQUENCE_LENGTH {
+                    self.sequence.clear();
+                }
+                Decoded::Nothing
+            },
+            0x40..=0x7E => {
+                let decoded = key_from_final(b, &self.sequence[2..])
+                    .map_or(Decoded::Nothing, Decoded::Key);
+                self.sequence.clear();
+                decoded
+            },
+            _ => {
+                // not a valid sequence after all
+                self.sequence.clear();
+                Decoded::Nothing
+            },
+        }
+    }
+
+    /// Called once all data received so far has been processed; returns whether a lone ESC is
+    /// still pending, which is then taken to be the Escape key itself.
+    ///
+    /// Terminals send each escape sequence in one go, so a sequence is not expected to be split up
+    /// between reads.
+    pub fn take_lone_escape(&mut self) -> bool {
+        if self.sequence == [ESC] {
+            self.sequence.clear();
+            true
+        } else {
+            false
+        }
+    }
+}
+
+
+/// Determines the key from the final byte and parameters of a CSI or SS3 sequence.
+fn key_from_final(final_byte: u8, params: &[u8]) -> Option<Key> {
+    // only the first parameter matters; the others are modifiers
+    let first_param = params.split(|&b| b == b';').next().unwrap_or(&[]);
+    match (final_byte, first_param) {
+        (b'A', _) => Some(Key::Up),
+        (b'B', _) => Some(Key::Down),
+        (b'C', _) => Some(Key::Right),
+        (b'D', _) => Some(Key::Left),
+        (b'H', _) => Some(Key::Home),
+        (b'F', _) => Some(Key::End),
+        (b'~', b"1"|b"7") => Some(Key::Home),
+        (b'~', b"4"|b"8") => Some(Key::End),
+        _ => None,
+    }
+}
+
+
+/// The Konami code: Up, Up, Down, Down, Left, Right, Left, Right, B, A.
+const KONAMI_CODE: [Event; 10] = [
+    Event::Key(Key::Up),
+    Event::Key(Key::Up),
+    Event::Key(Key::Down),
+    Event::Key(Key::Down),
+    Event::Key(Key::Left),
+    Event::Key(Key::Right),
+    Event::Key(Key::Left),
+    Event::Key(Key::Right),
+    Event::Data(b'b'),
+    Event::Data(b'a'),
+];
+
+
+/// Watches the input for the Konami code.
+#[derive(Clone, Debug, Default, Eq, Hash, Ord, PartialEq, PartialOrd)]
+pub(crate) struct KonamiCode {
+    /// How much of the code has been entered so far.
+    matched: usize,
+}
+impl KonamiCode {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Processes the next input event; returns whether it completes the code.
+    pub fn advance(&mut self, event: &Event) -> bool {
+        // letters may be typed in either case
+        let event = match event {
+            Event::Data(b) => Event::Data(b.to_ascii_lowercase()),
+            other => other.clone(),
+        };
+        if event == KONAMI_CODE[self.matched] {
+            self.matched += 1;
+            if self.matched == KONAMI_CODE.len() {
+                self.matched = 0;
+                return true;
+            }
+        } else if event == KONAMI_CODE[0] {
+            // a third Up still leaves the first two in place
+            self.matched = if self.matched == 2 { 2 } else { 1 };
+        } else {
+            self.matched = 0;
+        }
+        false
+    }
+}
@@ -0,0 +1,817 @@
+//! Serves ASCII animations via Telnet.
+//!
+//! This is mostly the `telnet-animations` command-line application (see [`run`]), but the server can
+//! also be embedded in other applications using [`ServerBuilder`].
+
+
+mod admin;
+mod animations;
+mod broadcast;
+mod builder;
+mod cidr;
+mod cli;
+mod client;
+mod coaster;
+mod config;
+mod connection;
+mod console;
+mod daemon;
+mod default_config;
+mod export;
+mod handoff;
+mod http;
+mod keys;
+mod limit;
+mod logging;
+mod menu;
+mod playlist;
+#[cfg(unix)]
+mod privileges;
+mod proxy;
+mod rdns;
+mod recording;
+mod reload;
+mod registry;
+mod runtime;
+mod sandbox;
+mod screen;
+mod server;
+#[cfg(windows)]
+mod service;
+mod session;
+mod stats;
+mod stress;
+mod task;
+mod telnet;
+mod tls;
+mod websocket;
+
+
+use std::fs::File;
+use std::future::Future;
+use std::io::{BufWriter, IsTerminal};
+use std::net::SocketAddr;
+use std::path::Path;
+use std::sync::Arc;
+use std::time::Duration;
+
+use clap::Parser;
+use log::{error, info, warn};
+use socket2::SockRef;
+use tokio::runtime::Runtime;
+use tokio::sync::watch;
+use tokio::task::JoinHandle;
+
+use crate::admin::{AdminListener, AdminState};
+use crate::animations::AnimationConfig;
+use crate::cli::{Cli, Command, ExportFormat};
+use crate::config::{Config, Format, Protocol, Severity, SocketConfig};
+use crate::daemon::PidFile;
+use crate::handoff::{InheritedListeners, RestartRequests};
+use crate::limit::{ConnectionLimit, IpLimit};
+use crate::logging::LogTarget;
+use crate::rdns::ReverseDns;
+use crate::runtime::{RuntimeConfig, RuntimeFlavor};
+use crate::registry::Registry;
+use crate::server::{ServerState, accept_loop, bind_socket};
+use crate::session::{Shared, run_session};
+use crate::connection::TelnetConnection;
+
+pub use crate::builder::{Limits, ServeError, ServerBuilder};
+pub use tokio_util::sync::CancellationToken;
+
+
+/// The Telnet decoder, for the fuzz targets in `fuzz/`; not a stable interface.
+#[cfg(feature = "fuzzing")]
+#[doc(hidden)]
+pub mod fuzzing {
+    pub use crate::connection::TelnetConnection;
+    pub use crate::keys::ESC;
+    pub use crate::telnet::{Element, Event, TelnetMachine, decode_element};
+}
+
+
+/// How long the startup banner animation is shown.
+const STARTUP_BANNER_DURATION: Duration = Duration::from_secs(3);
+
+/// How long sessions are given to say goodbye when the server shuts down.
+const SHUTDOWN_GRACE_PERIOD: Duration = Duration::from_secs(2);
+
+/// How often to check whether all sessions have ended after handing over to a new process.
+const DRAIN_POLL_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Exit code: the configuration could not be loaded or is invalid. (2 is used by clap for invalid
+/// command lines.)
+const EXIT_CONFIG: i32 = 3;
+
+/// Exit code: the sockets (or the admin interface) could not be set up.
+const EXIT_BIND: i32 = 4;
+
+/// Exit code: the process could not be prepared for serving (e.g. daemonizing, writing the PID
+/// file, starting the runtime, sandboxing or dropping privileges failed).
+const EXIT_SETUP: i32 = 5;
+
+
+/// Waits until the server is asked to shut down (Ctrl+C or, on Unix, SIGTERM).
+async fn shutdown_signal() {
+    #[cfg(unix)]
+    {
+        use tokio::signal::unix::{signal, SignalKind};
+
+        let mut sigterm = match signal(SignalKind::terminate()) {
+            Ok(s) => s,
+            Err(e) => {
+                warn!("failed to register SIGTERM handler; only Ctrl+C will stop the server: {}", e);
+                let _ = tokio::signal::ctrl_c().await;
+                return;
+            },
+        };
+        tokio::select! {
+            _ = tokio::signal::ctrl_c() => {},
+            _ = sigterm.recv() => {},
+        }
+    }
+
+    #[cfg(not(unix))]
+    {
+        let _ = tokio::signal::ctrl_c().await;
+    }
+}
+
+
+/// Shows the startup banner animation followed by a summary of the listening sockets.
+///
+/// Does nothing if stdout is not a terminal.
+async fn show_startup_banner(animation_name: &str, socket_configs: &[SocketConfig]) {
+    if !std::io::stdout().is_terminal() {
+        return;
+    }
+
+    match animations::by_name(animation_name) {
+        Ok(mut animation) => {
+            if let Err(e) = console::play(&mut *animation, Some(STARTUP_BANNER_DURATION)).await {
+                eprintln!("failed to show startup banner: {}", e);
+                return;
+            }
+
+            // clear screen and go to top left
+            print!("\x1B[2J\x1B[H");
+        },
+        Err(e) => eprintln!("failed to show startup banner: {}", e),
+    }
+
+    println!("telnet-animations is up and running:");
+    for socket_config in socket_configs {
+        let choices = socket_config.animation_choices();
+        let names: Vec<&str> = choices.iter()
+            .map(|c| c.name.as_str())
+            .collect();
+        println!("  {} => {}", socket_config.listen_socket_addr, names.join(", "));
+    }
+}
+
+
+/// Validates the configuration, outputting any problems.
+///
+/// Returns whether the configuration can be used.
+fn validate_config(config: &Config) -> bool {
+    let problems = config.validate();
+    for problem in &problems {
+        eprintln!("{}", problem);
+    }
+    !problems.iter().any(|p| p.severity == Severity::Error)
+}
+
+
+/// Checks the configuration, returning the exit code.
+fn check_config(config_file_name: Option<&Path>, format: Option<Format>) -> i32 {
+    let config = match Config::load(config_file_name, format) {
+        Ok(c) => c,
+        Err(e) => {
+            eprintln!("error: {}", e);
+            return EXIT_CONFIG;
+        },
+    };
+
+    if validate_config(&config) {
+        println!("configuration OK");
+        0
+    } else {
+        EXIT_CONFIG
+    }
+}
+
+
+/// Outputs the animations that can be configured.
+fn list_animations() {
+    for info in animations::ANIMATIONS {
+        let frame_rate = if info.default_frame_ms == 0 {
+            "as fast as possible".to_owned()
+        } else {
+            format!("{} fps", 1000.0 / info.default_frame_ms as f64)
+        };
+        println!("{}", info.name);
+        println!("    {}", info.description);
+        println!("    default frame rate: {}", frame_rate);
+        println!("    required terminal size: {}x{}", info.size.0, info.size.1);
+    }
+}
+
+
+/// Shows an animation on the local terminal, returning the exit code.
+async fn preview(name: &str) -> i32 {
+    let mut animation = match animations::by_name(name) {
+        Ok(a) => a,
+        Err(e) => {
+            eprintln!("error: {}", e);
+            return 1;
+        },
+    };
+    match console::preview(&mut *animation).await {
+        Ok(()) => 0,
+        Err(e) => {
+            eprintln!("error: failed to output animation: {}", e);
+            1
+        },
+    }
+}
+
+
+/// Exports an animation as an asciinema recording, returning the exit code.
+fn export_asciinema(name: &str, output: &Path, seconds: f64, width: Option<u16>, height: Option<u16>) -> i32 {
+    let info = match animations::ANIMATIONS.iter().find(|info| info.name == name) {
+        Some(i) => i,
+        None => {
+            eprintln!("error: unknown animation {:?}", name);
+            return 1;
+        },
+    };
+    let duration = match Duration::try_from_secs_f64(seconds) {
+        Ok(d) => d,
+        Err(_) => {
+            eprintln!("error: invalid duration {}", seconds);
+            return 1;
+        },
+    };
+    let mut animation = match animations::by_name(name) {
+        Ok(a) => a,
+        Err(e) => {
+            eprintln!("error: {}", e);
+            return 1;
+        },
+    };
+
+    let width = width.unwrap_or(info.size.0.max(80));
+    let height = height.unwrap_or(info.size.1.max(24));
+    let result = File::create(output)
+        .and_then(|f| {
+            let mut writer = BufWriter::new(f);
+            export::write_asciinema(&mut writer, &mut *animation, name, width, height, duration)
+        });
+    match result {
+        Ok(()) => 0,
+        Err(e) => {
+            eprintln!("error: failed to write {}: {}", output.display(), e);
+            1
+        },
+    }
+}
+
+
+/// Exports the screens of an animation as plain text, returning the exit code.
+fn export_screens(name: &str, output: &Path, frames: usize, width: Option<u16>, height: Option<u16>) -> i32 {
+    let info = match animations::ANIMATIONS.iter().find(|info| info.name == name) {
+        Some(i) => i,
+        None => {
+            eprintln!("error: unknown animation {:?}", name);
+            return 1;
+        },
+    };
+    let mut animation = match animations::by_name(name) {
+        Ok(a) => a,
+        Err(e) => {
+            eprintln!("error: {}", e);
+            return 1;
+        },
+    };
+
+    let width = width.unwrap_or(info.size.0.max(80));
+    let height = height.unwrap_or(info.size.1.max(24));
+    let result = File::create(output)
+        .and_then(|f| {
+            let mut writer = BufWriter::new(f);
+            export::write_screens(&mut writer, &mut *animation, width, height, frames)
+        });
+    match result {
+        Ok(()) => 0,
+        Err(e) => {
+            eprintln!("error: failed to write {}: {}", output.display(), e);
+            1
+        },
+    }
+}
+
+
+/// The address of the client connected via stdin, if known.
+///
+/// This is the case if the program has been started by inetd or a similar superserver;
+/// otherwise, the unspecified address is returned.
+fn stdio_peer_addr() -> SocketAddr {
+    #[cfg(unix)]
+    {
+        let stdin = std::io::stdin();
+        if let Ok(peer) = SockRef::from(&stdin).peer_addr() {
+            if let Some(addr) = peer.as_socket() {
+                return addr;
+            }
+        }
+    }
+    SocketAddr::from(([0, 0, 0, 0], 0))
+}
+
+
+/// Serves a single session on stdin and stdout using the first configured socket's settings,
+/// returning the exit code.
+async fn serve_stdio(config: Config) -> i32 {
+    let socket_config = match config.sockets.into_iter().next() {
+        Some(sc) => sc,
+        None => {
+            eprintln!("error: no sockets configured");
+            return EXIT_CONFIG;
+        },
+    };
+
+    let mut connection = TelnetConnection::from_parts(
+        Box::new(tokio::io::stdin()),
+        Box::new(tokio::io::stdout()),
+        stdio_peer_addr(),
+        socket_config.max_sub_negotiation_length,
+    );
+    if socket_config.protocol == Protocol::Raw {
+        connection.disable_telnet();
+    }
+    let (shutdown_sender, shutdown_receiver) = watch::channel(false);
+    tokio::spawn(async move {
+        shutdown_signal().await;
+        let _ = shutdown_sender.send(true);
+    });
+
+    match run_session(connection, socket_config, shutdown_receiver, CancellationToken::new(), None, None).await {
+        Ok(()) => 0,
+        Err(e) if e.is_disconnect() => 0,
+        Err(e) => {
+            error!("{}", e);
+            1
+        },
+    }
+}
+
+
+/// Runs the future on the runtime, then abandons any tasks still running (e.g. blocking reads from
+/// stdin, which would otherwise keep the process alive).
+fn block_on<F: Future<Output = i32>>(runtime: Runtime, future: F) -> i32 {
+    let ret = runtime.block_on(future);
+    runtime.shutdown_background();
+    ret
+}
+
+
+/// Runs the future on a runtime with the default configuration.
+fn block_on_default<F: Future<Output = i32>>(future: F) -> i32 {
+    match runtime::build(&RuntimeConfig::default()) {
+        Ok(r) => block_on(r, future),
+        Err(e) => {
+            eprintln!("error: failed to start runtime: {}", e);
+            1
+        },
+    }
+}
+
+
+/// Builds the configuration from the command line (loading the configuration file if needed),
+/// validates it and sets up logging accordingly.
+///
+/// Problems are reported on stderr, in which case `None` is returned.
+fn prepare_config(cli: &Cli) -> Option<Config> {
+    let mut config = if let Some(animation) = &cli.animation {
+        if let Err(e) = animations::by_name(animation) {
+            eprintln!("error: {}", e);
+            return None;
+        }
+        let animation_config = AnimationConfig {
+            name: animation.clone(),
+            params: toml::Table::new(),
+        };
+
+        // the address is only used for logging in stdio mode
+        let listen = cli.listen
+            .unwrap_or_else(stdio_peer_addr);
+        Config::new(vec![SocketConfig::new(listen, animation_config)])
+    } else {
+        let config_file_name = cli.config_path();
+        match Config::load(config_file_name.map(|p| p.as_path()), cli.format) {
+            Ok(c) => c,
+            Err(e) => {
+                eprintln!("error: {}", e);
+                return None;
+            },
+        }
+    };
+    if let Some(worker_threads) = cli.worker_threads {
+        config.runtime.flavor = RuntimeFlavor::MultiThread;
+        config.runtime.worker_threads = Some(worker_threads);
+    }
+    if cli.current_thread {
+        config.runtime.flavor = RuntimeFlavor::CurrentThread;
+    }
+    if let Some(pid_file) = &cli.pid_file {
+        config.pid_file = Some(pid_file.clone());
+    }
+
+    if cli.stdio {
+        if cli.animation.is_none() && !validate_config(&config) {
+            return None;
+        }
+    } else if !validate_config(&config) {
+        return None;
+    }
+    if let Err(e) = logging::configure(&config.log) {
+        eprintln!("error: failed to set up logging: {}", e);
+        return None;
+    }
+    Some(config)
+}
+
+
+/// Runs the command-line application, returning the exit code.
+pub fn run() -> i32 {
+    let cli = Cli::parse();
+    logging::init();
+    logging::set_verbosity(cli.verbose);
+
+    match &cli.command {
+        Some(Command::Connect { target }) => return block_on_default(client::run(target)),
+        Some(Command::Replay { recording, target, speed }) => {
+            if !speed.is_finite() || *speed <= 0.0 {
+                eprintln!("error: invalid speed {}", speed);
+                return 1;
+            }
+            return block_on_default(recording::replay(recording, target, *speed));
+        },
+        Some(Command::Stress { target, sessions, seconds }) => {
+            let duration = match Duration::try_from_secs_f64(*seconds) {
+                Ok(d) => d,
+                Err(_) => {
+                    eprintln!("error: invalid duration {}", seconds);
+                    return 1;
+                },
+            };
+            return block_on_default(stress::run(target, *sessions, duration));
+        },
+        Some(Command::Check { config }) => {
+            let config_file_name = config.as_ref()
+                .or(cli.config_path());
+            return check_config(config_file_name.map(|p| p.as_path()), cli.format);
+        },
+        Some(Command::ListAnimations) => {
+            list_animations();
+            return 0;
+        },
+        Some(Command::DumpDefaultConfig) => {
+            print!("{}", default_config::default_config());
+            return 0;
+        },
+        Some(Command::Preview { name }) => return block_on_default(preview(name)),
+        Some(Command::Export(ExportFormat::Asciinema { name, output, seconds, width, height })) => {
+            return export_asciinema(name, output, *seconds, *width, *height);
+        },
+        Some(Command::Export(ExportFormat::Screens { name, output, frames, width, height })) => {
+            return export_screens(name, output, *frames, *width, *height);
+        },
+        #[cfg(windows)]
+        Some(Command::InstallService { config }) => {
+            let config_path = config.as_ref()
+                .or(cli.config_path())
+                .map(|p| p.as_path())
+                .unwrap_or(Path::new(config::DEFAULT_PATH));
+            return match service::install(config_path) {
+                Ok(()) => 0,
+                Err(e) => {
+                    eprintln!("error: failed to install service: {}", e);
+                    1
+                },
+            };
+        },
+        #[cfg(windows)]
+        Some(Command::UninstallService) => {
+            return match service::uninstall() {
+                Ok(()) => 0,
+                Err(e) => {
+                    eprintln!("error: failed to uninstall service: {}", e);
+                    1
+                },
+            };
+        },
+        #[cfg(windows)]
+        Some(Command::RunService) => {
+            return match service::run() {
+                Ok(()) => 0,
+                Err(e) => {
+                    eprintln!("error: failed to run as service: {}", e);
+                    1
+                },
+            };
+        },
+        None => {},
+    }
+
+    let config = match prepare_config(&cli) {
+        Some(c) => c,
+        None => return EXIT_CONFIG,
+    };
+
+    if cli.daemon {
+        #[cfg(unix)]
+        {
+            let log_path = match &config.log.target {
+                LogTarget::File { path, .. } => Some(path.as_path()),
+                _ => None,
+            };
+            if config.log.target == LogTarget::Stderr {
+                eprintln!("warning: logging to stderr; log messages will be discarded in the background");
+            }
+            if let Err(e) = daemon::daemonize(log_path) {
+                eprintln!("error: failed to daemonize: {}", e);
+                return EXIT_SETUP;
+            }
+        }
+        #[cfg(not(unix))]
+        {
+            eprintln!("error: --daemon is only supported on Unix");
+            return EXIT_SETUP;
+        }
+    }
+    let _pid_file = match &config.pid_file {
+        Some(path) => match PidFile::create(path) {
+            Ok(pf) => Some(pf),
+            Err(e) => {
+                error!("failed to write PID file {}: {}", path.display(), e);
+                return EXIT_SETUP;
+            },
+        },
+        None => None,
+    };
+
+    if let Some(sandbox_config) = &config.sandbox {
+        let paths = sandbox::Paths::for_config(&config, cli.config_path().map(|p| p.as_path()));
+        if let Err(e) = sandbox::apply(sandbox_config, &paths) {
+            error!("failed to set up sandbox: {}", e);
+            return EXIT_SETUP;
+        }
+    }
+
+    // the watcher starts a thread, which must not escape the sandbox
+    let _watcher = if config.watch_animation_files {
+        let files: Vec<_> = config.sockets.iter()
+            .flat_map(|socket_config| socket_config.animation_files())
+            .collect();
+        match reload::watch(&files) {
+            Ok(w) => Some(w),
+            Err(e) => {
+                error!("failed to watch animation files: {}", e);
+                return EXIT_SETUP;
+            },
+        }
+    } else {
+        None
+    };
+
+    let runtime = match runtime::build(&config.runtime) {
+        Ok(r) => r,
+        Err(e) => {
+            eprintln!("error: failed to start runtime: {}", e);
+            return EXIT_SETUP;
+        },
+    };
+    if cli.stdio {
+        block_on(runtime, serve_stdio(config))
+    } else {
+        block_on(runtime, serve_process(config, cli.best_effort, shutdown_signal()))
+    }
+}
+
+
+/// Serves the sockets of the configuration as the server process until `stop` completes,
+/// returning the exit code.
+async fn serve_process<F: Future<Output = ()>>(config: Config, best_effort: bool, stop: F) -> i32 {
+    let options = ServeOptions {
+        best_effort,
+        restartable: true,
+    };
+    match serve(config, options, stop).await {
+        Ok(()) => 0,
+        Err(e) => {
+            error!("{}", e);
+            match e {
+                ServeError::InvalidConfig { .. } => EXIT_CONFIG,
+                ServeError::DropPrivileges { .. } => EXIT_SETUP,
+                _ => EXIT_BIND,
+            }
+        },
+    }
+}
+
+
+/// How the sockets of a configuration are served.
+#[derive(Clone, Copy, Debug, Default, Eq, Hash, PartialEq)]
+pub(crate) struct ServeOptions {
+    /// Skip sockets (and admin interfaces) that cannot be set up, as long as at least one socket
+    /// remains.
+    pub best_effort: bool,
+
+    /// Take over listening sockets from a previous process and hand them over to a new process
+    /// when asked to restart (SIGUSR2 on Unix); not something an embedding application wants.
+    pub restartable: bool,
+}
+
+
+/// Serves the sockets of the configuration until `stop` completes.
+async fn serve<F: Future<Output = ()>>(config: Config, options: ServeOptions, stop: F) -> Result<(), ServeError> {
+    // after a restart, the sockets are already listening
+    let mut inherited_listeners = if options.restartable {
+        InheritedListeners::from_env()
+    } else {
+        InheritedListeners::default()
+    };
+    let mut bound_sockets = Vec::with_capacity(config.sockets.len());
+    let mut listener_copies = Vec::new();
+    for socket_config in &config.sockets {
+        let bound_socket = match bind_socket(socket_config, &mut inherited_listeners) {
+            Ok(bs) => bs,
+            Err(e) if options.best_effort => {
+                error!("{}; skipping this socket", e);
+                continue;
+            },
+            Err(e) => return Err(ServeError::Listen { error: Box::new(e) }),
+        };
+        for listener in &bound_socket.listeners {
+            // kept to be handed over to a new process on restart
+            match SockRef::from(listener).try_clone() {
+                Ok(copy) => listener_copies.push(std::net::TcpListener::from(copy)),
+                Err(e) => warn!("{}: restarting will not be possible: {}", socket_config.listen_socket_addr, e),
+            }
+        }
+        bound_sockets.push(bound_socket);
+    }
+    drop(inherited_listeners);
+    if bound_sockets.is_empty() {
+        return Err(ServeError::NoListeners);
+    }
+
+    if let Some(startup_banner) = &config.startup_banner {
+        let socket_configs: Vec<SocketConfig> = bound_sockets.iter()
+            .map(|bs| bs.config.clone())
+            .collect();
+        show_startup_banner(startup_banner, &socket_configs).await;
+    }
+
+    let admin_listeners = match &config.admin {
+        Some(admin_config) => match AdminListener::bind(admin_config) {
+            Ok(l) => l,
+            Err(e) if options.best_effort => {
+                error!("failed to bind admin interface: {}; continuing without it", e);
+                Vec::new()
+            },
+            Err(e) => return Err(ServeError::Admin { error: e }),
+        },
+        None => Vec::new(),
+    };
+
+    // everything requiring privileges has been done
+    #[cfg(unix)]
+    if config.user.is_some() || config.group.is_some() {
+        if let Err(e) = privileges::drop_to(config.user.as_deref(), config.group.as_deref()) {
+            return Err(ServeError::DropPrivileges { error: Box::new(e) });
+        }
+        info!(
+            "dropped privileges to user {}, group {}",
+            config.user.as_deref().unwrap_or("(unchanged)"),
+            config.group.as_deref().unwrap_or("(primary)"),
+        );
+    }
+
+    let registry = Registry::new();
+    let (shutdown_sender, shutdown_receiver) = watch::channel(false);
+    let (stop_accepting_sender, stop_accepting_receiver) = watch::channel(false);
+    let server_state = ServerState {
+        connection_limit: ConnectionLimit::new(config.max_total_connections),
+        ip_limit: IpLimit::new(config.max_connections_per_ip, config.max_new_connections_per_ip_per_minute),
+        registry: registry.clone(),
+        shared: Shared::new(),
+        reverse_dns: config.reverse_dns.then(ReverseDns::new),
+        shutdown: shutdown_receiver.clone(),
+        stop_accepting: stop_accepting_receiver,
+    };
+    let mut socket_config_senders = Vec::with_capacity(bound_sockets.len());
+    for bound_socket in bound_sockets {
+        let (socket_config_sender, socket_config_receiver) = watch::channel(bound_socket.config);
+        socket_config_senders.push(socket_config_sender);
+        for listener in bound_socket.listeners {
+            task::spawn_logged(
+                format!("accept loop on {}", socket_config_receiver.borrow().listen_socket_addr),
+                accept_loop(listener, socket_config_receiver.clone(), bound_socket.tls_acceptor.clone(), server_state.clone()),
+            );
+        }
+    }
+    drop(server_state);
+
+    let admin_state = Arc::new(AdminState {
+        registry: registry.clone(),
+        sockets: socket_config_senders,
+    });
+    let mut admin_tasks = spawn_admin(admin_listeners, &admin_state, &shutdown_receiver);
+
+    if let Some(stats_interval_mins) = config.stats_interval_mins {
+        let interval = Duration::from_secs(stats_interval_mins.saturating_mul(60));
+        task::spawn_logged("statistics logger".to_owned(), stats::log_periodically(registry.clone(), interval, shutdown_receiver.clone()));
+    }
+
+    // serve until asked to stop; after handing over to a new process, only until all sessions have
+    // ended
+    tokio::pin!(stop);
+    let mut restart_requests = if options.restartable {
+        RestartRequests::new()
+    } else {
+        RestartRequests::disabled()
+    };
+    let mut draining = false;
+    loop {
+        tokio::select! {
+            _ = &mut stop => break,
+            _ = restart_requests.next(), if !draining => {
+                // the admin sockets cannot be shared, so they are closed before the new process
+                // binds them again
+                for task in admin_tasks.drain(..) {
+                    task.abort();
+                    let _ = task.await;
+                }
+                if config.sandbox.is_some() {
+                    error!("cannot restart: the sandbox forbids starting programs");
+                } else {
+                    match handoff::spawn_successor(&listener_copies) {
+                        Ok(pid) => {
+                            info!("handed over to process {}; waiting for {} sessions to end", pid, registry.len());
+                            let _ = stop_accepting_sender.send(true);
+                            listener_copies.clear();
+                            draining = true;
+                            continue;
+                        },
+                        Err(e) => error!("failed to restart: {}", e),
+                    }
+                }
+
+                // carry on as before
+                let admin_listeners = match &config.admin {
+                    Some(admin_config) => AdminListener::bind(admin_config)
+                        .unwrap_or_else(|e| {
+                            error!("failed to bind admin interface again: {}", e);
+                            Vec::new()
+                        }),
+                    None => Vec::new(),
+                };
+                admin_tasks = spawn_admin(admin_listeners, &admin_state, &shutdown_receiver);
+            },
+            _ = tokio::time::sleep(DRAIN_POLL_INTERVAL), if draining => {
+                if registry.len() == 0 {
+                    info!("all sessions have ended");
+                    return Ok(());
+                }
+            },
+        }
+    }
+
+    // ask the sessions to say goodbye and wait until they are done (or we run out of patience)
+    info!("shutting down ({} sessions live)", registry.len());
+    let _ = stop_accepting_sender.send(true);
+    drop(shutdown_receiver);
+    let _ = shutdown_sender.send(true);
+    if tokio::time::timeout(SHUTDOWN_GRACE_PERIOD, shutdown_sender.closed()).await.is_err() {
+        for info in registry.list() {
+            warn!(
+                "aborting session {} of {} (connected to {} for {:?}, animation {:?})",
+                info.id, info.peer_addr, info.listen_addr, info.connected_at.elapsed(), info.animation,
+            );
+        }
+        registry.abort_all();
+    }
+    Ok(())
+}
+
+
+/// Spawns a task serving the admin interface on each listener.
+fn spawn_admin(listeners: Vec<AdminListener>, state: &Arc<AdminState>, shutdown: &watch::Receiver<bool>) -> Vec<JoinHandle<()>> {
+    listeners.into_iter()
+        .map(|listener| task::spawn_logged("admin interface".to_owned(), admin::serve(listener, Arc::clone(state), shutdown.clone())))
+        .collect()
+}
+
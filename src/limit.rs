@@ -0,0 +1,204 @@
+//! Limits on the number of concurrent sessions and new connections.
+
+
+use std::collections::{HashMap, VecDeque};
+use std::fmt;
+use std::net::IpAddr;
+use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::Duration;
+
+use tokio::time::Instant;
+
+
+/// The window within which new connections per address are counted.
+const RATE_WINDOW: Duration = Duration::from_secs(60);
+
+
+/// Counts the live sessions and enforces an optional ceiling on them.
+#[derive(Clone, Debug)]
+pub(crate) struct ConnectionLimit {
+    count: Arc<AtomicUsize>,
+    max: Option<usize>,
+}
+impl ConnectionLimit {
+    pub fn new(max: Option<usize>) -> Self {
+        Self {
+            count: Arc::new(AtomicUsize::new(0)),
+            max,
+        }
+    }
+
+    /// The number of sessions currently live.
+    pub fn count(&self) -> usize {
+        self.count.load(Ordering::SeqCst)
+    }
+
+    /// The maximum number of sessions, if any.
+    pub fn max(&self) -> Option<usize> {
+        self.max
+    }
+
+    /// Attempts to register a new session.
+    ///
+    /// Returns `None` if the limit has been reached; otherwise, returns a permit that unregisters
+    /// the session when dropped.
+    pub fn try_acquire(&self) -> Option<ConnectionPermit> {
+        let max = self.max.unwrap_or(usize::MAX);
+        self.count
+            .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |count| {
+                if count < max {
+                    Some(count + 1)
+                } else {
+                    None
+                }
+            })
+            .ok()?;
+        Some(ConnectionPermit {
+            count: Arc::clone(&self.count),
+        })
+    }
+}
+
+
+/// Proof that a session has been registered with a [`ConnectionLimit`].
+///
+/// The session is unregistered when the permit is dropped.
+#[derive(Debug)]
+pub(crate) struct ConnectionPermit {
+    count: Arc<AtomicUsize>,
+}
+impl Drop for ConnectionPermit {
+    fn drop(&mut self) {
+        self.count.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+
+/// Why a connection was refused by an [`IpLimit`].
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub(crate) enum IpLimitExceeded {
+    /// The address already has the maximum number of sessions live.
+    Concurrent { live: usize, max: usize },
+
+    /// The address has opened the maximum number of connections within the last minute.
+    Rate { recent: usize, max: usize },
+}
+impl fmt::Display for IpLimitExceeded {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Concurrent { live, max }
+                => write!(f, "{} sessions live from this address (limit {})", live, max),
+            Self::Rate { recent, max }
+                => write!(f, "{} connections from this address within the last minute (limit {})", recent, max),
+        }
+    }
+}
+
+
+#[derive(Debug, Default)]
+struct IpState {
+    live: usize,
+
+    /// When the connections within the rate window were accepted, oldest first.
+    recent: VecDeque<Instant>,
+}
+impl IpState {
+    fn forget_before(&mut self, cutoff: Instant) {
+        while self.recent.front().map(|t| *t < cutoff).unwrap_or(false) {
+            self.recent.pop_front();
+        }
+    }
+
+    fn is_unused(&self) -> bool {
+        self.live == 0 && self.recent.is_empty()
+    }
+}
+
+
+#[derive(Debug)]
+struct IpLimitInner {
+    states: HashMap<IpAddr, IpState>,
+    last_sweep: Instant,
+}
+
+
+/// Limits the number of live sessions and new connections per client address.
+#[derive(Clone, Debug)]
+pub(crate) struct IpLimit {
+    inner: Arc<Mutex<IpLimitInner>>,
+    max_concurrent: Option<usize>,
+    max_per_minute: Option<usize>,
+}
+impl IpLimit {
+    pub fn new(max_concurrent: Option<usize>, max_per_minute: Option<usize>) -> Self {
+        Self {
+            inner: Arc::new(Mutex::new(IpLimitInner {
+                states: HashMap::new(),
+                last_sweep: Instant::now(),
+            })),
+            max_concurrent,
+            max_per_minute,
+        }
+    }
+
+    /// Attempts to register a new session from the given address.
+    ///
+    /// On success, returns a permit that unregisters the session when dropped.
+    pub fn try_acquire(&self, ip: IpAddr) -> Result<IpPermit, IpLimitExceeded> {
+        let now = Instant::now();
+        let cutoff = now.checked_sub(RATE_WINDOW).unwrap_or(now);
+        let mut inner = self.inner.lock().unwrap();
+
+        // occasionally drop the addresses that have gone quiet, so scanners don't fill up memory
+        if now.duration_since(inner.last_sweep) >= RATE_WINDOW {
+            inner.states.retain(|_, state| {
+                state.forget_before(cutoff);
+                !state.is_unused()
+            });
+            inner.last_sweep = now;
+        }
+
+        let state = inner.states.entry(ip).or_default();
+        state.forget_before(cutoff);
+        if let Some(max) = self.max_concurrent {
+            if state.live >= max {
+                return Err(IpLimitExceeded::Concurrent { live: state.live, max });
+            }
+        }
+        if let Some(max) = self.max_per_minute {
+            if state.recent.len() >= max {
+                return Err(IpLimitExceeded::Rate { recent: state.recent.len(), max });
+            }
+        }
+        state.live += 1;
+        if self.max_per_minute.is_some() {
+            state.recent.push_back(now);
+        }
+        Ok(IpPermit {
+            inner: Arc::clone(&self.inner),
+            ip,
+        })
+    }
+}
+
+
+/// Proof that a session has been registered with an [`IpLimit`].
+///
+/// The session is unregistered when the permit is dropped.
+#[derive(Debug)]
+pub(crate) struct IpPermit {
+    inner: Arc<Mutex<IpLimitInner>>,
+    ip: IpAddr,
+}
+impl Drop for IpPermit {
+    fn drop(&mut self) {
+        let mut inner = self.inner.lock().unwrap();
+        if let Some(state) = inner.states.get_mut(&self.ip) {
+            state.live -= 1;
+            if state.is_unused() {
+                inner.states.remove(&self.ip);
+            }
+        }
+    }
+}
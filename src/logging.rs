@@ -0,0 +1,433 @@
+//! Output of log messages to stderr, a file, syslog or journald.
+
+
+use std::fs::{self, File, OpenOptions};
+use std::fmt::Write as _;
+use std::io::{self, Write};
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicU8, AtomicUsize, Ordering};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use log::{Level, LevelFilter, Log, Metadata, Record, trace};
+use schemars::JsonSchema;
+use serde::{Deserialize, Deserializer, Serialize};
+use serde::de::Error as _;
+
+
+/// The name under which messages are sent to syslog and journald.
+const IDENTIFIER: &str = "telnet-animations";
+
+#[cfg(unix)]
+const SYSLOG_SOCKET_PATH: &str = "/dev/log";
+
+#[cfg(unix)]
+const JOURNALD_SOCKET_PATH: &str = "/run/systemd/journal/socket";
+
+/// The syslog facility used for messages ("daemon").
+#[cfg(unix)]
+const SYSLOG_FACILITY: u8 = 3;
+
+
+/// Configuration of log output.
+#[derive(Clone, Debug, Default, JsonSchema, PartialEq, Serialize)]
+pub(crate) struct LogConfig {
+    /// The least severe messages to output.
+    #[serde(default)]
+    pub level: LogLevel,
+
+    /// Where to output messages.
+    #[serde(default, flatten)]
+    pub target: LogTarget,
+}
+
+
+impl<'de> Deserialize<'de> for LogConfig {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        #[derive(Deserialize)]
+        struct Fields {
+            #[serde(default)]
+            level: LogLevel,
+
+            #[serde(flatten)]
+            target: LogTarget,
+        }
+
+        // serde does not fall back to the default variant if the tag of a flattened enum is missing
+        let mut table = toml::Table::deserialize(deserializer)?;
+        table.entry("target")
+            .or_insert_with(|| toml::Value::String("stderr".to_owned()));
+        let fields: Fields = table.try_into()
+            .map_err(|e: toml::de::Error| D::Error::custom(e.message()))?;
+        Ok(Self {
+            level: fields.level,
+            target: fields.target,
+        })
+    }
+}
+
+
+/// The severity of a log message.
+#[derive(Clone, Copy, Debug, Default, Deserialize, Eq, Hash, JsonSchema, Ord, PartialEq, PartialOrd, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub(crate) enum LogLevel {
+    Error,
+    Warn,
+    #[default]
+    Info,
+    Debug,
+    Trace,
+}
+impl From<LogLevel> for LevelFilter {
+    fn from(level: LogLevel) -> Self {
+        match level {
+            LogLevel::Error => LevelFilter::Error,
+            LogLevel::Warn => LevelFilter::Warn,
+            LogLevel::Info => LevelFilter::Info,
+            LogLevel::Debug => LevelFilter::Debug,
+            LogLevel::Trace => LevelFilter::Trace,
+        }
+    }
+}
+
+
+/// Where log messages are output.
+#[derive(Clone, Debug, Default, Deserialize, JsonSchema, PartialEq, Serialize)]
+#[serde(tag = "target", rename_all = "snake_case")]
+pub(crate) enum LogTarget {
+    /// Standard error.
+    #[default]
+    Stderr,
+
+    /// A file, which is rotated when it becomes too large or too old.
+    File {
+        /// The path of the log file. Rotated files are named by appending `.1`, `.2` etc.
+        path: PathBuf,
+
+        /// Rotate the file once it has reached this size, in bytes.
+        #[serde(default)]
+        max_bytes: Option<u64>,
+
+        /// Rotate the file once it has been written to for this many seconds.
+        #[serde(default)]
+        rotate_interval_secs: Option<u64>,
+
+        /// How many rotated files to keep.
+        #[serde(default = "LogTarget::default_keep_files")]
+        keep_files: usize,
+    },
+
+    /// The local syslog daemon (Unix only).
+    Syslog,
+
+    /// The systemd journal (Unix only).
+    Journald,
+}
+impl LogTarget {
+    fn default_keep_files() -> usize { 5 }
+}
+
+
+/// A log file that is rotated according to size and age.
+struct RotatingFile {
+    path: PathBuf,
+    file: File,
+    size: u64,
+    opened_at: SystemTime,
+    max_bytes: Option<u64>,
+    rotate_interval: Option<Duration>,
+    keep_files: usize,
+}
+impl RotatingFile {
+    fn open(path: &Path, max_bytes: Option<u64>, rotate_interval: Option<Duration>, keep_files: usize) -> io::Result<Self> {
+        let file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)?;
+        let metadata = file.metadata()?;
+        let opened_at = metadata.created()
+            .unwrap_or_else(|_| SystemTime::now());
+        Ok(Self {
+            path: path.to_owned(),
+            file,
+            size: metadata.len(),
+            opened_at,
+            max_bytes,
+            rotate_interval,
+            keep_files,
+        })
+    }
+
+    fn rotated_path(&self, index: usize) -> PathBuf {
+        let mut name = self.path.clone().into_os_string();
+        name.push(format!(".{}", index));
+        PathBuf::from(name)
+    }
+
+    fn needs_rotation(&self, now: SystemTime) -> bool {
+        let too_large = self.max_bytes
+            .map(|max| self.size >= max)
+            .unwrap_or(false);
+        let too_old = self.rotate_interval
+            .map(|interval| now.duration_since(self.opened_at).map(|age| age >= interval).unwrap_or(false))
+            .unwrap_or(false);
+        too_large || too_old
+    }
+
+    fn rotate(&mut self) -> io::Result<()> {
+        if self.keep_files == 0 {
+            fs::remove_file(&self.path)?;
+        } else {
+            // shift the older files out of the way; the oldest one is overwritten
+            for index in (1..self.keep_files).rev() {
+                let from = self.rotated_path(index);
+                if from.exists() {
+                    fs::rename(&from, self.rotated_path(index + 1))?;
+                }
+            }
+            fs::rename(&self.path, self.rotated_path(1))?;
+        }
+
+        self.file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.path)?;
+        self.size = 0;
+        self.opened_at = SystemTime::now();
+        Ok(())
+    }
+
+    fn write_line(&mut self, line: &str) -> io::Result<()> {
+        let now = SystemTime::now();
+        if self.needs_rotation(now) {
+            self.rotate()?;
+        }
+        self.file.write_all(line.as_bytes())?;
+        self.file.write_all(b"\n")?;
+        self.size += line.len() as u64 + 1;
+        Ok(())
+    }
+}
+
+
+enum Sink {
+    Stderr,
+    File(RotatingFile),
+    #[cfg(unix)]
+    Syslog(std::os::unix::net::UnixDatagram),
+    #[cfg(unix)]
+    Journald(std::os::unix::net::UnixDatagram),
+}
+impl Sink {
+    fn write(&mut self, level: Level, message: &str) -> io::Result<()> {
+        match self {
+            Self::Stderr => {
+                eprintln!("{}", message);
+                Ok(())
+            },
+            Self::File(file) => {
+                let line = format!("{} {:<5} {}", format_timestamp(SystemTime::now()), level, message);
+                file.write_line(&line)
+            },
+            #[cfg(unix)]
+            Self::Syslog(socket) => {
+                let priority = SYSLOG_FACILITY * 8 + syslog_severity(level);
+                let datagram = format!("<{}>{}[{}]: {}", priority, IDENTIFIER, std::process::id(), message);
+                socket.send(datagram.as_bytes()).map(|_| ())
+            },
+            #[cfg(unix)]
+            Self::Journald(socket) => {
+                // messages are single lines, so the simple variant of the native protocol suffices
+                let datagram = format!(
+                    "MESSAGE={}\nPRIORITY={}\nSYSLOG_IDENTIFIER={}\n",
+                    message.replace('\n', " "), syslog_severity(level), IDENTIFIER,
+                );
+                socket.send(datagram.as_bytes()).map(|_| ())
+            },
+        }
+    }
+}
+
+
+/// The syslog severity corresponding to the log level.
+#[cfg(unix)]
+fn syslog_severity(level: Level) -> u8 {
+    match level {
+        Level::Error => 3,
+        Level::Warn => 4,
+        Level::Info => 6,
+        Level::Debug|Level::Trace => 7,
+    }
+}
+
+
+/// Formats the time as an ISO 8601 timestamp in UTC, e.g. `2023-04-01T12:34:56Z`.
+pub(crate) fn format_timestamp(time: SystemTime) -> String {
+    let secs = time.duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0);
+    let days = (secs / 86400) as i64;
+    let secs_of_day = secs % 86400;
+
+    // convert days since the epoch to a civil date (Howard Hinnant's algorithm)
+    let z = days + 719468;
+    let era = z.div_euclid(146097);
+    let day_of_era = z.rem_euclid(146097);
+    let year_of_era = (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let month_index = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * month_index + 2) / 5 + 1;
+    let month = if month_index < 10 { month_index + 3 } else { month_index - 9 };
+    let year = year_of_era + era * 400 + if month <= 2 { 1 } else { 0 };
+
+    format!(
+        "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}Z",
+        year, month, day, secs_of_day / 3600, (secs_of_day / 60) % 60, secs_of_day % 60,
+    )
+}
+
+
+struct Logger {
+    sink: Mutex<Sink>,
+}
+impl Log for Logger {
+    fn enabled(&self, metadata: &Metadata<'_>) -> bool {
+        if !metadata.target().starts_with(env!("CARGO_CRATE_NAME")) {
+            // -v shouldn't unleash the chatter of the libraries we use
+            return metadata.level() as usize <= CONFIGURED_LEVEL.load(Ordering::Relaxed);
+        }
+        metadata.level() <= log::max_level()
+    }
+
+    fn log(&self, record: &Record<'_>) {
+        if !self.enabled(record.metadata()) {
+            return;
+        }
+        let message = record.args().to_string();
+        let mut sink = self.sink.lock().unwrap();
+        if let Err(e) = sink.write(record.level(), &message) {
+            // not much else we can do
+            eprintln!("failed to output log message {:?}: {}", message, e);
+        }
+    }
+
+    fn flush(&self) {
+        if let Sink::File(file) = &mut *self.sink.lock().unwrap() {
+            let _ = file.file.flush();
+        }
+    }
+}
+
+
+static LOGGER: Logger = Logger {
+    sink: Mutex::new(Sink::Stderr),
+};
+
+/// The configured log level (as a `LevelFilter`), which applies to messages of other crates.
+static CONFIGURED_LEVEL: AtomicUsize = AtomicUsize::new(LevelFilter::Info as usize);
+
+/// How often `-v` has been passed on the command line.
+static VERBOSITY: AtomicU8 = AtomicU8::new(0);
+
+
+/// Which data exchanged with clients is hexdumped into the log.
+#[derive(Clone, Copy, Debug, Eq, Hash, Ord, PartialEq, PartialOrd)]
+pub(crate) enum HexdumpScope {
+    Nothing,
+
+    /// Telnet negotiation (commands, option negotiation and subnegotiation) in both directions.
+    Negotiation,
+
+    /// Everything received from and sent to clients.
+    Everything,
+}
+
+
+/// The least severe messages output regardless of the configuration, given the verbosity.
+fn verbosity_level_filter() -> LevelFilter {
+    match VERBOSITY.load(Ordering::Relaxed) {
+        0 => LevelFilter::Off,
+        1 => LevelFilter::Debug,
+        _ => LevelFilter::Trace,
+    }
+}
+
+
+/// Sets the verbosity requested on the command line.
+///
+/// `-v` outputs debug messages, `-vv` additionally hexdumps Telnet negotiation and `-vvv`
+/// hexdumps all data exchanged with clients.
+pub(crate) fn set_verbosity(verbosity: u8) {
+    VERBOSITY.store(verbosity, Ordering::Relaxed);
+    log::set_max_level(log::max_level().max(verbosity_level_filter()));
+}
+
+
+/// Which data exchanged with clients is to be hexdumped.
+pub(crate) fn hexdump_scope() -> HexdumpScope {
+    match VERBOSITY.load(Ordering::Relaxed) {
+        0|1 => HexdumpScope::Nothing,
+        2 => HexdumpScope::Negotiation,
+        _ => HexdumpScope::Everything,
+    }
+}
+
+
+/// Outputs data exchanged with a client as a hexdump at trace level.
+///
+/// `direction` is `<` for data received from the client and `>` for data sent to it.
+pub(crate) fn hexdump(addr: SocketAddr, direction: &str, buf: &[u8]) {
+    for (index, line) in buf.chunks(16).enumerate() {
+        let mut text = format!("{} {} {:08x} ", addr, direction, index * 16);
+        for b in line {
+            write!(text, " {:02x}", b).unwrap();
+        }
+        for _ in line.len()..16 {
+            text.push_str("   ");
+        }
+        text.push_str("  ");
+        text.extend(line.iter().map(|&b| if (0x20..=0x7E).contains(&b) { b as char } else { '.' }));
+        trace!("{}", text);
+    }
+}
+
+
+/// Starts outputting log messages to stderr.
+pub(crate) fn init() {
+    log::set_logger(&LOGGER)
+        .expect("logger already set");
+    log::set_max_level(LevelFilter::Info);
+}
+
+
+/// Switches log output according to the configuration.
+pub(crate) fn configure(config: &LogConfig) -> io::Result<()> {
+    let sink = match &config.target {
+        LogTarget::Stderr => Sink::Stderr,
+        LogTarget::File { path, max_bytes, rotate_interval_secs, keep_files } => {
+            let rotate_interval = rotate_interval_secs.map(Duration::from_secs);
+            Sink::File(RotatingFile::open(path, *max_bytes, rotate_interval, *keep_files)?)
+        },
+        #[cfg(unix)]
+        LogTarget::Syslog => {
+            let socket = std::os::unix::net::UnixDatagram::unbound()?;
+            socket.connect(SYSLOG_SOCKET_PATH)?;
+            Sink::Syslog(socket)
+        },
+        #[cfg(unix)]
+        LogTarget::Journald => {
+            let socket = std::os::unix::net::UnixDatagram::unbound()?;
+            socket.connect(JOURNALD_SOCKET_PATH)?;
+            Sink::Journald(socket)
+        },
+        #[cfg(not(unix))]
+        LogTarget::Syslog|LogTarget::Journald => {
+            return Err(io::Error::new(io::ErrorKind::Unsupported, "syslog and journald are only supported on Unix"));
+        },
+    };
+    *LOGGER.sink.lock().unwrap() = sink;
+    CONFIGURED_LEVEL.store(LevelFilter::from(config.level) as usize, Ordering::Relaxed);
+    log::set_max_level(LevelFilter::from(config.level).max(verbosity_level_filter()));
+    Ok(())
+}
@@ -0,0 +1,241 @@
+//! Menus and prompts letting the client choose between multiple animations.
+
+
+use std::fmt::Write;
+
+use crate::keys::{BS, DEL, Key};
+use crate::telnet::Event;
+
+
+/// ASCII negative acknowledge, sent for Ctrl-U, which erases the whole line.
+const NAK: u8 = 0x15;
+
+/// The longest name that can be typed on a prompt.
+const MAX_NAME_LENGTH: usize = 64;
+
+const NAME_PROMPT: &str = "Type a name and press Enter: ";
+
+
+/// What happened in response to input to a menu.
+#[derive(Clone, Copy, Debug, Eq, Hash, Ord, PartialEq, PartialOrd)]
+pub(crate) enum MenuOutcome {
+    /// Nothing visible happened.
+    Nothing,
+
+    /// The menu changed and must be output again.
+    Redraw,
+
+    /// The entry with the given index has been chosen.
+    Chosen(usize),
+}
+
+
+/// A numbered menu of entries.
+///
+/// If there are at most nine entries, pressing the number key chooses an entry immediately;
+/// otherwise, the number must be followed by Enter. Alternatively, an entry can be highlighted
+/// using the cursor keys and chosen using Enter.
+#[derive(Clone, Debug, Eq, Hash, Ord, PartialEq, PartialOrd)]
+pub(crate) struct Menu {
+    title: String,
+    entries: Vec<String>,
+    input: String,
+    selected: usize,
+}
+impl Menu {
+    pub fn new<T: Into<String>, E: Into<Vec<String>>>(title: T, entries: E) -> Self {
+        let ret = Self {
+            title: title.into(),
+            entries: entries.into(),
+            input: String::new(),
+            selected: 0,
+        };
+        assert_ne!(ret.entries.len(), 0);
+        ret
+    }
+
+    /// Outputs the whole menu, clearing the screen beforehand.
+    pub fn render(&self) -> String {
+        let mut ret = String::new();
+
+        // clear screen, go to top left
+        ret.push_str("\x1B[2J\x1B[H");
+
+        write!(ret, "{}\r\n\r\n", self.title).unwrap();
+        for (i, entry) in self.entries.iter().enumerate() {
+            if i == self.selected {
+                // highlight in reverse video
+                write!(ret, "{:>3}. \x1B[7m{}\x1B[0m\r\n", i + 1, entry).unwrap();
+            } else {
+                write!(ret, "{:>3}. {}\r\n", i + 1, entry).unwrap();
+            }
+        }
+        ret.push_str("\r\n");
+        if self.entries.len() <= 9 {
+            ret.push_str("Press a number key, or use the arrow keys and Enter, to choose.");
+        } else {
+            write!(ret, "Type a number and press Enter, or use the arrow keys and Enter, to choose: {}", self.input).unwrap();
+        }
+
+        ret
+    }
+
+    /// Processes input from the client.
+    pub fn handle_event(&mut self, event: &Event) -> MenuOutcome {
+        match event {
+            Event::Data(digit @ b'1'..=b'9') if self.entries.len() <= 9 => {
+                let index = usize::from(digit - b'1');
+                if index < self.entries.len() {
+                    MenuOutcome::Chosen(index)
+                } else {
+                    MenuOutcome::Nothing
+                }
+            },
+            Event::Data(digit @ b'0'..=b'9') if self.entries.len() > 9 => {
+                self.input.push(char::from(*digit));
+                MenuOutcome::Redraw
+            },
+            Event::Data(BS|DEL) if !self.input.is_empty() => {
+                self.input.pop();
+                MenuOutcome::Redraw
+            },
+            Event::Newline if !self.input.is_empty() => {
+                let number: usize = self.input.parse().unwrap_or(0);
+                self.input.clear();
+                if number >= 1 && number <= self.entries.len() {
+                    MenuOutcome::Chosen(number - 1)
+                } else {
+                    MenuOutcome::Redraw
+                }
+            },
+            Event::Newline => MenuOutcome::Chosen(self.selected),
+            Event::Key(Key::Up) => self.select(self.selected.saturating_sub(1)),
+            Event::Key(Key::Down) => self.select(self.selected + 1),
+            Event::Key(Key::Home) => self.select(0),
+            Event::Key(Key::End) => self.select(self.entries.len() - 1),
+            _ => MenuOutcome::Nothing,
+        }
+    }
+
+    /// Highlights the entry with the given index, or the last one if the index is too large.
+    fn select(&mut self, index: usize) -> MenuOutcome {
+        let index = index.min(self.entries.len() - 1);
+        if index == self.selected {
+            return MenuOutcome::Nothing;
+        }
+        self.selected = index;
+        MenuOutcome::Redraw
+    }
+}
+
+
+/// What happened in response to input to a name prompt.
+#[derive(Clone, Debug, Eq, Hash, Ord, PartialEq, PartialOrd)]
+pub(crate) enum PromptOutcome {
+    /// Nothing visible happened.
+    Nothing,
+
+    /// The given text must be output, e.g. to echo the input.
+    Output(String),
+
+    /// The entry with the given index has been chosen.
+    Chosen(usize),
+}
+
+
+/// A prompt on which the client types the name of an entry.
+///
+/// Unlike [`Menu`], this only needs a terminal that can print lines, so it also works for clients
+/// whose cursor keys don't make it through. Names are matched regardless of case, and a name may
+/// be abbreviated as long as it remains unambiguous.
+#[derive(Clone, Debug, Eq, Hash, Ord, PartialEq, PartialOrd)]
+pub(crate) struct NamePrompt {
+    title: String,
+    names: Vec<String>,
+    input: String,
+
+    /// Whether to echo the input; if not, the client does so itself.
+    echo: bool,
+}
+impl NamePrompt {
+    pub fn new<T: Into<String>, N: Into<Vec<String>>>(title: T, names: N, echo: bool) -> Self {
+        let ret = Self {
+            title: title.into(),
+            names: names.into(),
+            input: String::new(),
+            echo,
+        };
+        assert_ne!(ret.names.len(), 0);
+        ret
+    }
+
+    /// Outputs the title, the names and the prompt, clearing the screen beforehand.
+    pub fn render(&self) -> String {
+        // clear screen, go to top left
+        format!(
+            "\x1B[2J\x1B[H{}\r\n\r\n{}\r\n\r\n{}{}",
+            self.title, self.names.join(", "), NAME_PROMPT, self.input,
+        )
+    }
+
+    /// Processes input from the client.
+    pub fn handle_event(&mut self, event: &Event) -> PromptOutcome {
+        match event {
+            Event::Data(b @ 0x20..=0x7E) => {
+                if self.input.len() >= MAX_NAME_LENGTH {
+                    return PromptOutcome::Nothing;
+                }
+                self.input.push(char::from(*b));
+                self.echo(char::from(*b).to_string())
+            },
+            Event::Data(BS|DEL) => {
+                if self.input.pop().is_none() {
+                    return PromptOutcome::Nothing;
+                }
+                self.echo("\x08 \x08".to_owned())
+            },
+            Event::Data(NAK) => {
+                let erase = "\x08 \x08".repeat(self.input.len());
+                self.input.clear();
+                self.echo(erase)
+            },
+            Event::Newline => {
+                let name = std::mem::take(&mut self.input);
+                match self.find(name.trim()) {
+                    Some(index) => PromptOutcome::Chosen(index),
+                    None if name.trim().is_empty() => PromptOutcome::Output(format!("\r\n{}", NAME_PROMPT)),
+                    None => PromptOutcome::Output(format!("\r\nNo animation matches {:?}.\r\n{}", name.trim(), NAME_PROMPT)),
+                }
+            },
+            _ => PromptOutcome::Nothing,
+        }
+    }
+
+    fn echo(&self, text: String) -> PromptOutcome {
+        if self.echo {
+            PromptOutcome::Output(text)
+        } else {
+            PromptOutcome::Nothing
+        }
+    }
+
+    /// Returns the index of the entry with the given name or, failing that, of the only entry
+    /// whose name starts with it.
+    fn find(&self, name: &str) -> Option<usize> {
+        if name.is_empty() {
+            return None;
+        }
+        let name = name.to_lowercase();
+        if let Some(index) = self.names.iter().position(|n| n.to_lowercase() == name) {
+            return Some(index);
+        }
+        let mut prefixed = self.names.iter()
+            .enumerate()
+            .filter(|(_i, n)| n.to_lowercase().starts_with(&name))
+            .map(|(i, _n)| i);
+        match (prefixed.next(), prefixed.next()) {
+            (Some(index), None) => Some(index),
+            _ => None,
+        }
+    }
+}
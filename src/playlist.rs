@@ -0,0 +1,143 @@
+//! Playlists: animations shown one after the other, each until a stop condition is met.
+
+
+use std::fmt;
+use std::str::FromStr;
+use std::time::Duration;
+
+use schemars::JsonSchema;
+use schemars::gen::SchemaGenerator;
+use schemars::schema::{InstanceType, Metadata, Schema, SchemaObject};
+use serde::{Deserialize, Serialize};
+
+
+/// An entry of a playlist, given as the name of an animation and optionally, after a colon, when
+/// to move on to the next entry: after a duration (e.g. `roflcopter:30s`, with the units `ms`,
+/// `s`, `m` and `h`) or after a number of cycles (e.g. `lollercoaster:2loops`). Without a stop
+/// condition, the animation is shown for one cycle.
+#[derive(Clone, Debug, Deserialize, Eq, Hash, PartialEq, Serialize)]
+#[serde(try_from = "String", into = "String")]
+pub(crate) struct PlaylistItem {
+    pub name: String,
+    pub stop: Stop,
+}
+impl JsonSchema for PlaylistItem {
+    fn schema_name() -> String {
+        "PlaylistItem".to_owned()
+    }
+
+    fn json_schema(_gen: &mut SchemaGenerator) -> Schema {
+        SchemaObject {
+            metadata: Some(Box::new(Metadata {
+                description: Some("The name of an animation, optionally followed by a colon and a duration (e.g. 30s) or a number of cycles (e.g. 2loops).".to_owned()),
+                ..Default::default()
+            })),
+            instance_type: Some(InstanceType::String.into()),
+            ..Default::default()
+        }.into()
+    }
+}
+impl fmt::Display for PlaylistItem {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}:{}", self.name, self.stop)
+    }
+}
+impl FromStr for PlaylistItem {
+    type Err = ParsePlaylistItemError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (name, stop) = match s.split_once(':') {
+            Some((name, stop_str)) => {
+                let stop = stop_str.parse()
+                    .map_err(|_| ParsePlaylistItemError::InvalidStop { item: s.to_owned() })?;
+                (name, stop)
+            },
+            None => (s, Stop::Loops(1)),
+        };
+        if name.is_empty() {
+            return Err(ParsePlaylistItemError::MissingName { item: s.to_owned() });
+        }
+        Ok(Self { name: name.to_owned(), stop })
+    }
+}
+impl TryFrom<String> for PlaylistItem {
+    type Error = ParsePlaylistItemError;
+
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        value.parse()
+    }
+}
+impl From<PlaylistItem> for String {
+    fn from(value: PlaylistItem) -> Self {
+        value.to_string()
+    }
+}
+
+
+/// When a playlist moves on from an animation.
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub(crate) enum Stop {
+    /// Once the animation has been shown for this long.
+    Duration(Duration),
+
+    /// Once the animation has run through this many cycles (or has ended).
+    Loops(u64),
+}
+impl fmt::Display for Stop {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Duration(duration) => write!(f, "{}ms", duration.as_millis()),
+            Self::Loops(1) => write!(f, "1loop"),
+            Self::Loops(loops) => write!(f, "{}loops", loops),
+        }
+    }
+}
+impl FromStr for Stop {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let digits_end = s.find(|c: char| !c.is_ascii_digit())
+            .unwrap_or(s.len());
+        let (count_str, unit) = s.split_at(digits_end);
+        let count: u64 = count_str.parse()
+            .map_err(|_| ())?;
+        if count == 0 {
+            // would skip the animation entirely
+            return Err(());
+        }
+        let millis_per_unit = match unit {
+            "loop" | "loops" => return Ok(Self::Loops(count)),
+            "ms" => 1,
+            "s" => 1000,
+            "m" => 60 * 1000,
+            "h" => 60 * 60 * 1000,
+            _ => return Err(()),
+        };
+        let millis = count.checked_mul(millis_per_unit)
+            .ok_or(())?;
+        Ok(Self::Duration(Duration::from_millis(millis)))
+    }
+}
+
+
+#[derive(Debug)]
+#[non_exhaustive]
+pub(crate) enum ParsePlaylistItemError {
+    #[non_exhaustive]
+    MissingName { item: String },
+
+    #[non_exhaustive]
+    InvalidStop { item: String },
+}
+impl fmt::Display for ParsePlaylistItemError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::MissingName { item }
+                => write!(f, "missing animation name in playlist item {:?}", item),
+            Self::InvalidStop { item }
+                => write!(f, "invalid stop condition in playlist item {:?} (expected e.g. 30s or 2loops)", item),
+        }
+    }
+}
+impl std::error::Error for ParsePlaylistItemError {
+}
@@ -0,0 +1,107 @@
+//! Giving up root privileges once the listeners have been bound.
+
+
+use std::fmt;
+
+use nix::unistd::{Gid, Group, Uid, User, setgid, setuid};
+#[cfg(not(any(target_os = "haiku", target_os = "ios", target_os = "macos", target_os = "redox")))]
+use nix::unistd::setgroups;
+
+
+#[derive(Debug)]
+#[non_exhaustive]
+pub(crate) enum Error {
+    #[non_exhaustive]
+    UnknownUser { name: String },
+
+    #[non_exhaustive]
+    UnknownGroup { name: String },
+
+    #[non_exhaustive]
+    Lookup { name: String, error: nix::Error },
+
+    #[non_exhaustive]
+    SetGroups { error: nix::Error },
+
+    #[non_exhaustive]
+    SetGid { gid: Gid, error: nix::Error },
+
+    #[non_exhaustive]
+    SetUid { uid: Uid, error: nix::Error },
+}
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::UnknownUser { name }
+                => write!(f, "unknown user {:?}", name),
+            Self::UnknownGroup { name }
+                => write!(f, "unknown group {:?}", name),
+            Self::Lookup { name, error }
+                => write!(f, "failed to look up {:?}: {}", name, error),
+            Self::SetGroups { error }
+                => write!(f, "failed to drop supplementary groups: {}", error),
+            Self::SetGid { gid, error }
+                => write!(f, "failed to switch to group ID {}: {}", gid, error),
+            Self::SetUid { uid, error }
+                => write!(f, "failed to switch to user ID {}: {}", uid, error),
+        }
+    }
+}
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Self::UnknownUser { .. } => None,
+            Self::UnknownGroup { .. } => None,
+            Self::Lookup { error, .. } => Some(error),
+            Self::SetGroups { error } => Some(error),
+            Self::SetGid { error, .. } => Some(error),
+            Self::SetUid { error, .. } => Some(error),
+        }
+    }
+}
+
+
+/// Switches the process to the given user and group.
+///
+/// If only the user is given, the process switches to the user's primary group. The supplementary
+/// groups are dropped in any case.
+pub(crate) fn drop_to(user_name: Option<&str>, group_name: Option<&str>) -> Result<(), Error> {
+    let user = match user_name {
+        Some(name) => match User::from_name(name) {
+            Ok(Some(u)) => Some(u),
+            Ok(None) => return Err(Error::UnknownUser { name: name.to_owned() }),
+            Err(error) => return Err(Error::Lookup { name: name.to_owned(), error }),
+        },
+        None => None,
+    };
+    let gid = match group_name {
+        Some(name) => match Group::from_name(name) {
+            Ok(Some(g)) => Some(g.gid),
+            Ok(None) => return Err(Error::UnknownGroup { name: name.to_owned() }),
+            Err(error) => return Err(Error::Lookup { name: name.to_owned(), error }),
+        },
+        None => user.as_ref().map(|u| u.gid),
+    };
+
+    // after a restart, the new process inherits the privileges we have already dropped
+    let effective_uid = Uid::effective();
+    if !effective_uid.is_root()
+        && user.as_ref().is_none_or(|u| u.uid == effective_uid)
+        && gid.is_none_or(|g| g == Gid::effective()) {
+        return Ok(());
+    }
+
+    // the group must be changed first; we may not do so anymore once we are no longer root
+    if let Some(gid) = gid {
+        #[cfg(not(any(target_os = "haiku", target_os = "ios", target_os = "macos", target_os = "redox")))]
+        setgroups(&[gid])
+            .map_err(|error| Error::SetGroups { error })?;
+        setgid(gid)
+            .map_err(|error| Error::SetGid { gid, error })?;
+    }
+    if let Some(user) = user {
+        setuid(user.uid)
+            .map_err(|error| Error::SetUid { uid: user.uid, error })?;
+    }
+    Ok(())
+}
@@ -0,0 +1,293 @@
+//! Parsing of the PROXY protocol header (versions 1 and 2) sent by load balancers such as HAProxy.
+//!
+//! The header precedes the data of the client and tells us the address of the actual client.
+
+
+use std::fmt;
+use std::io;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+
+use tokio::io::{AsyncRead, AsyncReadExt};
+
+
+/// The signature at the start of a version 2 header.
+const V2_SIGNATURE: [u8; 12] = *b"\r\n\r\n\0\r\nQUIT\n";
+
+/// The maximum length of a version 1 header, including the CR LF.
+const V1_MAX_LENGTH: usize = 107;
+
+
+#[derive(Debug)]
+#[non_exhaustive]
+pub(crate) enum Error {
+    #[non_exhaustive]
+    Read { error: io::Error },
+
+    #[non_exhaustive]
+    MissingHeader,
+
+    #[non_exhaustive]
+    Malformed { message: String },
+}
+impl Error {
+    fn malformed<M: Into<String>>(message: M) -> Self {
+        Self::Malformed { message: message.into() }
+    }
+}
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Read { error }
+                => write!(f, "failed to read PROXY protocol header: {}", error),
+            Self::MissingHeader
+                => write!(f, "connection does not start with a PROXY protocol header"),
+            Self::Malformed { message }
+                => write!(f, "malformed PROXY protocol header: {}", message),
+        }
+    }
+}
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Self::Read { error } => Some(error),
+            Self::MissingHeader => None,
+            Self::Malformed { .. } => None,
+        }
+    }
+}
+impl From<io::Error> for Error {
+    fn from(error: io::Error) -> Self {
+        Self::Read { error }
+    }
+}
+
+
+/// Reads a PROXY protocol header from the start of the connection.
+///
+/// Returns the address of the actual client, or `None` if the header does not specify one (e.g.
+/// health checks by the load balancer), in which case the address of the connection applies.
+/// Nothing beyond the header is read.
+pub(crate) async fn read_header<R: AsyncRead + Unpin>(reader: &mut R) -> Result<Option<SocketAddr>, Error> {
+    // both versions of the header are at least this long
+    let mut start = [0u8; 12];
+    reader.read_exact(&mut start).await?;
+
+    if start.starts_with(b"PROXY ") {
+        read_v1(reader, &start).await
+    } else if start == V2_SIGNATURE {
+        read_v2(reader).await
+    } else {
+        Err(Error::MissingHeader)
+    }
+}
+
+
+async fn read_v1<R: AsyncRead + Unpin>(reader: &mut R, start: &[u8]) -> Result<Option<SocketAddr>, Error> {
+    // read byte by byte to avoid consuming data beyond the header
+    let mut line = start.to_vec();
+    while !line.ends_with(b"\r\n") {
+        if line.len() >= V1_MAX_LENGTH {
+            return Err(Error::malformed("version 1 header too long"));
+        }
+        line.push(reader.read_u8().await?);
+    }
+    line.truncate(line.len() - 2);
+
+    let line = std::str::from_utf8(&line)
+        .map_err(|_| Error::malformed("version 1 header is not ASCII"))?;
+    let pieces: Vec<&str> = line.split(' ').collect();
+    match pieces.as_slice() {
+        ["PROXY", "UNKNOWN", ..] => Ok(None),
+        ["PROXY", protocol, source, _destination, source_port, _destination_port] => {
+            let ip: IpAddr = match *protocol {
+                "TCP4" => source.parse::<Ipv4Addr>()
+                    .map_err(|_| Error::malformed(format!("invalid IPv4 address {:?}", source)))?
+                    .into(),
+                "TCP6" => source.parse::<Ipv6Addr>()
+                    .map_err(|_| Error::malformed(format!("invalid IPv6 address {:?}", source)))?
+                    .into(),
+                other => return Err(Error::malformed(format!("unknown protocol {:?}", other))),
+            };
+            let port: u16 = source_port.parse()
+                .map_err(|_| Error::malformed(format!("invalid port {:?}", source_port)))?;
+            Ok(Some(SocketAddr::new(ip, port)))
+        },
+        _ => Err(Error::malformed("wrong number of fields in version 1 header")),
+    }
+}
+
+
+async fn read_v2<R: AsyncRead + Unpin>(reader: &mut R) -> Result<Option<SocketAddr>, Error> {
+    let version_command = reader.read_u8().await?;
+    let family_protocol = reader.read_u8().await?;
+    let length = reader.read_u16().await?;
+    let mut payload = vec![0u8; usize::from(length)];
+    reader.read_exact(&mut payload).await?;
+
+    if version_command >> 4 != 2 {
+        return Err(Error::malformed(format!("unknown version {}", version_command >> 4)));
+    }
+    match version_command & 0x0F {
+        // LOCAL: the connection was opened by the proxy itself
+        0x0 => return Ok(None),
+        // PROXY
+        0x1 => {},
+        other => return Err(Error::malformed(format!("unknown command {}", other))),
+    }
+
+    // the addresses are followed by the ports; the destination is of no interest to us
+    match family_protocol {
+        // TCP over IPv4
+        0x11 => {
+            if payload.len() < 12 {
+                return Err(Error::malformed("IPv4 addresses truncated"));
+            }
+            let ip = Ipv4Addr::from(<[u8; 4]>::try_from(&payload[0..4]).unwrap());
+            let port = u16::from_be_bytes([payload[8], payload[9]]);
+            Ok(Some(SocketAddr::new(ip.into(), port)))
+        },
+        // TCP over IPv6
+        0x21 => {
+            if payload.len() < 36 {
+                return Err(Error::malformed("IPv6 addresses truncated"));
+            }
+            let ip = Ipv6Addr::from(<[u8; 16]>::try_from(&payload[0..16]).unwrap());
+            let port = u16::from_be_bytes([payload[32], payload[33]]);
+            Ok(Some(SocketAddr::new(ip.into(), port)))
+        },
+        // UNSPEC or something we cannot express as a socket address
+        _ => Ok(None),
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Reads a header from the data, checking that whatever follows it is left unread.
+    async fn read(data: &[u8]) -> Result<Option<SocketAddr>, Error> {
+        let mut data = data.to_vec();
+        data.extend_from_slice(b"rest");
+        let mut reader = data.as_slice();
+        let result = read_header(&mut reader).await;
+        if result.is_ok() {
+            assert_eq!(reader, b"rest");
+        }
+        result
+    }
+
+    fn v2_header(command: u8, family_protocol: u8, payload: &[u8]) -> Vec<u8> {
+        let mut ret = V2_SIGNATURE.to_vec();
+        ret.push(0x20 | command);
+        ret.push(family_protocol);
+        ret.extend_from_slice(&(payload.len() as u16).to_be_bytes());
+        ret.extend_from_slice(payload);
+        ret
+    }
+
+    #[tokio::test]
+    async fn v1() {
+        assert_eq!(
+            read(b"PROXY TCP4 192.0.2.1 198.51.100.2 56324 23\r\n").await.unwrap(),
+            Some("192.0.2.1:56324".parse().unwrap()),
+        );
+        assert_eq!(
+            read(b"PROXY TCP6 2001:db8::1 2001:db8::2 65535 23\r\n").await.unwrap(),
+            Some("[2001:db8::1]:65535".parse().unwrap()),
+        );
+        assert_eq!(read(b"PROXY UNKNOWN\r\n").await.unwrap(), None);
+        assert_eq!(read(b"PROXY UNKNOWN 192.0.2.1 198.51.100.2 56324 23\r\n").await.unwrap(), None);
+
+        // the longest possible header
+        let longest = format!("PROXY TCP6 {0} {0} 65535 65535\r\n", "ffff:".repeat(7) + "ffff");
+        assert!(read(longest.as_bytes()).await.unwrap().is_some());
+    }
+
+    #[tokio::test]
+    async fn v1_malformed() {
+        let cases: [&[u8]; 6] = [
+            b"PROXY TCP4 192.0.2.1 198.51.100.2 56324\r\n",
+            b"PROXY TCP4 2001:db8::1 198.51.100.2 56324 23\r\n",
+            b"PROXY TCP6 192.0.2.1 2001:db8::2 56324 23\r\n",
+            b"PROXY TCP4 192.0.2.1 198.51.100.2 65536 23\r\n",
+            b"PROXY UDP4 192.0.2.1 198.51.100.2 56324 23\r\n",
+            b"PROXY TCP4 192.0.2.\xFF 198.51.100.2 56324 23\r\n",
+        ];
+        for case in cases {
+            assert!(matches!(read(case).await, Err(Error::Malformed { .. })), "{:?}", case);
+        }
+    }
+
+    #[tokio::test]
+    async fn v1_oversized() {
+        // no more than the maximum length is read in search of the end of the line
+        let mut data = b"PROXY TCP4 ".to_vec();
+        data.resize(200, b'1');
+        data.extend_from_slice(b"\r\n");
+        let mut reader = data.as_slice();
+        match read_header(&mut reader).await {
+            Err(Error::Malformed { message }) => assert_eq!(message, "version 1 header too long"),
+            other => panic!("unexpected {:?}", other),
+        }
+        assert_eq!(reader.len(), data.len() - V1_MAX_LENGTH);
+    }
+
+    #[tokio::test]
+    async fn v2() {
+        let mut ipv4 = vec![192, 0, 2, 1, 198, 51, 100, 2];
+        ipv4.extend_from_slice(&56324u16.to_be_bytes());
+        ipv4.extend_from_slice(&23u16.to_be_bytes());
+        assert_eq!(
+            read(&v2_header(0x1, 0x11, &ipv4)).await.unwrap(),
+            Some("192.0.2.1:56324".parse().unwrap()),
+        );
+
+        // additional TLVs after the addresses are skipped
+        let mut ipv6 = Vec::new();
+        ipv6.extend_from_slice(&"2001:db8::1".parse::<Ipv6Addr>().unwrap().octets());
+        ipv6.extend_from_slice(&"2001:db8::2".parse::<Ipv6Addr>().unwrap().octets());
+        ipv6.extend_from_slice(&56324u16.to_be_bytes());
+        ipv6.extend_from_slice(&23u16.to_be_bytes());
+        ipv6.extend_from_slice(&[0x04, 0x00, 0x01, 0x00]);
+        assert_eq!(
+            read(&v2_header(0x1, 0x21, &ipv6)).await.unwrap(),
+            Some("[2001:db8::1]:56324".parse().unwrap()),
+        );
+
+        // LOCAL connections and unspecified families have no client address, but the addresses
+        // are still skipped
+        assert_eq!(read(&v2_header(0x0, 0x11, &ipv4)).await.unwrap(), None);
+        assert_eq!(read(&v2_header(0x1, 0x00, &[])).await.unwrap(), None);
+        assert_eq!(read(&v2_header(0x1, 0x31, &[0; 216])).await.unwrap(), None);
+    }
+
+    #[tokio::test]
+    async fn v2_malformed() {
+        let mut wrong_version = v2_header(0x1, 0x11, &[0; 12]);
+        wrong_version[12] = 0x11;
+        assert!(matches!(read(&wrong_version).await, Err(Error::Malformed { .. })));
+        assert!(matches!(read(&v2_header(0x2, 0x11, &[0; 12])).await, Err(Error::Malformed { .. })));
+        assert!(matches!(read(&v2_header(0x1, 0x11, &[0; 8])).await, Err(Error::Malformed { .. })));
+        assert!(matches!(read(&v2_header(0x1, 0x21, &[0; 12])).await, Err(Error::Malformed { .. })));
+    }
+
+    #[tokio::test]
+    async fn truncated() {
+        let mut v1 = b"PROXY TCP4 192.0.2.1 198.51.100.2 56324 23".as_slice();
+        let mut v2 = &v2_header(0x1, 0x11, &[0; 12])[..20];
+        let mut short = b"PROXY".as_slice();
+        for reader in [&mut v1, &mut v2, &mut short] {
+            match read_header(reader).await {
+                Err(Error::Read { error }) => assert_eq!(error.kind(), io::ErrorKind::UnexpectedEof),
+                other => panic!("unexpected {:?}", other),
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn missing() {
+        assert!(matches!(read(b"GET / HTTP/1.1\r\n").await, Err(Error::MissingHeader)));
+        assert!(matches!(read(b"\xFF\xFD\x18hello world").await, Err(Error::MissingHeader)));
+    }
+}
@@ -0,0 +1,83 @@
+//! Looking up the host names of connecting clients for the logs.
+
+
+use std::collections::HashMap;
+use std::net::{IpAddr, SocketAddr};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use log::debug;
+use tokio::time::Instant;
+
+
+/// How long to wait for the resolver before logging the bare address.
+const LOOKUP_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// How long a looked-up name (or the lack thereof) is remembered.
+const CACHE_TTL: Duration = Duration::from_secs(60 * 60);
+
+/// How many addresses are remembered at most; stale entries are swept once this is reached.
+const MAX_CACHE_ENTRIES: usize = 4096;
+
+
+#[derive(Debug)]
+struct CacheEntry {
+    name: Option<String>,
+    looked_up_at: Instant,
+}
+
+
+/// Looks up PTR records of client addresses, remembering the results for a while.
+#[derive(Clone, Debug, Default)]
+pub(crate) struct ReverseDns {
+    cache: Arc<Mutex<HashMap<IpAddr, CacheEntry>>>,
+}
+impl ReverseDns {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Returns the host name of the address, or `None` if it has none or the lookup failed or took
+    /// too long.
+    pub async fn lookup(&self, ip: IpAddr) -> Option<String> {
+        if let Some(entry) = self.cache.lock().unwrap().get(&ip) {
+            if entry.looked_up_at.elapsed() < CACHE_TTL {
+                return entry.name.clone();
+            }
+        }
+
+        // the system resolver blocks, so keep it away from the runtime's threads
+        let lookup = tokio::task::spawn_blocking(move || dns_lookup::lookup_addr(&ip));
+        let name = match tokio::time::timeout(LOOKUP_TIMEOUT, lookup).await {
+            Ok(Ok(Ok(name))) => Some(name),
+            Ok(Ok(Err(e))) => {
+                debug!("reverse lookup of {} failed: {}", ip, e);
+                None
+            },
+            Ok(Err(_)) => None,
+            Err(_) => {
+                debug!("reverse lookup of {} timed out", ip);
+                None
+            },
+        };
+
+        let mut cache = self.cache.lock().unwrap();
+        if cache.len() >= MAX_CACHE_ENTRIES {
+            cache.retain(|_, entry| entry.looked_up_at.elapsed() < CACHE_TTL);
+            if cache.len() >= MAX_CACHE_ENTRIES {
+                cache.clear();
+            }
+        }
+        cache.insert(ip, CacheEntry { name: name.clone(), looked_up_at: Instant::now() });
+        name
+    }
+}
+
+
+/// Formats a client address for the logs, adding its host name if known.
+pub(crate) fn describe(addr: SocketAddr, host_name: Option<&str>) -> String {
+    match host_name {
+        Some(name) => format!("{} ({})", addr, name),
+        None => addr.to_string(),
+    }
+}
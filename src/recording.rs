@@ -0,0 +1,414 @@
+//! Recording the bytes exchanged with clients, and playing recorded clients back.
+//!
+//! A recording starts with a JSON header line, followed by one JSON line per chunk of data:
+//! `[seconds_since_start, "i", "hex"]` for data received from the client and
+//! `[seconds_since_start, "o", "hex"]` for data sent to it.
+
+
+use std::fmt::{self, Write};
+use std::io;
+use std::net::{IpAddr, SocketAddr};
+use std::path::{Path, PathBuf};
+use std::pin::Pin;
+use std::task::{Context, Poll};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use log::{info, warn};
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt, BufWriter, ReadBuf};
+use tokio::net::TcpStream;
+use tokio::sync::mpsc;
+use tokio::sync::mpsc::error::TrySendError;
+use tokio::time::{Instant, sleep_until};
+
+use crate::cidr::Cidr;
+use crate::session::sleep_until_opt;
+use crate::task;
+
+
+/// The version of the recording format written by this version of the server.
+const FORMAT_VERSION: u32 = 1;
+
+/// How long to keep listening to the server after the end of the recording during a replay.
+const REPLAY_GRACE_PERIOD: Duration = Duration::from_secs(1);
+
+/// How many chunks may wait to be written; if writing falls this far behind (e.g. because the disk
+/// is slow or full), the recording is stopped.
+const CHUNK_CAPACITY: usize = 1024;
+
+
+/// Settings of session recording on a socket.
+#[derive(Clone, Debug, Deserialize, Eq, Hash, JsonSchema, PartialEq, Serialize)]
+pub(crate) struct RecordConfig {
+    /// The directory in which to store the recordings (one file per session).
+    pub dir: PathBuf,
+
+    /// Only record sessions of clients within these address ranges; by default, all sessions are
+    /// recorded.
+    #[serde(default)]
+    pub clients: Vec<Cidr>,
+}
+impl RecordConfig {
+    /// Whether sessions of clients with this address are to be recorded.
+    pub fn wants(&self, addr: IpAddr) -> bool {
+        self.clients.is_empty() || self.clients.iter().any(|c| c.contains(addr))
+    }
+}
+
+
+#[derive(Debug)]
+#[non_exhaustive]
+pub(crate) enum Error {
+    #[non_exhaustive]
+    Io { path: PathBuf, error: io::Error },
+
+    #[non_exhaustive]
+    Format { path: PathBuf, line: usize, message: String },
+}
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Io { path, error }
+                => write!(f, "failed to read recording {}: {}", path.display(), error),
+            Self::Format { path, line, message }
+                => write!(f, "invalid recording {} (line {}): {}", path.display(), line, message),
+        }
+    }
+}
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Self::Io { error, .. } => Some(error),
+            Self::Format { .. } => None,
+        }
+    }
+}
+
+
+/// The first line of a recording.
+#[derive(Clone, Debug, Deserialize, Eq, Hash, PartialEq, Serialize)]
+struct Header {
+    version: u32,
+    client: SocketAddr,
+    listen: SocketAddr,
+
+    /// When the session started, in seconds since the Unix epoch.
+    started: u64,
+}
+
+
+/// The direction in which a chunk of data was sent.
+#[derive(Clone, Copy, Debug, Eq, Hash, Ord, PartialEq, PartialOrd)]
+enum Direction {
+    /// From the client to the server.
+    In,
+
+    /// From the server to the client.
+    Out,
+}
+impl Direction {
+    fn code(self) -> &'static str {
+        match self {
+            Self::In => "i",
+            Self::Out => "o",
+        }
+    }
+}
+
+
+/// A chunk of data on its way to the recording file.
+struct Chunk {
+    time: Duration,
+    direction: Direction,
+    data: Vec<u8>,
+}
+
+
+/// Passes the data exchanged with a client on to the task writing the recording.
+pub(crate) struct Recorder {
+    start: Instant,
+    path: PathBuf,
+
+    /// Where the chunks go, or `None` once the recording has been stopped.
+    sender: Option<mpsc::Sender<Chunk>>,
+}
+impl Recorder {
+    /// Records everything read from and written to the stream from now on.
+    pub fn wrap<S>(self, inner: S) -> Recorded<S> {
+        Recorded {
+            inner,
+            recorder: self,
+        }
+    }
+
+    fn record(&mut self, direction: Direction, data: &[u8]) {
+        let sender = match &self.sender {
+            Some(s) => s,
+            None => return,
+        };
+        if data.is_empty() {
+            return;
+        }
+        let chunk = Chunk {
+            time: self.start.elapsed(),
+            direction,
+            data: data.to_vec(),
+        };
+        // either way, the session goes on without the recording
+        match sender.try_send(chunk) {
+            Ok(()) => {},
+            Err(TrySendError::Full(_)) => {
+                warn!("writing recording {} cannot keep up with the session; stopping it", self.path.display());
+                self.sender = None;
+            },
+            Err(TrySendError::Closed(_)) => {
+                // writing has failed, which has already been logged
+                self.sender = None;
+            },
+        }
+    }
+}
+
+
+/// A stream whose data is recorded.
+pub(crate) struct Recorded<S> {
+    inner: S,
+    recorder: Recorder,
+}
+impl<S: AsyncRead + Unpin> AsyncRead for Recorded<S> {
+    fn poll_read(mut self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &mut ReadBuf<'_>) -> Poll<io::Result<()>> {
+        let filled_before = buf.filled().len();
+        let result = Pin::new(&mut self.inner).poll_read(cx, buf);
+        if let Poll::Ready(Ok(())) = result {
+            self.recorder.record(Direction::In, &buf.filled()[filled_before..]);
+        }
+        result
+    }
+}
+impl<S: AsyncWrite + Unpin> AsyncWrite for Recorded<S> {
+    fn poll_write(mut self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &[u8]) -> Poll<io::Result<usize>> {
+        let result = Pin::new(&mut self.inner).poll_write(cx, buf);
+        if let Poll::Ready(Ok(byte_count)) = result {
+            self.recorder.record(Direction::Out, &buf[..byte_count]);
+        }
+        result
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.inner).poll_flush(cx)
+    }
+
+    fn poll_shutdown(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.inner).poll_shutdown(cx)
+    }
+}
+
+
+fn encode_hex(data: &[u8]) -> String {
+    let mut ret = String::with_capacity(data.len() * 2);
+    for b in data {
+        write!(ret, "{:02x}", b).unwrap();
+    }
+    ret
+}
+
+fn decode_hex(hex: &str) -> Option<Vec<u8>> {
+    if !hex.len().is_multiple_of(2) {
+        return None;
+    }
+    (0..hex.len())
+        .step_by(2)
+        .map(|i| hex.get(i..i+2).and_then(|pair| u8::from_str_radix(pair, 16).ok()))
+        .collect()
+}
+
+
+async fn write_chunk<W: AsyncWrite + Unpin>(writer: &mut W, chunk: &Chunk) -> io::Result<()> {
+    let line = serde_json::to_string(&(chunk.time.as_secs_f64(), chunk.direction.code(), encode_hex(&chunk.data)))
+        .expect("failed to serialize chunk");
+    writer.write_all(line.as_bytes()).await?;
+    writer.write_all(b"\n").await
+}
+
+
+/// Writes the chunks to the file until the session is over.
+async fn write_chunks<W: AsyncWrite + Unpin>(mut writer: W, mut receiver: mpsc::Receiver<Chunk>) -> io::Result<()> {
+    while let Some(chunk) = receiver.recv().await {
+        write_chunk(&mut writer, &chunk).await?;
+
+        // write whatever else has piled up in the meantime before flushing
+        while let Ok(chunk) = receiver.try_recv() {
+            write_chunk(&mut writer, &chunk).await?;
+        }
+        writer.flush().await?;
+    }
+    Ok(())
+}
+
+
+/// Creates the file recording a session with the given client.
+pub(crate) async fn start(config: &RecordConfig, client: SocketAddr, listen: SocketAddr) -> io::Result<Recorder> {
+    let started = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or(Duration::ZERO);
+
+    // colons are not allowed in file names everywhere
+    let file_name = format!("{}-{}-{}.rec", started.as_millis(), client.ip().to_string().replace(':', "-"), client.port());
+    let path = config.dir.join(file_name);
+    tokio::fs::create_dir_all(&config.dir).await?;
+    let mut writer = BufWriter::new(tokio::fs::File::create(&path).await?);
+
+    let header = Header {
+        version: FORMAT_VERSION,
+        client,
+        listen,
+        started: started.as_secs(),
+    };
+    let header_line = serde_json::to_string(&header)
+        .expect("failed to serialize header");
+    writer.write_all(header_line.as_bytes()).await?;
+    writer.write_all(b"\n").await?;
+    info!("recording session of {} to {}", client, path.display());
+
+    let (sender, receiver) = mpsc::channel(CHUNK_CAPACITY);
+    let task_path = path.clone();
+    task::spawn_logged(format!("recording of {}", client), async move {
+        if let Err(e) = write_chunks(writer, receiver).await {
+            warn!("failed to write recording {}: {}", task_path.display(), e);
+        }
+    });
+    Ok(Recorder {
+        start: Instant::now(),
+        path,
+        sender: Some(sender),
+    })
+}
+
+
+/// Loads the chunks of a recording.
+async fn load(path: &Path) -> Result<(Header, Vec<Chunk>), Error> {
+    let text = tokio::fs::read_to_string(path)
+        .await.map_err(|error| Error::Io { path: path.to_owned(), error })?;
+    let format_error = |line: usize, message: String| Error::Format { path: path.to_owned(), line, message };
+
+    let mut lines = text.lines().enumerate();
+    let header: Header = match lines.next() {
+        Some((_, line)) => serde_json::from_str(line)
+            .map_err(|e| format_error(1, e.to_string()))?,
+        None => return Err(format_error(1, "the file is empty".to_owned())),
+    };
+    if header.version != FORMAT_VERSION {
+        return Err(format_error(1, format!("unsupported version {}", header.version)));
+    }
+
+    let mut chunks = Vec::new();
+    for (index, line) in lines {
+        if line.trim().is_empty() {
+            continue;
+        }
+        let (time, direction, hex): (f64, String, String) = serde_json::from_str(line)
+            .map_err(|e| format_error(index + 1, e.to_string()))?;
+        let time = Duration::try_from_secs_f64(time)
+            .map_err(|e| format_error(index + 1, e.to_string()))?;
+        let direction = match direction.as_str() {
+            "i" => Direction::In,
+            "o" => Direction::Out,
+            other => return Err(format_error(index + 1, format!("unknown direction {:?}", other))),
+        };
+        let data = decode_hex(&hex)
+            .ok_or_else(|| format_error(index + 1, "invalid hex data".to_owned()))?;
+        chunks.push(Chunk { time, direction, data });
+    }
+    Ok((header, chunks))
+}
+
+
+/// Plays the client side of a recording back against the server at `target`, outputting what is
+/// sent in both directions, and returns the exit code.
+///
+/// The data is sent with the same timing as in the recording, divided by `speed`.
+pub(crate) async fn replay(path: &Path, target: &str, speed: f64) -> i32 {
+    let (header, chunks) = match load(path).await {
+        Ok(hc) => hc,
+        Err(e) => {
+            eprintln!("error: {}", e);
+            return 1;
+        },
+    };
+    println!("replaying session of {} with {} (recorded on {})", header.client, target, header.listen);
+
+    let mut stream = match TcpStream::connect(target).await {
+        Ok(s) => s,
+        Err(e) => {
+            eprintln!("error: failed to connect to {}: {}", target, e);
+            return 1;
+        },
+    };
+
+    let start = Instant::now();
+    let scaled = |time: Duration| start + time.div_f64(speed);
+    let end = scaled(chunks.last().map(|c| c.time).unwrap_or(Duration::ZERO)) + REPLAY_GRACE_PERIOD;
+    let mut inputs = chunks.iter()
+        .filter(|c| c.direction == Direction::In)
+        .peekable();
+    let mut buf = vec![0u8; 4096];
+    loop {
+        let next_input_at = inputs.peek().map(|c| scaled(c.time));
+        tokio::select! {
+            read = stream.read(&mut buf) => match read {
+                Ok(0) => {
+                    println!("{:>9.3} server closed the connection", start.elapsed().as_secs_f64());
+                    break;
+                },
+                Ok(byte_count) => {
+                    println!("{:>9.3} server: {}", start.elapsed().as_secs_f64(), buf[..byte_count].escape_ascii());
+                },
+                Err(e) => {
+                    eprintln!("error: failed to receive from {}: {}", target, e);
+                    return 1;
+                },
+            },
+            _ = sleep_until_opt(next_input_at) => {
+                let chunk = inputs.next().unwrap();
+                println!("{:>9.3} client: {}", start.elapsed().as_secs_f64(), chunk.data.escape_ascii());
+                if let Err(e) = stream.write_all(&chunk.data).await {
+                    eprintln!("error: failed to send to {}: {}", target, e);
+                    return 1;
+                }
+            },
+            _ = sleep_until(end) => break,
+        }
+    }
+    0
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn recording_stops_when_writing_falls_behind() {
+        let (sender, mut receiver) = mpsc::channel(2);
+        let mut recorder = Recorder {
+            start: Instant::now(),
+            path: PathBuf::from("test.rec"),
+            sender: Some(sender),
+        };
+        recorder.record(Direction::In, b"a");
+        recorder.record(Direction::Out, b"");
+        recorder.record(Direction::Out, b"b");
+        assert!(recorder.sender.is_some());
+
+        // nothing more is queued once the queue has been full
+        recorder.record(Direction::In, b"c");
+        assert!(recorder.sender.is_none());
+        recorder.record(Direction::In, b"d");
+
+        // what was queued before still gets written
+        let mut written = Vec::new();
+        while let Some(chunk) = receiver.recv().await {
+            written.push((chunk.direction, chunk.data));
+        }
+        assert_eq!(written, [(Direction::In, b"a".to_vec()), (Direction::Out, b"b".to_vec())]);
+    }
+}
@@ -0,0 +1,329 @@
+//! Bookkeeping of the live sessions.
+
+
+use std::collections::HashMap;
+use std::net::{IpAddr, SocketAddr};
+use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::Duration;
+
+use tokio::sync::{broadcast, mpsc, watch};
+use tokio::task::AbortHandle;
+use tokio::time::Instant;
+
+use crate::stats::{self, FrameStats, Stats};
+
+
+/// Identifies a connection within the registry.
+pub(crate) type ConnectionId = u64;
+
+/// How many frames sent to a session may be queued up for each spectator.
+const MIRROR_CAPACITY: usize = 64;
+
+
+/// A request to a session from outside, e.g. from the admin interface.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub(crate) enum SessionCommand {
+    /// End the session (with a goodbye message).
+    Kick,
+
+    /// Show a message to the client.
+    Message(String),
+}
+
+
+/// Information about a live session.
+#[derive(Clone, Debug)]
+pub(crate) struct ConnectionInfo {
+    pub id: ConnectionId,
+
+    /// The address of the client.
+    pub peer_addr: SocketAddr,
+
+    /// The address of the socket the client connected to.
+    pub listen_addr: SocketAddr,
+
+    /// The animation currently being shown, if any.
+    pub animation: Option<String>,
+
+    /// When the client connected.
+    pub connected_at: Instant,
+
+    /// How many bytes have been sent to the client.
+    pub bytes_sent: u64,
+}
+
+
+#[derive(Debug)]
+struct Entry {
+    info: ConnectionInfo,
+    bytes_sent: Arc<AtomicU64>,
+    frame_stats: Arc<Mutex<FrameStats>>,
+    commands: mpsc::UnboundedSender<SessionCommand>,
+    mirror: broadcast::Sender<Arc<[u8]>>,
+    abort_handle: Option<AbortHandle>,
+}
+
+
+#[derive(Debug, Default)]
+struct RegistryInner {
+    next_id: ConnectionId,
+    entries: HashMap<ConnectionId, Entry>,
+
+    /// The sessions started since the statistics were last taken, by client address.
+    recent_connections: HashMap<IpAddr, u64>,
+}
+
+
+/// What has been sent to all clients since the statistics were last taken.
+#[derive(Debug, Default)]
+struct Totals {
+    bytes_sent: AtomicU64,
+    frames_sent: AtomicU64,
+    frames_rendered: AtomicU64,
+    render_nanos: AtomicU64,
+    write_nanos: AtomicU64,
+    frames_skipped: AtomicU64,
+}
+
+
+/// Keeps track of all live sessions.
+#[derive(Clone, Debug, Default)]
+pub(crate) struct Registry {
+    inner: Arc<Mutex<RegistryInner>>,
+    totals: Arc<Totals>,
+}
+impl Registry {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Registers a new session.
+    ///
+    /// The session is unregistered when the returned registration is dropped.
+    pub fn register(&self, peer_addr: SocketAddr, listen_addr: SocketAddr) -> Registration {
+        let mut inner = self.inner.lock().unwrap();
+        let id = inner.next_id;
+        inner.next_id += 1;
+        let info = ConnectionInfo {
+            id,
+            peer_addr,
+            listen_addr,
+            animation: None,
+            connected_at: Instant::now(),
+            bytes_sent: 0,
+        };
+        let bytes_sent = Arc::new(AtomicU64::new(0));
+        let frame_stats = Arc::new(Mutex::new(FrameStats::default()));
+        let (command_sender, command_receiver) = mpsc::unbounded_channel();
+        let (mirror, _) = broadcast::channel(MIRROR_CAPACITY);
+        let entry = Entry {
+            info,
+            bytes_sent: Arc::clone(&bytes_sent),
+            frame_stats: Arc::clone(&frame_stats),
+            commands: command_sender,
+            mirror: mirror.clone(),
+            abort_handle: None,
+        };
+        inner.entries.insert(id, entry);
+        let (animation, _) = watch::channel(None);
+        *inner.recent_connections.entry(peer_addr.ip()).or_insert(0) += 1;
+        Registration {
+            registry: self.clone(),
+            id,
+            bytes_sent,
+            frame_stats,
+            commands: command_receiver,
+            animation,
+            mirror,
+        }
+    }
+
+    /// Stores the handle with which the task running the session can be aborted.
+    pub fn set_abort_handle(&self, id: ConnectionId, handle: AbortHandle) {
+        let mut inner = self.inner.lock().unwrap();
+        if let Some(entry) = inner.entries.get_mut(&id) {
+            entry.abort_handle = Some(handle);
+        }
+    }
+
+    /// Returns information about all live sessions, ordered by connection ID.
+    pub fn list(&self) -> Vec<ConnectionInfo> {
+        let inner = self.inner.lock().unwrap();
+        let mut ret: Vec<ConnectionInfo> = inner.entries.values()
+            .map(|entry| ConnectionInfo {
+                bytes_sent: entry.bytes_sent.load(Ordering::Relaxed),
+                ..entry.info.clone()
+            })
+            .collect();
+        ret.sort_unstable_by_key(|info| info.id);
+        ret
+    }
+
+    /// Returns the frame statistics of the given session, or `None` if there is no such session.
+    pub fn frame_stats(&self, id: ConnectionId) -> Option<FrameStats> {
+        let inner = self.inner.lock().unwrap();
+        inner.entries.get(&id)
+            .map(|entry| entry.frame_stats.lock().unwrap().clone())
+    }
+
+    /// The number of live sessions.
+    pub fn len(&self) -> usize {
+        self.inner.lock().unwrap().entries.len()
+    }
+
+    /// Returns the statistics since the last call and starts counting anew.
+    pub fn take_stats(&self) -> Stats {
+        let mut inner = self.inner.lock().unwrap();
+        let mut live_per_socket: Vec<(SocketAddr, usize)> = Vec::new();
+        for entry in inner.entries.values() {
+            match live_per_socket.iter_mut().find(|(addr, _)| *addr == entry.info.listen_addr) {
+                Some((_, count)) => *count += 1,
+                None => live_per_socket.push((entry.info.listen_addr, 1)),
+            }
+        }
+        live_per_socket.sort_unstable();
+        let recent_connections = std::mem::take(&mut inner.recent_connections);
+        Stats {
+            connections_accepted: recent_connections.values().sum(),
+            live_per_socket,
+            bytes_sent: self.totals.bytes_sent.swap(0, Ordering::Relaxed),
+            frames_sent: self.totals.frames_sent.swap(0, Ordering::Relaxed),
+            frames_rendered: self.totals.frames_rendered.swap(0, Ordering::Relaxed),
+            render_time: Duration::from_nanos(self.totals.render_nanos.swap(0, Ordering::Relaxed)),
+            write_time: Duration::from_nanos(self.totals.write_nanos.swap(0, Ordering::Relaxed)),
+            frames_skipped: self.totals.frames_skipped.swap(0, Ordering::Relaxed),
+            connections_by_ip: recent_connections,
+        }
+    }
+
+    /// Sends a command to the given session. Returns whether the session was found.
+    pub fn send_command(&self, id: ConnectionId, command: SessionCommand) -> bool {
+        let inner = self.inner.lock().unwrap();
+        match inner.entries.get(&id) {
+            Some(entry) => entry.commands.send(command).is_ok(),
+            None => false,
+        }
+    }
+
+    /// Sends a command to all sessions. Returns the number of sessions reached.
+    pub fn send_command_to_all(&self, command: SessionCommand) -> usize {
+        let inner = self.inner.lock().unwrap();
+        inner.entries.values()
+            .filter(|entry| entry.commands.send(command.clone()).is_ok())
+            .count()
+    }
+
+    /// Returns a receiver of everything the given session sends to its client from now on, or
+    /// `None` if there is no such session.
+    pub fn spectate(&self, id: ConnectionId) -> Option<broadcast::Receiver<Arc<[u8]>>> {
+        let inner = self.inner.lock().unwrap();
+        inner.entries.get(&id)
+            .map(|entry| entry.mirror.subscribe())
+    }
+
+    /// Aborts the tasks running all sessions.
+    pub fn abort_all(&self) {
+        let inner = self.inner.lock().unwrap();
+        for entry in inner.entries.values() {
+            if let Some(h) = &entry.abort_handle {
+                h.abort();
+            }
+        }
+    }
+
+    fn set_animation(&self, id: ConnectionId, animation: Option<String>) {
+        let mut inner = self.inner.lock().unwrap();
+        if let Some(entry) = inner.entries.get_mut(&id) {
+            entry.info.animation = animation;
+        }
+    }
+
+    fn unregister(&self, id: ConnectionId) -> Option<Entry> {
+        self.inner.lock().unwrap().entries.remove(&id)
+    }
+}
+
+
+/// The entry of a session in the [`Registry`], removed when dropped.
+#[derive(Debug)]
+pub(crate) struct Registration {
+    registry: Registry,
+    id: ConnectionId,
+    bytes_sent: Arc<AtomicU64>,
+    frame_stats: Arc<Mutex<FrameStats>>,
+    commands: mpsc::UnboundedReceiver<SessionCommand>,
+    animation: watch::Sender<Option<String>>,
+    mirror: broadcast::Sender<Arc<[u8]>>,
+}
+impl Registration {
+    pub fn id(&self) -> ConnectionId {
+        self.id
+    }
+
+    /// The registry the session is registered with.
+    pub fn registry(&self) -> &Registry {
+        &self.registry
+    }
+
+    /// Returns the sender to which everything sent to the client is to be copied for spectators.
+    pub fn mirror(&self) -> broadcast::Sender<Arc<[u8]>> {
+        self.mirror.clone()
+    }
+
+    /// Records which animation the session is showing.
+    pub fn set_animation(&self, animation: Option<String>) {
+        self.animation.send_replace(animation.clone());
+        self.registry.set_animation(self.id, animation);
+    }
+
+    /// Returns a receiver of the animation the session is showing, which keeps the last value even
+    /// once the registration is gone (e.g. to tell which animation a crashed session was showing).
+    pub fn watch_animation(&self) -> watch::Receiver<Option<String>> {
+        self.animation.subscribe()
+    }
+
+    /// Records that bytes have been sent to the client.
+    pub fn add_bytes_sent(&self, count: u64) {
+        self.bytes_sent.fetch_add(count, Ordering::Relaxed);
+        self.registry.totals.bytes_sent.fetch_add(count, Ordering::Relaxed);
+    }
+
+    /// Records that a frame of an animation has been sent to the client, along with how long it
+    /// took to render it (if it was rendered for this session) and to write it.
+    pub fn add_frame_sent(&self, render_time: Option<Duration>, write_time: Duration, byte_count: u64) {
+        self.frame_stats.lock().unwrap().record(render_time, write_time, byte_count);
+        let totals = &self.registry.totals;
+        totals.frames_sent.fetch_add(1, Ordering::Relaxed);
+        totals.write_nanos.fetch_add(nanos(write_time), Ordering::Relaxed);
+        if let Some(render_time) = render_time {
+            totals.frames_rendered.fetch_add(1, Ordering::Relaxed);
+            totals.render_nanos.fetch_add(nanos(render_time), Ordering::Relaxed);
+        }
+    }
+
+    /// Records that the client has missed broadcast frames because it could not keep up.
+    pub fn add_frames_skipped(&self, count: u64) {
+        self.frame_stats.lock().unwrap().frames_skipped += count;
+        self.registry.totals.frames_skipped.fetch_add(count, Ordering::Relaxed);
+    }
+
+    /// Waits for the next command to the session.
+    ///
+    /// This method is cancel-safe.
+    pub async fn next_command(&mut self) -> Option<SessionCommand> {
+        self.commands.recv().await
+    }
+}
+impl Drop for Registration {
+    fn drop(&mut self) {
+        if let Some(entry) = self.registry.unregister(self.id) {
+            stats::log_session(entry.info.peer_addr, &entry.frame_stats.lock().unwrap());
+        }
+    }
+}
+
+
+fn nanos(duration: Duration) -> u64 {
+    u64::try_from(duration.as_nanos()).unwrap_or(u64::MAX)
+}
@@ -0,0 +1,89 @@
+//! Loading animations again when the files they are loaded from change, for iterating on them
+//! without restarting the server.
+//!
+//! Every change counts up a generation; whatever has been loaded at an older generation is out of
+//! date.
+
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+use log::{debug, info, warn};
+use notify::{Event, EventKind, RecommendedWatcher, RecursiveMode, Watcher};
+
+
+static GENERATION: AtomicU64 = AtomicU64::new(0);
+
+
+/// The current generation of the watched files.
+pub(crate) fn generation() -> u64 {
+    GENERATION.load(Ordering::Relaxed)
+}
+
+
+/// Returns the directories watched for changes to the given files and directories, and whether
+/// they are watched recursively.
+///
+/// Editors often replace a file instead of writing to it, which a watch on the file itself would
+/// not survive, so files are watched through the directory containing them.
+fn directories(watched: &BTreeSet<PathBuf>) -> BTreeMap<PathBuf, RecursiveMode> {
+    let mut directories = BTreeMap::new();
+    for path in watched {
+        if path.is_dir() {
+            directories.insert(path.clone(), RecursiveMode::Recursive);
+        } else if let Some(parent) = path.parent() {
+            directories.entry(parent.to_owned()).or_insert(RecursiveMode::NonRecursive);
+        }
+    }
+    directories
+}
+
+
+/// Returns the directories that [`watch`] watches for changes to the given files and directories.
+pub(crate) fn watched_directories(paths: &[PathBuf]) -> Vec<PathBuf> {
+    let watched = paths.iter()
+        .filter_map(|path| path.canonicalize().ok())
+        .collect();
+    directories(&watched).into_keys().collect()
+}
+
+
+/// Starts watching the given files and directories (including everything beneath them) for
+/// changes. The watching stops once the returned watcher is dropped.
+pub(crate) fn watch(paths: &[PathBuf]) -> notify::Result<RecommendedWatcher> {
+    let mut watched = BTreeSet::new();
+    for path in paths {
+        match path.canonicalize() {
+            Ok(p) => { watched.insert(p); },
+            Err(e) => warn!("not watching {}: {}", path.display(), e),
+        }
+    }
+    let directories = directories(&watched);
+
+    let watched_paths = watched.clone();
+    let mut watcher = notify::recommended_watcher(move |result: notify::Result<Event>| {
+        let event = match result {
+            Ok(e) => e,
+            Err(e) => {
+                warn!("failed to watch animation files: {}", e);
+                return;
+            },
+        };
+        if matches!(event.kind, EventKind::Access(_)) {
+            return;
+        }
+        let changed = event.paths.iter()
+            .find(|path| watched_paths.iter().any(|watched| path.starts_with(watched)));
+        if let Some(path) = changed {
+            debug!("{} changed", path.display());
+            GENERATION.fetch_add(1, Ordering::Relaxed);
+        }
+    })?;
+    for (directory, mode) in directories {
+        watcher.watch(&directory, mode)?;
+    }
+    info!("watching {} animation files and directories for changes", watched.len());
+    Ok(watcher)
+}
+
@@ -0,0 +1,62 @@
+//! Configuration of the async runtime.
+
+
+use std::io;
+
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use tokio::runtime::{Builder, Runtime};
+
+
+/// Configuration of the async runtime.
+#[derive(Clone, Debug, Default, Deserialize, JsonSchema, PartialEq, Serialize)]
+pub(crate) struct RuntimeConfig {
+    /// How tasks are scheduled: `multi_thread` spreads them over a pool of worker threads,
+    /// `current_thread` runs everything on the main thread (enough for small devices).
+    #[serde(default)]
+    pub flavor: RuntimeFlavor,
+
+    /// The number of worker threads of the `multi_thread` flavor. Defaults to the number of CPU
+    /// cores.
+    #[serde(default)]
+    pub worker_threads: Option<usize>,
+
+    /// The name given to the threads of the runtime.
+    #[serde(default)]
+    pub thread_name: Option<String>,
+}
+
+
+/// How the runtime schedules tasks.
+#[derive(Clone, Copy, Debug, Default, Deserialize, Eq, Hash, JsonSchema, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub(crate) enum RuntimeFlavor {
+    #[default]
+    MultiThread,
+    CurrentThread,
+}
+
+
+/// Builds a runtime according to the configuration.
+pub(crate) fn build(config: &RuntimeConfig) -> io::Result<Runtime> {
+    let mut builder = match config.flavor {
+        RuntimeFlavor::MultiThread => {
+            let mut builder = Builder::new_multi_thread();
+            if let Some(worker_threads) = config.worker_threads {
+                if worker_threads == 0 {
+                    // the builder would panic
+                    return Err(io::Error::new(io::ErrorKind::InvalidInput, "worker_threads must be at least 1"));
+                }
+                builder.worker_threads(worker_threads);
+            }
+            builder
+        },
+        RuntimeFlavor::CurrentThread => Builder::new_current_thread(),
+    };
+    if let Some(thread_name) = &config.thread_name {
+        builder.thread_name(thread_name);
+    }
+    builder
+        .enable_all()
+        .build()
+}
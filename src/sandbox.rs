@@ -0,0 +1,298 @@
+//! Restricting what the process may do once it is up and running (Linux only).
+//!
+//! Landlock limits the filesystem to the files the server still needs to read (and the
+//! directories it still needs to write to); a seccomp filter forbids system calls that an animation
+//! server has no business making, such as executing programs or loading kernel modules.
+
+
+#[cfg(target_os = "linux")]
+use std::collections::BTreeMap;
+use std::fmt;
+use std::path::{Path, PathBuf};
+
+#[cfg(target_os = "linux")]
+use landlock::{
+    ABI, Access, AccessFs, PathBeneath, PathFd, Ruleset, RulesetAttr, RulesetCreatedAttr,
+    RulesetStatus,
+};
+#[cfg(target_os = "linux")]
+use log::{info, warn};
+use schemars::JsonSchema;
+#[cfg(target_os = "linux")]
+use seccompiler::{BpfProgram, SeccompAction, SeccompFilter, TargetArch};
+use serde::{Deserialize, Serialize};
+
+use crate::animations::{guestbook, weather};
+use crate::config::Config;
+use crate::logging::LogTarget;
+use crate::reload;
+
+
+/// Configuration of the sandbox applied after startup.
+#[derive(Clone, Debug, Deserialize, Eq, Hash, JsonSchema, PartialEq, Serialize)]
+pub(crate) struct SandboxConfig {
+    /// Whether to restrict filesystem access using Landlock.
+    #[serde(default = "SandboxConfig::default_landlock")]
+    pub landlock: bool,
+
+    /// Whether to forbid dangerous system calls using seccomp.
+    #[serde(default = "SandboxConfig::default_seccomp")]
+    pub seccomp: bool,
+
+    /// Additional files and directories that may be read, beyond those named in the configuration.
+    #[serde(default)]
+    pub read_paths: Vec<PathBuf>,
+}
+impl SandboxConfig {
+    fn default_landlock() -> bool { true }
+    fn default_seccomp() -> bool { true }
+}
+
+
+#[derive(Debug)]
+#[non_exhaustive]
+pub(crate) enum Error {
+    #[cfg(target_os = "linux")]
+    #[non_exhaustive]
+    Landlock { error: landlock::RulesetError },
+
+    #[cfg(target_os = "linux")]
+    #[non_exhaustive]
+    Seccomp { error: seccompiler::Error },
+
+    #[cfg(not(target_os = "linux"))]
+    #[non_exhaustive]
+    Unsupported,
+}
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            #[cfg(target_os = "linux")]
+            Self::Landlock { error }
+                => write!(f, "failed to apply Landlock ruleset: {}", error),
+            #[cfg(target_os = "linux")]
+            Self::Seccomp { error }
+                => write!(f, "failed to apply seccomp filter: {}", error),
+            #[cfg(not(target_os = "linux"))]
+            Self::Unsupported
+                => write!(f, "sandboxing is only supported on Linux"),
+        }
+    }
+}
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            #[cfg(target_os = "linux")]
+            Self::Landlock { error } => Some(error),
+            #[cfg(target_os = "linux")]
+            Self::Seccomp { error } => Some(error),
+            #[cfg(not(target_os = "linux"))]
+            Self::Unsupported => None,
+        }
+    }
+}
+
+
+/// The paths the sandboxed process may still access.
+#[derive(Clone, Debug, Default)]
+pub(crate) struct Paths {
+    /// Files and directories (including everything beneath them) that may be read.
+    pub read: Vec<PathBuf>,
+
+    /// Directories beneath which files may be created, written, renamed and removed.
+    pub write: Vec<PathBuf>,
+}
+impl Paths {
+    /// Collects the paths the server needs once it has started with the given configuration.
+    pub fn for_config(config: &Config, config_path: Option<&Path>) -> Self {
+        let mut paths = Self::default();
+        paths.read.extend(config_path.map(|p| p.to_owned()));
+        for socket_config in &config.sockets {
+            paths.read.extend(socket_config.banner_file.clone());
+            if let Some(tls) = &socket_config.tls {
+                paths.read.push(tls.cert_path.clone());
+                paths.read.push(tls.key_path.clone());
+                paths.read.extend(tls.client_ca_path.clone());
+            }
+        }
+
+        // files shown by animations, and the directories containing them if they are watched,
+        // since a file replaced by an editor is a new one that a rule for the old one does not cover
+        let animation_files: Vec<PathBuf> = config.sockets.iter()
+            .flat_map(|socket_config| socket_config.animation_files())
+            .collect();
+        if config.watch_animation_files {
+            paths.read.extend(reload::watched_directories(&animation_files));
+        }
+        paths.read.extend(animation_files);
+
+        // name resolution, user database, time zones and runtime sizing
+        let mut system_paths = vec!["/etc", "/proc/self", "/sys/fs/cgroup", "/usr/share/zoneinfo"];
+        let resolves_names = config.reverse_dns
+            || config.sockets.iter().any(|s| s.uses_animation(weather::INFO.name));
+        if resolves_names {
+            // the resolver may load name service modules
+            system_paths.extend(["/lib", "/lib64", "/usr/lib", "/usr/lib64"]);
+        }
+        paths.read.extend(
+            system_paths.into_iter()
+                .map(Path::new)
+                .filter(|p| p.exists())
+                .map(|p| p.to_owned())
+        );
+        if let Some(sandbox) = &config.sandbox {
+            paths.read.extend(sandbox.read_paths.iter().cloned());
+        }
+
+        // log rotation, removal of the PID file and creation of the admin socket
+        if let LogTarget::File { path, .. } = &config.log.target {
+            paths.write.push(parent_dir(path));
+        }
+        if let Some(pid_file) = &config.pid_file {
+            paths.write.push(parent_dir(pid_file));
+        }
+        if let Some(unix_socket_path) = config.admin.as_ref().and_then(|a| a.unix_socket_path.as_ref()) {
+            paths.write.push(parent_dir(unix_socket_path));
+        }
+
+        // session recordings
+        for socket_config in &config.sockets {
+            if let Some(record) = &socket_config.record {
+                paths.write.push(record.dir.clone());
+            }
+        }
+
+        // guestbook files
+        for socket_config in &config.sockets {
+            let playlist_animations = socket_config.playlist.iter()
+                .map(|item| socket_config.playlist_animation(item));
+            for choice in socket_config.animation_choices().into_iter().chain(playlist_animations) {
+                if choice.name != guestbook::INFO.name {
+                    continue;
+                }
+                if let Ok(params) = choice.parse_params::<guestbook::Params>() {
+                    paths.write.push(parent_dir(&params.path));
+                }
+            }
+        }
+        paths
+    }
+}
+
+
+/// Returns the directory containing the file.
+fn parent_dir(path: &Path) -> PathBuf {
+    match path.parent() {
+        Some(p) if !p.as_os_str().is_empty() => p.to_owned(),
+        _ => PathBuf::from("."),
+    }
+}
+
+
+/// System calls that are refused once the sandbox is in place.
+#[cfg(target_os = "linux")]
+const FORBIDDEN_SYSCALLS: &[libc::c_long] = &[
+    libc::SYS_execve,
+    libc::SYS_execveat,
+    libc::SYS_ptrace,
+    libc::SYS_process_vm_readv,
+    libc::SYS_process_vm_writev,
+    libc::SYS_mount,
+    libc::SYS_umount2,
+    libc::SYS_pivot_root,
+    libc::SYS_chroot,
+    libc::SYS_unshare,
+    libc::SYS_setns,
+    libc::SYS_reboot,
+    libc::SYS_kexec_load,
+    libc::SYS_kexec_file_load,
+    libc::SYS_init_module,
+    libc::SYS_finit_module,
+    libc::SYS_delete_module,
+    libc::SYS_bpf,
+    libc::SYS_perf_event_open,
+    libc::SYS_userfaultfd,
+    libc::SYS_keyctl,
+    libc::SYS_add_key,
+    libc::SYS_request_key,
+    libc::SYS_swapon,
+    libc::SYS_swapoff,
+    libc::SYS_acct,
+    libc::SYS_settimeofday,
+    libc::SYS_clock_settime,
+    libc::SYS_sethostname,
+    libc::SYS_setdomainname,
+    libc::SYS_open_by_handle_at,
+    libc::SYS_name_to_handle_at,
+    libc::SYS_personality,
+];
+
+
+/// Restricts the process according to the configuration.
+///
+/// Must be called before any threads are started; Landlock only restricts the calling thread and
+/// the threads it starts afterwards.
+#[cfg(target_os = "linux")]
+pub(crate) fn apply(config: &SandboxConfig, paths: &Paths) -> Result<(), Error> {
+    if config.landlock {
+        apply_landlock(paths)
+            .map_err(|error| Error::Landlock { error })?;
+    }
+    if config.seccomp {
+        apply_seccomp()
+            .map_err(|error| Error::Seccomp { error })?;
+    }
+    Ok(())
+}
+
+#[cfg(not(target_os = "linux"))]
+pub(crate) fn apply(_config: &SandboxConfig, _paths: &Paths) -> Result<(), Error> {
+    Err(Error::Unsupported)
+}
+
+
+#[cfg(target_os = "linux")]
+fn apply_landlock(paths: &Paths) -> Result<(), landlock::RulesetError> {
+    let abi = ABI::V5;
+    let mut ruleset = Ruleset::default()
+        .handle_access(AccessFs::from_all(abi))?
+        .create()?;
+    let rules = paths.read.iter()
+        .map(|path| (path, AccessFs::from_read(abi)))
+        .chain(paths.write.iter().map(|path| (path, AccessFs::from_all(abi))));
+    for (path, access) in rules {
+        // paths that do not exist (yet) cannot be allowed
+        match PathFd::new(path) {
+            Ok(fd) => {
+                ruleset = ruleset.add_rule(PathBeneath::new(fd, access))?;
+            },
+            Err(e) => warn!("sandbox: not allowing access to {}: {}", path.display(), e),
+        }
+    }
+    let status = ruleset.restrict_self()?;
+    match status.ruleset {
+        RulesetStatus::FullyEnforced => info!("sandbox: filesystem access restricted"),
+        RulesetStatus::PartiallyEnforced => info!("sandbox: filesystem access partially restricted (old kernel)"),
+        RulesetStatus::NotEnforced => warn!("sandbox: Landlock is not supported by the kernel; filesystem access is not restricted"),
+    }
+    Ok(())
+}
+
+
+#[cfg(target_os = "linux")]
+fn apply_seccomp() -> Result<(), seccompiler::Error> {
+    let rules: BTreeMap<_, _> = FORBIDDEN_SYSCALLS.iter()
+        .map(|&syscall| (syscall, Vec::new()))
+        .collect();
+    let arch = TargetArch::try_from(std::env::consts::ARCH)?;
+    let filter = SeccompFilter::new(
+        rules,
+        SeccompAction::Allow,
+        SeccompAction::Errno(libc::EPERM as u32),
+        arch,
+    )?;
+    let program: BpfProgram = filter.try_into()?;
+    seccompiler::apply_filter_all_threads(&program)?;
+    info!("sandbox: {} system calls forbidden", FORBIDDEN_SYSCALLS.len());
+    Ok(())
+}
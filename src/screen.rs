@@ -0,0 +1,357 @@
+//! A virtual terminal screen, for looking at the output of animations without a terminal.
+//!
+//! Only the escape sequences the animations actually emit are interpreted (cursor movement,
+//! erasing and colors); everything else is skipped.
+
+
+use std::fmt::Write;
+use std::ops::Range;
+
+use unicode_width::UnicodeWidthChar;
+
+
+/// The longest control sequence that is parsed; longer ones are abandoned.
+const MAX_SEQUENCE_LENGTH: usize = 32;
+
+
+/// The state of the escape sequence parser.
+#[derive(Clone, Debug, Eq, Hash, Ord, PartialEq, PartialOrd)]
+enum ParseState {
+    Ground,
+
+    /// After ESC.
+    Escape,
+
+    /// Within a control sequence (after ESC `[`), collecting the parameters.
+    ControlSequence(String),
+}
+
+
+/// The colors and attributes characters are written with, as set by SGR sequences.
+#[derive(Clone, Debug, Default, Eq, Hash, Ord, PartialEq, PartialOrd)]
+struct Rendition {
+    /// The parameters selecting the foreground color, e.g. `91` or `38;5;208`.
+    foreground: Option<String>,
+
+    /// The parameters selecting the background color.
+    background: Option<String>,
+
+    /// Other attributes, e.g. `1` for bold or `4` for underlined.
+    attributes: Vec<u16>,
+}
+impl Rendition {
+    /// Applies the parameters of an SGR sequence.
+    fn apply(&mut self, parameters: &str) {
+        let mut parameters = parameters.split(';');
+        while let Some(parameter) = parameters.next() {
+            let number: u16 = parameter.parse().unwrap_or(0);
+            match number {
+                0 => *self = Self::default(),
+                30..=37|90..=97 => self.foreground = Some(number.to_string()),
+                40..=47|100..=107 => self.background = Some(number.to_string()),
+                39 => self.foreground = None,
+                49 => self.background = None,
+                38|48 => {
+                    // 5;index or 2;red;green;blue
+                    let mut color = number.to_string();
+                    let kind = parameters.next().unwrap_or("");
+                    let count = match kind {
+                        "5" => 1,
+                        "2" => 3,
+                        _ => 0,
+                    };
+                    write!(color, ";{}", kind).unwrap();
+                    for value in parameters.by_ref().take(count) {
+                        write!(color, ";{}", value).unwrap();
+                    }
+                    if number == 38 {
+                        self.foreground = Some(color);
+                    } else {
+                        self.background = Some(color);
+                    }
+                },
+                22 => self.attributes.retain(|&a| a != 1 && a != 2),
+                23..=29 => self.attributes.retain(|&a| a != number - 20),
+                _ => {
+                    if !self.attributes.contains(&number) {
+                        self.attributes.push(number);
+                    }
+                },
+            }
+        }
+    }
+
+    /// Returns the SGR sequence that switches to this rendition from any other.
+    fn sequence(&self) -> String {
+        let mut ret = String::from("\x1B[0");
+        for attribute in &self.attributes {
+            write!(ret, ";{}", attribute).unwrap();
+        }
+        for color in self.foreground.iter().chain(self.background.iter()) {
+            write!(ret, ";{}", color).unwrap();
+        }
+        ret.push('m');
+        ret
+    }
+}
+
+
+/// A grid of characters together with a cursor.
+#[derive(Clone, Debug, Eq, Hash, Ord, PartialEq, PartialOrd)]
+pub(crate) struct Screen {
+    cols: u16,
+    rows: u16,
+    cells: Vec<Vec<char>>,
+
+    /// The rendition of each cell, row by row.
+    renditions: Vec<Vec<Rendition>>,
+
+    /// The rendition with which characters are currently written.
+    rendition: Rendition,
+
+    /// The position of the cursor as (column, row), both zero-based. The column may equal `cols`
+    /// if the last character of a line has just been written.
+    cursor: (u16, u16),
+
+    state: ParseState,
+}
+impl Screen {
+    pub fn new(cols: u16, rows: u16) -> Self {
+        let cols = cols.max(1);
+        let rows = rows.max(1);
+        Self {
+            cols,
+            rows,
+            cells: vec![vec![' '; usize::from(cols)]; usize::from(rows)],
+            renditions: vec![vec![Rendition::default(); usize::from(cols)]; usize::from(rows)],
+            rendition: Rendition::default(),
+            cursor: (0, 0),
+            state: ParseState::Ground,
+        }
+    }
+
+    /// Interprets output sent to the terminal.
+    pub fn feed(&mut self, output: &str) {
+        for c in output.chars() {
+            self.feed_char(c);
+        }
+    }
+
+    /// Returns the contents of the screen, one line per row, without trailing spaces.
+    pub fn text(&self) -> String {
+        let mut ret = String::new();
+        for row in &self.cells {
+            let line: String = row.iter().collect();
+            ret.push_str(line.trim_end());
+            ret.push('\n');
+        }
+        ret
+    }
+
+    /// Returns the characters on the screen, row by row.
+    pub fn rows(&self) -> &[Vec<char>] {
+        &self.cells
+    }
+
+    /// Returns the commands that draw the contents of the screen from scratch on a terminal of at
+    /// least the same size, leaving the cursor and the colors as they are on this screen.
+    pub fn redraw(&self) -> String {
+        let mut ret = String::from("\x1B[0m\x1B[2J");
+        let default_rendition = Rendition::default();
+        let mut rendition = &default_rendition;
+        for (row, (cells, renditions)) in self.cells.iter().zip(self.renditions.iter()).enumerate() {
+            // blank cells at the end of the line have just been cleared
+            let end = cells.iter().zip(renditions.iter())
+                .rposition(|(&c, r)| c != ' ' || r != &default_rendition)
+                .map_or(0, |last| last + 1);
+            if end == 0 {
+                continue;
+            }
+            write!(ret, "\x1B[{};1H", row + 1).unwrap();
+            let mut column = 0;
+            while column < end {
+                if &renditions[column] != rendition {
+                    rendition = &renditions[column];
+                    ret.push_str(&rendition.sequence());
+                }
+                let c = cells[column];
+                ret.push(c);
+                // the right half of a wide character is covered by it
+                column += c.width().unwrap_or(1).max(1);
+            }
+        }
+        if rendition != &self.rendition {
+            ret.push_str(&self.rendition.sequence());
+        }
+        let (column, row) = self.cursor;
+        write!(ret, "\x1B[{};{}H", row + 1, column.min(self.cols - 1) + 1).unwrap();
+        ret
+    }
+
+    fn feed_char(&mut self, c: char) {
+        match std::mem::replace(&mut self.state, ParseState::Ground) {
+            ParseState::Ground => match c {
+                '\x1B' => self.state = ParseState::Escape,
+                '\r' => self.cursor.0 = 0,
+                '\n' => self.line_feed(),
+                '\x08' => self.cursor.0 = self.cursor.0.min(self.cols - 1).saturating_sub(1),
+                c if c.is_control() => {},
+                c => self.print(c),
+            },
+            ParseState::Escape => {
+                if c == '[' {
+                    self.state = ParseState::ControlSequence(String::new());
+                }
+                // other escape sequences consist of a single character, which is skipped
+            },
+            ParseState::ControlSequence(mut parameters) => {
+                if ('\x40'..='\x7E').contains(&c) {
+                    self.control_sequence(&parameters, c);
+                } else if parameters.len() < MAX_SEQUENCE_LENGTH {
+                    parameters.push(c);
+                    self.state = ParseState::ControlSequence(parameters);
+                }
+            },
+        }
+    }
+
+    fn print(&mut self, c: char) {
+        let width = c.width().unwrap_or(0) as u16;
+        if width == 0 {
+            return;
+        }
+        if self.cursor.0 + width > self.cols {
+            // wrap around
+            self.cursor.0 = 0;
+            self.line_feed();
+        }
+        let (column, row) = self.cursor;
+        let (row, column) = (usize::from(row), usize::from(column));
+        self.cells[row][column] = c;
+        self.renditions[row][column] = self.rendition.clone();
+        if width > 1 && column + 1 < usize::from(self.cols) {
+            // the right half of a wide character
+            self.cells[row][column + 1] = ' ';
+            self.renditions[row][column + 1] = self.rendition.clone();
+        }
+        self.cursor.0 += width;
+    }
+
+    fn line_feed(&mut self) {
+        if self.cursor.1 + 1 < self.rows {
+            self.cursor.1 += 1;
+        } else {
+            // scroll up
+            self.cells.remove(0);
+            self.cells.push(vec![' '; usize::from(self.cols)]);
+            self.renditions.remove(0);
+            self.renditions.push(vec![Rendition::default(); usize::from(self.cols)]);
+        }
+    }
+
+    fn control_sequence(&mut self, parameters: &str, command: char) {
+        if parameters.starts_with('?') {
+            // private modes, such as showing and hiding the cursor
+            return;
+        }
+        let numbers: Vec<u16> = parameters.split(';')
+            .map(|p| p.parse().unwrap_or(0))
+            .collect();
+        // missing and zero parameters mean the default, which is 1 for movements
+        let count = |i: usize| numbers.get(i).copied().filter(|&n| n > 0).unwrap_or(1);
+        let (column, row) = self.cursor;
+        match command {
+            'A' => self.move_to(column, row.saturating_sub(count(0))),
+            'B' => self.move_to(column, row.saturating_add(count(0))),
+            'C' => self.move_to(column.saturating_add(count(0)), row),
+            'D' => self.move_to(column.min(self.cols - 1).saturating_sub(count(0)), row),
+            'G' => self.move_to(count(0) - 1, row),
+            'd' => self.move_to(column, count(0) - 1),
+            'H'|'f' => self.move_to(count(1) - 1, count(0) - 1),
+            'J' => self.erase_display(numbers[0]),
+            'K' => self.erase_line(numbers[0]),
+            'm' => self.rendition.apply(parameters),
+            _ => {},
+        }
+    }
+
+    fn move_to(&mut self, column: u16, row: u16) {
+        self.cursor = (column.min(self.cols - 1), row.min(self.rows - 1));
+    }
+
+    fn erase_display(&mut self, mode: u16) {
+        let (column, row) = self.cursor;
+        let row = usize::from(row);
+        let cols = usize::from(self.cols);
+        match mode {
+            0 => {
+                self.erase_line(0);
+                for line in row + 1..self.cells.len() {
+                    self.erase(line, 0..cols);
+                }
+            },
+            1 => {
+                for line in 0..row {
+                    self.erase(line, 0..cols);
+                }
+                let end = usize::from(column).min(cols - 1);
+                self.erase(row, 0..end + 1);
+            },
+            _ => {
+                for line in 0..self.cells.len() {
+                    self.erase(line, 0..cols);
+                }
+            },
+        }
+    }
+
+    fn erase_line(&mut self, mode: u16) {
+        let (column, row) = self.cursor;
+        let cols = usize::from(self.cols);
+        let column = usize::from(column).min(cols);
+        let row = usize::from(row);
+        match mode {
+            0 => self.erase(row, column..cols),
+            1 => self.erase(row, 0..column.min(cols - 1) + 1),
+            _ => self.erase(row, 0..cols),
+        }
+    }
+
+    /// Blanks the given columns of a row.
+    fn erase(&mut self, row: usize, columns: Range<usize>) {
+        self.cells[row][columns.clone()].fill(' ');
+        self.renditions[row][columns].fill(Rendition::default());
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn redraw_reproduces_screen() {
+        let mut screen = Screen::new(20, 5);
+        screen.feed("\x1B[2J\x1B[H\x1B[91mred\x1B[39m plain\r\n");
+        screen.feed("\x1B[3;5H\x1B[1;38;5;208m\u{6F22}\u{5B57}\x1B[0m!");
+        screen.feed("\x1B[2;1H\x1B[44mblue\x1B[K\x1B[4;3H");
+
+        let mut copy = Screen::new(20, 5);
+        copy.feed("garbage\x1B[92m");
+        copy.feed(&screen.redraw());
+        assert_eq!(copy.text(), screen.text());
+        assert_eq!(copy.renditions, screen.renditions);
+        assert_eq!(copy.rendition, screen.rendition);
+        assert_eq!(copy.cursor, screen.cursor);
+    }
+
+    #[test]
+    fn sgr_parameters() {
+        let mut rendition = Rendition::default();
+        rendition.apply("1;4;31;42");
+        rendition.apply("38;2;1;2;3;24");
+        assert_eq!(rendition.sequence(), "\x1B[0;1;38;2;1;2;3;42m");
+        rendition.apply("22;39;49");
+        assert_eq!(rendition, Rendition::default());
+    }
+}
@@ -0,0 +1,297 @@
+//! Listening for and accepting connections.
+
+
+use std::fmt;
+use std::io;
+use std::net::SocketAddr;
+use std::time::Duration;
+
+use log::{debug, error, info, warn};
+use socket2::{Domain, Protocol, Socket, Type};
+use tokio::io::AsyncWriteExt;
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::watch;
+use tokio_rustls::TlsAcceptor;
+
+use crate::config::SocketConfig;
+use crate::handoff::InheritedListeners;
+use crate::limit::{ConnectionLimit, IpLimit};
+use crate::proxy;
+use crate::rdns::{self, ReverseDns};
+use crate::registry::Registry;
+use crate::session::{Shared, handle_connection};
+use crate::task;
+use crate::tls;
+
+
+/// The message sent to clients turned away because too many sessions are live.
+const TOO_MANY_CONNECTIONS_MESSAGE: &[u8] = b"Too many connections; please try again later.\r\n";
+
+/// How long to wait before accepting again after accepting has failed (e.g. because the process
+/// has run out of file descriptors).
+const ACCEPT_ERROR_BACKOFF: Duration = Duration::from_millis(100);
+
+/// How long a load balancer may take to send the PROXY protocol header.
+const PROXY_HEADER_TIMEOUT: Duration = Duration::from_secs(5);
+
+
+/// An error preventing a socket from being set up.
+#[derive(Debug)]
+#[non_exhaustive]
+pub(crate) enum StartupError {
+    #[non_exhaustive]
+    Bind { addr: SocketAddr, error: io::Error },
+
+    #[non_exhaustive]
+    Tls { addr: SocketAddr, error: tls::Error },
+}
+impl fmt::Display for StartupError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Bind { addr, error } => {
+                write!(f, "cannot listen on {}: {}", addr, error)?;
+                match error.kind() {
+                    io::ErrorKind::AddrInUse
+                        => write!(f, " (is another program already listening on this port?)"),
+                    io::ErrorKind::PermissionDenied if addr.port() < 1024
+                        => write!(f, " (ports below 1024 usually require elevated privileges)"),
+                    io::ErrorKind::AddrNotAvailable
+                        => write!(f, " (this address does not belong to this host)"),
+                    _ => Ok(()),
+                }
+            },
+            Self::Tls { addr, error }
+                => write!(f, "cannot set up TLS on {}: {}", addr, error),
+        }
+    }
+}
+impl std::error::Error for StartupError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Self::Bind { error, .. } => Some(error),
+            Self::Tls { error, .. } => Some(error),
+        }
+    }
+}
+
+
+/// A socket that is ready to accept connections.
+pub(crate) struct BoundSocket {
+    /// One listener per worker.
+    pub listeners: Vec<TcpListener>,
+
+    pub config: SocketConfig,
+    pub tls_acceptor: Option<TlsAcceptor>,
+}
+
+
+/// Sets up the listeners of a socket, taking them over from the previous process if it has handed
+/// them over.
+pub(crate) fn bind_socket(socket_config: &SocketConfig, inherited_listeners: &mut InheritedListeners) -> Result<BoundSocket, StartupError> {
+    let addr = socket_config.listen_socket_addr;
+    let listeners = (0..socket_config.workers.max(1))
+        .map(|_| match inherited_listeners.take(addr) {
+            Some(listener) => listener.set_nonblocking(true)
+                .and_then(|()| TcpListener::from_std(listener)),
+            None => bind_listener(socket_config),
+        })
+        .collect::<io::Result<Vec<TcpListener>>>()
+        .map_err(|error| StartupError::Bind { addr, error })?;
+    let tls_acceptor = socket_config.tls.as_ref()
+        .map(tls::build_acceptor)
+        .transpose()
+        .map_err(|error| StartupError::Tls { addr, error })?;
+    Ok(BoundSocket {
+        listeners,
+        config: socket_config.clone(),
+        tls_acceptor,
+    })
+}
+
+
+/// Creates a listener according to the socket configuration.
+pub(crate) fn bind_listener(socket_config: &SocketConfig) -> io::Result<TcpListener> {
+    let addr = socket_config.listen_socket_addr;
+    let socket = Socket::new(Domain::for_address(addr), Type::STREAM, Some(Protocol::TCP))?;
+    socket.set_reuse_address(socket_config.reuse_address)?;
+    #[cfg(unix)]
+    if socket_config.reuse_port || socket_config.workers > 1 {
+        // with multiple workers, each of them has its own listener on the same address
+        socket.set_reuse_port(true)?;
+    }
+    socket.set_nonblocking(true)?;
+    socket.bind(&addr.into())?;
+    let backlog = i32::try_from(socket_config.listen_backlog).unwrap_or(i32::MAX);
+    socket.listen(backlog)?;
+    TcpListener::from_std(socket.into())
+}
+
+
+/// What the sessions on all sockets share.
+#[derive(Clone, Debug)]
+pub(crate) struct ServerState {
+    pub connection_limit: ConnectionLimit,
+    pub ip_limit: IpLimit,
+    pub registry: Registry,
+
+    /// State shared between the sessions.
+    pub shared: Shared,
+
+    /// Looks up the host names of clients for the logs, if enabled.
+    pub reverse_dns: Option<ReverseDns>,
+
+    /// Changes when the server is shutting down.
+    pub shutdown: watch::Receiver<bool>,
+
+    /// Changes when the server stops accepting connections, either because it is shutting down or
+    /// because it has handed its sockets over to a new process.
+    pub stop_accepting: watch::Receiver<bool>,
+}
+
+
+/// Accepts connections on the listener and spawns a session for each of them, until the server
+/// stops accepting.
+///
+/// Each session uses the socket configuration current at the time the connection is accepted.
+pub(crate) async fn accept_loop(
+    listener: TcpListener,
+    socket_config: watch::Receiver<SocketConfig>,
+    tls_acceptor: Option<TlsAcceptor>,
+    mut state: ServerState,
+) {
+    loop {
+        let accepted = tokio::select! {
+            accepted = listener.accept() => accepted,
+            _ = state.stop_accepting.changed() => break,
+        };
+        match accepted {
+            Ok((socket, addr)) => {
+                let current_config = socket_config.borrow().clone();
+                if current_config.proxy_protocol {
+                    task::spawn_logged(
+                        format!("PROXY header reader for {}", addr),
+                        accept_proxied(socket, addr, current_config, tls_acceptor.clone(), state.clone()),
+                    );
+                } else {
+                    spawn_session(socket, addr, &current_config, tls_acceptor.as_ref(), &state);
+                }
+            },
+            Err(e) => {
+                error!("{}: failed to accept connection: {}", socket_config.borrow().listen_socket_addr, e);
+                tokio::time::sleep(ACCEPT_ERROR_BACKOFF).await;
+            },
+        }
+    }
+}
+
+
+/// Reads the PROXY protocol header from a newly accepted connection, then spawns a session for the
+/// client it names.
+async fn accept_proxied(
+    mut socket: TcpStream,
+    proxy_addr: SocketAddr,
+    socket_config: SocketConfig,
+    tls_acceptor: Option<TlsAcceptor>,
+    state: ServerState,
+) {
+    let addr = match tokio::time::timeout(PROXY_HEADER_TIMEOUT, proxy::read_header(&mut socket)).await {
+        Ok(Ok(Some(client_addr))) => client_addr,
+        Ok(Ok(None)) => proxy_addr,
+        Ok(Err(e)) => {
+            warn!("{} rejected: {}", proxy_addr, e);
+            return;
+        },
+        Err(_) => {
+            warn!("{} rejected: timed out waiting for PROXY protocol header", proxy_addr);
+            return;
+        },
+    };
+    debug!("{} is proxied for {}", proxy_addr, addr);
+    spawn_session(socket, addr, &socket_config, tls_acceptor.as_ref(), &state);
+}
+
+
+/// Spawns a session for a newly accepted connection, or turns the client away if too many
+/// sessions are live.
+///
+/// Clients whose address is not allowed or who exceed their per-address limits are disconnected
+/// without further ado.
+fn spawn_session(
+    mut socket: TcpStream,
+    addr: SocketAddr,
+    socket_config: &SocketConfig,
+    tls_acceptor: Option<&TlsAcceptor>,
+    state: &ServerState,
+) {
+    if !socket_config.is_allowed(addr.ip()) {
+        info!("{} rejected: address not allowed on {}", addr, socket_config.listen_socket_addr);
+        return;
+    }
+    let ip_permit = match state.ip_limit.try_acquire(addr.ip()) {
+        Ok(p) => p,
+        Err(e) => {
+            warn!("{} rejected: {}", addr, e);
+            return;
+        },
+    };
+    let connection_limit = &state.connection_limit;
+    let permit = match connection_limit.try_acquire() {
+        Some(p) => p,
+        None => {
+            warn!(
+                "{} rejected: {} sessions live (limit {})",
+                addr, connection_limit.count(), connection_limit.max().unwrap_or(usize::MAX),
+            );
+            let is_tls = tls_acceptor.is_some();
+            tokio::spawn(async move {
+                // best effort; TLS clients would not understand a plain message anyway
+                if !is_tls {
+                    let _ = socket.write_all(TOO_MANY_CONNECTIONS_MESSAGE).await;
+                }
+                let _ = socket.shutdown().await;
+            });
+            return;
+        },
+    };
+    let registration = state.registry.register(addr, socket_config.listen_socket_addr);
+    let id = registration.id();
+    let animation = registration.watch_animation();
+    let config = socket_config.clone();
+    let listen_addr = socket_config.listen_socket_addr;
+    let task_limit = connection_limit.clone();
+    let task_tls_acceptor = tls_acceptor.cloned();
+    let task_shutdown = state.shutdown.clone();
+    let task_shared = state.shared.clone();
+    let task_reverse_dns = state.reverse_dns.clone();
+    let handle = tokio::spawn(async move {
+        // the session starts right away; the connection is logged once the host name is known
+        let announce = async {
+            let host_name = match &task_reverse_dns {
+                Some(reverse_dns) => reverse_dns.lookup(addr.ip()).await,
+                None => None,
+            };
+            let client = rdns::describe(addr, host_name.as_deref());
+            info!("{} connected to {} ({} sessions live)", client, listen_addr, task_limit.count());
+            client
+        };
+        let session = async {
+            let result = task::catch_panic(
+                handle_connection(socket, task_tls_acceptor, addr, config, task_shutdown, registration, task_shared)
+            ).await;
+            drop(permit);
+            drop(ip_permit);
+            result
+        };
+        let (client, result) = tokio::join!(announce, session);
+        match result {
+            Ok(Ok(())) => info!("{} disconnected ({} sessions live)", client, task_limit.count()),
+            Ok(Err(e)) if e.is_disconnect() => info!("{} disconnected ({} sessions live)", client, task_limit.count()),
+            Ok(Err(e)) => warn!("{} disconnected with error: {} ({} sessions live)", client, e, task_limit.count()),
+            Err(message) => error!(
+                "{} disconnected after the session panicked while showing {}: {} ({} sessions live)",
+                client, animation.borrow().as_deref().unwrap_or("no animation"), message, task_limit.count(),
+            ),
+        }
+    });
+    state.registry.set_abort_handle(id, handle.abort_handle());
+}
@@ -0,0 +1,158 @@
+//! Running as a Windows service.
+
+
+use std::ffi::OsString;
+use std::path::Path;
+use std::sync::Arc;
+use std::time::Duration;
+
+use clap::Parser;
+use log::error;
+use tokio::sync::Notify;
+use windows_service::{define_windows_service, service_control_handler, service_dispatcher};
+use windows_service::service::{
+    ServiceAccess, ServiceControl, ServiceControlAccept, ServiceErrorControl, ServiceExitCode,
+    ServiceInfo, ServiceStartType, ServiceState, ServiceStatus, ServiceType,
+};
+use windows_service::service_control_handler::{ServiceControlHandlerResult, ServiceStatusHandle};
+use windows_service::service_manager::{ServiceManager, ServiceManagerAccess};
+
+use crate::cli::Cli;
+use crate::runtime;
+
+
+/// The name under which the service is registered.
+const SERVICE_NAME: &str = "telnet-animations";
+
+/// The name shown in the list of services.
+const SERVICE_DISPLAY_NAME: &str = "Telnet Animations";
+
+const SERVICE_DESCRIPTION: &str = "Serves ASCII animations via Telnet.";
+
+const SERVICE_TYPE: ServiceType = ServiceType::OWN_PROCESS;
+
+
+/// Registers the service, which runs this executable with the given configuration file and starts
+/// automatically with the system.
+pub(crate) fn install(config_path: &Path) -> windows_service::Result<()> {
+    let manager = ServiceManager::local_computer(
+        None::<&str>,
+        ServiceManagerAccess::CONNECT | ServiceManagerAccess::CREATE_SERVICE,
+    )?;
+
+    // the service does not start in our working directory
+    let config_path = std::path::absolute(config_path)
+        .map_err(windows_service::Error::Winapi)?;
+    let executable_path = std::env::current_exe()
+        .map_err(windows_service::Error::Winapi)?;
+    let service_info = ServiceInfo {
+        name: OsString::from(SERVICE_NAME),
+        display_name: OsString::from(SERVICE_DISPLAY_NAME),
+        service_type: SERVICE_TYPE,
+        start_type: ServiceStartType::AutoStart,
+        error_control: ServiceErrorControl::Normal,
+        executable_path,
+        launch_arguments: vec![
+            OsString::from("--config"),
+            config_path.into_os_string(),
+            OsString::from("run-service"),
+        ],
+        dependencies: Vec::new(),
+        // LocalSystem
+        account_name: None,
+        account_password: None,
+    };
+    let service = manager.create_service(&service_info, ServiceAccess::CHANGE_CONFIG)?;
+    service.set_description(SERVICE_DESCRIPTION)
+}
+
+
+/// Stops the service if it is running and unregisters it.
+pub(crate) fn uninstall() -> windows_service::Result<()> {
+    let manager = ServiceManager::local_computer(None::<&str>, ServiceManagerAccess::CONNECT)?;
+    let service = manager.open_service(
+        SERVICE_NAME,
+        ServiceAccess::QUERY_STATUS | ServiceAccess::STOP | ServiceAccess::DELETE,
+    )?;
+
+    // the service is removed once it has stopped and all handles to it are closed
+    service.delete()?;
+    if service.query_status()?.current_state != ServiceState::Stopped {
+        service.stop()?;
+    }
+    Ok(())
+}
+
+
+/// Hands the process over to the service control manager, which then runs the server.
+///
+/// Only returns once the service has stopped.
+pub(crate) fn run() -> windows_service::Result<()> {
+    service_dispatcher::start(SERVICE_NAME, ffi_service_main)
+}
+
+
+define_windows_service!(ffi_service_main, service_main);
+
+
+fn service_main(_arguments: Vec<OsString>) {
+    if let Err(e) = run_service() {
+        error!("service failed: {}", e);
+    }
+}
+
+
+fn set_state(status_handle: &ServiceStatusHandle, state: ServiceState, exit_code: u32) -> windows_service::Result<()> {
+    let controls_accepted = if state == ServiceState::Running {
+        ServiceControlAccept::STOP | ServiceControlAccept::SHUTDOWN
+    } else {
+        ServiceControlAccept::empty()
+    };
+    status_handle.set_service_status(ServiceStatus {
+        service_type: SERVICE_TYPE,
+        current_state: state,
+        controls_accepted,
+        exit_code: ServiceExitCode::Win32(exit_code),
+        checkpoint: 0,
+        wait_hint: Duration::default(),
+        process_id: None,
+    })
+}
+
+
+fn run_service() -> windows_service::Result<()> {
+    let stop = Arc::new(Notify::new());
+    let handler_stop = Arc::clone(&stop);
+    let event_handler = move |control_event| match control_event {
+        ServiceControl::Interrogate => ServiceControlHandlerResult::NoError,
+        ServiceControl::Stop|ServiceControl::Shutdown => {
+            handler_stop.notify_one();
+            ServiceControlHandlerResult::NoError
+        },
+        _ => ServiceControlHandlerResult::NotImplemented,
+    };
+    let status_handle = service_control_handler::register(SERVICE_NAME, event_handler)?;
+    set_state(&status_handle, ServiceState::Running, 0)?;
+
+    let exit_code = serve(stop);
+    set_state(&status_handle, ServiceState::Stopped, exit_code as u32)
+}
+
+
+/// Runs the server according to the command line the service was started with, until `stop` is
+/// notified.
+fn serve(stop: Arc<Notify>) -> i32 {
+    let cli = Cli::parse();
+    let config = match crate::prepare_config(&cli) {
+        Some(c) => c,
+        None => return 1,
+    };
+    let runtime = match runtime::build(&config.runtime) {
+        Ok(r) => r,
+        Err(e) => {
+            error!("failed to start runtime: {}", e);
+            return 1;
+        },
+    };
+    crate::block_on(runtime, crate::serve_process(config, cli.best_effort, async move { stop.notified().await }))
+}
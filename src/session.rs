@@ -0,0 +1,1196 @@
+//! Handling of a single client session.
+
+
+use std::fmt::Write;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::time::Duration;
+
+use log::{error, info, warn};
+use socket2::{SockRef, TcpKeepalive};
+use tokio::net::TcpStream;
+use tokio::sync::{broadcast, watch};
+use tokio::time::{Instant, sleep_until};
+use tokio_rustls::TlsAcceptor;
+use tokio_util::sync::CancellationToken;
+
+use crate::animations::{self, Animation, AnimationConfig, Context, Frame};
+use crate::animations::bonus::Bonus;
+use crate::animations::chatwall::ChatWalls;
+use crate::animations::guestbook::Guestbooks;
+use crate::broadcast::{Broadcaster, Subscription, Update};
+use crate::http;
+use crate::recording;
+use crate::keys::{BS, DEL, ESC, KonamiCode};
+use crate::config::{KeepaliveConfig, Protocol, SocketConfig};
+use crate::connection::{Stream, TelnetConnection};
+use crate::menu::{Menu, MenuOutcome, NamePrompt, PromptOutcome};
+use crate::playlist::Stop;
+use crate::registry::{ConnectionId, Registration, SessionCommand};
+use crate::reload;
+use crate::telnet::{self, Event};
+use crate::websocket;
+
+
+/// The width reserved for the number of viewers of a broadcast.
+const VIEWER_OVERLAY_WIDTH: usize = 24;
+
+/// The slowest playback speed selectable using `-`, as a factor of the normal speed.
+const MIN_SPEED: f64 = 0.25;
+
+/// The fastest playback speed selectable using `+`, as a factor of the normal speed.
+const MAX_SPEED: f64 = 4.0;
+
+/// How long the title of an animation chosen using the number keys is shown before it starts.
+const TITLE_CARD_DURATION: Duration = Duration::from_millis(1500);
+
+
+/// State shared between the sessions of a server.
+#[derive(Clone, Debug)]
+pub(crate) struct Shared {
+    pub broadcaster: Broadcaster,
+    pub chat_walls: ChatWalls,
+    pub guestbooks: Guestbooks,
+}
+impl Shared {
+    pub fn new() -> Self {
+        Self {
+            broadcaster: Broadcaster::new(),
+            chat_walls: ChatWalls::new(),
+            guestbooks: Guestbooks::new(),
+        }
+    }
+}
+
+
+/// The reason the session loop woke up.
+enum Wakeup {
+    Event(Event),
+    Frame,
+    NegotiationTimeout,
+    SessionTimeout,
+    Countdown,
+    PlaylistNext,
+    IdleTimeout,
+    Shutdown,
+    Cancelled,
+    Command(SessionCommand),
+    Broadcast(Update),
+    Mirror(Result<Arc<[u8]>, broadcast::error::RecvError>),
+}
+
+
+/// The phase a session is in.
+enum Phase {
+    /// Waiting for the client to answer our negotiation requests.
+    Negotiating,
+
+    /// Showing the banner file.
+    Banner { wait_for_key: bool },
+
+    /// Waiting for the client to choose an animation.
+    Menu(Menu),
+
+    /// Waiting for the client to type the name of an animation.
+    Prompt(NamePrompt),
+
+    /// Showing the title of the animation with the given index before starting it.
+    TitleCard(usize),
+
+    /// Showing an animation.
+    Playing(Box<dyn Animation>),
+
+    /// Showing an animation broadcast to all its viewers.
+    Watching(Subscription),
+
+    /// Waiting for the client to enter the ID of the session to spectate.
+    SpectatorPrompt(String),
+
+    /// Showing what another session is being sent.
+    Spectating { id: ConnectionId, mirror: broadcast::Receiver<Arc<[u8]>> },
+
+    /// The animation is over; waiting for the client to watch it again or quit.
+    Replay,
+
+    /// Nothing more to show.
+    Idle,
+
+    /// The session is over; the connection is to be closed.
+    Finished,
+}
+
+
+struct Session {
+    connection: TelnetConnection,
+    config: SocketConfig,
+    phase: Phase,
+    next_frame_at: Instant,
+    session_deadline: Option<Instant>,
+    next_countdown_at: Option<Instant>,
+    cycles_started: u64,
+
+    /// How much faster than normal the animation is played.
+    speed: f64,
+
+    /// If playback is paused, how long the current frame still had to be shown.
+    paused_with: Option<Duration>,
+
+    /// The index of the animation being shown among the choices, or `None` for the bonus
+    /// animation.
+    chosen_index: Option<usize>,
+
+    /// The index of the playlist entry being shown, or `None` if the playlist isn't playing.
+    playlist_position: Option<usize>,
+
+    /// When to move on to the next playlist entry if its stop condition is a duration.
+    playlist_deadline: Option<Instant>,
+
+    /// How many times the playlist has been played through.
+    playlist_passes: u64,
+
+    /// The animation being played and the generation of the animation files it was loaded at, so
+    /// that it can be loaded again once they change.
+    loaded: Option<(AnimationConfig, u64)>,
+
+    /// Watches the input for the code unlocking the bonus animation.
+    konami_code: KonamiCode,
+
+    shutdown: watch::Receiver<bool>,
+
+    /// Cancelled once the connection is gone, e.g. when the client closes the WebSocket.
+    cancel: CancellationToken,
+
+    registration: Option<Registration>,
+    reported_bytes_sent: u64,
+    shared: Option<Shared>,
+}
+impl Session {
+    async fn run(&mut self) -> Result<(), telnet::Error> {
+        if self.connection.speaks_telnet() {
+            // "can you do terminal type?"
+            self.connection.negotiate().await?;
+        } else {
+            // nothing to negotiate
+            self.negotiation_finished().await?;
+        }
+
+        let start = Instant::now();
+        let negotiation_deadline = start + Duration::from_millis(self.config.negotiation_timeout_ms);
+        self.session_deadline = self.config.max_session_secs
+            .map(|secs| start + Duration::from_secs(secs));
+        if self.config.countdown_secs > 0 {
+            let countdown = Duration::from_secs(self.config.countdown_secs);
+            self.next_countdown_at = self.session_deadline
+                .map(|deadline| deadline.checked_sub(countdown).unwrap_or(start).max(start));
+        }
+        let idle_duration = self.config.idle_secs
+            .map(Duration::from_secs);
+        let mut last_activity = start;
+        loop {
+            let negotiating = matches!(self.phase, Phase::Negotiating);
+            let playing = match self.phase {
+                Phase::Playing(_) => self.paused_with.is_none(),
+                Phase::Banner { wait_for_key } => !wait_for_key,
+                Phase::TitleCard(_) => true,
+                _ => false,
+            };
+            let wakeup = tokio::select! {
+                event = self.connection.read_event() => Wakeup::Event(event?),
+                _ = sleep_until(self.next_frame_at), if playing => Wakeup::Frame,
+                _ = sleep_until(negotiation_deadline), if negotiating => Wakeup::NegotiationTimeout,
+                _ = sleep_until_opt(self.session_deadline) => Wakeup::SessionTimeout,
+                _ = sleep_until_opt(self.next_countdown_at), if !negotiating => Wakeup::Countdown,
+                _ = sleep_until_opt(self.playlist_deadline), if self.paused_with.is_none() => Wakeup::PlaylistNext,
+                _ = sleep_until_opt(idle_duration.map(|d| last_activity + d)) => Wakeup::IdleTimeout,
+                _ = self.shutdown.changed() => Wakeup::Shutdown,
+                _ = self.cancel.cancelled() => Wakeup::Cancelled,
+                command = next_command(&mut self.registration) => Wakeup::Command(command),
+                wakeup = next_fed(&mut self.phase) => wakeup,
+            };
+
+            match wakeup {
+                Wakeup::Event(event) => {
+                    last_activity = Instant::now();
+                    self.handle_event(event).await?;
+                },
+                Wakeup::Frame => {
+                    match self.phase {
+                        Phase::Banner { .. } => self.show_choices().await?,
+                        Phase::TitleCard(index) => self.start_animation(index).await?,
+                        _ => self.send_next_frame().await?,
+                    }
+                },
+                Wakeup::NegotiationTimeout => {
+                    // the client isn't answering; assume ANSI
+                    info!("{} did not answer terminal type query in time", self.connection.addr());
+                    self.negotiation_finished().await?;
+                },
+                Wakeup::SessionTimeout => {
+                    info!("{} reached the session time limit", self.connection.addr());
+                    self.disconnect().await?;
+                },
+                Wakeup::Countdown => self.show_countdown().await?,
+                Wakeup::PlaylistNext => self.advance_playlist().await?,
+                Wakeup::IdleTimeout => {
+                    info!("{} has been idle for too long", self.connection.addr());
+                    self.disconnect().await?;
+                },
+                Wakeup::Shutdown => {
+                    // (also taken if the server has gone away without saying anything)
+                    self.disconnect().await?;
+                },
+                Wakeup::Cancelled => {
+                    // nobody is listening anymore
+                    self.phase = Phase::Finished;
+                },
+                Wakeup::Command(SessionCommand::Kick) => {
+                    info!("{} has been kicked", self.connection.addr());
+                    self.disconnect().await?;
+                },
+                Wakeup::Command(SessionCommand::Message(message)) => self.show_message(&message).await?,
+                Wakeup::Broadcast(Update::Frame(frame)) => {
+                    if let (Phase::Watching(subscription), Some(registration)) = (&mut self.phase, &self.registration) {
+                        let skipped = subscription.take_frames_skipped();
+                        if skipped > 0 {
+                            registration.add_frames_skipped(skipped);
+                        }
+                    }
+                    // broadcast frames are rendered once for all viewers
+                    self.show_frame(&frame, None).await?;
+                },
+                Wakeup::Broadcast(Update::ViewersChanged) => {
+                    if let Some(overlay) = self.viewer_overlay() {
+                        self.connection.send_frame(overlay.as_bytes()).await?;
+                    }
+                },
+                Wakeup::Broadcast(Update::Over) => self.animation_over().await?,
+                Wakeup::Mirror(Ok(frame)) => self.connection.send_frame(&frame).await?,
+                Wakeup::Mirror(Err(broadcast::error::RecvError::Lagged(count))) => {
+                    warn!("{} missed {} frames of the session being spectated", self.connection.addr(), count);
+                },
+                Wakeup::Mirror(Err(broadcast::error::RecvError::Closed)) => {
+                    if let Phase::Spectating { id, .. } = self.phase {
+                        self.show_spectator_prompt(Some(&format!("Session {} has ended.", id))).await?;
+                    }
+                },
+            }
+            self.report_bytes_sent();
+
+            if self.connection.input_closed() && self.waits_for_input() {
+                // the client can't get any further
+                self.disconnect().await?;
+            }
+            if let Phase::Finished = self.phase {
+                return Ok(());
+            }
+            self.connection.flush_output().await?;
+        }
+    }
+
+    async fn handle_event(&mut self, event: Event) -> Result<(), telnet::Error> {
+        if let Event::EndOfInput = event {
+            return self.input_ended().await;
+        }
+        if let Event::Interrupt = event {
+            if !matches!(self.phase, Phase::Finished) {
+                info!("{} interrupted the session", self.connection.addr());
+                self.interrupt().await?;
+            }
+            return Ok(());
+        }
+
+        let showing_animation = matches!(self.phase, Phase::Playing(_)|Phase::Watching(_)|Phase::Replay|Phase::Idle);
+        if self.konami_code.advance(&event) && showing_animation {
+            info!("{} entered the Konami code", self.connection.addr());
+            self.start_bonus();
+            return Ok(());
+        }
+
+        match &mut self.phase {
+            Phase::Negotiating => {
+                if let Event::TerminalType(_) | Event::NoTerminalType = event {
+                    self.negotiation_finished().await?;
+                }
+            },
+            Phase::Banner { wait_for_key } => {
+                if *wait_for_key {
+                    if let Event::Data(_) | Event::Newline = event {
+                        self.show_choices().await?;
+                    }
+                }
+            },
+            Phase::SpectatorPrompt(input) => {
+                match event {
+                    Event::Data(digit @ b'0'..=b'9') if input.len() < 20 => {
+                        input.push(char::from(digit));
+                        if self.connection.echoes() {
+                            self.connection.send_frame(&[digit]).await?;
+                        }
+                    },
+                    Event::Data(BS|DEL) if !input.is_empty() => {
+                        input.pop();
+                        if self.connection.echoes() {
+                            self.connection.send_frame(b"\x08 \x08").await?;
+                        }
+                    },
+                    Event::Data(b'q'|b'Q') => self.quit().await?,
+                    Event::Newline => {
+                        let input = std::mem::take(input);
+                        self.start_spectating(&input).await?;
+                    },
+                    _ => {},
+                }
+            },
+            Phase::Spectating { .. } => {
+                match event {
+                    Event::Data(ESC) => self.show_spectator_prompt(None).await?,
+                    Event::Data(b'q'|b'Q') => self.quit().await?,
+                    _ => {},
+                }
+            },
+            Phase::Menu(menu) => {
+                match menu.handle_event(&event) {
+                    MenuOutcome::Nothing => {
+                        if let Event::Data(b'q'|b'Q') = event {
+                            self.quit().await?;
+                        }
+                    },
+                    MenuOutcome::Redraw => {
+                        let commands = menu.render();
+                        self.connection.send_frame(commands.as_bytes()).await?;
+                    },
+                    MenuOutcome::Chosen(index) => self.start_animation(index).await?,
+                }
+            },
+            Phase::Prompt(prompt) => {
+                match prompt.handle_event(&event) {
+                    PromptOutcome::Nothing => {},
+                    PromptOutcome::Output(text) => self.connection.send_frame(text.as_bytes()).await?,
+                    PromptOutcome::Chosen(index) => self.start_animation(index).await?,
+                }
+            },
+            Phase::Playing(animation) => {
+                if animation.handle_event(&event) {
+                    // show the reaction right away
+                    if self.paused_with.is_none() {
+                        self.next_frame_at = Instant::now();
+                    }
+                    return Ok(());
+                }
+                match event {
+                    Event::Data(ESC) if self.config.has_menu() => self.show_choices().await?,
+                    Event::Data(b'q'|b'Q') => self.quit().await?,
+                    Event::Data(b' ') => self.toggle_pause().await?,
+                    Event::Data(b'+') => self.change_speed(2.0).await?,
+                    Event::Data(b'-') => self.change_speed(0.5).await?,
+                    Event::Data(digit @ b'1'..=b'9') => self.switch_animation(digit).await?,
+                    _ => {},
+                }
+            },
+            Phase::Replay => {
+                match event {
+                    Event::Data(ESC) if self.config.has_menu() => self.show_choices().await?,
+                    Event::Data(b'q'|b'Q') => self.quit().await?,
+                    Event::Data(_)|Event::Key(_)|Event::Newline => self.replay().await?,
+                    _ => {},
+                }
+            },
+            Phase::TitleCard(_)|Phase::Watching(_)|Phase::Idle => {
+                // a broadcast cannot be paused or sped up for a single viewer
+                match event {
+                    Event::Data(ESC) if self.config.has_menu() => self.show_choices().await?,
+                    Event::Data(b'q'|b'Q') => self.quit().await?,
+                    Event::Data(digit @ b'1'..=b'9') => self.switch_animation(digit).await?,
+                    _ => {},
+                }
+            },
+            Phase::Finished => {},
+        }
+        Ok(())
+    }
+
+    /// Says goodbye at the client's request.
+    async fn quit(&mut self) -> Result<(), telnet::Error> {
+        info!("{} quit", self.connection.addr());
+        self.disconnect().await
+    }
+
+    /// Pauses or resumes playback.
+    async fn toggle_pause(&mut self) -> Result<(), telnet::Error> {
+        match self.paused_with.take() {
+            Some(remaining) => {
+                let now = Instant::now();
+                if let Some(deadline) = &mut self.playlist_deadline {
+                    // the playlist entry's time doesn't run out while paused; the pause began when
+                    // the frame still had `remaining` to go
+                    let paused_at = self.next_frame_at.checked_sub(remaining).unwrap_or(now);
+                    *deadline += now.saturating_duration_since(paused_at);
+                }
+                self.next_frame_at = now + remaining;
+                self.show_message("").await
+            },
+            None => {
+                self.paused_with = Some(self.next_frame_at.saturating_duration_since(Instant::now()));
+                self.show_message("Paused. Press space to continue.").await
+            },
+        }
+    }
+
+    /// Multiplies the playback speed by the given factor, within limits.
+    async fn change_speed(&mut self, factor: f64) -> Result<(), telnet::Error> {
+        let speed = (self.speed * factor).clamp(MIN_SPEED, MAX_SPEED);
+        if speed == self.speed {
+            return Ok(());
+        }
+        self.speed = speed;
+        self.show_message(&format!("Speed: {}x", speed)).await
+    }
+
+    /// Shows the banner file if one is configured, otherwise continues with [`Self::show_choices`].
+    async fn negotiation_finished(&mut self) -> Result<(), telnet::Error> {
+        let banner_file = match &self.config.banner_file {
+            Some(bf) => bf,
+            None => return self.show_choices().await,
+        };
+        let banner = match tokio::fs::read(banner_file).await {
+            Ok(b) => b,
+            Err(e) => {
+                error!("failed to read banner file {}: {}", banner_file.display(), e);
+                return self.show_choices().await;
+            },
+        };
+
+        let mut commands = b"\x1B[2J\x1B[H".to_vec();
+        commands.extend(normalize_newlines(&banner));
+        let wait_for_key = self.config.banner_press_any_key;
+        if wait_for_key {
+            commands.extend_from_slice(b"\r\nPress any key to continue...");
+        } else {
+            self.next_frame_at = Instant::now() + Duration::from_secs(self.config.banner_secs);
+        }
+        self.connection.send_frame(&commands).await?;
+        self.phase = Phase::Banner { wait_for_key };
+        Ok(())
+    }
+
+    /// Shows the menu or starts the animation, depending on how many animations are configured.
+    async fn show_choices(&mut self) -> Result<(), telnet::Error> {
+        if self.config.spectator {
+            return self.show_spectator_prompt(None).await;
+        }
+        if !self.config.playlist.is_empty() {
+            self.playlist_passes = 0;
+            return self.start_playlist_entry(0).await;
+        }
+        if !self.config.has_menu() {
+            return self.start_animation(0).await;
+        }
+
+        let choices = self.config.animation_choices();
+        if self.config.name_prompt {
+            let names: Vec<String> = choices.iter()
+                .map(|c| c.name.clone())
+                .collect();
+            let prompt = NamePrompt::new(self.config.menu_title.clone(), names, self.connection.echoes());
+            self.connection.send_frame(prompt.render().as_bytes()).await?;
+            if let Some(registration) = &self.registration {
+                registration.set_animation(None);
+            }
+            self.phase = Phase::Prompt(prompt);
+            return Ok(());
+        }
+
+        // describe the animations in a column of their own
+        let name_width = choices.iter()
+            .map(|c| c.name.chars().count())
+            .max()
+            .unwrap_or(0);
+        let entries: Vec<String> = choices.iter()
+            .map(|c| match animations::info(&c.name) {
+                Some(info) => format!("{:<width$}  {}", c.name, info.description, width = name_width),
+                None => c.name.clone(),
+            })
+            .collect();
+        let menu = Menu::new(self.config.menu_title.clone(), entries);
+        self.connection.send_frame(menu.render().as_bytes()).await?;
+        if let Some(registration) = &self.registration {
+            registration.set_animation(None);
+        }
+        self.phase = Phase::Menu(menu);
+        Ok(())
+    }
+
+    async fn start_animation(&mut self, index: usize) -> Result<(), telnet::Error> {
+        let choice = self.config.animation_choices().swap_remove(index).into_owned();
+        self.leave_playlist();
+        self.chosen_index = Some(index);
+        self.play(&choice).await
+    }
+
+    /// Starts the entry of the playlist at the given position.
+    async fn start_playlist_entry(&mut self, position: usize) -> Result<(), telnet::Error> {
+        let item = &self.config.playlist[position];
+        let choice = self.config.playlist_animation(item).into_owned();
+        self.playlist_position = Some(position);
+        self.playlist_deadline = match item.stop {
+            Stop::Duration(duration) => Some(Instant::now() + duration),
+            Stop::Loops(_) => None,
+        };
+        self.chosen_index = None;
+        self.play(&choice).await
+    }
+
+    /// Moves on to the next entry of the playlist, unless the playlist has been played through
+    /// often enough.
+    async fn advance_playlist(&mut self) -> Result<(), telnet::Error> {
+        let mut position = self.playlist_position.map_or(0, |p| p + 1);
+        if position == self.config.playlist.len() {
+            self.playlist_passes += 1;
+            let limit = if self.config.replay_on_key { Some(self.config.loops.unwrap_or(1)) } else { self.config.loops };
+            if limit.is_some_and(|limit| self.playlist_passes >= limit) {
+                info!("{} watched the playlist {} times", self.connection.addr(), self.playlist_passes);
+                self.playlist_deadline = None;
+                return self.end_playback().await;
+            }
+            position = 0;
+        }
+        self.start_playlist_entry(position).await
+    }
+
+    /// Stops the playlist, e.g. because the client has chosen an animation of their own.
+    fn leave_playlist(&mut self) {
+        self.playlist_position = None;
+        self.playlist_deadline = None;
+    }
+
+    /// Shows the given animation (or its broadcast) from the beginning.
+    async fn play(&mut self, choice: &AnimationConfig) -> Result<(), telnet::Error> {
+        let generation = reload::generation();
+        let phase = match &self.shared {
+            Some(shared) if self.config.broadcast => {
+                shared.broadcaster.subscribe(self.config.listen_socket_addr, choice)
+                    .map(Phase::Watching)
+            },
+            _ => animations::create_in(choice, &self.animation_context())
+                .map(Phase::Playing),
+        };
+        match phase {
+            Ok(phase) => {
+                if let Some(registration) = &self.registration {
+                    registration.set_animation(Some(choice.name.clone()));
+                }
+                self.loaded = matches!(phase, Phase::Playing(_)).then(|| (choice.clone(), generation));
+                self.phase = phase;
+                self.next_frame_at = Instant::now();
+                self.cycles_started = 0;
+                self.paused_with = None;
+            },
+            Err(e) => {
+                error!("failed to start animation: {}", e);
+                self.connection.send_frame(b"Animation missing.").await?;
+                self.phase = Phase::Idle;
+            },
+        }
+        Ok(())
+    }
+
+    /// Switches to the animation with the given number (as an ASCII digit counting from 1),
+    /// showing its title first.
+    async fn switch_animation(&mut self, digit: u8) -> Result<(), telnet::Error> {
+        let index = usize::from(digit - b'1');
+        let choices = self.config.animation_choices();
+        let choice = match choices.get(index) {
+            Some(c) => c,
+            None => return Ok(()),
+        };
+        info!("{} switched to {}", self.connection.addr(), choice.name);
+
+        let (cols, rows) = self.connection.session_info().window_size
+            .map(|(cols, rows)| (usize::from(cols), usize::from(rows)))
+            .unwrap_or((80, 24));
+        let description = animations::info(&choice.name)
+            .map(|info| info.description)
+            .unwrap_or("");
+
+        // reset attributes, clear screen, then the name and description (a line apart) centered
+        let mut commands = String::from("\x1B[0m\x1B[2J");
+        let top = rows.saturating_sub(3) / 2 + 1;
+        for (i, line) in [choice.name.as_str(), "", description].iter().enumerate() {
+            if line.is_empty() {
+                continue;
+            }
+            let column = cols.saturating_sub(line.chars().count()) / 2 + 1;
+            write!(commands, "\x1B[{};{}H{}", top + i, column, line).unwrap();
+        }
+        self.connection.send_frame(commands.as_bytes()).await?;
+
+        if let Some(registration) = &self.registration {
+            registration.set_animation(None);
+        }
+        self.leave_playlist();
+        self.phase = Phase::TitleCard(index);
+        self.next_frame_at = Instant::now() + TITLE_CARD_DURATION;
+        self.paused_with = None;
+        Ok(())
+    }
+
+    /// Lists the live sessions and asks the client which one to spectate, after the given notice.
+    async fn show_spectator_prompt(&mut self, notice: Option<&str>) -> Result<(), telnet::Error> {
+        let own_id = self.registration.as_ref().map(|r| r.id());
+        let sessions = self.registration.as_ref()
+            .map(|r| r.registry().list())
+            .unwrap_or_default();
+
+        // reset attributes, clear screen, go to top left
+        let mut commands = String::from("\x1B[0m\x1B[2J\x1B[H");
+        if let Some(notice) = notice {
+            write!(commands, "{}\r\n\r\n", notice).unwrap();
+        }
+        commands.push_str("Live sessions:\r\n");
+        for info in sessions.iter().filter(|info| Some(info.id) != own_id) {
+            write!(
+                commands,
+                "{:>6}  {:<40} {}\r\n",
+                info.id, info.peer_addr, info.animation.as_deref().unwrap_or("-"),
+            ).unwrap();
+        }
+        commands.push_str("\r\nSession ID to spectate (Escape returns here, q quits): ");
+        self.connection.send_frame(commands.as_bytes()).await?;
+        self.phase = Phase::SpectatorPrompt(String::new());
+        Ok(())
+    }
+
+    /// Starts showing what the session with the given ID is being sent.
+    async fn start_spectating(&mut self, input: &str) -> Result<(), telnet::Error> {
+        let registration = match &self.registration {
+            Some(r) => r,
+            None => return Ok(()),
+        };
+        let mirror = input.parse::<ConnectionId>().ok()
+            .filter(|&id| id != registration.id())
+            .and_then(|id| registration.registry().spectate(id).map(|mirror| (id, mirror)));
+        match mirror {
+            Some((id, mirror)) => {
+                info!("{} is spectating session {}", self.connection.addr(), id);
+                // start with a clean screen; the picture is complete once the session redraws it
+                self.connection.send_frame(b"\x1B[0m\x1B[2J\x1B[H").await?;
+                self.phase = Phase::Spectating { id, mirror };
+                Ok(())
+            },
+            None => self.show_spectator_prompt(Some(&format!("No session {:?} to spectate.", input))).await,
+        }
+    }
+
+    /// Switches to the hidden bonus animation.
+    fn start_bonus(&mut self) {
+        if let Some(registration) = &self.registration {
+            registration.set_animation(Some("bonus".to_owned()));
+        }
+        self.leave_playlist();
+        self.phase = Phase::Playing(Box::new(Bonus::new()));
+        self.loaded = None;
+        self.chosen_index = None;
+        self.next_frame_at = Instant::now();
+        self.cycles_started = 0;
+        self.paused_with = None;
+    }
+
+    /// Starts the animation that has just ended again.
+    async fn replay(&mut self) -> Result<(), telnet::Error> {
+        info!("{} is watching again", self.connection.addr());
+        self.show_message("").await?;
+        if self.playlist_position.is_some() {
+            self.playlist_passes = 0;
+            return self.start_playlist_entry(0).await;
+        }
+        match self.chosen_index {
+            Some(index) => self.start_animation(index).await,
+            None => {
+                self.start_bonus();
+                Ok(())
+            },
+        }
+    }
+
+    /// Describes the session to the animations shown in it.
+    fn animation_context(&self) -> Context {
+        Context {
+            client_addr: Some(self.connection.addr()),
+            chat_wall: self.shared.as_ref()
+                .map(|shared| shared.chat_walls.for_socket(self.config.listen_socket_addr)),
+            guestbooks: self.shared.as_ref()
+                .map(|shared| shared.guestbooks.clone()),
+        }
+    }
+
+    /// Shows a message in the bottom line of the client's terminal.
+    async fn show_message(&mut self, message: &str) -> Result<(), telnet::Error> {
+        let rows = self.connection.session_info().window_size
+            .map(|(_cols, rows)| rows)
+            .unwrap_or(24);
+        let text = telnet::decode_string(message.as_bytes());
+
+        // save cursor, go to bottom line, reset attributes, clear line, output, restore cursor
+        let commands = format!("\x1B7\x1B[{};1H\x1B[0m\x1B[2K{}\x1B8", rows, text);
+        self.connection.send_frame(commands.as_bytes()).await
+    }
+
+    /// Returns the commands drawing the time left in the top right corner, or `None` if the end of
+    /// the session is not yet near.
+    fn countdown_overlay(&self) -> Option<String> {
+        let countdown_at = self.next_countdown_at?;
+        let deadline = self.session_deadline?;
+        let now = Instant::now();
+        if now < countdown_at {
+            return None;
+        }
+        let secs_left = deadline.saturating_duration_since(now).as_secs_f64().ceil() as u64;
+        let text = format!(" {}s left ", secs_left);
+        let cols = self.connection.session_info().window_size
+            .map(|(cols, _rows)| usize::from(cols))
+            .unwrap_or(80);
+        let column = cols.saturating_sub(text.len()) + 1;
+
+        // save cursor and attributes, go to top right, output in reverse video, restore
+        Some(format!("\x1B7\x1B[1;{}H\x1B[0;7m{}\x1B8", column, text))
+    }
+
+    /// Returns the commands drawing the number of viewers of the broadcast in the bottom right
+    /// corner, or `None` if no broadcast is being watched or the count is not to be shown.
+    fn viewer_overlay(&self) -> Option<String> {
+        let subscription = match &self.phase {
+            Phase::Watching(s) if self.config.show_viewer_count => s,
+            _ => return None,
+        };
+        let text = match subscription.viewer_count() {
+            1 => " 1 person watching ".to_owned(),
+            count => format!(" {} people watching ", count),
+        };
+        let (cols, rows) = self.connection.session_info().window_size
+            .map(|(cols, rows)| (usize::from(cols), rows))
+            .unwrap_or((80, 24));
+
+        // pad on the left to cover longer counts shown before
+        let padding = VIEWER_OVERLAY_WIDTH.saturating_sub(text.len());
+        let column = cols.saturating_sub(padding + text.len()) + 1;
+
+        // save cursor and attributes, go to bottom right, output in reverse video, restore
+        Some(format!("\x1B7\x1B[{};{}H\x1B[0m{}\x1B[7m{}\x1B8", rows, column, " ".repeat(padding), text))
+    }
+
+    /// Updates the countdown to the end of the session and schedules its next update.
+    async fn show_countdown(&mut self) -> Result<(), telnet::Error> {
+        if let Some(overlay) = self.countdown_overlay() {
+            self.connection.send_frame(overlay.as_bytes()).await?;
+        }
+
+        // update whenever another second has passed
+        let deadline = match self.session_deadline {
+            Some(d) => d,
+            None => return Ok(()),
+        };
+        let secs_left = deadline.saturating_duration_since(Instant::now()).as_secs_f64().ceil() as u64;
+        self.next_countdown_at = secs_left.checked_sub(1)
+            .filter(|&secs| secs > 0)
+            .and_then(|secs| deadline.checked_sub(Duration::from_secs(secs)));
+        Ok(())
+    }
+
+    /// Passes the number of bytes sent since the last call on to the registry.
+    fn report_bytes_sent(&mut self) {
+        if let Some(registration) = &self.registration {
+            let bytes_sent = self.connection.bytes_sent();
+            registration.add_bytes_sent(bytes_sent - self.reported_bytes_sent);
+            self.reported_bytes_sent = bytes_sent;
+        }
+    }
+
+    /// Resets the terminal and sends the goodbye message, then marks the session as finished.
+    async fn disconnect(&mut self) -> Result<(), telnet::Error> {
+        // reset attributes, show cursor, clear screen and go to top left
+        let text = format!("\x1B[0m\x1B[?25h\x1B[2J\x1B[H{}\r\n", self.config.goodbye_message);
+        self.connection.send_frame(text.as_bytes()).await?;
+        self.phase = Phase::Finished;
+        Ok(())
+    }
+
+    /// Reacts to the client closing its sending side of the connection.
+    ///
+    /// If so configured, the animation continues as long as it does not need any input;
+    /// otherwise, the client is bid goodbye.
+    async fn input_ended(&mut self) -> Result<(), telnet::Error> {
+        if !self.config.play_after_end_of_input {
+            info!("{} has stopped sending", self.connection.addr());
+            return self.disconnect().await;
+        }
+        info!("{} has stopped sending; playing on", self.connection.addr());
+        if let Phase::Negotiating = self.phase {
+            // no answers are coming
+            self.negotiation_finished().await?;
+        }
+        Ok(())
+    }
+
+    /// Whether the session cannot progress without input from the client.
+    fn waits_for_input(&self) -> bool {
+        match self.phase {
+            Phase::Banner { wait_for_key } => wait_for_key,
+            Phase::Menu(_)|Phase::Prompt(_)|Phase::SpectatorPrompt(_)|Phase::Replay|Phase::Idle => true,
+            Phase::Playing(_) => self.paused_with.is_some(),
+            _ => false,
+        }
+    }
+
+    /// Restores the terminal and says a short goodbye after the client has interrupted the
+    /// session, then marks the session as finished.
+    async fn interrupt(&mut self) -> Result<(), telnet::Error> {
+        // reset attributes, show cursor, leave the screen as it is
+        self.connection.send_frame(b"\x1B[0m\x1B[?25h\r\nbye!\r\n").await?;
+        self.phase = Phase::Finished;
+        Ok(())
+    }
+
+    async fn send_next_frame(&mut self) -> Result<(), telnet::Error> {
+        let animation = match &mut self.phase {
+            Phase::Playing(a) => a,
+            _ => return Ok(()),
+        };
+
+        // time for the next frame
+        let render_start = Instant::now();
+        match animation.next_frame(self.connection.session_info()) {
+            Some(frame) => {
+                let render_time = render_start.elapsed();
+                if self.show_frame(&frame, Some(render_time)).await? {
+                    self.next_frame_at = Instant::now() + frame.delay.div_f64(self.speed);
+                }
+                Ok(())
+            },
+            None => self.animation_over().await,
+        }
+    }
+
+    /// Sends a frame of the animation, unless the client has watched enough cycles already.
+    /// Returns whether the animation goes on.
+    ///
+    /// `render_time` is how long it took to render the frame, if it was rendered for this session.
+    async fn show_frame(&mut self, frame: &Frame, render_time: Option<Duration>) -> Result<bool, telnet::Error> {
+        if frame.starts_cycle {
+            let playlist_stop = self.playlist_position
+                .map(|position| self.config.playlist[position].stop);
+            match playlist_stop {
+                Some(Stop::Loops(loops)) if self.cycles_started >= loops => {
+                    self.advance_playlist().await?;
+                    return Ok(false);
+                },
+                Some(_) => {},
+                None => {
+                    if self.config.replay_on_key && self.cycles_started >= self.config.loops.unwrap_or(1) {
+                        self.animation_over().await?;
+                        return Ok(false);
+                    }
+                    if self.config.loops.is_some_and(|loops| self.cycles_started >= loops) {
+                        info!("{} watched {} cycles", self.connection.addr(), self.cycles_started);
+                        self.disconnect().await?;
+                        return Ok(false);
+                    }
+                },
+            }
+            if self.reload_if_changed() {
+                // start over with the first frame of the new version
+                return Ok(false);
+            }
+            self.cycles_started += 1;
+        }
+        if frame.commands.is_empty() {
+            // the animation has nothing new to show
+            return Ok(true);
+        }
+        // keep the countdown and the viewer count on top of the animation
+        let overlays: Vec<String> = self.countdown_overlay().into_iter()
+            .chain(self.viewer_overlay())
+            .collect();
+        let write_start = Instant::now();
+        let bytes_sent_before = self.connection.bytes_sent();
+        if overlays.is_empty() {
+            self.connection.send_frame(frame.commands.as_bytes()).await?;
+        } else {
+            let commands = format!("{}{}", frame.commands, overlays.concat());
+            self.connection.send_frame(commands.as_bytes()).await?;
+        }
+        if let Some(registration) = &self.registration {
+            let byte_count = self.connection.bytes_sent() - bytes_sent_before;
+            registration.add_frame_sent(render_time, write_start.elapsed(), byte_count);
+        }
+        Ok(true)
+    }
+
+    /// Replaces the animation being played with a newly loaded one if the files it was loaded from
+    /// have changed since. Returns whether it has been replaced.
+    fn reload_if_changed(&mut self) -> bool {
+        let generation = reload::generation();
+        let choice = match &mut self.loaded {
+            Some((choice, loaded_generation)) if *loaded_generation != generation => {
+                *loaded_generation = generation;
+                choice.clone()
+            },
+            _ => return false,
+        };
+        match animations::create_in(&choice, &self.animation_context()) {
+            Ok(animation) => {
+                info!("{} reloaded {}", self.connection.addr(), choice.name);
+                self.phase = Phase::Playing(animation);
+                self.next_frame_at = Instant::now();
+                true
+            },
+            Err(e) => {
+                warn!("{} keeps the previous version of {}: {}", self.connection.addr(), choice.name, e);
+                false
+            },
+        }
+    }
+
+    async fn animation_over(&mut self) -> Result<(), telnet::Error> {
+        if self.playlist_position.is_some() {
+            return self.advance_playlist().await;
+        }
+        self.end_playback().await
+    }
+
+    /// Holds the final frame for a replay, disconnects or leaves the session idle, as configured,
+    /// once there is nothing more to show.
+    async fn end_playback(&mut self) -> Result<(), telnet::Error> {
+        if self.config.replay_on_key {
+            // keep showing the final frame
+            self.phase = Phase::Replay;
+            return self.show_message("Press any key to watch again, q to quit.").await;
+        }
+        if self.config.loops.is_some() {
+            return self.disconnect().await;
+        }
+        self.phase = Phase::Idle;
+        Ok(())
+    }
+}
+
+
+/// Converts all line endings in the text to CR LF.
+fn normalize_newlines(text: &[u8]) -> Vec<u8> {
+    let mut ret = Vec::with_capacity(text.len());
+    for (i, &b) in text.iter().enumerate() {
+        match b {
+            b'\r' => {
+                if text.get(i + 1) != Some(&b'\n') {
+                    // lone CR (classic Mac OS)
+                    ret.extend_from_slice(b"\r\n");
+                }
+            },
+            b'\n' => ret.extend_from_slice(b"\r\n"),
+            other => ret.push(other),
+        }
+    }
+    ret
+}
+
+
+/// Waits for the next command to the session, or forever if the session is not registered.
+async fn next_command(registration: &mut Option<Registration>) -> SessionCommand {
+    if let Some(r) = registration {
+        if let Some(command) = r.next_command().await {
+            return command;
+        }
+    }
+    std::future::pending().await
+}
+
+
+/// Waits for the next update of the broadcast being watched or the next frame sent to the session
+/// being spectated, or forever if neither is.
+async fn next_fed(phase: &mut Phase) -> Wakeup {
+    match phase {
+        Phase::Watching(subscription) => Wakeup::Broadcast(subscription.next_update().await),
+        Phase::Spectating { mirror, .. } => Wakeup::Mirror(mirror.recv().await),
+        _ => std::future::pending().await,
+    }
+}
+
+
+/// Sleeps until the given instant or forever if there is none.
+pub(crate) async fn sleep_until_opt(deadline: Option<Instant>) {
+    match deadline {
+        Some(d) => sleep_until(d).await,
+        None => std::future::pending().await,
+    }
+}
+
+
+/// Converts the keepalive configuration into the form understood by the socket, leaving out what
+/// the platform cannot set.
+fn tcp_keepalive(config: &KeepaliveConfig) -> TcpKeepalive {
+    let keepalive = TcpKeepalive::new()
+        .with_time(Duration::from_secs(config.idle_secs));
+    #[cfg(any(
+        target_os = "android", target_os = "dragonfly", target_os = "freebsd", target_os = "fuchsia",
+        target_os = "illumos", target_os = "ios", target_os = "linux", target_os = "macos",
+        target_os = "netbsd", target_os = "windows",
+    ))]
+    let keepalive = keepalive.with_interval(Duration::from_secs(config.interval_secs));
+    #[cfg(any(
+        target_os = "android", target_os = "dragonfly", target_os = "freebsd", target_os = "fuchsia",
+        target_os = "illumos", target_os = "ios", target_os = "linux", target_os = "macos",
+        target_os = "netbsd",
+    ))]
+    let keepalive = keepalive.with_retries(config.retries);
+    keepalive
+}
+
+
+/// Runs a session with a newly connected client, performing the TLS handshake first if an acceptor
+/// is passed.
+///
+/// The session is ended with a goodbye message once `shutdown` changes.
+pub(crate) async fn handle_connection(
+    socket: TcpStream,
+    tls_acceptor: Option<TlsAcceptor>,
+    addr: SocketAddr,
+    config: SocketConfig,
+    shutdown: watch::Receiver<bool>,
+    registration: Registration,
+    shared: Shared,
+) -> Result<(), telnet::Error> {
+    if let Err(e) = socket.set_nodelay(config.tcp_nodelay) {
+        warn!("{}: failed to set TCP_NODELAY: {}", addr, e);
+    }
+    if let Some(size) = config.send_buffer_size {
+        if let Err(e) = SockRef::from(&socket).set_send_buffer_size(size as usize) {
+            warn!("{}: failed to set send buffer size: {}", addr, e);
+        }
+    }
+    if let Some(keepalive) = &config.tcp_keepalive {
+        if let Err(e) = SockRef::from(&socket).set_tcp_keepalive(&tcp_keepalive(keepalive)) {
+            warn!("{}: failed to enable TCP keepalive: {}", addr, e);
+        }
+    }
+    handle_stream(socket, tls_acceptor, addr, config, shutdown, registration, shared).await
+}
+
+
+/// Runs a session over a stream that is not necessarily a TCP connection, performing the TLS
+/// handshake first if an acceptor is passed.
+///
+/// The session is ended with a goodbye message once `shutdown` changes.
+pub(crate) async fn handle_stream<S: Stream + 'static>(
+    stream: S,
+    tls_acceptor: Option<TlsAcceptor>,
+    addr: SocketAddr,
+    config: SocketConfig,
+    shutdown: watch::Receiver<bool>,
+    registration: Registration,
+    shared: Shared,
+) -> Result<(), telnet::Error> {
+    // however the session ends, everything else serving the connection is told to stop
+    let cancel = CancellationToken::new();
+    let _cancel_on_exit = cancel.clone().drop_guard();
+
+    let stream: Box<dyn Stream> = match tls_acceptor {
+        Some(acceptor) => {
+            let handshake_timeout = Duration::from_millis(config.negotiation_timeout_ms);
+            match tokio::time::timeout(handshake_timeout, acceptor.accept(stream)).await {
+                Ok(Ok(s)) => Box::new(s),
+                Ok(Err(e)) => {
+                    warn!("{}: TLS handshake failed: {}", addr, e);
+                    return Ok(());
+                },
+                Err(_) => {
+                    warn!("{}: TLS handshake timed out", addr);
+                    return Ok(());
+                },
+            }
+        },
+        None => Box::new(stream),
+    };
+    let stream: Box<dyn Stream> = match &config.record {
+        Some(record_config) if record_config.wants(addr.ip()) => {
+            match recording::start(record_config, addr, config.listen_socket_addr).await {
+                Ok(recorder) => Box::new(recorder.wrap(stream)),
+                Err(e) => {
+                    // not worth turning the client away over
+                    error!("{}: failed to start recording: {}", addr, e);
+                    stream
+                },
+            }
+        },
+        _ => stream,
+    };
+
+    let connection = match config.protocol {
+        Protocol::Http => return http::serve(stream, addr, config, shutdown, registration).await,
+        Protocol::Telnet => TelnetConnection::new(stream, addr, config.max_sub_negotiation_length),
+        Protocol::Raw => {
+            let mut connection = TelnetConnection::new(stream, addr, config.max_sub_negotiation_length);
+            connection.disable_telnet();
+            connection
+        },
+        Protocol::WebSocket => {
+            let handshake_timeout = Duration::from_millis(config.negotiation_timeout_ms);
+            let (reader, writer) = match tokio::time::timeout(handshake_timeout, websocket::accept(stream, addr, cancel.clone())).await {
+                Ok(Ok(rw)) => rw,
+                Ok(Err(e)) => {
+                    warn!("{}: WebSocket handshake failed: {}", addr, e);
+                    return Ok(());
+                },
+                Err(_) => {
+                    warn!("{}: WebSocket handshake timed out", addr);
+                    return Ok(());
+                },
+            };
+            let mut connection = TelnetConnection::from_parts(reader, writer, addr, config.max_sub_negotiation_length);
+            connection.disable_telnet();
+            connection
+        },
+    };
+    run_session(connection, config, shutdown, cancel, Some(registration), Some(shared)).await
+}
+
+
+/// Runs a session on an established Telnet connection.
+///
+/// The session is ended with a goodbye message once `shutdown` changes, and silently once `cancel`
+/// is cancelled. If a registration is passed, it is kept up to date with the animation being shown
+/// and the frames sent. Without the state shared with other sessions, each session plays its own
+/// animation even on sockets in broadcast mode, and the chat wall is private to the session.
+pub(crate) async fn run_session(
+    mut connection: TelnetConnection,
+    config: SocketConfig,
+    shutdown: watch::Receiver<bool>,
+    cancel: CancellationToken,
+    registration: Option<Registration>,
+    shared: Option<Shared>,
+) -> Result<(), telnet::Error> {
+    if let Some(registration) = &registration {
+        connection.set_mirror(registration.mirror());
+    }
+    let mut session = Session {
+        connection,
+        config,
+        phase: Phase::Negotiating,
+        next_frame_at: Instant::now(),
+        session_deadline: None,
+        next_countdown_at: None,
+        cycles_started: 0,
+        speed: 1.0,
+        paused_with: None,
+        chosen_index: None,
+        playlist_position: None,
+        playlist_deadline: None,
+        playlist_passes: 0,
+        loaded: None,
+        konami_code: KonamiCode::new(),
+        shutdown,
+        cancel,
+        registration,
+        reported_bytes_sent: 0,
+        shared,
+    };
+    session.run().await
+}
@@ -0,0 +1,255 @@
+//! Statistics about what the server has been up to, periodically logged as a summary, and about
+//! the frames sent to each session.
+
+
+use std::collections::{HashMap, VecDeque};
+use std::fmt;
+use std::net::{IpAddr, SocketAddr};
+use std::time::Duration;
+
+use log::info;
+use tokio::sync::watch;
+use tokio::time::{Instant, MissedTickBehavior};
+
+use crate::registry::Registry;
+
+
+/// How many of the busiest client addresses are mentioned in the summary.
+const TOP_SOURCE_COUNT: usize = 5;
+
+/// How many of the most recent frames of a session the render time percentiles are computed from.
+const RECENT_FRAME_COUNT: usize = 1000;
+
+
+/// What has happened since the statistics were last taken.
+#[derive(Clone, Debug, Default)]
+pub(crate) struct Stats {
+    /// The number of sessions started.
+    pub connections_accepted: u64,
+
+    /// The number of live sessions on each socket with any.
+    pub live_per_socket: Vec<(SocketAddr, usize)>,
+
+    /// The number of bytes sent to all clients.
+    pub bytes_sent: u64,
+
+    /// The number of animation frames sent to all clients.
+    pub frames_sent: u64,
+
+    /// The number of frames rendered specifically for a client (as opposed to broadcast frames,
+    /// which are rendered once for all viewers).
+    pub frames_rendered: u64,
+
+    /// The total time spent rendering the frames counted in `frames_rendered`.
+    pub render_time: Duration,
+
+    /// The total time spent writing the frames counted in `frames_sent`.
+    pub write_time: Duration,
+
+    /// The number of broadcast frames that viewers missed because they could not keep up.
+    pub frames_skipped: u64,
+
+    /// The number of sessions started by each client address.
+    pub connections_by_ip: HashMap<IpAddr, u64>,
+}
+impl Stats {
+    /// The client addresses that started the most sessions, busiest first.
+    pub fn top_sources(&self, count: usize) -> Vec<(IpAddr, u64)> {
+        let mut sources: Vec<(IpAddr, u64)> = self.connections_by_ip.iter()
+            .map(|(ip, connections)| (*ip, *connections))
+            .collect();
+        sources.sort_unstable_by(|(ip_a, count_a), (ip_b, count_b)| count_b.cmp(count_a).then(ip_a.cmp(ip_b)));
+        sources.truncate(count);
+        sources
+    }
+}
+impl fmt::Display for Stats {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f, "{} connections accepted, {} bytes and {} frames sent",
+            self.connections_accepted, self.bytes_sent, self.frames_sent,
+        )?;
+        if let Some(write_time) = average(self.write_time, self.frames_sent) {
+            write!(f, " (avg write {}", millis(write_time))?;
+            if let Some(render_time) = average(self.render_time, self.frames_rendered) {
+                write!(f, ", avg render {}", millis(render_time))?;
+            }
+            write!(f, ", {} skipped)", self.frames_skipped)?;
+        }
+        write!(f, "; live:")?;
+        if self.live_per_socket.is_empty() {
+            write!(f, " none")?;
+        }
+        for (listen_addr, count) in &self.live_per_socket {
+            write!(f, " {}={}", listen_addr, count)?;
+        }
+        let top_sources = self.top_sources(TOP_SOURCE_COUNT);
+        if !top_sources.is_empty() {
+            write!(f, "; top sources:")?;
+            for (ip, count) in top_sources {
+                write!(f, " {}={}", ip, count)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+
+/// The timing of the frames sent to a single session.
+#[derive(Clone, Debug, Default)]
+pub(crate) struct FrameStats {
+    /// The number of animation frames sent.
+    pub frames_sent: u64,
+
+    /// The number of frames rendered specifically for this session (as opposed to broadcast
+    /// frames).
+    pub frames_rendered: u64,
+
+    /// The number of broadcast frames missed because the session could not keep up.
+    pub frames_skipped: u64,
+
+    /// The number of bytes sent as part of animation frames.
+    pub bytes_sent: u64,
+
+    total_render_time: Duration,
+    total_write_time: Duration,
+
+    /// The render times of the most recent frames, oldest first.
+    recent_render_times: VecDeque<Duration>,
+}
+impl FrameStats {
+    /// Records a frame that has been sent, along with how long it took to render it (if it was
+    /// rendered for this session) and to write it.
+    pub fn record(&mut self, render_time: Option<Duration>, write_time: Duration, byte_count: u64) {
+        self.frames_sent += 1;
+        self.bytes_sent += byte_count;
+        self.total_write_time += write_time;
+        if let Some(render_time) = render_time {
+            self.frames_rendered += 1;
+            self.total_render_time += render_time;
+            if self.recent_render_times.len() == RECENT_FRAME_COUNT {
+                self.recent_render_times.pop_front();
+            }
+            self.recent_render_times.push_back(render_time);
+        }
+    }
+
+    pub fn average_render_time(&self) -> Option<Duration> {
+        average(self.total_render_time, self.frames_rendered)
+    }
+
+    pub fn average_write_time(&self) -> Option<Duration> {
+        average(self.total_write_time, self.frames_sent)
+    }
+
+    pub fn bytes_per_frame(&self) -> Option<u64> {
+        self.bytes_sent.checked_div(self.frames_sent)
+    }
+
+    /// The render time below which the given percentage of the most recent frames were rendered.
+    pub fn render_time_percentile(&self, percent: usize) -> Option<Duration> {
+        if self.recent_render_times.is_empty() {
+            return None;
+        }
+        let mut sorted: Vec<Duration> = self.recent_render_times.iter().copied().collect();
+        sorted.sort_unstable();
+        let index = (sorted.len() * percent / 100).min(sorted.len() - 1);
+        Some(sorted[index])
+    }
+}
+impl fmt::Display for FrameStats {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} frames sent ({} skipped)", self.frames_sent, self.frames_skipped)?;
+        if let Some(bytes_per_frame) = self.bytes_per_frame() {
+            write!(f, ", {} bytes per frame", bytes_per_frame)?;
+        }
+        if let Some(render_time) = self.average_render_time() {
+            write!(f, ", render avg {}", millis(render_time))?;
+            if let Some(p95) = self.render_time_percentile(95) {
+                write!(f, " p95 {}", millis(p95))?;
+            }
+        }
+        if let Some(write_time) = self.average_write_time() {
+            write!(f, ", write avg {}", millis(write_time))?;
+        }
+        Ok(())
+    }
+}
+
+
+fn average(total: Duration, count: u64) -> Option<Duration> {
+    if count == 0 {
+        return None;
+    }
+    // durations can only be divided by u32
+    Some(Duration::from_secs_f64(total.as_secs_f64() / count as f64))
+}
+
+
+/// Formats a duration in milliseconds.
+fn millis(duration: Duration) -> String {
+    format!("{:.2} ms", duration.as_secs_f64() * 1000.0)
+}
+
+
+/// Logs the frame statistics of a session that has ended, if any frames were sent.
+pub(crate) fn log_session(addr: SocketAddr, frame_stats: &FrameStats) {
+    if frame_stats.frames_sent > 0 {
+        info!("{} frame stats: {}", addr, frame_stats);
+    }
+}
+
+
+/// Logs a summary of the statistics every `interval` until the server shuts down.
+pub(crate) async fn log_periodically(registry: Registry, interval: Duration, mut shutdown: watch::Receiver<bool>) {
+    let mut ticker = tokio::time::interval_at(Instant::now() + interval, interval);
+    ticker.set_missed_tick_behavior(MissedTickBehavior::Delay);
+
+    // start counting from now
+    registry.take_stats();
+    loop {
+        tokio::select! {
+            _ = ticker.tick() => {},
+            _ = shutdown.changed() => break,
+        }
+        info!("stats for the last {} min: {}", interval.as_secs() / 60, registry.take_stats());
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn summary() {
+        let stats = Stats {
+            connections_accepted: 4,
+            live_per_socket: vec![("0.0.0.0:23".parse().unwrap(), 3)],
+            bytes_sent: 1234,
+            frames_sent: 10,
+            frames_rendered: 5,
+            render_time: Duration::from_millis(2),
+            write_time: Duration::from_millis(10),
+            frames_skipped: 1,
+            connections_by_ip: HashMap::from([
+                ("192.0.2.1".parse().unwrap(), 3),
+                ("192.0.2.2".parse().unwrap(), 1),
+            ]),
+        };
+        assert_eq!(
+            stats.to_string(),
+            "4 connections accepted, 1234 bytes and 10 frames sent \
+                (avg write 1.00 ms, avg render 0.40 ms, 1 skipped); \
+                live: 0.0.0.0:23=3; top sources: 192.0.2.1=3 192.0.2.2=1",
+        );
+    }
+
+    #[test]
+    fn idle_summary() {
+        assert_eq!(
+            Stats::default().to_string(),
+            "0 connections accepted, 0 bytes and 0 frames sent; live: none",
+        );
+    }
+}
@@ -0,0 +1,220 @@
+//! Load testing a server by running many simulated clients at once.
+//!
+//! Each client connects, answers the server's negotiation requests like the built-in client does,
+//! and then receives (and discards) whatever the server sends until the test is over.
+
+
+use std::collections::BTreeMap;
+use std::net::SocketAddr;
+use std::time::Duration;
+
+use tokio::io::AsyncReadExt;
+use tokio::net::TcpStream;
+use tokio::time::{Instant, sleep_until, timeout_at};
+
+use crate::client::{process_element, send};
+use crate::telnet::{self, decode_element, option, DEFAULT_MAX_SUB_NEGOTIATION_LENGTH, IAC, WILL};
+
+
+/// Data arriving after at least this much silence is counted as the start of a new frame.
+const FRAME_GAP: Duration = Duration::from_millis(10);
+
+/// The window size reported by each simulated client.
+const WINDOW_SIZE: (u16, u16) = (80, 24);
+
+
+/// What happened during a single simulated session.
+#[derive(Clone, Debug, Default)]
+struct SessionReport {
+    /// How long it took to establish the connection, if it was established.
+    connect_time: Option<Duration>,
+
+    /// How long after connecting the first data (apart from negotiation) arrived.
+    first_data_time: Option<Duration>,
+
+    /// How many bursts of data have been received.
+    frames: u64,
+
+    /// How many bytes of data (apart from negotiation) have been received.
+    bytes: u64,
+
+    /// How long the session received data.
+    receive_time: Duration,
+
+    /// Whether the server closed the connection before the end of the test.
+    closed_by_server: bool,
+
+    /// What went wrong, if anything.
+    error: Option<String>,
+}
+
+
+/// Receives data from the server until the deadline.
+async fn receive(stream: TcpStream, addr: SocketAddr, deadline: Instant, report: &mut SessionReport) -> Result<(), telnet::Error> {
+    let connected_at = Instant::now();
+    let (mut reader, mut writer) = stream.into_split();
+
+    // offer to tell the server our window size
+    send(&mut writer, addr, &[IAC, WILL, option::NEGO_WIN_SIZE]).await?;
+
+    let mut read_buf = Vec::new();
+    let mut last_data_at: Option<Instant> = None;
+    loop {
+        let byte_count = tokio::select! {
+            read = reader.read_buf(&mut read_buf) => read.map_err(|e| telnet::Error::from_io_receive(e, addr))?,
+            _ = sleep_until(deadline) => break,
+        };
+        let now = Instant::now();
+        if byte_count == 0 {
+            report.closed_by_server = true;
+            break;
+        }
+
+        let mut consumed = 0;
+        let mut out = Vec::new();
+        let mut replies = Vec::new();
+        while let Some((element, length)) = decode_element(&read_buf[consumed..], addr, DEFAULT_MAX_SUB_NEGOTIATION_LENGTH)? {
+            consumed += length;
+            replies.extend(process_element(element, &mut out, WINDOW_SIZE));
+        }
+        read_buf.drain(..consumed);
+
+        if !replies.is_empty() {
+            send(&mut writer, addr, &replies).await?;
+        }
+
+        if !out.is_empty() {
+            if report.first_data_time.is_none() {
+                report.first_data_time = Some(now - connected_at);
+            }
+            if last_data_at.is_none_or(|at| now - at >= FRAME_GAP) {
+                report.frames += 1;
+            }
+            last_data_at = Some(now);
+            report.bytes += out.len() as u64;
+        }
+    }
+    report.receive_time = Instant::now() - connected_at;
+    Ok(())
+}
+
+
+/// Runs a single simulated session until the deadline.
+async fn run_session(target: String, deadline: Instant) -> SessionReport {
+    let mut report = SessionReport::default();
+    let start = Instant::now();
+    let stream = match timeout_at(deadline, TcpStream::connect(&target)).await {
+        Ok(Ok(s)) => s,
+        Ok(Err(e)) => {
+            report.error = Some(format!("failed to connect: {}", e));
+            return report;
+        },
+        Err(_) => {
+            report.error = Some("timed out connecting".to_owned());
+            return report;
+        },
+    };
+    report.connect_time = Some(start.elapsed());
+
+    let addr = match stream.peer_addr() {
+        Ok(a) => a,
+        Err(e) => {
+            report.error = Some(format!("failed to obtain peer address: {}", e));
+            return report;
+        },
+    };
+    if let Err(e) = receive(stream, addr, deadline, &mut report).await {
+        // the address is the same for every session; leave it out so that errors can be grouped
+        report.error = Some(match e {
+            telnet::Error::ConnectionReset { .. } => "connection reset".to_owned(),
+            telnet::Error::SendFailed { error, .. } => format!("send failed: {}", error),
+            telnet::Error::ReceiveFailed { error, .. } => format!("receive failed: {}", error),
+            other => other.to_string(),
+        });
+    }
+    report
+}
+
+
+/// Formats a duration in milliseconds.
+fn millis(duration: Duration) -> String {
+    format!("{:.1} ms", duration.as_secs_f64() * 1000.0)
+}
+
+
+/// Outputs the minimum, average, 95th percentile and maximum of the durations.
+fn print_durations(title: &str, mut durations: Vec<Duration>) {
+    if durations.is_empty() {
+        println!("{}: -", title);
+        return;
+    }
+    durations.sort_unstable();
+    let total: Duration = durations.iter().sum();
+    let average = total / durations.len() as u32;
+    let p95 = durations[(durations.len() * 95 / 100).min(durations.len() - 1)];
+    println!(
+        "{}: min {}, avg {}, p95 {}, max {}",
+        title, millis(durations[0]), millis(average), millis(p95), millis(durations[durations.len() - 1]),
+    );
+}
+
+
+/// Runs `session_count` simulated sessions against the target at the same time for the given
+/// duration and outputs a summary, returning the exit code.
+///
+/// The exit code is non-zero if any session failed.
+pub(crate) async fn run(target: &str, session_count: usize, duration: Duration) -> i32 {
+    let deadline = Instant::now() + duration;
+    let handles: Vec<_> = (0..session_count)
+        .map(|_| tokio::spawn(run_session(target.to_owned(), deadline)))
+        .collect();
+    let mut reports = Vec::with_capacity(session_count);
+    for handle in handles {
+        match handle.await {
+            Ok(r) => reports.push(r),
+            Err(e) => reports.push(SessionReport { error: Some(format!("session task failed: {}", e)), ..SessionReport::default() }),
+        }
+    }
+
+    let connected = reports.iter()
+        .filter(|r| r.connect_time.is_some())
+        .count();
+    println!("sessions: {} ({} connected, {} failed to connect)", session_count, connected, session_count - connected);
+    print_durations("connect latency", reports.iter().filter_map(|r| r.connect_time).collect());
+    print_durations("time to first data", reports.iter().filter_map(|r| r.first_data_time).collect());
+
+    let frames: u64 = reports.iter().map(|r| r.frames).sum();
+    let bytes: u64 = reports.iter().map(|r| r.bytes).sum();
+    let receive_secs: f64 = reports.iter().map(|r| r.receive_time.as_secs_f64()).sum();
+    let (frame_rate, byte_rate) = if receive_secs > 0.0 {
+        (frames as f64 / receive_secs, bytes as f64 / receive_secs)
+    } else {
+        (0.0, 0.0)
+    };
+    println!("frames received: {} ({:.1} per second per session)", frames, frame_rate);
+    println!("bytes received: {} ({:.0} per second per session)", bytes, byte_rate);
+
+    let closed_by_server = reports.iter()
+        .filter(|r| r.closed_by_server)
+        .count();
+    if closed_by_server > 0 {
+        println!("closed by the server before the end: {}", closed_by_server);
+    }
+
+    // group identical errors
+    let mut errors: BTreeMap<&str, usize> = BTreeMap::new();
+    for error in reports.iter().filter_map(|r| r.error.as_deref()) {
+        *errors.entry(error).or_insert(0) += 1;
+    }
+    let error_count: usize = errors.values().sum();
+    println!("errors: {}", error_count);
+    for (error, count) in &errors {
+        println!("  {} x {}", count, error);
+    }
+
+    if error_count > 0 {
+        1
+    } else {
+        0
+    }
+}
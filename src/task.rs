@@ -0,0 +1,44 @@
+//! Spawning tasks whose panics do not go unnoticed.
+
+
+use std::any::Any;
+use std::future::Future;
+use std::panic::AssertUnwindSafe;
+
+use futures_util::FutureExt;
+use log::error;
+use tokio::task::JoinHandle;
+
+
+/// Runs the future to completion, catching any panic.
+///
+/// Returns the panic message if the future panicked. Whatever the future owned has been dropped by
+/// then, so connections, permits and registrations are cleaned up as usual.
+pub(crate) async fn catch_panic<F: Future>(future: F) -> Result<F::Output, String> {
+    AssertUnwindSafe(future)
+        .catch_unwind()
+        .await
+        .map_err(|payload| panic_message(payload.as_ref()))
+}
+
+
+/// Spawns a task, logging a panic of the future with the given description.
+pub(crate) fn spawn_logged<F: Future<Output = ()> + Send + 'static>(description: String, future: F) -> JoinHandle<()> {
+    tokio::spawn(async move {
+        if let Err(message) = catch_panic(future).await {
+            error!("{} panicked: {}", description, message);
+        }
+    })
+}
+
+
+/// Extracts the message from the payload of a panic.
+fn panic_message(payload: &(dyn Any + Send)) -> String {
+    if let Some(message) = payload.downcast_ref::<&str>() {
+        (*message).to_owned()
+    } else if let Some(message) = payload.downcast_ref::<String>() {
+        message.clone()
+    } else {
+        "(no message)".to_owned()
+    }
+}
@@ -1,18 +1,21 @@
 //! Implementation of the Telnet protocol.
 //!
 //! Telnet, as implemented here, is defined mostly in RFC854.
+//!
+//! Nothing in here performs any I/O: [`TelnetMachine`] is fed the bytes received from the client
+//! and collects the bytes to be sent to it. Running it over actual connections is the business of
+//! [`crate::connection`].
 
 
+use std::collections::HashSet;
 use std::fmt;
 use std::io;
 use std::net::SocketAddr;
-use std::sync::Arc;
 
-use tokio::io::{AsyncReadExt, AsyncWriteExt, BufReader, BufWriter};
-use tokio::net::tcp::{OwnedReadHalf, OwnedWriteHalf};
-use tokio::sync::Mutex;
+use log::{debug, info, warn};
 
-use crate::SocketConfig;
+use crate::keys::{Decoded, ESC, Key, KeyDecoder};
+use crate::logging::{self, HexdumpScope};
 
 
 /// Interpret As Command (escape sequence)
@@ -21,6 +24,9 @@ pub const IAC: u8 = 255;
 /// Subnegotiation End
 pub const SE: u8 = 240;
 
+/// Interrupt Process, sent by most clients when the user presses Ctrl-C
+pub const IP: u8 = 244;
+
 /// Subnegotiation Begin
 pub const SB: u8 = 250;
 
@@ -36,11 +42,58 @@ pub const DO: u8 = 253;
 /// Indicates that a party wishes to disable a feature on the other end of the session.
 pub const DONT: u8 = 254;
 
+/// ASCII end of text, sent for Ctrl-C by clients that don't translate it into Interrupt Process.
+const ETX: u8 = 0x03;
+
 pub mod option {
+    pub const TRANSMIT_BINARY: u8 = 0;
+    pub const ECHO: u8 = 1;
+    pub const SUPPRESS_GO_AHEAD: u8 = 3;
     pub const TERMINAL_TYPE: u8 = 24;
     pub const NEGO_WIN_SIZE: u8 = 31;
 }
 
+/// Which options we agree to enable when the client asks for them; everything else is politely
+/// refused (once).
+struct OptionPolicy {
+    option: u8,
+
+    /// Whether we agree to enable the option on our end when the client sends DO.
+    ours: bool,
+
+    /// Whether we agree to the client enabling the option on its end when it sends WILL.
+    theirs: bool,
+}
+impl OptionPolicy {
+    /// Whether the client's request (DO or WILL) to enable the option is to be accepted.
+    fn accepts(command: u8, option_byte: u8) -> bool {
+        OPTION_POLICIES.iter()
+            .find(|p| p.option == option_byte)
+            .is_some_and(|p| if command == DO { p.ours } else { p.theirs })
+    }
+}
+
+const OPTION_POLICIES: [OptionPolicy; 5] = [
+    // we have nothing against eight-bit bytes in either direction
+    OptionPolicy { option: option::TRANSMIT_BINARY, ours: true, theirs: true },
+    // we "echo" so that the client doesn't; the client echoing our output back would be silly
+    OptionPolicy { option: option::ECHO, ours: true, theirs: false },
+    // many clients offer both halves in their opening burst
+    OptionPolicy { option: option::SUPPRESS_GO_AHEAD, ours: true, theirs: true },
+    // we have no terminal type or window size to tell; some clients ask anyway
+    OptionPolicy { option: option::TERMINAL_TYPE, ours: false, theirs: true },
+    OptionPolicy { option: option::NEGO_WIN_SIZE, ours: false, theirs: true },
+];
+
+/// The default maximum length of a subnegotiation, in bytes.
+pub const DEFAULT_MAX_SUB_NEGOTIATION_LENGTH: usize = 1024;
+
+/// The largest number of columns or rows of a client window that is passed on to the animations.
+///
+/// Animations allocate memory in proportion to the window size, so larger sizes reported by the
+/// client are clamped to this.
+pub const MAX_WINDOW_SIZE: u16 = 1000;
+
 pub mod termtype {
     pub const IS: u8 = 0;
     pub const SEND: u8 = 1;
@@ -74,6 +127,9 @@ pub enum Error {
 
     #[non_exhaustive]
     WrongWindowSizeBytes { byte_count: usize, source: SocketAddr },
+
+    #[non_exhaustive]
+    SubNegotiationTooLong { max_length: usize, source: SocketAddr },
 }
 impl Error {
     pub fn from_io_send(error: io::Error, target: SocketAddr) -> Self {
@@ -91,6 +147,19 @@ impl Error {
             Self::ReceiveFailed { error, source }
         }
     }
+
+    /// Whether this error means that the client has gone away.
+    pub fn is_disconnect(&self) -> bool {
+        match self {
+            Self::ConnectionReset { .. } => true,
+            Self::ReceiveFailed { error, .. } => error.kind() == io::ErrorKind::UnexpectedEof,
+            Self::SendFailed { error, .. } => matches!(
+                error.kind(),
+                io::ErrorKind::BrokenPipe | io::ErrorKind::ConnectionReset | io::ErrorKind::ConnectionAborted,
+            ),
+            _ => false,
+        }
+    }
 }
 impl fmt::Display for Error {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
@@ -111,6 +180,8 @@ impl fmt::Display for Error {
                 => write!(f, "unexpected terminal-type sub-negotiation byte 0x{:02X} from {}", byte, source),
             Self::WrongWindowSizeBytes { byte_count, source }
                 => write!(f, "unexpected byte count {} for window size option from {}", byte_count, source),
+            Self::SubNegotiationTooLong { max_length, source }
+                => write!(f, "sub-negotiation from {} exceeds maximum length of {} bytes", source, max_length),
         }
     }
 }
@@ -125,204 +196,821 @@ impl std::error::Error for Error {
             Self::NoTerminalTypeSubNegotiationCommand { .. } => None,
             Self::UnexpectedTerminalTypeSubNegotiationCommand { .. } => None,
             Self::WrongWindowSizeBytes { .. } => None,
+            Self::SubNegotiationTooLong { .. } => None,
         }
     }
 }
 
 
-/// Asks the client whether it can handle a "terminal type" query.
-pub(crate) async fn ask_can_do_terminal_type(writer: &mut BufWriter<OwnedWriteHalf>, target: SocketAddr) -> Result<(), Error> {
-    let can_term_type_ask_buf = [IAC, DO, option::TERMINAL_TYPE];
-    writer.write_all(&can_term_type_ask_buf)
-        .await.map_err(|e| Error::from_io_send(e, target))?;
-    writer.flush()
-        .await.map_err(|e| Error::from_io_send(e, target))
-}
+/// An event that occurred on a Telnet connection.
+#[derive(Clone, Debug, Eq, Hash, Ord, PartialEq, PartialOrd)]
+pub enum Event {
+    /// The client sent a byte of data.
+    Data(u8),
 
-pub(crate) async fn receive_u8(reader: &mut BufReader<OwnedReadHalf>, source: SocketAddr) -> Result<u8, Error> {
-    reader.read_u8()
-        .await.map_err(|e| Error::from_io_receive(e, source))
-}
+    /// The client pressed a special key, such as a cursor key.
+    Key(Key),
 
-pub(crate) async fn write_all(writer: &mut BufWriter<OwnedWriteHalf>, target: SocketAddr, buf: &[u8]) -> Result<(), Error> {
-    writer.write_all(buf)
-        .await.map_err(|e| Error::from_io_send(e, target))
+    /// The client sent an end of line.
+    ///
+    /// NVT requires CR LF for line ends and CR NUL for a bare carriage return; both (as well as bare
+    /// CR and LF sent by sloppy clients and the Enter key of the numeric keypad) are decoded into
+    /// this event.
+    Newline,
+
+    /// The client reported its terminal type (in lowercase).
+    TerminalType(String),
+
+    /// The client refused to report its terminal type.
+    NoTerminalType,
+
+    /// The client reported the size of its terminal window.
+    WindowSize { cols: u16, rows: u16 },
+
+    /// The client asked to interrupt what we are doing, usually because the user pressed Ctrl-C.
+    Interrupt,
+
+    /// The client has closed its sending side of the connection (e.g. `nc -q` after reaching the
+    /// end of its input); nothing more will be received, but the client may still be listening.
+    EndOfInput,
 }
 
-pub(crate) async fn flush(writer: &mut BufWriter<OwnedWriteHalf>, target: SocketAddr) -> Result<(), Error> {
-    writer.flush()
-        .await.map_err(|e| Error::from_io_send(e, target))
+
+/// A single element of the Telnet byte stream.
+#[derive(Clone, Debug, Eq, Hash, Ord, PartialEq, PartialOrd)]
+pub enum Element {
+    Data(u8),
+    Negotiation { command: u8, option: u8 },
+    SubNegotiation(Vec<u8>),
+    Command(u8),
 }
 
-pub(crate) async fn write_all_and_flush(writer: &mut BufWriter<OwnedWriteHalf>, target: SocketAddr, buf: &[u8]) -> Result<(), Error> {
-    write_all(writer, target, buf).await?;
-    flush(writer, target).await
+/// Attempts to decode a single element from the beginning of the buffer.
+///
+/// Returns the element and the number of bytes it occupies, or `None` if the buffer does not yet
+/// contain a complete element. Fails if a subnegotiation exceeds `max_sub_negotiation_length`
+/// bytes, even if it is not complete yet.
+pub fn decode_element(buf: &[u8], source: SocketAddr, max_sub_negotiation_length: usize) -> Result<Option<(Element, usize)>, Error> {
+    let first = match buf.first() {
+        Some(f) => *f,
+        None => return Ok(None),
+    };
+    if first != IAC {
+        return Ok(Some((Element::Data(first), 1)));
+    }
+
+    let cmd_byte = match buf.get(1) {
+        Some(c) => *c,
+        None => return Ok(None),
+    };
+    match cmd_byte {
+        IAC => Ok(Some((Element::Data(IAC), 2))), // escaped IAC
+        DO|DONT|WILL|WONT => {
+            // obtain feature ID
+            match buf.get(2) {
+                Some(option) => Ok(Some((Element::Negotiation { command: cmd_byte, option: *option }, 3))),
+                None => Ok(None),
+            }
+        },
+        SB => {
+            // client is sending additional negotiation information
+
+            // keep reading until we get IAC
+            let mut sub_buf = Vec::new();
+            let mut i = 2;
+            while i < buf.len() {
+                if buf[i] == IAC {
+                    // read another byte
+                    let cmd = match buf.get(i + 1) {
+                        Some(c) => *c,
+                        None => return Ok(None),
+                    };
+                    match cmd {
+                        SE => return Ok(Some((Element::SubNegotiation(sub_buf), i + 2))), // alright, it's over
+                        IAC => sub_buf.push(IAC), // escaped IAC
+                        other => {
+                            warn!("{}: unexpected 0x{:02x} following IAC within subnego", source, other);
+                            return Err(Error::UnexpectedSubNegotiationByte { byte: other, source });
+                        },
+                    }
+                    i += 2;
+                } else {
+                    sub_buf.push(buf[i]);
+                    i += 1;
+                }
+
+                if sub_buf.len() > max_sub_negotiation_length {
+                    warn!("{}: subnego exceeds {} bytes", source, max_sub_negotiation_length);
+                    return Err(Error::SubNegotiationTooLong { max_length: max_sub_negotiation_length, source });
+                }
+            }
+            Ok(None)
+        },
+        other => Ok(Some((Element::Command(other), 2))),
+    }
 }
 
-async fn run_animation(writer: Arc<Mutex<BufWriter<OwnedWriteHalf>>>, addr: SocketAddr, config: SocketConfig) -> Result<(), Error> {
-    if config.animation == "roflcopter" {
-        crate::animations::roflcopter::run(writer, addr).await
-    } else if config.animation == "lollerskates" {
-        crate::animations::lollerskates::run(writer, addr).await
-    } else if config.animation == "lollercoaster" {
-        crate::animations::lollercoaster::run(writer, addr).await
-    } else {
-        eprintln!("unknown animation {:?} configured", config.animation);
-        let mut writer_guard = writer.lock().await;
-        write_all_and_flush(&mut *writer_guard, addr, b"Animation missing.").await
+
+/// The state of a Telnet session as negotiated with the client.
+#[derive(Clone, Debug, Default, Eq, Hash, Ord, PartialEq, PartialOrd)]
+pub struct SessionInfo {
+    /// The terminal type reported by the client, if any, in lowercase.
+    pub terminal_type: Option<String>,
+
+    /// Whether the client agreed to report its window size (RFC1073).
+    pub window_size_negotiated: bool,
+
+    /// The most recently reported window size of the client as (columns, rows), if any.
+    pub window_size: Option<(u16, u16)>,
+
+    /// Whether the client agreed to receive binary data from us (RFC856), i.e. bytes beyond
+    /// 7-bit ASCII such as UTF-8 reach it unchanged.
+    pub binary: bool,
+}
+impl SessionInfo {
+    /// Whether the terminal type reported by the client suggests a terminal that shows neither
+    /// colors nor characters beyond ASCII (e.g. `dumb` or `vt100`).
+    pub fn is_basic_terminal(&self) -> bool {
+        self.terminal_type.as_deref()
+            .is_some_and(|t| t == "dumb" || t.starts_with("vt"))
     }
 }
 
 
-pub(crate) async fn process_command<'r, 'w>(
-    mut reader: &'r mut BufReader<OwnedReadHalf>,
-    writer: Arc<Mutex<BufWriter<OwnedWriteHalf>>>,
+/// Decodes a string sent by the client in a subnegotiation.
+///
+/// Such strings should be ASCII, but UTF-8 is accepted too; invalid sequences are replaced and
+/// control characters are removed.
+pub(crate) fn decode_string(bytes: &[u8]) -> String {
+    String::from_utf8_lossy(bytes)
+        .chars()
+        .filter(|c| !c.is_control())
+        .collect()
+}
+
+
+/// The state of a Telnet connection with a client, independent of how bytes get to and from it.
+///
+/// Data received from the client is passed to [`receive`](Self::receive) and decoded into
+/// [`Event`]s by [`next_event`](Self::next_event); negotiation requests from the client are answered
+/// automatically. Requests that would not change the state of an option (e.g. a repeated WILL) are
+/// not answered again, preventing negotiation loops.
+///
+/// Everything to be sent to the client, answers as well as data, is collected in an output buffer
+/// (see [`output`](Self::output)) until it is taken away.
+pub struct TelnetMachine {
     addr: SocketAddr,
-    config: SocketConfig,
-) -> Result<(), Error> {
-    let cmd_byte = receive_u8(&mut reader, addr).await?;
-    if [DO, DONT, WILL, WONT].contains(&cmd_byte) {
-        // obtain feature ID
-        let option_byte = receive_u8(&mut reader, addr).await?;
-
-        match cmd_byte {
-            DO => {
-                // client wants us to use a feature
-                match option_byte {
-                    _ => {
-                        eprintln!("unexpected DO option {} (0x{:02x})", option_byte, option_byte);
+    read_buf: Vec<u8>,
+    output: Vec<u8>,
+    session_info: SessionInfo,
+    max_sub_negotiation_length: usize,
+
+    /// Whether the last data byte was a CR, meaning that a following NUL or LF is to be swallowed.
+    after_cr: bool,
+
+    /// Decodes the escape sequences sent for special keys.
+    keys: KeyDecoder,
+
+    /// Options that the client has enabled on its end.
+    remote_enabled: HashSet<u8>,
+
+    /// Options that we have enabled (or offered to enable) on our end.
+    local_enabled: HashSet<u8>,
+
+    /// Requests (command and option) that we have already refused.
+    refused: HashSet<(u8, u8)>,
+
+    /// Whether the client speaks Telnet; if not, the connection is a plain byte stream.
+    speaks_telnet: bool,
+
+    /// Whether the client has closed its sending side of the connection.
+    input_closed: bool,
+
+    /// Whether [`Event::EndOfInput`] is yet to be returned.
+    end_of_input_pending: bool,
+}
+impl TelnetMachine {
+    /// Creates the state of a new connection with the client at the given address, which is used
+    /// in log messages and errors.
+    pub fn new(addr: SocketAddr, max_sub_negotiation_length: usize) -> Self {
+        Self {
+            addr,
+            read_buf: Vec::new(),
+            output: Vec::new(),
+            session_info: SessionInfo::default(),
+            max_sub_negotiation_length,
+            after_cr: false,
+            keys: KeyDecoder::new(),
+            remote_enabled: HashSet::new(),
+            local_enabled: HashSet::new(),
+            refused: HashSet::new(),
+            speaks_telnet: true,
+            input_closed: false,
+            end_of_input_pending: false,
+        }
+    }
+
+    /// Treats the connection as a plain byte stream: no negotiation takes place, and IAC bytes are
+    /// neither escaped nor interpreted.
+    pub fn disable_telnet(&mut self) {
+        self.speaks_telnet = false;
+    }
+
+    /// Whether the connection speaks Telnet (as opposed to being a plain byte stream).
+    pub fn speaks_telnet(&self) -> bool {
+        self.speaks_telnet
+    }
+
+    /// Whether the client has closed its sending side of the connection.
+    pub fn input_closed(&self) -> bool {
+        self.input_closed
+    }
+
+    /// Whether we have agreed to echo the client's input, meaning that the client doesn't.
+    pub fn echoes(&self) -> bool {
+        self.local_enabled.contains(&option::ECHO)
+    }
+
+    /// The address of the client.
+    pub fn addr(&self) -> SocketAddr {
+        self.addr
+    }
+
+    /// The state of the session as negotiated so far.
+    pub fn session_info(&self) -> &SessionInfo {
+        &self.session_info
+    }
+
+    /// Starts negotiation by asking the client whether it can handle a "terminal type" query.
+    ///
+    /// Also offers to echo and to suppress go-ahead, which makes most clients switch to sending
+    /// each keystroke immediately instead of whole lines.
+    pub fn negotiate(&mut self) {
+        if !self.speaks_telnet {
+            return;
+        }
+        self.local_enabled.insert(option::ECHO);
+        self.local_enabled.insert(option::SUPPRESS_GO_AHEAD);
+        self.queue_reply(&[
+            IAC, DO, option::TERMINAL_TYPE,
+            IAC, WILL, option::ECHO,
+            IAC, WILL, option::SUPPRESS_GO_AHEAD,
+        ]);
+    }
+
+    /// Takes data received from the client; the events it contains are returned by
+    /// [`next_event`](Self::next_event).
+    pub fn receive(&mut self, data: &[u8]) {
+        if !self.input_closed {
+            self.read_buf.extend_from_slice(data);
+        }
+    }
+
+    /// Notes that the client has closed its sending side of the connection.
+    pub fn receive_end(&mut self) {
+        if self.input_closed {
+            return;
+        }
+        // whatever incomplete sequence is left will never be completed
+        self.read_buf.clear();
+        self.input_closed = true;
+        self.end_of_input_pending = true;
+    }
+
+    /// Returns the next event from the data received so far, or `None` if more data is needed.
+    ///
+    /// Once the client has closed its sending side of the connection, [`Event::EndOfInput`] is
+    /// returned once after all other events.
+    pub fn next_event(&mut self) -> Result<Option<Event>, Error> {
+        if let Some(event) = self.decode_event()? {
+            return Ok(Some(event));
+        }
+        if std::mem::take(&mut self.end_of_input_pending) {
+            return Ok(Some(Event::EndOfInput));
+        }
+        Ok(None)
+    }
+
+    /// Queues a frame of data to be sent to the client, escaping it as necessary.
+    pub fn send_data(&mut self, data: &[u8]) {
+        if !self.speaks_telnet {
+            self.output.extend_from_slice(data);
+            return;
+        }
+        for chunk in data.split_inclusive(|b| *b == IAC) {
+            self.output.extend_from_slice(chunk);
+            if chunk.last() == Some(&IAC) {
+                // double IAC to escape it
+                self.output.push(IAC);
+            }
+        }
+    }
+
+    /// The bytes waiting to be sent to the client.
+    pub fn output(&self) -> &[u8] {
+        &self.output
+    }
+
+    /// Removes bytes that have been sent to the client from the beginning of the output.
+    pub fn consume_output(&mut self, byte_count: usize) {
+        self.output.drain(..byte_count);
+    }
+
+    /// Queues a negotiation request or answer to be sent to the client.
+    fn queue_reply(&mut self, reply: &[u8]) {
+        if logging::hexdump_scope() == HexdumpScope::Negotiation {
+            // with everything being dumped, this is dumped when it is sent
+            logging::hexdump(self.addr, ">", reply);
+        }
+        self.output.extend_from_slice(reply);
+    }
+
+    /// Decodes buffered elements until one of them yields an event.
+    fn decode_event(&mut self) -> Result<Option<Event>, Error> {
+        if !self.speaks_telnet {
+            let mut consumed = 0;
+            let mut ret = None;
+            while ret.is_none() && consumed < self.read_buf.len() {
+                ret = self.process_data(self.read_buf[consumed]);
+                consumed += 1;
+            }
+            self.read_buf.drain(..consumed);
+            return Ok(ret.or_else(|| self.lone_escape()));
+        }
 
-                        // answer with WON'T
-                        let mut writer_guard = writer.lock().await;
-                        write_all_and_flush(&mut *writer_guard, addr, &[IAC, WONT, option_byte]).await?;
+        let mut consumed = 0;
+        let mut ret = None;
+        while ret.is_none() {
+            let (element, length) = match decode_element(&self.read_buf[consumed..], self.addr, self.max_sub_negotiation_length)? {
+                Some(el) => el,
+                None => break,
+            };
+            if logging::hexdump_scope() == HexdumpScope::Negotiation && !matches!(element, Element::Data(_)) {
+                // with everything being dumped, this has already been dumped when it was received
+                logging::hexdump(self.addr, "<", &self.read_buf[consumed..consumed+length]);
+            }
+            consumed += length;
+            ret = self.process_element(element)?;
+        }
+        self.read_buf.drain(..consumed);
+        Ok(ret.or_else(|| self.lone_escape()))
+    }
+
+    /// Once all received data has been decoded, reports an ESC not followed by anything as the
+    /// Escape key.
+    fn lone_escape(&mut self) -> Option<Event> {
+        if self.keys.take_lone_escape() {
+            Some(Event::Data(ESC))
+        } else {
+            None
+        }
+    }
+
+    fn process_element(&mut self, element: Element) -> Result<Option<Event>, Error> {
+        match element {
+            Element::Data(b) => Ok(self.process_data(b)),
+            Element::Command(IP) => Ok(Some(Event::Interrupt)),
+            Element::Command(_) => Ok(None),
+            Element::Negotiation { command, option } => self.process_negotiation(command, option),
+            Element::SubNegotiation(buf) => match self.process_sub_negotiation(&buf) {
+                Ok(event) => Ok(event),
+                Err(e) => {
+                    // some clients get this wrong; not worth ending the session over
+                    warn!("{}; ignoring it", e);
+                    match e {
+                        Error::NoTerminalTypeSubNegotiationCommand { .. }
+                        | Error::UnexpectedTerminalTypeSubNegotiationCommand { .. } => {
+                            // don't keep waiting for a usable answer
+                            Ok(Some(Event::NoTerminalType))
+                        },
+                        _ => Ok(None),
                     }
+                },
+            },
+        }
+    }
+
+    fn process_data(&mut self, b: u8) -> Option<Event> {
+        let after_cr = self.after_cr;
+        self.after_cr = false;
+
+        let b = match self.keys.push(b) {
+            Decoded::Data(b) => b,
+            Decoded::Key(key) => return Some(Event::Key(key)),
+            Decoded::Enter => return Some(Event::Newline),
+            Decoded::Nothing => return None,
+        };
+        match b {
+            b'\r' => {
+                self.after_cr = true;
+                Some(Event::Newline)
+            },
+            b'\0'|b'\n' if after_cr => None, // second half of CR NUL or CR LF
+            b'\n' => Some(Event::Newline),
+            ETX => Some(Event::Interrupt),
+            other => Some(Event::Data(other)),
+        }
+    }
+
+    /// Refuses the given request unless it has already been refused before.
+    fn refuse(&mut self, command: u8, option_byte: u8) {
+        if !self.refused.insert((command, option_byte)) {
+            // don't keep repeating ourselves
+            return;
+        }
+
+        let answer = if command == DO { WONT } else { DONT };
+        self.queue_reply(&[IAC, answer, option_byte]);
+    }
+
+    fn process_negotiation(&mut self, command: u8, option_byte: u8) -> Result<Option<Event>, Error> {
+        match command {
+            DO => {
+                // client wants us to use a feature
+                if !OptionPolicy::accepts(command, option_byte) {
+                    debug!("{}: unexpected DO option {} (0x{:02x})", self.addr, option_byte, option_byte);
+
+                    // answer with WON'T
+                    self.refuse(command, option_byte);
+                } else if self.local_enabled.insert(option_byte) {
+                    // not an answer to our offer; agree
+                    // (we never actually echo anything; the client just shouldn't either)
+                    self.queue_reply(&[IAC, WILL, option_byte]);
                 }
+                self.session_info.binary = self.local_enabled.contains(&option::TRANSMIT_BINARY);
             },
             DONT => {
                 // client does not want us to use a feature
-                match option_byte {
-                    _ => {
-                        eprintln!("unexpected DON'T option {} (0x{:02x})", option_byte, option_byte);
-                    },
+                if self.local_enabled.remove(&option_byte) {
+                    // acknowledge that the option has been turned off
+                    self.queue_reply(&[IAC, WONT, option_byte]);
+                } else {
+                    debug!("{}: unexpected DON'T option {} (0x{:02x})", self.addr, option_byte, option_byte);
                 }
+                self.session_info.binary = self.local_enabled.contains(&option::TRANSMIT_BINARY);
             },
             WILL => {
                 // client is ready to use a feature
+                if self.remote_enabled.contains(&option_byte) {
+                    // we already know; don't answer again
+                    return Ok(None);
+                }
+                if !OptionPolicy::accepts(command, option_byte) {
+                    debug!("{}: unexpected WILL option {} (0x{:02x})", self.addr, option_byte, option_byte);
+
+                    // answer with DON'T
+                    self.refuse(command, option_byte);
+                    return Ok(None);
+                }
+
+                self.remote_enabled.insert(option_byte);
                 match option_byte {
-                    option::NEGO_WIN_SIZE => {
-                        // sure, go ahead
-                        let mut writer_guard = writer.lock().await;
-                        write_all_and_flush(&mut *writer_guard, addr, &[IAC, DO, option_byte]).await?;
-                    },
                     option::TERMINAL_TYPE => {
                         // okay, query the terminal type
-                        let mut writer_guard = writer.lock().await;
-                        write_all_and_flush(&mut *writer_guard, addr, &[IAC, SB, option::TERMINAL_TYPE, termtype::SEND, IAC, SE]).await?;
+                        // (we asked for this option, so WILL is the answer and needs no DO)
+                        self.queue_reply(&[IAC, SB, option::TERMINAL_TYPE, termtype::SEND, IAC, SE]);
+                    },
+                    option::NEGO_WIN_SIZE => {
+                        // sure, go ahead
+                        self.session_info.window_size_negotiated = true;
+                        self.queue_reply(&[IAC, DO, option_byte]);
                     },
                     _ => {
-                        eprintln!("unexpected WILL option {} (0x{:02x})", option_byte, option_byte);
-
-                        // answer with DON'T
-                        let mut writer_guard = writer.lock().await;
-                        write_all_and_flush(&mut *writer_guard, addr, &[IAC, DONT, option_byte]).await?;
+                        // sure, go ahead
+                        self.queue_reply(&[IAC, DO, option_byte]);
                     },
                 }
             },
             WONT => {
                 // client is not ready to use a feature
+                if self.remote_enabled.remove(&option_byte) {
+                    // acknowledge that the option has been turned off
+                    self.queue_reply(&[IAC, DONT, option_byte]);
+                }
+
                 match option_byte {
+                    option::NEGO_WIN_SIZE => {
+                        self.session_info.window_size_negotiated = false;
+                    },
                     option::TERMINAL_TYPE => {
                         // fine, assume ANSI
-                        // start the animation
-                        let writer_copy = Arc::clone(&writer);
-                        let config_copy = config.clone();
-                        tokio::spawn(async move {
-                            if let Err(e) = run_animation(writer_copy, addr, config_copy).await {
-                                eprintln!("connection to {} failed: {}", addr, e);
-                            }
-                        });
+                        return Ok(Some(Event::NoTerminalType));
                     },
                     _ => {
-                        eprintln!("unexpected WON'T option {} (0x{:02x})", option_byte, option_byte);
+                        debug!("{}: unexpected WON'T option {} (0x{:02x})", self.addr, option_byte, option_byte);
                     },
                 }
             },
             _ => unreachable!(),
         }
-    } else if cmd_byte == SB {
-        // client is sending additional negotiation information
-
-        // keep reading until we get IAC
-        let mut buf = Vec::new();
-        loop {
-            let b = receive_u8(&mut reader, addr).await?;
-            if b == IAC {
-                // read another byte
-                let cmd = receive_u8(&mut reader, addr).await?;
-                match cmd {
-                    SE => break, // alright, it's over
-                    IAC => buf.push(b), // escaped IAC
-                    other => {
-                        eprintln!("unexpected 0x{:02x} following IAC within subnego", other);
-                        return Err(Error::UnexpectedSubNegotiationByte { byte: other, source: addr });
-                    },
-                }
-            } else {
-                buf.push(b);
-            }
-        }
+        Ok(None)
+    }
 
+    fn process_sub_negotiation(&mut self, buf: &[u8]) -> Result<Option<Event>, Error> {
         // okay, what do we have?
-        if buf.len() == 0 {
-            eprintln!("no subnego command?!");
-            return Err(Error::NoSubNegotiationCommand { source: addr });
+        if buf.is_empty() {
+            return Err(Error::NoSubNegotiationCommand { source: self.addr });
         }
         let option_byte = buf[0];
         match option_byte {
             option::TERMINAL_TYPE => {
                 if buf.len() == 1 {
-                    eprintln!("no termtype subnego subcomand?!");
-                    return Err(Error::NoTerminalTypeSubNegotiationCommand { source: addr });
+                    return Err(Error::NoTerminalTypeSubNegotiationCommand { source: self.addr });
                 }
 
                 let subcommand_byte = buf[1];
+                if subcommand_byte == termtype::SEND {
+                    // the client is asking us for our terminal type, even though we have refused
+                    // to tell it
+                    debug!("{}: ignoring terminal type query", self.addr);
+                    return Ok(None);
+                }
                 if subcommand_byte != termtype::IS {
-                    eprintln!("termtype subnego subcommand is 0x{:02x}, expected 0x{:02x}", subcommand_byte, termtype::IS);
-                    return Err(Error::UnexpectedTerminalTypeSubNegotiationCommand { byte: subcommand_byte, source: addr });
+                    return Err(Error::UnexpectedTerminalTypeSubNegotiationCommand { byte: subcommand_byte, source: self.addr });
                 }
 
                 // the rest is the terminal type
                 let term_type = &buf[2..];
 
-                let term_type_string: String = term_type
-                    .iter()
-                    .map(|c| (*c) as char)
-                    .collect();
-                eprintln!("term type is {:?}", term_type_string);
-
-                // start the animation
-                let writer_copy = Arc::clone(&writer);
-                let config_copy = config.clone();
-                tokio::spawn(async move {
-                    if let Err(e) = run_animation(writer_copy, addr, config_copy).await {
-                        eprintln!("connection to {} failed: {}", addr, e);
-                    }
-                });
+                // terminal types are case-insensitive (RFC1091)
+                let term_type_string = decode_string(term_type).to_lowercase();
+                info!("{}: term type is {:?}", self.addr, term_type_string);
+
+                self.session_info.terminal_type = Some(term_type_string.clone());
+                Ok(Some(Event::TerminalType(term_type_string)))
             },
             option::NEGO_WIN_SIZE => {
                 // should be five bytes (including option)
                 if buf.len() != 5 {
-                    eprintln!("subnego NEGO_WIN_SIZE but buf has {} instead of 5 bytes", buf.len());
-                    return Err(Error::WrongWindowSizeBytes { byte_count: buf.len(), source: addr });
+                    return Err(Error::WrongWindowSizeBytes { byte_count: buf.len(), source: self.addr });
                 }
                 let cols = u16::from_be_bytes(buf[1..3].try_into().unwrap());
                 let rows = u16::from_be_bytes(buf[3..5].try_into().unwrap());
-                eprintln!("client terminal has {} columns and {} rows", cols, rows);
+                info!("{}: client terminal has {} columns and {} rows", self.addr, cols, rows);
+
+                if cols == 0 || rows == 0 {
+                    // the client does not know (RFC1073)
+                    self.session_info.window_size = None;
+                    return Ok(None);
+                }
+                let cols = cols.min(MAX_WINDOW_SIZE);
+                let rows = rows.min(MAX_WINDOW_SIZE);
+                self.session_info.window_size = Some((cols, rows));
+                Ok(Some(Event::WindowSize { cols, rows }))
             },
             other => {
-                eprintln!("unexpected subnego command {} (0x{:02x})", other, other);
+                debug!("{}: unexpected subnego command {} (0x{:02x})", self.addr, other, other);
+                Ok(None)
             },
         }
     }
-    Ok(())
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn machine() -> TelnetMachine {
+        TelnetMachine::new("192.0.2.1:1234".parse().unwrap(), DEFAULT_MAX_SUB_NEGOTIATION_LENGTH)
+    }
+
+    /// Feeds the bytes to the machine and returns the events they yield.
+    fn events(machine: &mut TelnetMachine, data: &[u8]) -> Vec<Event> {
+        machine.receive(data);
+        let mut ret = Vec::new();
+        while let Some(event) = machine.next_event().unwrap() {
+            ret.push(event);
+        }
+        ret
+    }
+
+    /// Takes everything the machine wants to send.
+    fn take_output(machine: &mut TelnetMachine) -> Vec<u8> {
+        let output = machine.output().to_vec();
+        machine.consume_output(output.len());
+        output
+    }
+
+    #[test]
+    fn opening_negotiation() {
+        let mut m = machine();
+        m.negotiate();
+        assert_eq!(take_output(&mut m), [
+            IAC, DO, option::TERMINAL_TYPE,
+            IAC, WILL, option::ECHO,
+            IAC, WILL, option::SUPPRESS_GO_AHEAD,
+        ]);
+        assert!(m.echoes());
+
+        // the client agreeing to our offers needs no answer
+        events(&mut m, &[IAC, DO, option::ECHO, IAC, DO, option::SUPPRESS_GO_AHEAD]);
+        assert!(take_output(&mut m).is_empty());
+
+        // the client agreeing to our request is answered with the query
+        events(&mut m, &[IAC, WILL, option::TERMINAL_TYPE]);
+        assert_eq!(take_output(&mut m), [IAC, SB, option::TERMINAL_TYPE, termtype::SEND, IAC, SE]);
+    }
+
+    #[test]
+    fn answers_are_not_repeated() {
+        let mut m = machine();
+        events(&mut m, &[IAC, WILL, option::NEGO_WIN_SIZE, IAC, WILL, option::NEGO_WIN_SIZE]);
+        assert_eq!(take_output(&mut m), [IAC, DO, option::NEGO_WIN_SIZE]);
+
+        events(&mut m, &[IAC, DO, option::ECHO, IAC, DO, option::ECHO]);
+        assert_eq!(take_output(&mut m), [IAC, WILL, option::ECHO]);
+
+        // refusals are only sent once, however often the client asks
+        events(&mut m, &[IAC, DO, 42, IAC, WILL, 42, IAC, DO, 42, IAC, WILL, 42]);
+        assert_eq!(take_output(&mut m), [IAC, WONT, 42, IAC, DONT, 42]);
+
+        // turning an option off is acknowledged once
+        events(&mut m, &[IAC, DONT, option::ECHO, IAC, DONT, option::ECHO]);
+        assert_eq!(take_output(&mut m), [IAC, WONT, option::ECHO]);
+        assert!(!m.echoes());
+        events(&mut m, &[IAC, WONT, option::NEGO_WIN_SIZE, IAC, WONT, option::NEGO_WIN_SIZE]);
+        assert_eq!(take_output(&mut m), [IAC, DONT, option::NEGO_WIN_SIZE]);
+        assert!(!m.session_info().window_size_negotiated);
+    }
+
+    #[test]
+    fn refusal_policy() {
+        let cases = [
+            (DO, option::ECHO, WILL),
+            (WILL, option::ECHO, DONT),
+            (DO, option::SUPPRESS_GO_AHEAD, WILL),
+            (WILL, option::SUPPRESS_GO_AHEAD, DO),
+            (DO, option::TERMINAL_TYPE, WONT),
+            (WILL, option::TERMINAL_TYPE, SB),
+            (DO, option::NEGO_WIN_SIZE, WONT),
+            (WILL, option::NEGO_WIN_SIZE, DO),
+            (DO, option::TRANSMIT_BINARY, WILL),
+            (WILL, option::TRANSMIT_BINARY, DO),
+            (DO, 42, WONT),
+            (WILL, 42, DONT),
+        ];
+        for (command, option_byte, answer) in cases {
+            let mut m = machine();
+            events(&mut m, &[IAC, command, option_byte]);
+            let output = take_output(&mut m);
+            assert_eq!(output[..3], [IAC, answer, option_byte], "answer to {} {}", command, option_byte);
+        }
+    }
+
+    #[test]
+    fn sub_negotiation_length_cap() {
+        let mut m = TelnetMachine::new("192.0.2.1:1234".parse().unwrap(), 8);
+
+        // exactly at the limit
+        let mut data = vec![IAC, SB, option::TERMINAL_TYPE, termtype::IS];
+        data.extend_from_slice(b"vt1002");
+        data.extend_from_slice(&[IAC, SE]);
+        assert_eq!(events(&mut m, &data), [Event::TerminalType("vt1002".to_owned())]);
+
+        // over it, even before the subnegotiation is complete
+        m.receive(&[IAC, SB, option::TERMINAL_TYPE, termtype::IS]);
+        m.receive(b"xterm-256color");
+        assert!(matches!(m.next_event(), Err(Error::SubNegotiationTooLong { max_length: 8, .. })));
+    }
+
+    #[test]
+    fn line_ends() {
+        let mut m = machine();
+        assert_eq!(events(&mut m, b"a\r\0b\r\nc\nd\re"), [
+            Event::Data(b'a'), Event::Newline,
+            Event::Data(b'b'), Event::Newline,
+            Event::Data(b'c'), Event::Newline,
+            Event::Data(b'd'), Event::Newline,
+            Event::Data(b'e'),
+        ]);
+
+        // split across receives
+        assert_eq!(events(&mut m, b"\r"), [Event::Newline]);
+        assert_eq!(events(&mut m, b"\nf"), [Event::Data(b'f')]);
+        assert_eq!(events(&mut m, b"\r"), [Event::Newline]);
+        assert_eq!(events(&mut m, b"\0"), []);
+    }
+
+    #[test]
+    fn escaped_data() {
+        let mut m = machine();
+        assert_eq!(events(&mut m, &[b'x', IAC, IAC, IAC, IP, 0x03]), [
+            Event::Data(b'x'), Event::Data(IAC), Event::Interrupt, Event::Interrupt,
+        ]);
+
+        m.send_data(&[1, IAC, 2]);
+        assert_eq!(take_output(&mut m), [1, IAC, IAC, 2]);
+    }
+
+    #[test]
+    fn window_size() {
+        let mut m = machine();
+        events(&mut m, &[IAC, WILL, option::NEGO_WIN_SIZE]);
+        assert!(m.session_info().window_size_negotiated);
+
+        let naws = [IAC, SB, option::NEGO_WIN_SIZE, 0, 80, 0, 24, IAC, SE];
+        assert_eq!(events(&mut m, &naws), [Event::WindowSize { cols: 80, rows: 24 }]);
+        assert_eq!(m.session_info().window_size, Some((80, 24)));
+
+        // a size byte of 255 arrives as an escaped IAC, possibly in pieces
+        m.receive(&[IAC, SB, option::NEGO_WIN_SIZE, 1, IAC]);
+        assert_eq!(m.next_event().unwrap(), None);
+        assert_eq!(events(&mut m, &[IAC, 0, 50, IAC, SE]), [Event::WindowSize { cols: 511, rows: 50 }]);
+
+        // a wrong length is ignored, and what follows is still decoded
+        assert_eq!(events(&mut m, &[IAC, SB, option::NEGO_WIN_SIZE, 0, 80, 0, IAC, SE, b'q']), [Event::Data(b'q')]);
+        assert_eq!(m.session_info().window_size, Some((511, 50)));
+    }
+
+    #[test]
+    fn window_size_limits() {
+        let mut m = machine();
+        events(&mut m, &[IAC, WILL, option::NEGO_WIN_SIZE]);
+
+        // huge sizes are clamped
+        let naws = [IAC, SB, option::NEGO_WIN_SIZE, IAC, IAC, IAC, IAC, IAC, IAC, IAC, IAC, IAC, SE];
+        assert_eq!(
+            events(&mut m, &naws),
+            [Event::WindowSize { cols: MAX_WINDOW_SIZE, rows: MAX_WINDOW_SIZE }],
+        );
+        assert_eq!(m.session_info().window_size, Some((MAX_WINDOW_SIZE, MAX_WINDOW_SIZE)));
+        assert_eq!(
+            events(&mut m, &[IAC, SB, option::NEGO_WIN_SIZE, 0, 80, 4, 0, IAC, SE]),
+            [Event::WindowSize { cols: 80, rows: MAX_WINDOW_SIZE }],
+        );
+
+        // zero in either dimension means the size is unknown
+        for naws in [[0, 0, 0, 24], [0, 80, 0, 0], [0, 0, 0, 0]] {
+            events(&mut m, &[IAC, SB, option::NEGO_WIN_SIZE, 0, 80, 0, 24, IAC, SE]);
+            let mut data = vec![IAC, SB, option::NEGO_WIN_SIZE];
+            data.extend_from_slice(&naws);
+            data.extend_from_slice(&[IAC, SE]);
+            assert!(events(&mut m, &data).is_empty());
+            assert_eq!(m.session_info().window_size, None);
+        }
+    }
+
+    #[test]
+    fn binary() {
+        let mut m = machine();
+        assert!(!m.session_info().binary);
+
+        // the client sending binary data has no bearing on what we send
+        events(&mut m, &[IAC, WILL, option::TRANSMIT_BINARY]);
+        assert_eq!(take_output(&mut m), [IAC, DO, option::TRANSMIT_BINARY]);
+        assert!(!m.session_info().binary);
+
+        events(&mut m, &[IAC, DO, option::TRANSMIT_BINARY]);
+        assert_eq!(take_output(&mut m), [IAC, WILL, option::TRANSMIT_BINARY]);
+        assert!(m.session_info().binary);
+
+        events(&mut m, &[IAC, DONT, option::TRANSMIT_BINARY]);
+        assert_eq!(take_output(&mut m), [IAC, WONT, option::TRANSMIT_BINARY]);
+        assert!(!m.session_info().binary);
+    }
+
+    #[test]
+    fn terminal_type() {
+        let mut m = machine();
+        let mut data = vec![IAC, SB, option::TERMINAL_TYPE, termtype::IS];
+        data.extend_from_slice(b"XTerm-256Color\x07");
+        data.extend_from_slice(&[IAC, SE]);
+        assert_eq!(events(&mut m, &data), [Event::TerminalType("xterm-256color".to_owned())]);
+        assert_eq!(m.session_info().terminal_type.as_deref(), Some("xterm-256color"));
+        assert!(!m.session_info().is_basic_terminal());
+
+        // being asked for ours is ignored
+        assert_eq!(events(&mut m, &[IAC, SB, option::TERMINAL_TYPE, termtype::SEND, IAC, SE]), []);
+        assert!(take_output(&mut m).is_empty());
+
+        // malformed answers and refusals end the wait for the terminal type
+        assert_eq!(events(&mut m, &[IAC, SB, option::TERMINAL_TYPE, IAC, SE]), [Event::NoTerminalType]);
+        assert_eq!(events(&mut m, &[IAC, SB, option::TERMINAL_TYPE, 7, IAC, SE]), [Event::NoTerminalType]);
+        assert_eq!(events(&mut m, &[IAC, WONT, option::TERMINAL_TYPE]), [Event::NoTerminalType]);
+    }
+
+    #[test]
+    fn plain_byte_stream() {
+        let mut m = machine();
+        m.disable_telnet();
+        m.negotiate();
+        assert!(take_output(&mut m).is_empty());
+        assert_eq!(events(&mut m, &[IAC, DO, b'a']), [Event::Data(IAC), Event::Data(DO), Event::Data(b'a')]);
+        m.send_data(&[IAC]);
+        assert_eq!(take_output(&mut m), [IAC]);
+    }
+
+    #[test]
+    fn end_of_input() {
+        let mut m = machine();
+        assert_eq!(events(&mut m, &[b'a', IAC, SB]), [Event::Data(b'a')]);
+
+        // the incomplete subnegotiation is dropped, as is anything received afterwards
+        m.receive_end();
+        m.receive(b"b");
+        assert_eq!(events(&mut m, b""), [Event::EndOfInput]);
+        assert_eq!(events(&mut m, b""), []);
+        assert!(m.input_closed());
+    }
 }
@@ -0,0 +1,110 @@
+//! TLS for sockets serving Telnet over TLS (telnets, RFC 8143 style).
+
+
+use std::fmt;
+use std::path::PathBuf;
+use std::sync::Arc;
+
+use rustls::RootCertStore;
+use rustls::pki_types::{CertificateDer, PrivateKeyDer};
+use rustls::pki_types::pem::PemObject;
+use rustls::server::WebPkiClientVerifier;
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use tokio_rustls::TlsAcceptor;
+
+
+/// Configuration of TLS on a socket.
+#[derive(Clone, Debug, Deserialize, JsonSchema, PartialEq, Serialize)]
+pub(crate) struct TlsConfig {
+    /// Path to a PEM file containing the server certificate, followed by any intermediate
+    /// certificates.
+    pub cert_path: PathBuf,
+
+    /// Path to a PEM file containing the private key of the server certificate.
+    pub key_path: PathBuf,
+
+    /// Path to a PEM file containing the certificates of the authorities whose client certificates
+    /// are accepted. If set, clients must present a valid certificate to connect.
+    #[serde(default)]
+    pub client_ca_path: Option<PathBuf>,
+}
+
+
+#[derive(Debug)]
+#[non_exhaustive]
+pub(crate) enum Error {
+    #[non_exhaustive]
+    Pem { path: PathBuf, error: rustls::pki_types::pem::Error },
+
+    #[non_exhaustive]
+    NoCertificates { path: PathBuf },
+
+    #[non_exhaustive]
+    Rustls { error: rustls::Error },
+
+    #[non_exhaustive]
+    ClientVerifier { error: rustls::server::VerifierBuilderError },
+}
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Pem { path, error }
+                => write!(f, "failed to read {}: {}", path.display(), error),
+            Self::NoCertificates { path }
+                => write!(f, "no certificates found in {}", path.display()),
+            Self::Rustls { error }
+                => write!(f, "invalid TLS configuration: {}", error),
+            Self::ClientVerifier { error }
+                => write!(f, "invalid client certificate authorities: {}", error),
+        }
+    }
+}
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Self::Pem { error, .. } => Some(error),
+            Self::NoCertificates { .. } => None,
+            Self::Rustls { error } => Some(error),
+            Self::ClientVerifier { error } => Some(error),
+        }
+    }
+}
+
+
+fn load_certificates(path: &PathBuf) -> Result<Vec<CertificateDer<'static>>, Error> {
+    let certificates = CertificateDer::pem_file_iter(path)
+        .and_then(|iter| iter.collect::<Result<Vec<_>, _>>())
+        .map_err(|error| Error::Pem { path: path.clone(), error })?;
+    if certificates.is_empty() {
+        return Err(Error::NoCertificates { path: path.clone() });
+    }
+    Ok(certificates)
+}
+
+
+/// Loads the certificates and key and prepares accepting TLS connections with them.
+pub(crate) fn build_acceptor(config: &TlsConfig) -> Result<TlsAcceptor, Error> {
+    let certificates = load_certificates(&config.cert_path)?;
+    let key = PrivateKeyDer::from_pem_file(&config.key_path)
+        .map_err(|error| Error::Pem { path: config.key_path.clone(), error })?;
+
+    let builder = rustls::ServerConfig::builder();
+    let builder = match &config.client_ca_path {
+        Some(client_ca_path) => {
+            let mut roots = RootCertStore::empty();
+            for certificate in load_certificates(client_ca_path)? {
+                roots.add(certificate)
+                    .map_err(|error| Error::Rustls { error })?;
+            }
+            let verifier = WebPkiClientVerifier::builder(Arc::new(roots))
+                .build()
+                .map_err(|error| Error::ClientVerifier { error })?;
+            builder.with_client_cert_verifier(verifier)
+        },
+        None => builder.with_no_client_auth(),
+    };
+    let server_config = builder.with_single_cert(certificates, key)
+        .map_err(|error| Error::Rustls { error })?;
+    Ok(TlsAcceptor::from(Arc::new(server_config)))
+}
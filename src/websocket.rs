@@ -0,0 +1,85 @@
+//! Serving sessions over WebSocket, e.g. to xterm.js with its attach addon.
+//!
+//! The messages of the WebSocket are bridged to a plain byte stream, which the session then uses
+//! like any other connection: data from the client arrives from binary and text messages, data to
+//! the client is sent in binary messages.
+
+
+use std::net::SocketAddr;
+
+use futures_util::{SinkExt, StreamExt};
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt, DuplexStream};
+use tokio_tungstenite::WebSocketStream;
+use tokio_tungstenite::tungstenite::{self, Message};
+use tokio_util::sync::CancellationToken;
+
+use crate::task;
+use crate::connection::{Reader, Writer};
+
+
+/// How many bytes can be buffered between the WebSocket and the session in each direction.
+const BRIDGE_BUFFER_SIZE: usize = 64 * 1024;
+
+
+/// Performs the WebSocket handshake on the stream and bridges the resulting WebSocket to a byte
+/// stream.
+///
+/// The bridge stops as soon as `cancel` is cancelled, and cancels it itself once the WebSocket is
+/// closed, so that the session does not outlive the client (or vice versa).
+pub(crate) async fn accept<S: AsyncRead + AsyncWrite + Send + Unpin + 'static>(stream: S, addr: SocketAddr, cancel: CancellationToken) -> Result<(Reader, Writer), tungstenite::Error> {
+    let websocket = tokio_tungstenite::accept_async(stream).await?;
+    let (session_side, bridge_side) = tokio::io::duplex(BRIDGE_BUFFER_SIZE);
+    task::spawn_logged(format!("WebSocket bridge of {}", addr), async move {
+        tokio::select! {
+            _ = bridge(websocket, bridge_side) => {},
+            // even if stuck sending to a client that isn't reading
+            _ = cancel.cancelled() => {},
+        }
+        cancel.cancel();
+    });
+    let (reader, writer) = tokio::io::split(session_side);
+    Ok((Box::new(reader), Box::new(writer)))
+}
+
+
+/// Shuttles data between the WebSocket and the byte stream until either of them is closed.
+async fn bridge<S: AsyncRead + AsyncWrite + Unpin>(websocket: WebSocketStream<S>, stream: DuplexStream) {
+    let (mut websocket_sink, mut websocket_stream) = websocket.split();
+    let (mut stream_reader, mut stream_writer) = tokio::io::split(stream);
+    let mut buf = vec![0u8; BRIDGE_BUFFER_SIZE];
+    loop {
+        tokio::select! {
+            message = websocket_stream.next() => {
+                let written = match message {
+                    Some(Ok(Message::Binary(data))) => stream_writer.write_all(&data).await,
+                    Some(Ok(Message::Text(text))) => stream_writer.write_all(text.as_bytes()).await,
+                    // pings are answered by tungstenite
+                    Some(Ok(Message::Ping(_)|Message::Pong(_)|Message::Frame(_))) => Ok(()),
+                    Some(Ok(Message::Close(_))) => {
+                        // sends the reply to the close request
+                        let _ = websocket_sink.close().await;
+                        break;
+                    },
+                    Some(Err(_))|None => break,
+                };
+                if written.is_err() {
+                    break;
+                }
+            },
+            read = stream_reader.read(&mut buf) => {
+                match read {
+                    Ok(0)|Err(_) => {
+                        // the session is over
+                        let _ = websocket_sink.send(Message::Close(None)).await;
+                        break;
+                    },
+                    Ok(byte_count) => {
+                        if websocket_sink.send(Message::binary(buf[..byte_count].to_vec())).await.is_err() {
+                            break;
+                        }
+                    },
+                }
+            },
+        }
+    }
+}